@@ -4,13 +4,16 @@
 use clap::{Parser, Subcommand};
 use logi_mx_driver::prelude::*;
 use masterror::prelude::*;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
 #[derive(Parser)]
 #[command(name = "logi-mx")]
 #[command(about = "Logitech MX series mouse configuration tool", long_about = None)]
 struct Cli {
+    #[arg(long, global = true, value_name = "FILE")]
+    capture: Option<std::path::PathBuf>,
+
     #[command(subcommand)]
     command: Commands
 }
@@ -19,6 +22,8 @@ struct Cli {
 enum Commands {
     Info,
 
+    Capabilities,
+
     Battery,
 
     Set {
@@ -29,6 +34,53 @@ enum Commands {
     Config {
         #[command(subcommand)]
         action: ConfigCommands
+    },
+
+    Host {
+        #[command(subcommand)]
+        action: HostCommands
+    },
+
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCommands
+    },
+
+    Onboard {
+        #[command(subcommand)]
+        action: OnboardCommands
+    },
+
+    Remap {
+        #[command(subcommand)]
+        action: RemapCommands
+    },
+
+    Keyboard {
+        #[arg(long, default_value_t = 1, help = "Bolt receiver slot of the keyboard")]
+        slot: u8,
+
+        #[command(subcommand)]
+        action: KeyboardCommands
+    },
+
+    Firmware {
+        #[command(subcommand)]
+        action: FirmwareCommands
+    },
+
+    Decode {
+        #[arg(
+            required = true,
+            help = "Report bytes in hex, e.g. 10 01 00 15 22 01 00"
+        )]
+        report: Vec<String>,
+
+        #[arg(long, help = "Decode as a report received from the device")]
+        received: bool,
+
+        #[arg(long, value_parser = parse_feature_id, help = "Feature id behind the index, e.g. 2201")]
+        feature: Option<u16>
     }
 }
 
@@ -52,6 +104,113 @@ enum SetCommands {
 
         #[arg(long)]
         inverted: bool
+    },
+
+    ReportRate {
+        hz: u16
+    }
+}
+
+#[derive(Subcommand)]
+enum HostCommands {
+    List,
+
+    Switch {
+        #[arg(help = "next, prev or a channel number (1-based)")]
+        target: HostTarget
+    }
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    List,
+
+    Use {
+        name: String
+    },
+
+    Show {
+        #[arg(help = "Profile to show instead of the active one")]
+        name: Option<String>
+    }
+}
+
+#[derive(Subcommand)]
+enum OnboardCommands {
+    Dump,
+
+    Backup {
+        path: String
+    },
+
+    Restore {
+        path: String
+    },
+
+    Mode {
+        #[arg(value_parser = ["onboard", "host"])]
+        mode: String
+    }
+}
+
+#[derive(Subcommand)]
+enum RemapCommands {
+    List,
+
+    Set {
+        button: ButtonId,
+
+        #[arg(required = true, help = "Key names, e.g. KEY_LEFTCTRL KEY_C")]
+        keys: Vec<String>
+    },
+
+    Disable {
+        button: ButtonId
+    },
+
+    Reset {
+        button: ButtonId
+    }
+}
+
+#[derive(Subcommand)]
+enum KeyboardCommands {
+    Info,
+
+    Backlight {
+        #[arg(help = "off, auto or manual")]
+        mode: BacklightMode,
+
+        #[arg(long, default_value_t = 0, help = "Brightness level in manual mode")]
+        level: u8
+    },
+
+    FnInversion {
+        #[arg(value_parser = clap::builder::BoolishValueParser::new(), help = "on or off")]
+        enabled: bool
+    },
+
+    DisableKeys {
+        #[arg(help = "capslock, numlock, scrolllock, insert or super; none re-enables all")]
+        keys: Vec<DisableableKey>
+    }
+}
+
+#[derive(Subcommand)]
+enum FirmwareCommands {
+    Info {
+        #[arg(long, help = "Query the Bolt receiver instead of the mouse")]
+        receiver: bool
+    },
+
+    Update {
+        path: String,
+
+        #[arg(long, help = "Update the Bolt receiver instead of the mouse")]
+        receiver: bool,
+
+        #[arg(long, help = "Validate against an emulated device only")]
+        dry_run: bool
     }
 }
 
@@ -61,9 +220,18 @@ enum ConfigCommands {
 
     Edit,
 
-    Export { path: String },
+    Export {
+        path: String
+    },
+
+    Import {
+        path: String
+    },
 
-    Import { path: String }
+    Check {
+        #[arg(help = "Config file to check instead of the current one")]
+        path: Option<std::path::PathBuf>
+    }
 }
 
 fn main() -> Result<()> {
@@ -74,52 +242,179 @@ fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
+    if let Some(path) = cli.capture {
+        set_capture_path(path);
+    }
+
     match cli.command {
         Commands::Info => cmd_info(),
+        Commands::Capabilities => cmd_capabilities(),
         Commands::Battery => cmd_battery(),
         Commands::Set {
             setting
         } => cmd_set(setting),
         Commands::Config {
             action
-        } => cmd_config(action)
+        } => cmd_config(action),
+        Commands::Host {
+            action
+        } => cmd_host(action),
+        Commands::Profile {
+            action
+        } => cmd_profile(action),
+        Commands::Onboard {
+            action
+        } => cmd_onboard(action),
+        Commands::Remap {
+            action
+        } => cmd_remap(action),
+        Commands::Keyboard {
+            slot,
+            action
+        } => cmd_keyboard(slot, action),
+        Commands::Firmware {
+            action
+        } => cmd_firmware(action),
+        Commands::Decode {
+            report,
+            received,
+            feature
+        } => cmd_decode(&report.join(" "), received, feature)
     }
 }
 
 fn cmd_info() -> Result<()> {
     info!("Opening device...");
 
-    let mut device = MxMaster3s::open_bolt_receiver(2)?;
+    let mut device = GenericHidppMouse::open_any()?;
+    let capabilities = device.capabilities()?;
 
     let name = device.get_device_name()?;
-    let dpi = device.get_dpi()?;
-    let smartshift = device.get_smartshift()?;
-    let hires = device.get_hires_scroll()?;
 
     println!("Device Information:");
     println!("  Name: {}", name);
-    println!("  DPI: {}", dpi);
-    println!(
-        "  SmartShift: {} (threshold: {})",
-        if smartshift.enabled {
-            "enabled"
-        } else {
-            "disabled"
-        },
-        smartshift.threshold
-    );
-    println!(
-        "  Hi-Res Scroll: {}",
-        if hires.enabled { "enabled" } else { "disabled" }
-    );
+
+    if capabilities.supports(Setting::Dpi) {
+        println!("  DPI: {}", device.get_dpi()?);
+    }
+
+    if capabilities.supports(Setting::SmartShift) {
+        let smartshift = device.get_smartshift()?;
+        println!(
+            "  SmartShift: {} (threshold: {})",
+            if smartshift.enabled {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            smartshift.threshold
+        );
+    }
+
+    if capabilities.supports(Setting::HiResScroll) {
+        let hires = device.get_hires_scroll()?;
+        println!(
+            "  Hi-Res Scroll: {}",
+            if hires.enabled { "enabled" } else { "disabled" }
+        );
+    }
+
+    if let Ok(rate) = device.get_report_rate() {
+        let allowed = device
+            .get_report_rate_list()
+            .map(|rates| {
+                rates
+                    .iter()
+                    .map(|hz| hz.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        println!("  Report Rate: {} Hz (supported: {})", rate, allowed);
+    }
+
+    if let Ok(firmware) = device.firmware_info()
+        && let Some(main) = firmware
+            .entities
+            .iter()
+            .find(|e| e.kind == FirmwareEntityKind::MainApplication)
+    {
+        println!("  Firmware: {}", main.version);
+    }
 
     Ok(())
 }
 
+fn cmd_capabilities() -> Result<()> {
+    let mut device = GenericHidppMouse::open_any()?;
+    let capabilities = device.capabilities()?;
+
+    println!("Capabilities:");
+    println!("  Features:");
+    for feature in &capabilities.features {
+        println!(
+            "    {:04x} {} v{}",
+            feature.feature_id,
+            feature_name(feature.feature_id).unwrap_or("Unknown"),
+            feature.version
+        );
+    }
+
+    match &capabilities.dpi {
+        Some(range) if !range.values.is_empty() => {
+            println!("  DPI Values: {:?}", range.values)
+        }
+        Some(range) if range.step > 0 => println!(
+            "  DPI Range: {}-{} (step {})",
+            range.min, range.max, range.step
+        ),
+        Some(range) => println!("  DPI Range: {}-{}", range.min, range.max),
+        None => println!("  DPI Range: unknown")
+    }
+
+    if let Some(wheel) = capabilities.wheel {
+        println!(
+            "  Wheel: multiplier {}, inversion {}, ratchet switch {}",
+            wheel.multiplier,
+            if wheel.invertible { "yes" } else { "no" },
+            if wheel.ratchet_switch { "yes" } else { "no" }
+        );
+    }
+
+    println!("  Hosts: {}", capabilities.hosts);
+
+    let controls: Vec<String> = capabilities
+        .controls
+        .iter()
+        .map(|button| format!("{:?}", button))
+        .collect();
+    println!("  Controls: {}", controls.join(", "));
+
+    let unsupported: Vec<String> = Setting::ALL
+        .into_iter()
+        .filter_map(|setting| capabilities.unsupported_reason(setting))
+        .collect();
+    if !unsupported.is_empty() {
+        println!("  Unavailable:");
+        for reason in unsupported {
+            println!("    {}", reason);
+        }
+    }
+
+    Ok(())
+}
+
+fn ensure_supported(device: &mut GenericHidppMouse, setting: Setting) -> Result<()> {
+    match device.capabilities()?.unsupported_reason(setting) {
+        Some(reason) => Err(AppError::bad_request(reason)),
+        None => Ok(())
+    }
+}
+
 fn cmd_battery() -> Result<()> {
     info!("Checking battery...");
 
-    let mut device = MxMaster3s::open_bolt_receiver(2)?;
+    let mut device = GenericHidppMouse::open_any()?;
     let battery = device.get_battery_info()?;
 
     println!("Battery Status:");
@@ -130,12 +425,13 @@ fn cmd_battery() -> Result<()> {
 }
 
 fn cmd_set(setting: SetCommands) -> Result<()> {
-    let mut device = MxMaster3s::open_bolt_receiver(2)?;
+    let mut device = GenericHidppMouse::open_any()?;
 
     match setting {
         SetCommands::Dpi {
             value
         } => {
+            ensure_supported(&mut device, Setting::Dpi)?;
             info!("Setting DPI to {}...", value);
             device.set_dpi(value)?;
             println!("DPI set to {}", value);
@@ -144,6 +440,7 @@ fn cmd_set(setting: SetCommands) -> Result<()> {
             enabled,
             threshold
         } => {
+            ensure_supported(&mut device, Setting::SmartShift)?;
             info!(
                 "Configuring SmartShift: enabled={}, threshold={}",
                 enabled, threshold
@@ -162,6 +459,10 @@ fn cmd_set(setting: SetCommands) -> Result<()> {
             enabled,
             inverted
         } => {
+            ensure_supported(&mut device, Setting::HiResScroll)?;
+            if inverted {
+                ensure_supported(&mut device, Setting::ScrollInversion)?;
+            }
             info!(
                 "Configuring hi-res scroll: enabled={}, inverted={}",
                 enabled, inverted
@@ -176,15 +477,418 @@ fn cmd_set(setting: SetCommands) -> Result<()> {
                 if inverted { "yes" } else { "no" }
             );
         }
+        SetCommands::ReportRate {
+            hz
+        } => {
+            ensure_supported(&mut device, Setting::ReportRate)?;
+            let rates = device.get_report_rate_list()?;
+            if !rates.contains(&hz) {
+                let allowed: Vec<String> = rates.iter().map(|r| r.to_string()).collect();
+                return Err(AppError::bad_request(format!(
+                    "Unsupported report rate {} Hz, supported: {}",
+                    hz,
+                    allowed.join(", ")
+                )));
+            }
+            info!("Setting report rate to {} Hz...", hz);
+            device.set_report_rate(hz)?;
+            println!("Report rate set to {} Hz", hz);
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_host(action: HostCommands) -> Result<()> {
+    let mut device = GenericHidppMouse::open_any()?;
+    let hosts = device.get_host_info()?;
+
+    match action {
+        HostCommands::List => {
+            println!("Easy-Switch Hosts:");
+            for host in &hosts.hosts {
+                println!(
+                    "  {}{}: {} ({}, {:?})",
+                    if host.index == hosts.current {
+                        "*"
+                    } else {
+                        " "
+                    },
+                    host.index + 1,
+                    host.name.as_deref().unwrap_or("<unnamed>"),
+                    if host.paired { "paired" } else { "empty" },
+                    host.os
+                );
+            }
+        }
+        HostCommands::Switch {
+            target
+        } => {
+            ensure_supported(&mut device, Setting::EasySwitch)?;
+            let host = target
+                .resolve(hosts.current, hosts.count)
+                .ok_or_else(|| AppError::bad_request("Host channel out of range"))?;
+            info!("Switching to host {}...", host + 1);
+            device.change_host(host)?;
+            println!("Switched to host {}", host + 1);
+        }
+    }
+
+    Ok(())
+}
+
+// Profiles are switched by the daemon, which is asked over its control
+// socket.
+fn active_profile() -> Result<String> {
+    match send_control_request(&control_socket_path(), &ControlRequest::ActiveProfile)? {
+        ControlResponse::Profile {
+            active, ..
+        } => Ok(active),
+        response => Err(AppError::internal(format!(
+            "Unexpected reply from logi-mx-daemon: {:?}",
+            response
+        )))
+    }
+}
+
+fn cmd_profile(action: ProfileCommands) -> Result<()> {
+    match action {
+        ProfileCommands::List => {
+            let config = load_config_reporting()?;
+            let active = active_profile();
+            for device in &config.devices {
+                println!("{}:", device.name);
+                for name in device.profile_names() {
+                    let marker = match &active {
+                        Ok(active) if active == name => "*",
+                        _ => " "
+                    };
+                    println!("  {}{}", marker, name);
+                }
+            }
+            if let Err(e) = active {
+                warn!("Active profile unknown: {}", e);
+            }
+        }
+        ProfileCommands::Use {
+            name
+        } => {
+            let response = send_control_request(
+                &control_socket_path(),
+                &ControlRequest::UseProfile {
+                    name
+                }
+            )?;
+            if let ControlResponse::Profile {
+                active,
+                app
+            } = response
+            {
+                println!("Active profile: {}", active);
+                if let Some(app) = app {
+                    println!(
+                        "The [[apps]] rule for {} applies on top while it has focus",
+                        app
+                    );
+                }
+            }
+        }
+        ProfileCommands::Show {
+            name
+        } => {
+            let config = load_config_reporting()?;
+            let name = match name {
+                Some(name) => name,
+                None => active_profile().unwrap_or_else(|e| {
+                    warn!("Showing the default profile: {}", e);
+                    DEFAULT_PROFILE.to_string()
+                })
+            };
+            if !config.has_profile(&name) {
+                return Err(AppError::bad_request(format!(
+                    "No device defines a profile named {}",
+                    name
+                )));
+            }
+
+            // The settings each entry applies under `name`, as a config.
+            let resolved = Config {
+                devices: config
+                    .devices
+                    .iter()
+                    .map(|device| device.resolve_profile(&name))
+                    .collect(),
+                keyboards: Vec::new(),
+                ..config
+            };
+            let toml_str = toml::to_string_pretty(&resolved)
+                .map_err(|e| AppError::internal("Failed to serialize config").with_source(e))?;
+            println!("# Profile: {}\n{}", name, toml_str);
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_remap(action: RemapCommands) -> Result<()> {
+    let mut device = GenericHidppMouse::open_any()?;
+    ensure_supported(&mut device, Setting::PersistentRemap)?;
+
+    let (button, persistent) = match action {
+        RemapCommands::List => {
+            println!("Persistent Remaps:");
+            for control in device.list_remappable_controls()? {
+                let label = control.button.map_or_else(
+                    || format!("CID {:04x}", control.control_id),
+                    |b| format!("{:?}", b)
+                );
+                let action = control
+                    .action
+                    .to_keys()
+                    .map_or_else(|| format!("{:?}", control.action), |keys| keys.join("+"));
+                println!("  {}: {}", label, action);
+            }
+            return Ok(());
+        }
+        RemapCommands::Set {
+            button,
+            keys
+        } => (button, PersistentAction::from_keys(&keys)?),
+        RemapCommands::Disable {
+            button
+        } => (button, PersistentAction::Disabled),
+        RemapCommands::Reset {
+            button
+        } => (button, PersistentAction::Default)
+    };
+
+    info!("Storing {:?} on button {:?}...", persistent, button);
+    device.set_persistent_action(button, persistent)?;
+    println!("Button {:?} remapped on device", button);
+
+    Ok(())
+}
+
+fn cmd_keyboard(slot: u8, action: KeyboardCommands) -> Result<()> {
+    let mut keyboard = GenericHidppKeyboard::open_bolt_receiver(slot)?;
+
+    match action {
+        KeyboardCommands::Info => {
+            println!("Keyboard Information:");
+            println!("  Name: {}", keyboard.get_device_name()?);
+            if let Ok(battery) = keyboard.get_battery_info() {
+                println!("  Battery: {}% ({:?})", battery.level, battery.status);
+            }
+            if let Ok(backlight) = keyboard.get_backlight() {
+                println!(
+                    "  Backlight: {:?} (level {})",
+                    backlight.mode, backlight.level
+                );
+            }
+            if let Ok(inverted) = keyboard.get_fn_inversion() {
+                println!("  Fn Inversion: {}", if inverted { "on" } else { "off" });
+            }
+            if let Ok(keys) = keyboard.get_disabled_keys() {
+                println!("  Disabled Keys: {:?}", keys);
+            }
+        }
+        KeyboardCommands::Backlight {
+            mode,
+            level
+        } => {
+            keyboard.set_backlight(BacklightConfig {
+                mode,
+                level
+            })?;
+            println!("Backlight set to {:?}", mode);
+        }
+        KeyboardCommands::FnInversion {
+            enabled
+        } => {
+            keyboard.set_fn_inversion(enabled)?;
+            println!(
+                "Fn inversion {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+        KeyboardCommands::DisableKeys {
+            keys
+        } => {
+            keyboard.set_disabled_keys(&keys)?;
+            println!("Disabled keys: {:?}", keys);
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_onboard(action: OnboardCommands) -> Result<()> {
+    let mut device = GenericHidppMouse::open_any()?;
+    let mut profiles = device.onboard_profiles()?;
+
+    match action {
+        OnboardCommands::Dump => {
+            let mut backup = profiles.backup()?;
+            // Sectors that did not decode only have their raw data to show.
+            for slot in backup
+                .profiles
+                .iter_mut()
+                .filter(|slot| slot.profile.is_some())
+            {
+                slot.raw.clear();
+            }
+            let toml_str = toml::to_string_pretty(&backup)
+                .map_err(|e| AppError::internal("Failed to serialize profiles").with_source(e))?;
+            println!("{}", toml_str);
+        }
+        OnboardCommands::Backup {
+            path
+        } => {
+            let backup = profiles.backup()?;
+            let toml_str = toml::to_string_pretty(&backup)
+                .map_err(|e| AppError::internal("Failed to serialize profiles").with_source(e))?;
+            std::fs::write(&path, toml_str)
+                .map_err(|e| AppError::internal("Failed to write backup").with_source(e))?;
+            println!(
+                "Backed up {} onboard profiles to {}",
+                backup.profiles.len(),
+                path
+            );
+        }
+        OnboardCommands::Restore {
+            path
+        } => {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| AppError::internal("Failed to read backup").with_source(e))?;
+            let backup: ProfileBackup = toml::from_str(&content)
+                .map_err(|e| AppError::bad_request("Invalid backup format").with_source(e))?;
+            info!("Restoring {} onboard profiles...", backup.profiles.len());
+            profiles.restore(&backup)?;
+            println!(
+                "Restored {} onboard profiles from {}",
+                backup.profiles.len(),
+                path
+            );
+        }
+        OnboardCommands::Mode {
+            mode
+        } => {
+            let mode = if mode == "onboard" {
+                OnboardMode::Onboard
+            } else {
+                OnboardMode::Host
+            };
+            profiles.set_mode(mode)?;
+            println!("Onboard mode set to {:?}", mode);
+        }
+    }
+
+    Ok(())
+}
+
+fn firmware_target(receiver: bool) -> DfuTarget {
+    if receiver {
+        DfuTarget::Receiver
+    } else {
+        DfuTarget::Device(2)
+    }
+}
+
+fn print_progress(progress: DfuProgress) {
+    print!(
+        "\r  Writing firmware: {:3}% ({}/{} blocks)",
+        progress.block * 100 / progress.total.max(1),
+        progress.block,
+        progress.total
+    );
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+}
+
+fn cmd_firmware(action: FirmwareCommands) -> Result<()> {
+    match action {
+        FirmwareCommands::Info {
+            receiver
+        } => {
+            let mut hidpp = open_target(firmware_target(receiver))?;
+            let firmware = read_device_firmware(&mut hidpp)?;
+
+            println!("Firmware:");
+            let model_ids: Vec<String> = firmware
+                .model_ids
+                .iter()
+                .map(|pid| format!("{:04x}", pid))
+                .collect();
+            println!("  Model IDs: {}", model_ids.join(", "));
+            for entity in &firmware.entities {
+                println!("  {}: {:?} {}", entity.index, entity.kind, entity.version);
+            }
+        }
+        FirmwareCommands::Update {
+            path,
+            receiver,
+            dry_run
+        } => {
+            let data = std::fs::read(&path)
+                .map_err(|e| AppError::internal("Failed to read firmware image").with_source(e))?;
+            let image = FirmwareImage::parse(data)?;
+            println!(
+                "Image: {} for model {:04x}, entity {} ({} blocks)",
+                image.prefix,
+                image.model_id,
+                image.entity,
+                image.block_count() + 1
+            );
+
+            // The dry run emulates the device it is about to flash, so the
+            // device has to answer; nothing is written before the dry run
+            // has passed.
+            let firmware = open_target(firmware_target(receiver))
+                .and_then(|mut hidpp| read_device_firmware(&mut hidpp))?;
+
+            println!("Dry run against emulated device:");
+            let report = logi_mx_driver::firmware::dry_run(&image, &firmware, print_progress)?;
+            println!();
+            println!(
+                "  OK: {} blocks in {} packets, final status {:?}",
+                report.blocks, report.packets, report.status
+            );
+
+            if dry_run {
+                return Ok(());
+            }
+
+            println!("Updating device:");
+            let mut hidpp = open_target(firmware_target(receiver))?;
+            let status = update_firmware(&mut hidpp, &image, print_progress)?;
+            println!();
+            println!("Firmware entity {} installed ({:?})", image.entity, status);
+        }
     }
 
     Ok(())
 }
 
+fn parse_feature_id(value: &str) -> std::result::Result<u16, String> {
+    u16::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("invalid feature id: {}", e))
+}
+
+fn cmd_decode(report: &str, received: bool, feature: Option<u16>) -> Result<()> {
+    let packet = parse_report(report)?;
+    let direction = if received {
+        Direction::Received
+    } else {
+        Direction::Sent
+    };
+
+    println!("{}", dissect(&packet, feature, direction));
+    Ok(())
+}
+
 fn cmd_config(action: ConfigCommands) -> Result<()> {
     match action {
         ConfigCommands::Show => {
-            let config = load_config()?;
+            let config = load_config_reporting()?;
             let toml_str = toml::to_string_pretty(&config)
                 .map_err(|e| AppError::internal("Failed to serialize config").with_source(e))?;
             println!("{}", toml_str);
@@ -197,7 +901,7 @@ fn cmd_config(action: ConfigCommands) -> Result<()> {
         ConfigCommands::Export {
             path
         } => {
-            let config = load_config()?;
+            let config = load_config_reporting()?;
             let toml_str = toml::to_string_pretty(&config)
                 .map_err(|e| AppError::internal("Failed to serialize config").with_source(e))?;
             std::fs::write(&path, toml_str)
@@ -207,11 +911,52 @@ fn cmd_config(action: ConfigCommands) -> Result<()> {
         ConfigCommands::Import {
             path
         } => {
-            let config = load_config_from_path(std::path::Path::new(&path))?;
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| AppError::internal("Failed to read config file").with_source(e))?;
+            let (config, migration) = parse_config(&content)?;
+            if let Some(migration) = migration {
+                eprintln!("{}", migration);
+            }
             save_config(&config)?;
             println!("Config imported from {}", path);
         }
+        ConfigCommands::Check {
+            path
+        } => {
+            let path = match path {
+                Some(path) => path,
+                None => get_config_path()?
+            };
+            let diagnostics = check_config_file(&path)?;
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic.in_file(&path));
+            }
+
+            let errors = diagnostics
+                .iter()
+                .filter(|d| d.severity == Severity::Error)
+                .count();
+            let warnings = diagnostics.len() - errors;
+            if diagnostics.is_empty() {
+                println!("{}: no problems found", path.display());
+            } else {
+                println!("{} error(s), {} warning(s)", errors, warnings);
+            }
+            if errors > 0 {
+                return Err(AppError::bad_request("Config has errors"));
+            }
+        }
     }
 
     Ok(())
 }
+
+// Upgrading happens on load; the report goes to stderr so `config show`
+// output stays valid TOML.
+fn load_config_reporting() -> Result<Config> {
+    let (config, migration) = load_config_migrating()?;
+    if let Some(migration) = migration {
+        eprintln!("{}\n", migration);
+    }
+    Ok(config)
+}
@@ -0,0 +1,57 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// Reports every window that takes focus to logi-mx-daemon. Calls are
+// dropped while the daemon isn't running; the focused window is reported
+// again whenever the daemon (re)appears on the bus.
+
+import Gio from 'gi://Gio';
+import GLib from 'gi://GLib';
+import {Extension} from 'resource:///org/gnome/shell/extensions/extension.js';
+
+const BUS_NAME = 'io.github.RAprogramm.LogiMx';
+const OBJECT_PATH = '/io/github/RAprogramm/LogiMx/Focus';
+const INTERFACE = 'io.github.RAprogramm.LogiMx.Focus';
+
+export default class LogiMxFocusExtension extends Extension {
+    enable() {
+        this._focusChanged = global.display.connect('notify::focus-window', () => this._report());
+        this._busWatch = Gio.bus_watch_name(
+            Gio.BusType.SESSION,
+            BUS_NAME,
+            Gio.BusNameWatcherFlags.NONE,
+            () => this._report(),
+            null);
+    }
+
+    disable() {
+        global.display.disconnect(this._focusChanged);
+        this._focusChanged = null;
+        Gio.bus_unwatch_name(this._busWatch);
+        this._busWatch = null;
+    }
+
+    _report() {
+        const window = global.display.focus_window;
+        const appId = window?.get_gtk_application_id() ?? window?.get_sandboxed_app_id() ?? '';
+        const wmClass = window?.get_wm_class() ?? '';
+
+        Gio.DBus.session.call(
+            BUS_NAME,
+            OBJECT_PATH,
+            INTERFACE,
+            'WindowActivated',
+            new GLib.Variant('(ss)', [appId, wmClass]),
+            null,
+            Gio.DBusCallFlags.NO_AUTO_START,
+            -1,
+            null,
+            (connection, result) => {
+                try {
+                    connection.call_finish(result);
+                } catch {
+                    // The daemon is not running.
+                }
+            });
+    }
+}
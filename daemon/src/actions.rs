@@ -0,0 +1,269 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::process::Command;
+
+use logi_mx_driver::prelude::*;
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonTransition {
+    Pressed(ButtonId),
+    Released(ButtonId)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionOutcome {
+    DpiChanged(u16),
+    HostChanged(u8),
+    // Profiles span every device, so the manager does the switching.
+    ProfileToggled(String)
+}
+
+#[derive(Debug, Default)]
+pub struct ButtonState {
+    pressed:     Vec<ButtonId>,
+    dpi_step:    Option<usize>,
+    dpi_restore: Option<u16>
+}
+
+impl ButtonState {
+    pub fn update(&mut self, pressed: Vec<ButtonId>) -> Vec<ButtonTransition> {
+        let mut transitions: Vec<ButtonTransition> = pressed
+            .iter()
+            .filter(|b| !self.pressed.contains(b))
+            .map(|b| ButtonTransition::Pressed(*b))
+            .collect();
+
+        transitions.extend(
+            self.pressed
+                .iter()
+                .filter(|b| !pressed.contains(b))
+                .map(|b| ButtonTransition::Released(*b))
+        );
+
+        self.pressed = pressed;
+        transitions
+    }
+
+    pub fn next_dpi_step(&mut self, steps: &[u16], current: u16) -> Option<u16> {
+        if steps.is_empty() {
+            return None;
+        }
+
+        let next = match self.dpi_step {
+            Some(step) if steps.get(step) == Some(&current) => (step + 1) % steps.len(),
+            _ => steps
+                .iter()
+                .position(|&dpi| dpi == current)
+                .map_or(0, |i| (i + 1) % steps.len())
+        };
+
+        self.dpi_step = Some(next);
+        Some(steps[next])
+    }
+
+    pub fn begin_dpi_shift(&mut self, current: u16) {
+        if self.dpi_restore.is_none() {
+            self.dpi_restore = Some(current);
+        }
+    }
+
+    pub fn end_dpi_shift(&mut self) -> Option<u16> {
+        self.dpi_restore.take()
+    }
+}
+
+pub fn requires_diversion(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::DpiCycle { .. }
+            | Action::DpiShift { .. }
+            | Action::ChangeHost { .. }
+            | Action::SwitchProfile { .. }
+    )
+}
+
+// Undoes what `before` set up that `after` does not: diversions no longer
+// needed and actions for buttons `after` leaves out. Stored buttons stay.
+pub fn release_buttons<D: MouseDevice>(
+    device: &mut D,
+    before: &DeviceConfig,
+    after: &DeviceConfig
+) -> Result<()> {
+    for (button, action) in &before.buttons {
+        if before.button_mode(*button) == ButtonMode::Persistent {
+            continue;
+        }
+        let next = after.buttons.get(button);
+        if next.is_none() {
+            device.set_button_action(*button, Action::None)?;
+        }
+        if requires_diversion(action) && !next.is_some_and(requires_diversion) {
+            debug!("Button {:?} no longer diverted", button);
+            device.set_button_diverted(*button, false)?;
+        }
+    }
+    Ok(())
+}
+
+// Writes go to device flash, so an action that is already stored is left alone.
+pub fn apply_persistent_action<D: MouseDevice>(
+    device: &mut D,
+    button: ButtonId,
+    action: &Action
+) -> Result<()> {
+    let persistent = PersistentAction::from_action(action)?;
+    if device.get_persistent_action(button).ok() == Some(persistent) {
+        debug!("Button {:?} already stored as {:?}", button, persistent);
+        return Ok(());
+    }
+
+    device.set_persistent_action(button, persistent)
+}
+
+pub fn handle_transition<D: MouseDevice>(
+    device: &mut D,
+    state: &mut ButtonState,
+    transition: ButtonTransition
+) -> Result<Option<ActionOutcome>> {
+    match transition {
+        ButtonTransition::Pressed(button) => match device.get_button_action(button).ok() {
+            Some(Action::DpiCycle {
+                steps
+            }) => {
+                let current = device.get_dpi()?;
+                let Some(dpi) = state.next_dpi_step(&steps, current) else {
+                    return Ok(None);
+                };
+                device.set_dpi(dpi)?;
+                info!("DPI cycled to {}", dpi);
+                Ok(Some(ActionOutcome::DpiChanged(dpi)))
+            }
+            Some(Action::DpiShift {
+                dpi
+            }) => {
+                state.begin_dpi_shift(device.get_dpi()?);
+                device.set_dpi(dpi)?;
+                info!("DPI shifted to {}", dpi);
+                Ok(Some(ActionOutcome::DpiChanged(dpi)))
+            }
+            Some(Action::ChangeHost {
+                target
+            }) => {
+                let hosts = device.get_host_info()?;
+                let Some(host) = target.resolve(hosts.current, hosts.count) else {
+                    return Ok(None);
+                };
+                if host == hosts.current {
+                    return Ok(None);
+                }
+                device.change_host(host)?;
+                Ok(Some(ActionOutcome::HostChanged(host)))
+            }
+            Some(Action::SwitchProfile {
+                profile
+            }) => Ok(Some(ActionOutcome::ProfileToggled(profile))),
+            _ => Ok(None)
+        },
+        ButtonTransition::Released(button) => match device.get_button_action(button).ok() {
+            Some(Action::DpiShift {
+                ..
+            }) => {
+                let Some(dpi) = state.end_dpi_shift() else {
+                    return Ok(None);
+                };
+                device.set_dpi(dpi)?;
+                info!("DPI restored to {}", dpi);
+                Ok(Some(ActionOutcome::DpiChanged(dpi)))
+            }
+            _ => Ok(None)
+        }
+    }
+}
+
+pub fn send_notification(summary: &str, body: &str) {
+    if let Err(e) = Command::new("notify-send")
+        .args(["--app-name=logi-mx", "--expire-time=1500", summary, body])
+        .spawn()
+    {
+        debug!("Failed to send desktop notification: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_reports_press_and_release() {
+        let mut state = ButtonState::default();
+
+        let transitions = state.update(vec![ButtonId::Forward]);
+        assert_eq!(
+            transitions,
+            vec![ButtonTransition::Pressed(ButtonId::Forward)]
+        );
+
+        let transitions = state.update(vec![ButtonId::Forward, ButtonId::Back]);
+        assert_eq!(transitions, vec![ButtonTransition::Pressed(ButtonId::Back)]);
+
+        let transitions = state.update(vec![]);
+        assert_eq!(
+            transitions,
+            vec![
+                ButtonTransition::Released(ButtonId::Forward),
+                ButtonTransition::Released(ButtonId::Back),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dpi_cycle_wraps_around() {
+        let mut state = ButtonState::default();
+        let steps = [800, 1600, 3200];
+
+        assert_eq!(state.next_dpi_step(&steps, 800), Some(1600));
+        assert_eq!(state.next_dpi_step(&steps, 1600), Some(3200));
+        assert_eq!(state.next_dpi_step(&steps, 3200), Some(800));
+    }
+
+    #[test]
+    fn test_dpi_cycle_starts_at_first_step_for_unknown_dpi() {
+        let mut state = ButtonState::default();
+        assert_eq!(state.next_dpi_step(&[800, 1600], 1000), Some(800));
+    }
+
+    #[test]
+    fn test_dpi_cycle_empty_steps() {
+        let mut state = ButtonState::default();
+        assert_eq!(state.next_dpi_step(&[], 1000), None);
+    }
+
+    #[test]
+    fn test_dpi_shift_restores_original_value() {
+        let mut state = ButtonState::default();
+        state.begin_dpi_shift(1600);
+        state.begin_dpi_shift(400);
+        assert_eq!(state.end_dpi_shift(), Some(1600));
+        assert_eq!(state.end_dpi_shift(), None);
+    }
+
+    #[test]
+    fn test_requires_diversion() {
+        assert!(requires_diversion(&Action::DpiCycle {
+            steps: vec![800]
+        }));
+        assert!(requires_diversion(&Action::DpiShift {
+            dpi: 400
+        }));
+        assert!(requires_diversion(&Action::ChangeHost {
+            target: HostTarget::Next
+        }));
+        assert!(requires_diversion(&Action::SwitchProfile {
+            profile: "gaming".to_string()
+        }));
+        assert!(!requires_diversion(&Action::ToggleSmartShift));
+        assert!(!requires_diversion(&Action::None));
+    }
+}
@@ -0,0 +1,200 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{fs::Permissions, os::unix::fs::PermissionsExt, path::Path};
+
+use logi_mx_driver::prelude::*;
+use masterror::{field, prelude::*};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc, oneshot}
+};
+use tracing::{debug, info, warn};
+
+// Requests are a few dozen bytes; anything near this is not from `logi-mx`.
+const MAX_REQUEST_LEN: u64 = 4096;
+
+// A request from `logi-mx` and where its answer goes. The main loop owns the
+// devices, so it answers; the socket tasks only carry lines back and forth.
+pub type ControlMessage = (ControlRequest, oneshot::Sender<ControlResponse>);
+
+// Only one daemon holds the instance lock, so a socket left at `path` is
+// from one that died and is replaced. The socket may sit in a shared temp
+// dir, so only its owner may connect.
+pub fn listen(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|e| {
+            AppError::internal("Failed to remove stale control socket").with_source(e)
+        })?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|e| AppError::internal("Failed to bind control socket").with_source(e))?;
+    std::fs::set_permissions(path, Permissions::from_mode(0o600)).map_err(|e| {
+        AppError::internal("Failed to restrict control socket permissions").with_source(e)
+    })?;
+    info!("Listening for control requests on {:?}", path);
+    Ok(listener)
+}
+
+pub async fn serve(listener: UnixListener, tx: mpsc::Sender<ControlMessage>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                debug!("Failed to accept control connection: {}", e);
+                continue;
+            }
+        };
+
+        if !is_own_user(&stream) {
+            warn!("Refused a control connection from another user");
+            continue;
+        }
+
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, tx).await {
+                debug!("Control connection failed: {}", e);
+            }
+        });
+    }
+}
+
+fn is_own_user(stream: &UnixStream) -> bool {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    let uid = unsafe { libc::getuid() };
+    stream
+        .peer_cred()
+        .map(|cred| cred.uid() == uid)
+        .unwrap_or(false)
+}
+
+async fn handle_connection(stream: UnixStream, tx: mpsc::Sender<ControlMessage>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = Vec::new();
+    BufReader::new(reader)
+        .take(MAX_REQUEST_LEN)
+        .read_until(b'\n', &mut line)
+        .await
+        .map_err(|e| AppError::internal("Failed to read control request").with_source(e))?;
+
+    let response = match parse_request(&line) {
+        Ok(request) => {
+            debug!("Control request: {:?}", request);
+            let (reply_tx, reply_rx) = oneshot::channel();
+            tx.send((request, reply_tx))
+                .await
+                .map_err(|_| AppError::internal("Daemon is shutting down"))?;
+            reply_rx
+                .await
+                .map_err(|_| AppError::internal("Daemon dropped the control request"))?
+        }
+        Err(e) => ControlResponse::Error {
+            message: e.render_message().to_string()
+        }
+    };
+
+    writer
+        .write_all(response.to_line().as_bytes())
+        .await
+        .map_err(|e| AppError::internal("Failed to send control response").with_source(e))
+}
+
+fn parse_request(line: &[u8]) -> Result<ControlRequest> {
+    if line.len() as u64 == MAX_REQUEST_LEN && !line.ends_with(b"\n") {
+        return Err(AppError::bad_request("Control request is too long")
+            .with_field(field::u64("limit", MAX_REQUEST_LEN)));
+    }
+    let line = std::str::from_utf8(line)
+        .map_err(|e| AppError::bad_request("Invalid control request").with_source(e))?;
+    ControlRequest::parse(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_reach_the_main_loop() {
+        let path = std::env::temp_dir().join(format!(
+            "logi-mx-daemon-control-{}.sock",
+            std::process::id()
+        ));
+        let (tx, mut rx) = mpsc::channel(4);
+        tokio::spawn(serve(listen(&path).unwrap(), tx));
+
+        // Stands in for the main loop.
+        tokio::spawn(async move {
+            while let Some((request, reply)) = rx.recv().await {
+                let response = match request {
+                    ControlRequest::UseProfile {
+                        name
+                    } => ControlResponse::Profile {
+                        active: name,
+                        app:    None
+                    },
+                    ControlRequest::ActiveProfile => ControlResponse::Error {
+                        message: "no profile".to_string()
+                    }
+                };
+                reply.send(response).ok();
+            }
+        });
+
+        let client_path = path.clone();
+        let (used, active) = tokio::task::spawn_blocking(move || {
+            let used = send_control_request(
+                &client_path,
+                &ControlRequest::UseProfile {
+                    name: "gaming".to_string()
+                }
+            );
+            let active = send_control_request(&client_path, &ControlRequest::ActiveProfile);
+            (used, active)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            used.unwrap(),
+            ControlResponse::Profile {
+                active: "gaming".to_string(),
+                app:    None
+            }
+        );
+        assert_eq!(active.unwrap_err().render_message(), "no profile");
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_is_refused() {
+        let path = std::env::temp_dir().join(format!(
+            "logi-mx-daemon-control-long-{}.sock",
+            std::process::id()
+        ));
+        let listener = listen(&path).unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        let (tx, _rx) = mpsc::channel(4);
+        tokio::spawn(serve(listener, tx));
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(&vec![b'x'; 2 * MAX_REQUEST_LEN as usize])
+            .await
+            .unwrap();
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply).await.unwrap();
+        assert_eq!(
+            reply,
+            ControlResponse::Error {
+                message: "Control request is too long".to_string()
+            }
+            .to_line()
+        );
+        std::fs::remove_file(&path).ok();
+    }
+}
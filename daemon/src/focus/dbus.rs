@@ -0,0 +1,186 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// KDE and GNOME don't tell other clients which window has focus, so a
+// script inside the compositor does: a KWin script the daemon loads itself,
+// or the GNOME Shell extension shipped next to the daemon. Both call
+// `WindowActivated` on the session bus.
+
+use std::path::Path;
+
+use logi_mx_driver::prelude::*;
+use masterror::prelude::*;
+use tokio::sync::mpsc;
+use tracing::info;
+use zbus::connection::Builder;
+
+pub const BUS_NAME: &str = "io.github.RAprogramm.LogiMx";
+pub const OBJECT_PATH: &str = "/io/github/RAprogramm/LogiMx/Focus";
+
+const KWIN_SCRIPT: &str = include_str!("kwin-focus.js");
+const KWIN_SCRIPT_NAME: &str = "logi-mx-focus";
+
+struct FocusService {
+    tx: mpsc::Sender<FocusedWindow>
+}
+
+#[zbus::interface(name = "io.github.RAprogramm.LogiMx.Focus")]
+impl FocusService {
+    // Empty strings stand for names the window doesn't have.
+    async fn window_activated(&self, app_id: String, wm_class: String) {
+        let non_empty = |name: String| Some(name).filter(|name| !name.is_empty());
+        let window = FocusedWindow {
+            app_id:   non_empty(app_id),
+            wm_class: non_empty(wm_class)
+        };
+        self.tx.send(window).await.ok();
+    }
+}
+
+// `builder` picks the bus: the session bus normally, a private one in tests.
+pub async fn serve(
+    builder: Builder<'_>,
+    tx: mpsc::Sender<FocusedWindow>
+) -> Result<zbus::Connection> {
+    builder
+        .name(BUS_NAME)
+        .and_then(|builder| {
+            builder.serve_at(
+                OBJECT_PATH,
+                FocusService {
+                    tx
+                }
+            )
+        })
+        .map_err(dbus_error)?
+        .build()
+        .await
+        .map_err(dbus_error)
+}
+
+// The script is written next to the control socket and replaces one left
+// loaded by an earlier daemon.
+pub async fn load_kwin_script(connection: &zbus::Connection, dir: &Path) -> Result<()> {
+    let path = dir.join(format!("{}.js", KWIN_SCRIPT_NAME));
+    std::fs::write(&path, KWIN_SCRIPT)
+        .map_err(|e| AppError::internal("Failed to write the KWin script").with_source(e))?;
+
+    let scripting = zbus::Proxy::new(
+        connection,
+        "org.kde.KWin",
+        "/Scripting",
+        "org.kde.kwin.Scripting"
+    )
+    .await
+    .map_err(dbus_error)?;
+    let _: bool = scripting
+        .call("unloadScript", &(KWIN_SCRIPT_NAME,))
+        .await
+        .map_err(dbus_error)?;
+    let id: i32 = scripting
+        .call(
+            "loadScript",
+            &(path.to_string_lossy().as_ref(), KWIN_SCRIPT_NAME)
+        )
+        .await
+        .map_err(dbus_error)?;
+    if id < 0 {
+        return Err(AppError::internal("KWin refused the focus script"));
+    }
+    scripting
+        .call_method("start", &())
+        .await
+        .map_err(dbus_error)?;
+
+    info!("Loaded KWin focus script from {:?}", path);
+    Ok(())
+}
+
+fn dbus_error(e: zbus::Error) -> AppError {
+    AppError::internal("D-Bus request failed").with_source(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio}
+    };
+
+    use super::*;
+
+    // A private bus, or `None` where dbus-daemon isn't installed.
+    fn private_bus(dir: &Path) -> Option<(Child, String)> {
+        std::fs::create_dir_all(dir).unwrap();
+        let config = dir.join("bus.conf");
+        std::fs::write(
+            &config,
+            format!(
+                "<busconfig><type>session</type><listen>unix:path={}</listen>\
+                 <policy context=\"default\"><allow send_destination=\"*\"/>\
+                 <allow receive_sender=\"*\"/><allow own=\"*\"/></policy></busconfig>",
+                dir.join("bus.sock").display()
+            )
+        )
+        .unwrap();
+
+        let mut bus = Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(["--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(bus.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some((bus, address.trim().to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_window_activated_is_reported() {
+        let dir = std::env::temp_dir().join(format!("logi-mx-dbus-{}", std::process::id()));
+        let Some((mut bus, address)) = private_bus(&dir) else {
+            eprintln!("dbus-daemon is not available, skipping");
+            return;
+        };
+
+        let (tx, mut rx) = mpsc::channel(4);
+        let _service = serve(Builder::address(address.as_str()).unwrap(), tx)
+            .await
+            .unwrap();
+
+        // What the KWin script and the GNOME extension send.
+        let client = Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        for (app_id, wm_class) in [("org.kde.dolphin", "dolphin"), ("", "")] {
+            client
+                .call_method(
+                    Some(BUS_NAME),
+                    OBJECT_PATH,
+                    Some("io.github.RAprogramm.LogiMx.Focus"),
+                    "WindowActivated",
+                    &(app_id, wm_class)
+                )
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            FocusedWindow {
+                app_id:   Some("org.kde.dolphin".to_string()),
+                wm_class: Some("dolphin".to_string())
+            }
+        );
+        assert_eq!(rx.recv().await.unwrap(), FocusedWindow::default());
+
+        bus.kill().ok();
+        bus.wait().ok();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
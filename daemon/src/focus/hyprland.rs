@@ -0,0 +1,134 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// Hyprland answers queries on `.socket.sock` and streams events, one
+// `name>>data` line each, on `.socket2.sock`.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf}
+};
+
+use logi_mx_driver::prelude::*;
+use masterror::prelude::*;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+const QUERY_SOCKET: &str = ".socket.sock";
+const EVENT_SOCKET: &str = ".socket2.sock";
+
+pub fn socket_dir() -> Result<PathBuf> {
+    let signature = std::env::var("HYPRLAND_INSTANCE_SIGNATURE")
+        .map_err(|_| AppError::not_found("HYPRLAND_INSTANCE_SIGNATURE is not set"))?;
+
+    // Hyprland 0.40 moved its sockets from /tmp/hypr to the runtime dir.
+    if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
+        let dir = PathBuf::from(runtime_dir).join("hypr").join(&signature);
+        if dir.exists() {
+            return Ok(dir);
+        }
+    }
+    Ok(PathBuf::from("/tmp/hypr").join(signature))
+}
+
+pub fn watch(dir: &Path, tx: &mpsc::Sender<FocusedWindow>) -> Result<()> {
+    // Subscribed before asking, so no change in between is missed.
+    let events = UnixStream::connect(dir.join(EVENT_SOCKET)).map_err(|e| {
+        AppError::internal("Failed to connect to the Hyprland event socket").with_source(e)
+    })?;
+
+    if tx.blocking_send(active_window(dir)?).is_err() {
+        return Ok(());
+    }
+
+    for line in BufReader::new(events).lines() {
+        let line =
+            line.map_err(|e| AppError::internal("Failed to read Hyprland events").with_source(e))?;
+        let Some(data) = line.strip_prefix("activewindow>>") else {
+            continue;
+        };
+        // `class,title`; both are empty when nothing has focus.
+        let class = data.split_once(',').map_or(data, |(class, _)| class);
+        if tx.blocking_send(window_of(class)).is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+fn active_window(dir: &Path) -> Result<FocusedWindow> {
+    let query_error =
+        |e: std::io::Error| AppError::internal("Failed to query Hyprland").with_source(e);
+
+    let mut stream = UnixStream::connect(dir.join(QUERY_SOCKET)).map_err(query_error)?;
+    stream.write_all(b"j/activewindow").map_err(query_error)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).map_err(query_error)?;
+
+    let window: Value = serde_json::from_str(&reply)
+        .map_err(|e| AppError::internal("Invalid reply from Hyprland").with_source(e))?;
+    Ok(window_of(window["class"].as_str().unwrap_or_default()))
+}
+
+// Hyprland's class is the app id of Wayland windows and the WM class of
+// Xwayland ones.
+fn window_of(class: &str) -> FocusedWindow {
+    FocusedWindow {
+        app_id:   None,
+        wm_class: Some(class.to_string()).filter(|class| !class.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    fn stand_in_hyprland(dir: &Path) -> std::thread::JoinHandle<()> {
+        std::fs::create_dir_all(dir).unwrap();
+        let query = UnixListener::bind(dir.join(QUERY_SOCKET)).unwrap();
+        let events = UnixListener::bind(dir.join(EVENT_SOCKET)).unwrap();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = events.accept().unwrap();
+            let lines = "workspace>>2\nactivewindow>>firefox,Mozilla Firefox\n\
+                         activewindowv2>>55d1c7c0\nactivewindow>>,\n";
+            stream.write_all(lines.as_bytes()).unwrap();
+
+            let (mut stream, _) = query.accept().unwrap();
+            let mut request = [0u8; 64];
+            let len = stream.read(&mut request).unwrap();
+            assert_eq!(&request[..len], b"j/activewindow");
+            stream
+                .write_all(br#"{"class": "kitty", "title": "~"}"#)
+                .unwrap();
+        })
+    }
+
+    #[test]
+    fn test_focus_from_query_and_events() {
+        let dir = std::env::temp_dir().join(format!("logi-mx-hypr-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let hyprland = stand_in_hyprland(&dir);
+
+        let (tx, mut rx) = mpsc::channel(4);
+        let watcher = {
+            let dir = dir.clone();
+            std::thread::spawn(move || watch(&dir, &tx))
+        };
+
+        let classes: Vec<Option<String>> = (0..3)
+            .map(|_| rx.blocking_recv().unwrap().wm_class)
+            .collect();
+        assert_eq!(
+            classes,
+            [Some("kitty".to_string()), Some("firefox".to_string()), None]
+        );
+
+        hyprland.join().unwrap();
+        assert!(watcher.join().unwrap().is_ok());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,19 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// Loaded into KWin by logi-mx-daemon; reports every window that takes focus.
+// KWin 6 calls the signal windowActivated, KWin 5 clientActivated.
+
+function report(window) {
+    callDBus(
+        "io.github.RAprogramm.LogiMx",
+        "/io/github/RAprogramm/LogiMx/Focus",
+        "io.github.RAprogramm.LogiMx.Focus",
+        "WindowActivated",
+        window ? String(window.desktopFileName || "") : "",
+        window ? String(window.resourceClass || "") : ""
+    );
+}
+
+(workspace.windowActivated || workspace.clientActivated).connect(report);
+report(workspace.activeWindow || workspace.activeClient);
@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// Follows the focused window for `[[apps]]` rules. Each backend reports the
+// window focused when it starts and then every change, until the receiver
+// is dropped or the compositor goes away.
+
+mod dbus;
+mod hyprland;
+mod sway;
+mod x11;
+
+use std::path::PathBuf;
+
+use logi_mx_driver::prelude::*;
+use masterror::prelude::*;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+// Picks the backend for `auto` from the session's environment. Compositor
+// sockets come first: a Sway session may well have DISPLAY set for
+// Xwayland.
+pub fn detect(var: impl Fn(&str) -> Option<String>) -> FocusBackend {
+    let desktop = var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .to_uppercase();
+
+    if var("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        FocusBackend::Hyprland
+    } else if var("SWAYSOCK").is_some() || var("I3SOCK").is_some() {
+        FocusBackend::Sway
+    } else if desktop.split(':').any(|name| name == "KDE") {
+        FocusBackend::Kde
+    } else if desktop.split(':').any(|name| name == "GNOME") {
+        FocusBackend::Gnome
+    } else if var("DISPLAY").is_some() && var("WAYLAND_DISPLAY").is_none() {
+        FocusBackend::X11
+    } else {
+        FocusBackend::None
+    }
+}
+
+pub fn spawn(backend: FocusBackend, tx: mpsc::Sender<FocusedWindow>) {
+    let backend = match backend {
+        FocusBackend::Auto => detect(|name| std::env::var(name).ok()),
+        backend => backend
+    };
+    info!(
+        "Following the focused window with the {:?} backend",
+        backend
+    );
+
+    match backend {
+        FocusBackend::Auto | FocusBackend::None => {
+            warn!("No window watcher for this session, [[apps]] rules stay inactive");
+        }
+        FocusBackend::Sway => spawn_thread(move || sway::watch(&sway::socket_path()?, &tx)),
+        FocusBackend::Hyprland => {
+            spawn_thread(move || hyprland::watch(&hyprland::socket_dir()?, &tx))
+        }
+        FocusBackend::X11 => spawn_thread(move || x11::watch(&tx)),
+        FocusBackend::Kde | FocusBackend::Gnome => {
+            tokio::spawn(async move {
+                if let Err(e) = serve_dbus(backend, tx).await {
+                    error!("Window watcher stopped: {}", e);
+                }
+            });
+        }
+    }
+}
+
+fn spawn_thread(watch: impl FnOnce() -> Result<()> + Send + 'static) {
+    std::thread::spawn(move || {
+        if let Err(e) = watch() {
+            error!("Window watcher stopped: {}", e);
+        }
+    });
+}
+
+async fn serve_dbus(backend: FocusBackend, tx: mpsc::Sender<FocusedWindow>) -> Result<()> {
+    let closed = tx.clone();
+    let builder = zbus::connection::Builder::session()
+        .map_err(|e| AppError::internal("Failed to reach the session bus").with_source(e))?;
+    let connection = dbus::serve(builder, tx).await?;
+
+    if backend == FocusBackend::Kde {
+        let dir = control_socket_path()
+            .parent()
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        dbus::load_kwin_script(&connection, &dir).await?;
+    } else {
+        info!("Waiting for the logi-mx GNOME Shell extension to report windows");
+    }
+
+    // The connection answers calls for as long as it is alive.
+    closed.closed().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_with(vars: &[(&str, &str)]) -> FocusBackend {
+        detect(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn test_detect_backend() {
+        assert_eq!(
+            detect_with(&[("HYPRLAND_INSTANCE_SIGNATURE", "abc"), ("DISPLAY", ":0")]),
+            FocusBackend::Hyprland
+        );
+        assert_eq!(
+            detect_with(&[
+                ("SWAYSOCK", "/run/user/1000/sway-ipc.sock"),
+                ("DISPLAY", ":0")
+            ]),
+            FocusBackend::Sway
+        );
+        assert_eq!(detect_with(&[("I3SOCK", "/tmp/i3")]), FocusBackend::Sway);
+        assert_eq!(
+            detect_with(&[
+                ("XDG_CURRENT_DESKTOP", "KDE"),
+                ("WAYLAND_DISPLAY", "wayland-0")
+            ]),
+            FocusBackend::Kde
+        );
+        assert_eq!(
+            detect_with(&[("XDG_CURRENT_DESKTOP", "ubuntu:GNOME")]),
+            FocusBackend::Gnome
+        );
+        assert_eq!(
+            detect_with(&[("XDG_CURRENT_DESKTOP", "XFCE"), ("DISPLAY", ":0")]),
+            FocusBackend::X11
+        );
+        assert_eq!(
+            detect_with(&[("DISPLAY", ":0"), ("WAYLAND_DISPLAY", "wayland-0")]),
+            FocusBackend::None
+        );
+        assert_eq!(detect_with(&[]), FocusBackend::None);
+    }
+}
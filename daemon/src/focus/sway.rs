@@ -0,0 +1,220 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// Sway and i3 share this IPC: the magic string, then the payload length and
+// message type in native byte order, then a JSON payload.
+
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf}
+};
+
+use logi_mx_driver::prelude::*;
+use masterror::prelude::*;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+const HEADER_LEN: usize = MAGIC.len() + 8;
+
+const SUBSCRIBE: u32 = 2;
+const GET_TREE: u32 = 4;
+const EVENT_WINDOW: u32 = 0x8000_0003;
+
+pub fn socket_path() -> Result<PathBuf> {
+    std::env::var("SWAYSOCK")
+        .or_else(|_| std::env::var("I3SOCK"))
+        .map(PathBuf::from)
+        .map_err(|_| AppError::not_found("Neither SWAYSOCK nor I3SOCK is set"))
+}
+
+// Reports the window focused now, then each one that takes focus, until
+// the compositor goes away or nobody listens.
+pub fn watch(path: &Path, tx: &mpsc::Sender<FocusedWindow>) -> Result<()> {
+    let mut stream = UnixStream::connect(path).map_err(|e| {
+        AppError::internal("Failed to connect to the Sway/i3 socket").with_source(e)
+    })?;
+
+    send(&mut stream, GET_TREE, b"")?;
+    let tree = parse(&expect_reply(&mut stream, GET_TREE)?)?;
+    let window = focused(&tree).map(window_of).unwrap_or_default();
+    if tx.blocking_send(window).is_err() {
+        return Ok(());
+    }
+
+    send(&mut stream, SUBSCRIBE, br#"["window"]"#)?;
+    let subscribed = parse(&expect_reply(&mut stream, SUBSCRIBE)?)?;
+    if subscribed["success"] != Value::Bool(true) {
+        return Err(AppError::internal(
+            "Sway/i3 refused the window subscription"
+        ));
+    }
+
+    loop {
+        let (kind, payload) = receive(&mut stream)?;
+        if kind != EVENT_WINDOW {
+            continue;
+        }
+        let event = parse(&payload)?;
+        if event["change"] != "focus" {
+            continue;
+        }
+        if tx.blocking_send(window_of(&event["container"])).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn send(stream: &mut UnixStream, kind: u32, payload: &[u8]) -> Result<()> {
+    let mut message = Vec::with_capacity(HEADER_LEN + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload);
+    stream
+        .write_all(&message)
+        .map_err(|e| AppError::internal("Failed to write to the Sway/i3 socket").with_source(e))
+}
+
+fn receive(stream: &mut UnixStream) -> Result<(u32, Vec<u8>)> {
+    let read_error = |e: std::io::Error| {
+        AppError::internal("Failed to read from the Sway/i3 socket").with_source(e)
+    };
+
+    let mut header = [0u8; HEADER_LEN];
+    stream.read_exact(&mut header).map_err(read_error)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(AppError::internal("Not a Sway/i3 IPC message"));
+    }
+    let len = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+    let kind = u32::from_ne_bytes([header[10], header[11], header[12], header[13]]);
+
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload).map_err(read_error)?;
+    Ok((kind, payload))
+}
+
+// Nothing is subscribed yet when replies are awaited, so the next message
+// is the reply.
+fn expect_reply(stream: &mut UnixStream, kind: u32) -> Result<Vec<u8>> {
+    match receive(stream)? {
+        (received, payload) if received == kind => Ok(payload),
+        (received, _) => Err(AppError::internal(format!(
+            "Expected Sway/i3 reply {}, got {:#x}",
+            kind, received
+        )))
+    }
+}
+
+fn parse(payload: &[u8]) -> Result<Value> {
+    serde_json::from_slice(payload)
+        .map_err(|e| AppError::internal("Invalid message from Sway/i3").with_source(e))
+}
+
+// The focused container; an empty workspace is focused itself.
+fn focused(node: &Value) -> Option<&Value> {
+    if node["focused"] == Value::Bool(true) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .into_iter()
+        .filter_map(|children| node[children].as_array())
+        .flatten()
+        .find_map(focused)
+}
+
+// Wayland windows carry an app id; Xwayland ones only their WM class.
+fn window_of(node: &Value) -> FocusedWindow {
+    FocusedWindow {
+        app_id:   node["app_id"].as_str().map(String::from),
+        wm_class: node["window_properties"]["class"]
+            .as_str()
+            .map(String::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    const TREE: &str = r#"{
+        "type": "root", "focused": false,
+        "nodes": [{
+            "type": "workspace", "focused": false,
+            "nodes": [{ "type": "con", "focused": false, "app_id": "org.gnome.Nautilus" }],
+            "floating_nodes": [{ "type": "floating_con", "focused": true, "app_id": "foot" }]
+        }]
+    }"#;
+
+    // Answers like sway: the tree, the subscription, then window events.
+    fn stand_in_sway(path: &Path) -> std::thread::JoinHandle<()> {
+        std::fs::remove_file(path).ok();
+        let listener = UnixListener::bind(path).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            assert_eq!(receive(&mut stream).unwrap().0, GET_TREE);
+            send(&mut stream, GET_TREE, TREE.as_bytes()).unwrap();
+
+            let (kind, payload) = receive(&mut stream).unwrap();
+            assert_eq!(
+                (kind, payload.as_slice()),
+                (SUBSCRIBE, &br#"["window"]"#[..])
+            );
+            send(&mut stream, SUBSCRIBE, br#"{"success": true}"#).unwrap();
+
+            let events = [
+                r#"{"change": "title", "container": {"app_id": "foot"}}"#,
+                r#"{"change": "focus", "container": {"app_id": null,
+                    "window_properties": {"class": "Firefox", "instance": "Navigator"}}}"#
+            ];
+            for event in events {
+                send(&mut stream, EVENT_WINDOW, event.as_bytes()).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn test_focus_from_tree_and_events() {
+        let path = std::env::temp_dir().join(format!("logi-mx-sway-{}.sock", std::process::id()));
+        let sway = stand_in_sway(&path);
+        let (tx, mut rx) = mpsc::channel(4);
+        let watcher = {
+            let path = path.clone();
+            std::thread::spawn(move || watch(&path, &tx))
+        };
+
+        assert_eq!(
+            rx.blocking_recv().unwrap(),
+            FocusedWindow {
+                app_id:   Some("foot".to_string()),
+                wm_class: None
+            }
+        );
+        assert_eq!(
+            rx.blocking_recv().unwrap(),
+            FocusedWindow {
+                app_id:   None,
+                wm_class: Some("Firefox".to_string())
+            }
+        );
+
+        // The stand-in hangs up after its events.
+        sway.join().unwrap();
+        assert!(watcher.join().unwrap().is_err());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_empty_workspace_has_no_window() {
+        let tree: Value =
+            serde_json::from_str(r#"{"nodes": [{"type": "workspace", "focused": true}]}"#)
+                .unwrap();
+        assert_eq!(
+            focused(&tree).map(window_of),
+            Some(FocusedWindow::default())
+        );
+    }
+}
@@ -0,0 +1,313 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// Follows `_NET_ACTIVE_WINDOW` on the root window and reads WM_CLASS of the
+// window it names. x11rb finds the display and its cookie from DISPLAY and
+// XAUTHORITY.
+
+use logi_mx_driver::prelude::*;
+use masterror::prelude::*;
+use tokio::sync::mpsc;
+use tracing::debug;
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        Event,
+        xproto::{AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, EventMask, Window}
+    }
+};
+
+// In 4-byte units; far more than a window id or class needs.
+const PROPERTY_LENGTH: u32 = 1024;
+
+pub fn watch(tx: &mpsc::Sender<FocusedWindow>) -> Result<()> {
+    let (connection, screen) = x11rb::connect(None)
+        .map_err(|e| AppError::internal("Failed to connect to the X server").with_source(e))?;
+    watch_connection(&connection, screen, tx)
+}
+
+fn watch_connection(
+    connection: &impl Connection,
+    screen: usize,
+    tx: &mpsc::Sender<FocusedWindow>
+) -> Result<()> {
+    let root = connection
+        .setup()
+        .roots
+        .get(screen)
+        .map(|screen| screen.root)
+        .ok_or_else(|| AppError::internal("X server has no such screen"))?;
+    let active = connection
+        .intern_atom(false, b"_NET_ACTIVE_WINDOW")
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?
+        .atom;
+    connection
+        .change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE)
+        )
+        .map_err(x11_error)?;
+    connection.flush().map_err(x11_error)?;
+
+    loop {
+        if tx
+            .blocking_send(active_window(connection, root, active)?)
+            .is_err()
+        {
+            return Ok(());
+        }
+        loop {
+            match connection.wait_for_event().map_err(x11_error)? {
+                Event::PropertyNotify(event) if event.window == root && event.atom == active => {
+                    break;
+                }
+                Event::Error(e) => debug!("X11 error {:?}", e),
+                _ => {}
+            }
+        }
+    }
+}
+
+// `None` when the window is gone.
+fn get_property(
+    connection: &impl Connection,
+    window: Window,
+    property: u32,
+    kind: AtomEnum
+) -> Result<Option<Vec<u8>>> {
+    let cookie = connection
+        .get_property(false, window, property, kind, 0, PROPERTY_LENGTH)
+        .map_err(x11_error)?;
+    match cookie.reply() {
+        Ok(reply) => Ok(Some(reply.value)),
+        Err(x11rb::errors::ReplyError::X11Error(e)) => {
+            debug!(
+                "Property {} of window {:#x} unavailable: {:?}",
+                property, window, e
+            );
+            Ok(None)
+        }
+        Err(e) => Err(x11_error(e))
+    }
+}
+
+fn active_window(
+    connection: &impl Connection,
+    root: Window,
+    active: u32
+) -> Result<FocusedWindow> {
+    let window = get_property(connection, root, active, AtomEnum::WINDOW)?
+        .and_then(|value| {
+            value
+                .get(..4)
+                .map(|id| u32::from_ne_bytes([id[0], id[1], id[2], id[3]]))
+        })
+        .unwrap_or(0);
+    if window == 0 {
+        return Ok(FocusedWindow::default());
+    }
+
+    // The instance and then the class, each NUL-terminated.
+    let class = get_property(
+        connection,
+        window,
+        AtomEnum::WM_CLASS.into(),
+        AtomEnum::STRING
+    )?
+    .unwrap_or_default();
+    Ok(FocusedWindow {
+        app_id:   None,
+        wm_class: class
+            .split(|byte| *byte == 0)
+            .nth(1)
+            .filter(|class| !class.is_empty())
+            .map(|class| String::from_utf8_lossy(class).into_owned())
+    })
+}
+
+fn x11_error(e: impl std::error::Error + Send + Sync + 'static) -> AppError {
+    AppError::internal("X server connection failed").with_source(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        path::Path
+    };
+
+    use x11rb::rust_connection::{DefaultStream, RustConnection};
+
+    use super::*;
+
+    const OPCODE_CHANGE_WINDOW_ATTRIBUTES: u8 = 2;
+    const OPCODE_INTERN_ATOM: u8 = 16;
+    const OPCODE_GET_PROPERTY: u8 = 20;
+    const ERROR: u8 = 0;
+    const PROPERTY_NOTIFY: u8 = 28;
+
+    const ROOT: u32 = 0x0000_0512;
+    const NET_ACTIVE_WINDOW: u32 = 0x01A0;
+    const OTHER_ATOM: u32 = 0x01A1;
+
+    // Serves the setup, atoms and properties from `active`, one window per
+    // round: after each round it changes the active window and announces
+    // it, behind an unrelated property change. It hangs up on the first
+    // request after the last round.
+    fn stand_in_x_server(
+        path: &Path,
+        active: Vec<(u32, Option<&'static [u8]>)>
+    ) -> std::thread::JoinHandle<()> {
+        std::fs::remove_file(path).ok();
+        let listener = UnixListener::bind(path).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; 12];
+            stream.read_exact(&mut header).unwrap();
+            assert_eq!(header[0], b'l');
+            let name_len = padded(u16::from_le_bytes([header[6], header[7]]) as usize);
+            let data_len = padded(u16::from_le_bytes([header[8], header[9]]) as usize);
+            let mut auth = vec![0u8; name_len + data_len];
+            stream.read_exact(&mut auth).unwrap();
+            assert!(auth.is_empty());
+
+            let mut body = vec![0u8; 32 + 40];
+            body[8..12].copy_from_slice(&0x001F_FFFFu32.to_le_bytes());
+            body[18..20].copy_from_slice(&u16::MAX.to_le_bytes());
+            body[20] = 1;
+            body[32..36].copy_from_slice(&ROOT.to_le_bytes());
+            let mut setup = vec![1, 0, 11, 0, 0, 0];
+            setup.extend_from_slice(&((body.len() / 4) as u16).to_le_bytes());
+            setup.extend_from_slice(&body);
+            stream.write_all(&setup).unwrap();
+
+            let mut round = 0;
+            let mut sequence: u16 = 0;
+            loop {
+                let mut request = [0u8; 4];
+                if stream.read_exact(&mut request).is_err() || round == active.len() {
+                    return;
+                }
+                let len = u16::from_le_bytes([request[2], request[3]]) as usize * 4;
+                let mut rest = vec![0u8; len - 4];
+                stream.read_exact(&mut rest).unwrap();
+                sequence += 1;
+                let seq = sequence.to_le_bytes();
+
+                let property_reply = |format: u8, value: &[u8]| {
+                    let mut reply = vec![1, format, seq[0], seq[1]];
+                    reply.extend_from_slice(&((padded(value.len()) / 4) as u32).to_le_bytes());
+                    reply.extend_from_slice(&[0u8; 8]);
+                    let units = value.len() / (format as usize / 8);
+                    reply.extend_from_slice(&(units as u32).to_le_bytes());
+                    reply.resize(32, 0);
+                    push_padded(&mut reply, value);
+                    reply
+                };
+                let property_notify = |atom: u32| {
+                    let mut event = vec![PROPERTY_NOTIFY, 0, seq[0], seq[1]];
+                    event.extend_from_slice(&ROOT.to_le_bytes());
+                    event.extend_from_slice(&atom.to_le_bytes());
+                    event.resize(32, 0);
+                    event
+                };
+
+                let (window, class) = active[round];
+                let mut round_done = false;
+                let reply = match request[0] {
+                    OPCODE_INTERN_ATOM => {
+                        let mut reply = vec![1, 0, seq[0], seq[1]];
+                        reply.resize(8, 0);
+                        reply.extend_from_slice(&NET_ACTIVE_WINDOW.to_le_bytes());
+                        reply.resize(32, 0);
+                        reply
+                    }
+                    OPCODE_CHANGE_WINDOW_ATTRIBUTES => {
+                        assert_eq!(read_u32(&rest, 0).unwrap(), ROOT);
+                        continue;
+                    }
+                    OPCODE_GET_PROPERTY if read_u32(&rest, 4).unwrap() == NET_ACTIVE_WINDOW => {
+                        round_done = window == 0;
+                        property_reply(32, &window.to_le_bytes())
+                    }
+                    OPCODE_GET_PROPERTY => {
+                        round_done = true;
+                        match class {
+                            Some(class) => property_reply(8, class),
+                            // BadWindow: the window closed meanwhile.
+                            None => {
+                                let mut error = vec![ERROR, 3, seq[0], seq[1]];
+                                error.resize(32, 0);
+                                error
+                            }
+                        }
+                    }
+                    opcode => panic!("unexpected request {}", opcode)
+                };
+                stream.write_all(&reply).unwrap();
+
+                if round_done {
+                    round += 1;
+                    stream.write_all(&property_notify(OTHER_ATOM)).unwrap();
+                    stream
+                        .write_all(&property_notify(NET_ACTIVE_WINDOW))
+                        .unwrap();
+                }
+            }
+        })
+    }
+
+    fn padded(len: usize) -> usize {
+        len.next_multiple_of(4)
+    }
+
+    fn push_padded(buffer: &mut Vec<u8>, data: &[u8]) {
+        buffer.extend_from_slice(data);
+        buffer.resize(buffer.len() + padded(data.len()) - data.len(), 0);
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    #[test]
+    fn test_active_window_changes() {
+        let path = std::env::temp_dir().join(format!("logi-mx-x11-{}", std::process::id()));
+        let server = stand_in_x_server(
+            &path,
+            vec![
+                (0x0040_0003, Some(&b"xterm\0XTerm\0"[..])),
+                (0, None),
+                (0x0060_0001, None),
+                (0x0080_0001, Some(&b"Navigator\0firefox\0"[..])),
+            ]
+        );
+        let stream = DefaultStream::from_unix_stream(UnixStream::connect(&path).unwrap())
+            .unwrap()
+            .0;
+        let connection = RustConnection::connect_to_stream(stream, 0).unwrap();
+        let (tx, mut rx) = mpsc::channel(4);
+        let watcher = std::thread::spawn(move || watch_connection(&connection, 0, &tx));
+
+        let classes: Vec<Option<String>> = (0..4)
+            .map(|_| rx.blocking_recv().unwrap().wm_class)
+            .collect();
+        assert_eq!(
+            classes,
+            [
+                Some("XTerm".to_string()),
+                None,
+                None,
+                Some("firefox".to_string())
+            ]
+        );
+
+        server.join().unwrap();
+        assert!(watcher.join().unwrap().is_err());
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,279 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+
+use logi_mx_driver::{emulator, prelude::*};
+use masterror::prelude::*;
+use tracing::debug;
+
+type Result<T> = std::result::Result<T, AppError>;
+
+const BUS_USB: u16 = 0x0003;
+const BUS_BLUETOOTH: u16 = 0x0005;
+const HIDPP_USB_INTERFACE: u8 = 2;
+
+// A hidraw node together with what udev knows about the device behind it,
+// read from the parent `hid` device and, over USB, the `usb_interface`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HidrawNode {
+    pub devnode:    String,
+    pub bus:        u16,
+    pub vendor_id:  u16,
+    pub product_id: u16,
+    pub interface:  Option<u8>,
+    pub uniq:       Option<String>,
+    pub descriptor: Option<ReportDescriptor>
+}
+
+impl HidrawNode {
+    pub fn from_udev(device: &udev::Device) -> Option<Self> {
+        let devnode = device.devnode()?.to_str()?.to_string();
+        let hid = device.parent_with_subsystem("hid").ok().flatten()?;
+        let (bus, vendor_id, product_id) = parse_hid_id(hid.property_value("HID_ID")?.to_str()?)?;
+        let uniq = hid
+            .property_value("HID_UNIQ")
+            .and_then(|uniq| uniq.to_str())
+            .map(str::trim)
+            .filter(|uniq| !uniq.is_empty())
+            .map(String::from);
+        let interface = device
+            .parent_with_subsystem_devtype("usb", "usb_interface")
+            .ok()
+            .flatten()
+            .and_then(|usb| {
+                usb.attribute_value("bInterfaceNumber")
+                    .and_then(|number| number.to_str())
+                    .and_then(|number| u8::from_str_radix(number.trim(), 16).ok())
+            });
+        let descriptor = ReportDescriptor::read_for_hidraw(&devnode)
+            .inspect_err(|e| debug!("No report descriptor for {}: {}", devnode, e))
+            .ok();
+
+        Some(Self {
+            devnode,
+            bus,
+            vendor_id,
+            product_id,
+            interface,
+            uniq,
+            descriptor
+        })
+    }
+
+    // The software receiver from `logi_mx_driver::emulator`.
+    pub fn emulated() -> Self {
+        Self {
+            devnode:    emulator::EMULATOR_PATH.to_string(),
+            bus:        BUS_USB,
+            vendor_id:  VID_LOGITECH,
+            product_id: PID_BOLT_RECEIVER,
+            interface:  Some(HIDPP_USB_INTERFACE),
+            uniq:       None,
+            descriptor: ReportDescriptor::parse(emulator::RECEIVER_REPORT_DESCRIPTOR).ok()
+        }
+    }
+
+    pub fn is_receiver(&self) -> bool {
+        self.bus == BUS_USB && is_receiver_pid(self.product_id)
+    }
+
+    pub fn connection(&self) -> Option<ConnectionType> {
+        match self.bus {
+            BUS_BLUETOOTH => Some(ConnectionType::Bluetooth),
+            BUS_USB if self.is_receiver() => Some(ConnectionType::Receiver),
+            BUS_USB => Some(ConnectionType::Usb),
+            _ => None
+        }
+    }
+
+    // Receivers and USB devices expose several interfaces and only one of
+    // them speaks HID++, which its report descriptor tells. The interface
+    // number is a fallback for nodes whose descriptor could not be read.
+    pub fn is_hidpp(&self) -> bool {
+        if self.vendor_id != VID_LOGITECH || self.connection().is_none() {
+            return false;
+        }
+
+        match &self.descriptor {
+            Some(descriptor) => descriptor.is_hidpp(),
+            None => self
+                .interface
+                .is_none_or(|interface| interface == HIDPP_USB_INTERFACE)
+        }
+    }
+}
+
+impl fmt::Display for HidrawNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:04x}:{:04x}",
+            self.devnode, self.vendor_id, self.product_id
+        )?;
+        if let Some(connection) = self.connection() {
+            write!(f, " {}", connection)?;
+        }
+        if let Some(uniq) = &self.uniq {
+            write!(f, " {}", uniq)?;
+        }
+        f.write_str(")")
+    }
+}
+
+// One logical device: a hidraw node, and the slot when it is a receiver.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceKey {
+    pub devnode: String,
+    pub slot:    Option<u8>
+}
+
+impl fmt::Display for DeviceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.slot {
+            Some(slot) => write!(f, "{} slot {}", self.devnode, slot),
+            None => f.write_str(&self.devnode)
+        }
+    }
+}
+
+// `HID_ID` is "BUS:VENDOR:PRODUCT" in hex, e.g. "0003:0000046D:0000C548".
+pub fn parse_hid_id(hid_id: &str) -> Option<(u16, u16, u16)> {
+    let mut parts = hid_id.split(':').map(|part| {
+        u32::from_str_radix(part, 16)
+            .ok()
+            .and_then(|v| u16::try_from(v).ok())
+    });
+    let ids = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(ids)
+}
+
+pub fn enumerate() -> Result<Vec<HidrawNode>> {
+    let mut enumerator = udev::Enumerator::new()
+        .map_err(|e| AppError::internal("Failed to create udev enumerator").with_source(e))?;
+    enumerator
+        .match_subsystem("hidraw")
+        .map_err(|e| AppError::internal("Failed to match subsystem").with_source(e))?;
+    let devices = enumerator
+        .scan_devices()
+        .map_err(|e| AppError::internal("Failed to scan hidraw devices").with_source(e))?;
+
+    Ok(devices
+        .filter_map(|device| {
+            let node = HidrawNode::from_udev(&device);
+            if node.is_none() {
+                debug!("Skipping unresolvable hidraw device {:?}", device.syspath());
+            }
+            node
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(bus: u16, product_id: u16, interface: Option<u8>) -> HidrawNode {
+        HidrawNode {
+            devnode: "/dev/hidraw3".to_string(),
+            bus,
+            vendor_id: VID_LOGITECH,
+            product_id,
+            interface,
+            uniq: None,
+            descriptor: None
+        }
+    }
+
+    #[test]
+    fn test_parse_hid_id() {
+        assert_eq!(
+            parse_hid_id("0003:0000046D:0000C548"),
+            Some((BUS_USB, 0x046D, 0xC548))
+        );
+        assert_eq!(
+            parse_hid_id("0005:0000046D:0000B034"),
+            Some((BUS_BLUETOOTH, 0x046D, 0xB034))
+        );
+        assert_eq!(parse_hid_id("0003:0000046D"), None);
+        assert_eq!(parse_hid_id("0003:0000046D:0000C548:0"), None);
+        assert_eq!(parse_hid_id("0003:0001046D:0000C548"), None);
+        assert_eq!(parse_hid_id("usb:046d:c548"), None);
+    }
+
+    #[test]
+    fn test_connection_from_bus_and_product() {
+        assert_eq!(
+            node(BUS_USB, PID_BOLT_RECEIVER, Some(2)).connection(),
+            Some(ConnectionType::Receiver)
+        );
+        assert_eq!(
+            node(BUS_USB, 0xC52B, Some(2)).connection(),
+            Some(ConnectionType::Receiver)
+        );
+        assert_eq!(
+            node(BUS_USB, 0xC08B, Some(2)).connection(),
+            Some(ConnectionType::Usb)
+        );
+        assert_eq!(
+            node(BUS_BLUETOOTH, 0xB034, None).connection(),
+            Some(ConnectionType::Bluetooth)
+        );
+        assert_eq!(node(0x0018, 0xB034, None).connection(), None);
+    }
+
+    #[test]
+    fn test_only_the_hidpp_interface_is_used() {
+        assert!(node(BUS_USB, PID_BOLT_RECEIVER, Some(2)).is_hidpp());
+        assert!(!node(BUS_USB, PID_BOLT_RECEIVER, Some(0)).is_hidpp());
+        assert!(!node(BUS_USB, PID_BOLT_RECEIVER, Some(1)).is_hidpp());
+        assert!(node(BUS_BLUETOOTH, 0xB034, None).is_hidpp());
+
+        let other_vendor = HidrawNode {
+            vendor_id: 0x1234,
+            ..node(BUS_USB, PID_BOLT_RECEIVER, Some(2))
+        };
+        assert!(!other_vendor.is_hidpp());
+    }
+
+    #[test]
+    fn test_descriptor_overrides_interface_number() {
+        let hidpp = ReportDescriptor {
+            reports: vec![(REPORT_ID_SHORT, 0xFF00), (REPORT_ID_LONG, 0xFF00)]
+        };
+        let keyboard = ReportDescriptor {
+            reports: vec![(0x01, 0x0001)]
+        };
+
+        let unusual_layout = HidrawNode {
+            descriptor: Some(hidpp),
+            ..node(BUS_USB, PID_BOLT_RECEIVER, Some(1))
+        };
+        assert!(unusual_layout.is_hidpp());
+
+        let keyboard_interface = HidrawNode {
+            descriptor: Some(keyboard),
+            ..node(BUS_USB, PID_BOLT_RECEIVER, Some(2))
+        };
+        assert!(!keyboard_interface.is_hidpp());
+        assert!(HidrawNode::emulated().is_hidpp());
+    }
+
+    #[test]
+    fn test_display() {
+        let bluetooth = HidrawNode {
+            uniq: Some("aa:bb:cc:dd:ee:ff".to_string()),
+            ..node(BUS_BLUETOOTH, 0xB034, None)
+        };
+        assert_eq!(
+            bluetooth.to_string(),
+            "/dev/hidraw3 (046d:b034 bluetooth aa:bb:cc:dd:ee:ff)"
+        );
+
+        let key = DeviceKey {
+            devnode: "/dev/hidraw3".to_string(),
+            slot:    Some(2)
+        };
+        assert_eq!(key.to_string(), "/dev/hidraw3 slot 2");
+    }
+}
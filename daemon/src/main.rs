@@ -1,73 +1,301 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
+mod actions;
+mod control;
+mod focus;
+mod hidraw;
 #[cfg(feature = "tray")]
 mod tray;
 
-use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    fs,
+    os::fd::{AsRawFd, RawFd},
+    path::PathBuf,
+    time::Duration
+};
 
 use fslock::LockFile;
-use logi_mx_driver::prelude::*;
+use logi_mx_driver::{emulator, prelude::*};
 use masterror::prelude::*;
 use tokio::{
     select,
     signal::unix::{SignalKind, signal},
     sync::mpsc,
-    time::sleep
+    time::{interval, sleep}
 };
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 use udev::MonitorBuilder;
 
+use crate::{
+    actions::{
+        ActionOutcome, ButtonState, apply_persistent_action, handle_transition, release_buttons,
+        requires_diversion, send_notification
+    },
+    control::ControlMessage,
+    hidraw::{DeviceKey, HidrawNode}
+};
+
 type Result<T> = std::result::Result<T, AppError>;
 
+const EVENT_POLL_INTERVAL_MS: u64 = 20;
+const TRAY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+struct ManagedDevice {
+    identity: DeviceIdentity,
+    device:   GenericHidppMouse,
+    buttons:  ButtonState
+}
+
+// A receiver kept open for the link notifications of its slots.
+struct WatchedReceiver {
+    node:  HidrawNode,
+    watch: ReceiverWatch
+}
+
+struct ManagedKeyboard {
+    name:     String,
+    keyboard: GenericHidppKeyboard
+}
+
 struct DeviceManager {
-    devices: HashMap<String, MxMaster3s>,
-    config:  Config
+    devices:        HashMap<DeviceKey, ManagedDevice>,
+    keyboards:      HashMap<DeviceKey, ManagedKeyboard>,
+    receivers:      HashMap<String, WatchedReceiver>,
+    config:         Config,
+    // Applies to every device whose entry defines it; the rest stay on
+    // their entry's own settings.
+    active_profile: String,
+    // Position in `config.apps` of the rule for the focused window.
+    app_rule:       Option<usize>,
+    #[cfg(feature = "tray")]
+    tray_status:    Option<std::sync::Arc<std::sync::Mutex<tray::DeviceStatus>>>
 }
 
 impl DeviceManager {
     fn new(config: Config) -> Self {
         Self {
             devices: HashMap::new(),
-            config
+            keyboards: HashMap::new(),
+            receivers: HashMap::new(),
+            config,
+            active_profile: DEFAULT_PROFILE.to_string(),
+            app_rule: None,
+            #[cfg(feature = "tray")]
+            tray_status: None
         }
     }
 
-    async fn handle_device_added(&mut self, device_path: String) -> Result<()> {
-        info!("Device added: {}", device_path);
-
-        match MxMaster3s::open_bolt_receiver(2) {
-            Ok(mut device) => {
-                if let Ok(name) = device.get_device_name() {
-                    info!("Detected: {}", name);
+    async fn handle_device_added(&mut self, node: HidrawNode) -> Result<()> {
+        if !node.is_hidpp() {
+            debug!("Ignoring hidraw device {}", node);
+            return Ok(());
+        }
+        let Some(connection) = node.connection() else {
+            return Ok(());
+        };
+        info!("Device added: {}", node);
 
-                    if let Some(device_config) =
-                        self.config.devices.iter().find(|d| d.name == name)
-                    {
-                        info!("Applying configuration for {}", name);
-                        if let Err(e) = self.apply_config(&mut device, device_config).await {
-                            error!("Failed to apply config: {}", e);
+        if node.is_receiver() {
+            // Opened first so no device waking up during the probe is missed.
+            match ReceiverWatch::open_path(&node.devnode) {
+                Ok(watch) => {
+                    self.receivers.insert(
+                        node.devnode.clone(),
+                        WatchedReceiver {
+                            node: node.clone(),
+                            watch
                         }
-                    }
+                    );
+                }
+                Err(e) => warn!("Cannot watch {} for devices waking up: {}", node, e)
+            }
+            for slot in RECEIVER_SLOTS {
+                self.add_mouse(&node, connection, Some(slot)).await;
+            }
+            for slot in RECEIVER_SLOTS {
+                self.add_keyboard(&node.devnode, Some(slot)).await;
+            }
+        } else if !self.add_mouse(&node, connection, None).await {
+            self.add_keyboard(&node.devnode, None).await;
+        }
+
+        Ok(())
+    }
 
-                    self.devices.insert(device_path, device);
+    // Returns whether a mouse answered at `slot`, so direct connections can
+    // fall back to treating the node as a keyboard.
+    async fn add_mouse(
+        &mut self,
+        node: &HidrawNode,
+        connection: ConnectionType,
+        slot: Option<u8>
+    ) -> bool {
+        let key = DeviceKey {
+            devnode: node.devnode.clone(),
+            slot
+        };
+        if self.devices.contains_key(&key) {
+            debug!("{} is already managed", key);
+            return true;
+        }
+
+        let mut device = match GenericHidppMouse::open_path(&node.devnode, connection, slot) {
+            Ok(device) => device,
+            Err(e) => {
+                debug!("No mouse at {}: {}", key, e);
+                return false;
+            }
+        };
+        let identity = match device.identity() {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!("Failed to identify device at {}: {}", key, e);
+                return false;
+            }
+        };
+        info!("Detected {} at {}", identity, key);
+
+        // The same mouse can show up twice while it switches from the
+        // receiver to Bluetooth; keep the first handle.
+        if let Some((other, _)) = self.devices.iter().find(|(_, managed)| {
+            identity.serial.is_some() && managed.identity.serial == identity.serial
+        }) {
+            info!(
+                "{} is already managed at {}, skipping {}",
+                identity, other, key
+            );
+            return true;
+        }
+
+        match self.config.find_device_config(&identity) {
+            Some(found) => {
+                info!(
+                    "Applying {} to {} (profile {})",
+                    found, identity.name, self.active_profile
+                );
+                let settings =
+                    self.settings_for(found.config, &self.active_profile, self.app_rule);
+                if let Err(e) = self.apply_config(&mut device, &settings).await {
+                    error!("Failed to apply config: {}", e);
                 }
             }
+            None => info!("No configuration entry matches {}", identity)
+        }
+
+        self.devices.insert(
+            key,
+            ManagedDevice {
+                identity,
+                device,
+                buttons: ButtonState::default()
+            }
+        );
+        true
+    }
+
+    // Keyboards are kept open like mice so their settings can be re-applied
+    // when they reconnect or wake up.
+    async fn add_keyboard(&mut self, devnode: &str, slot: Option<u8>) {
+        if self.config.keyboards.is_empty() {
+            return;
+        }
+        let key = DeviceKey {
+            devnode: devnode.to_string(),
+            slot
+        };
+        if self.keyboards.contains_key(&key) {
+            debug!("{} is already managed", key);
+            return;
+        }
+
+        let mut keyboard = match GenericHidppKeyboard::open_path(devnode, slot) {
+            Ok(keyboard) => keyboard,
             Err(e) => {
-                warn!("Failed to open device: {}", e);
+                debug!("No keyboard at {}: {}", key, e);
+                return;
+            }
+        };
+        let Ok(name) = keyboard.get_device_name() else {
+            return;
+        };
+        info!("Detected keyboard {} at {}", name, key);
+
+        if let Some(keyboard_config) = self.config.keyboards.iter().find(|k| k.name == name) {
+            info!("Applying keyboard configuration for {}", name);
+            if let Err(e) = self
+                .apply_keyboard_config(&mut keyboard, keyboard_config)
+                .await
+            {
+                error!("Failed to apply keyboard config: {}", e);
+            }
+        }
+
+        self.keyboards.insert(
+            key,
+            ManagedKeyboard {
+                name,
+                keyboard
+            }
+        );
+    }
+
+    async fn apply_keyboard_config(
+        &self,
+        keyboard: &mut GenericHidppKeyboard,
+        config: &KeyboardConfig
+    ) -> Result<()> {
+        if let Some(backlight) = config.backlight {
+            debug!("Setting backlight to {:?}", backlight);
+            if let Err(e) = keyboard.set_backlight(backlight) {
+                error!("Failed to set backlight: {}", e);
             }
         }
 
+        if let Some(inverted) = config.fn_inversion {
+            debug!("Setting Fn inversion to {}", inverted);
+            if let Err(e) = keyboard.set_fn_inversion(inverted) {
+                error!("Failed to set Fn inversion: {}", e);
+            }
+        }
+
+        if let Some(keys) = &config.disabled_keys {
+            debug!("Disabling keys {:?}", keys);
+            if let Err(e) = keyboard.set_disabled_keys(keys) {
+                error!("Failed to disable keys: {}", e);
+            }
+        }
+
+        info!("Keyboard configuration applied successfully");
         Ok(())
     }
 
-    async fn handle_device_removed(&mut self, device_path: &str) {
-        info!("Device removed: {}", device_path);
-        self.devices.remove(device_path);
+    async fn handle_device_removed(&mut self, devnode: &str) {
+        self.receivers.remove(devnode);
+        self.devices.retain(|key, managed| {
+            let keep = key.devnode != devnode;
+            if !keep {
+                info!("Device removed: {} at {}", managed.identity, key);
+            }
+            keep
+        });
+        self.keyboards.retain(|key, managed| {
+            let keep = key.devnode != devnode;
+            if !keep {
+                info!("Keyboard removed: {} at {}", managed.name, key);
+            }
+            keep
+        });
+        self.update_tray_status();
     }
 
-    async fn apply_config(&self, device: &mut MxMaster3s, config: &DeviceConfig) -> Result<()> {
+    async fn apply_config(
+        &self,
+        device: &mut GenericHidppMouse,
+        config: &DeviceConfig
+    ) -> Result<()> {
         debug!("Setting DPI to {}", config.dpi);
         if let Err(e) = device.set_dpi(config.dpi) {
             error!("Failed to set DPI: {}", e);
@@ -89,27 +317,402 @@ impl DeviceManager {
             error!("Failed to set hi-res scroll: {}", e);
         }
 
+        if let Some(hz) = config.report_rate_hz {
+            debug!("Setting report rate to {} Hz", hz);
+            if let Err(e) = device.set_report_rate(hz) {
+                error!("Failed to set report rate: {}", e);
+            }
+        }
+
         for (button, action) in &config.buttons {
+            if config.button_mode(*button) == ButtonMode::Persistent {
+                if let Err(e) = apply_persistent_action(device, *button, action) {
+                    error!("Failed to store button {:?} on device: {}", button, e);
+                }
+                continue;
+            }
+
             debug!("Setting button {:?} to action {:?}", button, action);
             if let Err(e) = device.set_button_action(*button, action.clone()) {
                 error!("Failed to set button action: {}", e);
             }
+            if requires_diversion(action)
+                && let Err(e) = device.set_button_diverted(*button, true)
+            {
+                error!("Failed to divert button {:?}: {}", button, e);
+            }
         }
 
         info!("Configuration applied successfully");
         Ok(())
     }
 
+    // Slots that were empty or asleep when their receiver appeared are
+    // probed once their link comes up.
+    async fn poll_receivers(&mut self) {
+        let mut woken = Vec::new();
+
+        for (devnode, receiver) in self.receivers.iter_mut() {
+            loop {
+                match receiver.watch.read_link_change(0) {
+                    Ok(Some(LinkChange {
+                        slot,
+                        connected: true
+                    })) => woken.push((receiver.node.clone(), slot)),
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!("Failed to read receiver events for {}: {}", devnode, e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        for (node, slot) in woken {
+            let key = DeviceKey {
+                devnode: node.devnode.clone(),
+                slot:    Some(slot)
+            };
+            if self.devices.contains_key(&key) || self.keyboards.contains_key(&key) {
+                continue;
+            }
+            info!("Device in {} linked up", key);
+            let Some(connection) = node.connection() else {
+                continue;
+            };
+            if self.add_mouse(&node, connection, Some(slot)).await {
+                self.update_tray_status();
+            } else {
+                self.add_keyboard(&node.devnode, Some(slot)).await;
+            }
+        }
+    }
+
+    async fn poll_device_events(&mut self) {
+        self.poll_receivers().await;
+
+        let mut outcomes = Vec::new();
+        let mut reapply = Vec::new();
+        let mut link_lost = false;
+
+        for (path, managed) in self.devices.iter_mut() {
+            let event = match managed.device.read_event(0) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("Failed to read device events for {}: {}", path, e);
+                    continue;
+                }
+            };
+
+            match event {
+                DeviceEvent::ButtonsPressed(pressed) => {
+                    for transition in managed.buttons.update(pressed) {
+                        debug!("Button transition on {}: {:?}", path, transition);
+                        match handle_transition(
+                            &mut managed.device,
+                            &mut managed.buttons,
+                            transition
+                        ) {
+                            Ok(Some(outcome)) => outcomes.push(outcome),
+                            Ok(None) => {}
+                            Err(e) => error!("Failed to handle {:?}: {}", transition, e)
+                        }
+                    }
+                }
+                DeviceEvent::ReconfigurationNeeded
+                | DeviceEvent::LinkChanged {
+                    connected: true
+                } => {
+                    info!("{} reconnected ({:?}), re-applying config", path, event);
+                    reapply.push(path.clone());
+                }
+                DeviceEvent::LinkChanged {
+                    connected: false
+                } => {
+                    info!("{} lost its wireless link", path);
+                    managed.buttons = ButtonState::default();
+                    link_lost = true;
+                }
+            }
+        }
+
+        if link_lost {
+            self.set_tray_connected(false);
+        }
+
+        for path in reapply {
+            self.reapply_config(path).await;
+        }
+
+        self.poll_keyboard_events().await;
+
+        for outcome in outcomes {
+            self.handle_outcome(outcome).await;
+        }
+    }
+
+    async fn poll_keyboard_events(&mut self) {
+        let mut reapply = Vec::new();
+
+        for (path, managed) in self.keyboards.iter_mut() {
+            match managed.keyboard.read_event(0) {
+                Ok(Some(
+                    event @ (DeviceEvent::ReconfigurationNeeded
+                    | DeviceEvent::LinkChanged {
+                        connected: true
+                    })
+                )) => {
+                    info!("{} reconnected ({:?}), re-applying config", path, event);
+                    reapply.push(path.clone());
+                }
+                Ok(Some(event)) => debug!("Keyboard event on {}: {:?}", path, event),
+                Ok(None) => {}
+                Err(e) => debug!("Failed to read keyboard events for {}: {}", path, e)
+            }
+        }
+
+        for path in reapply {
+            self.reapply_keyboard_config(path).await;
+        }
+    }
+
+    async fn reapply_keyboard_config(&mut self, path: DeviceKey) {
+        let Some(mut managed) = self.keyboards.remove(&path) else {
+            return;
+        };
+        managed.keyboard.rediscover_features();
+
+        if let Some(keyboard_config) = self
+            .config
+            .keyboards
+            .iter()
+            .find(|k| k.name == managed.name)
+        {
+            debug!("Re-applying keyboard configuration to {}", managed.name);
+            if let Err(e) = self
+                .apply_keyboard_config(&mut managed.keyboard, keyboard_config)
+                .await
+            {
+                error!("Failed to re-apply keyboard config: {}", e);
+            }
+        }
+
+        self.keyboards.insert(path, managed);
+    }
+
+    async fn reapply_config(&mut self, path: DeviceKey) {
+        let Some(mut managed) = self.devices.remove(&path) else {
+            return;
+        };
+        managed.device.rediscover_features();
+
+        if let Some(found) = self.config.find_device_config(&managed.identity) {
+            debug!("Re-applying {} to {}", found, managed.identity.name);
+            let settings = self.settings_for(found.config, &self.active_profile, self.app_rule);
+            if let Err(e) = self.apply_config(&mut managed.device, &settings).await {
+                error!("Failed to re-apply config: {}", e);
+            }
+        }
+        managed.buttons = ButtonState::default();
+        self.set_tray_connected(true);
+
+        self.devices.insert(path, managed);
+    }
+
+    async fn switch_profile(&mut self, name: &str) -> Result<()> {
+        if !self.config.has_profile(name) {
+            return Err(AppError::bad_request(format!(
+                "No device defines a profile named {}",
+                name
+            )));
+        }
+        if self.active_profile == name {
+            return Ok(());
+        }
+
+        info!("Switching profile from {} to {}", self.active_profile, name);
+        let previous = std::mem::replace(&mut self.active_profile, name.to_string());
+        self.reconfigure(&previous, self.app_rule).await;
+
+        send_notification("Profile", name);
+        Ok(())
+    }
+
+    // Only a change of rule touches the devices; focus moving between
+    // windows that match the same rule, or none, changes nothing.
+    async fn handle_focus(&mut self, window: FocusedWindow) {
+        let rule = self.config.app_rule(&window).map(|(index, _)| index);
+        debug!("Focus moved to {:?}, rule {:?}", window, rule);
+        if rule == self.app_rule {
+            return;
+        }
+
+        match rule {
+            Some(index) => info!("Applying settings for {}", self.config.apps[index].app),
+            None => info!("Leaving application settings")
+        }
+        let previous = std::mem::replace(&mut self.app_rule, rule);
+        self.reconfigure(&self.active_profile.clone(), previous)
+            .await;
+    }
+
+    // The settings for a device entry under `profile` and the app rule at
+    // `rule`.
+    fn settings_for(
+        &self,
+        entry: &DeviceConfig,
+        profile: &str,
+        rule: Option<usize>
+    ) -> DeviceConfig {
+        entry.resolve_for_app(profile, rule.and_then(|index| self.config.apps.get(index)))
+    }
+
+    // Moves every device from the settings of `previous_profile` and
+    // `previous_rule` to the current ones.
+    async fn reconfigure(&mut self, previous_profile: &str, previous_rule: Option<usize>) {
+        let keys: Vec<DeviceKey> = self.devices.keys().cloned().collect();
+        for key in keys {
+            let Some(mut managed) = self.devices.remove(&key) else {
+                continue;
+            };
+
+            if let Some(found) = self.config.find_device_config(&managed.identity) {
+                let before = self.settings_for(found.config, previous_profile, previous_rule);
+                let after = self.settings_for(found.config, &self.active_profile, self.app_rule);
+                if let Err(e) = release_buttons(&mut managed.device, &before, &after) {
+                    error!("Failed to release buttons on {}: {}", key, e);
+                }
+                if let Err(e) = self.apply_config(&mut managed.device, &after).await {
+                    error!("Failed to reconfigure {}: {}", key, e);
+                }
+            }
+            managed.buttons = ButtonState::default();
+
+            self.devices.insert(key, managed);
+        }
+    }
+
+    async fn handle_control(&mut self, request: ControlRequest) -> ControlResponse {
+        if let ControlRequest::UseProfile {
+            name
+        } = request
+            && let Err(e) = self.switch_profile(&name).await
+        {
+            return ControlResponse::Error {
+                message: e.render_message().to_string()
+            };
+        }
+
+        ControlResponse::Profile {
+            active: self.active_profile.clone(),
+            app:    self
+                .app_rule
+                .and_then(|index| self.config.apps.get(index))
+                .map(|rule| rule.app.clone())
+        }
+    }
+
+    #[cfg_attr(not(feature = "tray"), allow(unused_variables))]
+    fn set_tray_connected(&self, connected: bool) {
+        #[cfg(feature = "tray")]
+        if let Some(tray_status) = &self.tray_status
+            && let Ok(mut status) = tray_status.lock()
+        {
+            status.connected = connected;
+        }
+    }
+
+    // The tray shows the first managed mouse, read through the handle the
+    // daemon already holds rather than a second one of its own.
+    fn update_tray_status(&mut self) {
+        #[cfg(feature = "tray")]
+        {
+            let Some(tray_status) = self.tray_status.clone() else {
+                return;
+            };
+            let managed = self.devices.values_mut().next();
+            let Ok(mut status) = tray_status.lock() else {
+                return;
+            };
+            let Some(managed) = managed else {
+                status.connected = false;
+                return;
+            };
+            status.name = managed.identity.name.clone();
+            let device = &mut managed.device;
+            status.connected = true;
+
+            match device.capabilities() {
+                Ok(capabilities) => {
+                    status.unsupported = Setting::ALL
+                        .into_iter()
+                        .filter(|&setting| !capabilities.supports(setting))
+                        .collect();
+                }
+                Err(e) => debug!("Capabilities unavailable: {}", e)
+            }
+            if status.supports(Setting::Battery)
+                && let Ok(battery) = device.get_battery_info()
+            {
+                status.battery_level = battery.level;
+                status.battery_status = format!("{:?}", battery.status);
+            }
+            if status.supports(Setting::Dpi)
+                && let Ok(dpi) = device.get_dpi()
+            {
+                status.dpi = dpi;
+            }
+            if status.supports(Setting::SmartShift)
+                && let Ok(smartshift) = device.get_smartshift()
+            {
+                status.smartshift = smartshift.enabled;
+                status.smartshift_threshold = smartshift.threshold;
+            }
+            debug!("Tray status updated: battery={}%", status.battery_level);
+        }
+    }
+
+    async fn handle_outcome(&mut self, outcome: ActionOutcome) {
+        match outcome {
+            ActionOutcome::DpiChanged(dpi) => {
+                #[cfg(feature = "tray")]
+                if let Some(tray_status) = &self.tray_status
+                    && let Ok(mut status) = tray_status.lock()
+                {
+                    status.dpi = dpi;
+                }
+
+                send_notification("Pointer speed", &format!("{} DPI", dpi));
+            }
+            ActionOutcome::HostChanged(host) => {
+                send_notification("Easy-Switch", &format!("Switched to host {}", host + 1));
+            }
+            // Pressing the button again goes back to the default profile.
+            ActionOutcome::ProfileToggled(profile) => {
+                let target = if self.active_profile == profile {
+                    DEFAULT_PROFILE.to_string()
+                } else {
+                    profile
+                };
+                if let Err(e) = self.switch_profile(&target).await {
+                    error!("Failed to switch profile: {}", e);
+                }
+            }
+        }
+    }
+
     #[allow(dead_code)]
     async fn monitor_battery(&mut self) {
         loop {
             sleep(Duration::from_secs(300)).await;
 
-            let paths: Vec<String> = self.devices.keys().cloned().collect();
+            let paths: Vec<DeviceKey> = self.devices.keys().cloned().collect();
 
             for path in paths {
-                if let Some(device) = self.devices.get_mut(&path) {
-                    match device.get_battery_info() {
+                if let Some(managed) = self.devices.get_mut(&path) {
+                    match managed.device.get_battery_info() {
                         Ok(battery) => {
                             info!(
                                 "Device {} battery: {}% ({:?})",
@@ -208,43 +811,22 @@ async fn main() -> Result<()> {
         warn!("Failed to load config: {}. Using default.", e);
         Config::default()
     });
+    log_config_diagnostics();
 
     let mut manager = DeviceManager::new(config);
 
+    let (tray_refresh_tx, mut tray_refresh_rx) = mpsc::unbounded_channel::<()>();
+    #[cfg(not(feature = "tray"))]
+    drop(tray_refresh_tx);
     #[cfg(feature = "tray")]
     {
         info!("Initializing system tray...");
-        use std::sync::Arc;
-
         use crate::tray::spawn_tray;
 
-        match spawn_tray().await {
+        match spawn_tray(tray_refresh_tx).await {
             Ok(tray_status) => {
                 info!("System tray initialized");
-
-                let tray_status_clone = Arc::clone(&tray_status);
-                tokio::spawn(async move {
-                    loop {
-                        sleep(Duration::from_secs(30)).await;
-                        if let Ok(mut device) = MxMaster3s::open_bolt_receiver(2)
-                            && let Ok(mut status) = tray_status_clone.lock()
-                        {
-                            status.connected = true;
-                            if let Ok(battery) = device.get_battery_info() {
-                                status.battery_level = battery.level;
-                                status.battery_status = format!("{:?}", battery.status);
-                            }
-                            if let Ok(dpi) = device.get_dpi() {
-                                status.dpi = dpi;
-                            }
-                            if let Ok(ss) = device.get_smartshift() {
-                                status.smartshift = ss.enabled;
-                                status.smartshift_threshold = ss.threshold;
-                            }
-                            debug!("Tray status auto-updated");
-                        }
-                    }
-                });
+                manager.tray_status = Some(tray_status);
             }
             Err(e) => {
                 warn!("Failed to initialize tray: {}. Continuing without tray.", e);
@@ -254,25 +836,90 @@ async fn main() -> Result<()> {
 
     let (tx, mut rx) = mpsc::channel::<UdevEvent>(32);
 
-    std::thread::spawn(move || {
-        if let Err(e) = monitor_udev_events_sync(tx) {
-            error!("Udev monitor error: {}", e);
+    if emulator::emulator_enabled() {
+        info!("Using the HID++ emulator instead of hidraw devices");
+        if let Err(e) = manager.handle_device_added(HidrawNode::emulated()).await {
+            error!("Error handling device add: {}", e);
         }
-    });
+        drop(tx);
+    } else {
+        let monitor = udev_monitor();
+        match hidraw::enumerate() {
+            Ok(nodes) => {
+                for node in nodes {
+                    if let Err(e) = manager.handle_device_added(node).await {
+                        error!("Error handling device add: {}", e);
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to enumerate hidraw devices: {}", e)
+        }
+
+        match monitor {
+            Ok(monitor) => {
+                std::thread::spawn(move || {
+                    if let Err(e) = monitor_udev_events_sync(monitor, tx) {
+                        error!("Udev monitor error: {}", e);
+                    }
+                });
+            }
+            Err(e) => error!("Udev monitor error: {}", e)
+        }
+    }
+
+    let (control_tx, mut control_rx) = mpsc::channel::<ControlMessage>(8);
+    let control_path = control_socket_path();
+    match control::listen(&control_path) {
+        Ok(listener) => {
+            tokio::spawn(control::serve(listener, control_tx));
+        }
+        Err(e) => warn!(
+            "Control socket unavailable, profiles cannot be switched: {}",
+            e
+        )
+    }
+
+    let (focus_tx, mut focus_rx) = mpsc::channel::<FocusedWindow>(8);
+    if manager.config.apps.is_empty() {
+        drop(focus_tx);
+    } else {
+        focus::spawn(manager.config.focus.backend, focus_tx);
+    }
 
     let mut sigterm = signal(SignalKind::terminate())
         .map_err(|e| AppError::internal("Failed to setup SIGTERM handler").with_source(e))?;
     let mut sigint = signal(SignalKind::interrupt())
         .map_err(|e| AppError::internal("Failed to setup SIGINT handler").with_source(e))?;
 
+    let mut event_poll = interval(Duration::from_millis(EVENT_POLL_INTERVAL_MS));
+    let mut tray_poll = interval(TRAY_REFRESH_INTERVAL);
+
     info!("Daemon started successfully");
 
     loop {
         select! {
+            _ = event_poll.tick() => {
+                manager.poll_device_events().await;
+            }
+            _ = tray_poll.tick() => {
+                manager.update_tray_status();
+            }
+            Some(()) = tray_refresh_rx.recv() => {
+                manager.update_tray_status();
+            }
+            Some((request, reply)) = control_rx.recv() => {
+                let response = manager.handle_control(request).await;
+                if reply.send(response).is_err() {
+                    debug!("Control client went away before the reply");
+                }
+            }
+            Some(window) = focus_rx.recv() => {
+                manager.handle_focus(window).await;
+            }
             Some(event) = rx.recv() => {
                 match event {
-                    UdevEvent::Add(path) => {
-                        if let Err(e) = manager.handle_device_added(path).await {
+                    UdevEvent::Add(node) => {
+                        if let Err(e) = manager.handle_device_added(node).await {
                             error!("Error handling device add: {}", e);
                         }
                     }
@@ -292,37 +939,71 @@ async fn main() -> Result<()> {
         }
     }
 
+    fs::remove_file(&control_path).ok();
     info!("Daemon stopped");
     Ok(())
 }
 
+// Problems `logi-mx config check` would report; the config is applied
+// regardless, as far as the device accepts it.
+fn log_config_diagnostics() {
+    let diagnostics = get_config_path()
+        .and_then(|path| check_config_file(&path).map(|diagnostics| (path, diagnostics)));
+    match diagnostics {
+        Ok((path, diagnostics)) => {
+            for diagnostic in diagnostics {
+                match diagnostic.severity {
+                    Severity::Error => error!("{}", diagnostic.in_file(&path)),
+                    Severity::Warning => warn!("{}", diagnostic.in_file(&path))
+                }
+            }
+        }
+        Err(e) => debug!("Config not checked: {}", e)
+    }
+}
+
 #[derive(Debug)]
 enum UdevEvent {
-    Add(String),
+    Add(HidrawNode),
     Remove(String)
 }
 
-fn monitor_udev_events_sync(tx: mpsc::Sender<UdevEvent>) -> Result<()> {
-    let monitor = MonitorBuilder::new()
+// The socket is created before any node is enumerated, so nothing plugged
+// in meanwhile is missed.
+fn udev_monitor() -> Result<udev::MonitorSocket> {
+    MonitorBuilder::new()
         .map_err(|e| AppError::internal("Failed to create udev monitor").with_source(e))?
         .match_subsystem("hidraw")
         .map_err(|e| AppError::internal("Failed to match subsystem").with_source(e))?
         .listen()
-        .map_err(|e| AppError::internal("Failed to start udev monitor").with_source(e))?;
+        .map_err(|e| AppError::internal("Failed to start udev monitor").with_source(e))
+}
 
+fn monitor_udev_events_sync(
+    monitor: udev::MonitorSocket,
+    tx: mpsc::Sender<UdevEvent>
+) -> Result<()> {
     info!("Monitoring udev events for hidraw devices");
 
-    let iter = monitor.iter();
-    for event in iter {
-        let device_path = event
-            .device()
-            .devnode()
-            .and_then(|p| p.to_str())
-            .map(String::from);
+    // The socket is non-blocking: its iterator ends once the pending events
+    // are read, so wait for the next ones before iterating again.
+    loop {
+        wait_readable(monitor.as_raw_fd())?;
+
+        for event in monitor.iter() {
+            let Some(path) = event
+                .device()
+                .devnode()
+                .and_then(|p| p.to_str())
+                .map(String::from)
+            else {
+                continue;
+            };
 
-        if let Some(path) = device_path {
+            // Parents are gone by the time a remove arrives, so only adds
+            // are resolved; removes are matched by node.
             let udev_event = match event.event_type() {
-                udev::EventType::Add => Some(UdevEvent::Add(path)),
+                udev::EventType::Add => HidrawNode::from_udev(&event.device()).map(UdevEvent::Add),
                 udev::EventType::Remove => Some(UdevEvent::Remove(path)),
                 _ => None
             };
@@ -330,19 +1011,59 @@ fn monitor_udev_events_sync(tx: mpsc::Sender<UdevEvent>) -> Result<()> {
             if let Some(evt) = udev_event
                 && tx.blocking_send(evt).is_err()
             {
-                error!("Failed to send udev event");
-                break;
+                debug!("Udev event receiver closed");
+                return Ok(());
             }
         }
     }
+}
 
+fn wait_readable(fd: RawFd) -> Result<()> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0
+    };
+    // SAFETY: `pollfd` is a single valid entry for the duration of the call.
+    if unsafe { libc::poll(&mut pollfd, 1, -1) } < 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(AppError::internal("Failed to poll udev monitor").with_source(e));
+        }
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use logi_mx_driver::emulator::{
+        DEFAULT_SLOT, Emulator, MouseState,
+        uhid::{UHID_PATH, UhidDevice, uhid_available}
+    };
+    use tokio::time::timeout;
+
     use super::*;
 
+    const UDEV_TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Tests that configure the process-wide emulated mouse take turns.
+    static SHARED_MOUSE: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    async fn next_event(
+        rx: &mut mpsc::Receiver<UdevEvent>,
+        matches: impl Fn(&UdevEvent) -> bool
+    ) -> UdevEvent {
+        loop {
+            let event = timeout(UDEV_TIMEOUT, rx.recv())
+                .await
+                .expect("no udev event in time")
+                .expect("udev monitor stopped");
+            if matches(&event) {
+                return event;
+            }
+        }
+    }
+
     #[test]
     fn test_get_lock_file_path() {
         let path = get_lock_file_path(None);
@@ -376,4 +1097,216 @@ mod tests {
 
         drop(lock);
     }
+
+    #[tokio::test]
+    async fn test_profile_switch_reapplies_settings() {
+        let _mouse = SHARED_MOUSE.lock().await;
+        let config: Config = toml::from_str(
+            r#"
+            [[devices]]
+            name = "MX Master 3S"
+            dpi = 1000
+
+            [devices.profiles.gaming]
+            dpi = 3200
+
+            [devices.profiles.gaming.buttons.Forward]
+            type = "DpiShift"
+            dpi = 400
+            "#
+        )
+        .unwrap();
+        let forward = registry()
+            .by_name("MX Master 3S")
+            .and_then(|spec| spec.control_id(ButtonId::Forward))
+            .unwrap();
+        let mouse = || emulator::shared().mouse(DEFAULT_SLOT).unwrap().unwrap();
+
+        let mut manager = DeviceManager::new(config);
+        manager
+            .handle_device_added(HidrawNode::emulated())
+            .await
+            .unwrap();
+        assert_eq!(mouse().dpi, 1000);
+
+        let response = manager
+            .handle_control(ControlRequest::UseProfile {
+                name: "gaming".to_string()
+            })
+            .await;
+        assert_eq!(
+            response,
+            ControlResponse::Profile {
+                active: "gaming".to_string(),
+                app:    None
+            }
+        );
+        assert_eq!(mouse().dpi, 3200);
+        assert!(mouse().diverted.contains(&forward));
+
+        let response = manager
+            .handle_control(ControlRequest::UseProfile {
+                name: "office".to_string()
+            })
+            .await;
+        assert_eq!(
+            response,
+            ControlResponse::Error {
+                message: "No device defines a profile named office".to_string()
+            }
+        );
+
+        // A `SwitchProfile` button for the active profile goes back.
+        manager
+            .handle_outcome(ActionOutcome::ProfileToggled("gaming".to_string()))
+            .await;
+        assert_eq!(manager.active_profile, DEFAULT_PROFILE);
+        assert_eq!(mouse().dpi, 1000);
+        assert!(!mouse().diverted.contains(&forward));
+    }
+
+    #[tokio::test]
+    async fn test_focus_applies_app_rules() {
+        let _mouse = SHARED_MOUSE.lock().await;
+        let config: Config = toml::from_str(
+            r#"
+            [[devices]]
+            name = "MX Master 3S"
+            dpi = 1000
+
+            [devices.profiles.gaming]
+            dpi = 3200
+
+            [[apps]]
+            app = "steam_app_*"
+            profile = "gaming"
+
+            [[apps]]
+            app = "org.inkscape.Inkscape"
+
+            [apps.buttons.Forward]
+            type = "DpiShift"
+            dpi = 400
+            "#
+        )
+        .unwrap();
+        let forward = registry()
+            .by_name("MX Master 3S")
+            .and_then(|spec| spec.control_id(ButtonId::Forward))
+            .unwrap();
+        let mouse = || emulator::shared().mouse(DEFAULT_SLOT).unwrap().unwrap();
+        let window = |app_id: &str| FocusedWindow {
+            app_id:   Some(app_id.to_string()),
+            wm_class: None
+        };
+
+        let mut manager = DeviceManager::new(config);
+        manager
+            .handle_device_added(HidrawNode::emulated())
+            .await
+            .unwrap();
+        assert_eq!(mouse().dpi, 1000);
+
+        manager.handle_focus(window("steam_app_570")).await;
+        assert_eq!(mouse().dpi, 3200);
+        assert_eq!(
+            manager.handle_control(ControlRequest::ActiveProfile).await,
+            ControlResponse::Profile {
+                active: DEFAULT_PROFILE.to_string(),
+                app:    Some("steam_app_*".to_string())
+            }
+        );
+
+        manager.handle_focus(window("org.inkscape.Inkscape")).await;
+        assert_eq!(mouse().dpi, 1000);
+        assert!(mouse().diverted.contains(&forward));
+
+        manager.handle_focus(FocusedWindow::default()).await;
+        assert_eq!(manager.app_rule, None);
+        assert!(!mouse().diverted.contains(&forward));
+    }
+
+    #[tokio::test]
+    async fn test_sleeping_mouse_is_managed_on_link_up() {
+        let _mouse = SHARED_MOUSE.lock().await;
+        let config: Config = toml::from_str(
+            r#"
+            [[devices]]
+            name = "MX Master 3S"
+            dpi = 1200
+            "#
+        )
+        .unwrap();
+        let key = DeviceKey {
+            devnode: emulator::EMULATOR_PATH.to_string(),
+            slot:    Some(DEFAULT_SLOT)
+        };
+
+        emulator::shared()
+            .set_connected(DEFAULT_SLOT, false)
+            .unwrap();
+        let mut manager = DeviceManager::new(config);
+        manager
+            .handle_device_added(HidrawNode::emulated())
+            .await
+            .unwrap();
+        assert!(manager.devices.is_empty());
+
+        emulator::shared()
+            .set_connected(DEFAULT_SLOT, true)
+            .unwrap();
+        manager.poll_device_events().await;
+        assert_eq!(manager.devices.keys().collect::<Vec<_>>(), [&key]);
+        let mouse = emulator::shared().mouse(DEFAULT_SLOT).unwrap().unwrap();
+        assert_eq!(mouse.dpi, 1200);
+    }
+
+    // Plugs a receiver in through /dev/uhid and follows it from the udev
+    // event to a managed mouse and back out; needs root.
+    #[tokio::test]
+    async fn test_hotplug_of_a_uhid_receiver() {
+        if !uhid_available() {
+            eprintln!("skipping: {} is not accessible", UHID_PATH);
+            return;
+        }
+
+        let (tx, mut rx) = mpsc::channel(32);
+        let monitor = udev_monitor().unwrap();
+        std::thread::spawn(move || monitor_udev_events_sync(monitor, tx));
+
+        let emulator = Emulator::with_mouse(DEFAULT_SLOT, MouseState::mx_master_3s());
+        emulator
+            .update_mouse(DEFAULT_SLOT, |mouse| mouse.dpi = 1600)
+            .unwrap();
+        let device = UhidDevice::create(&emulator).unwrap();
+        let uniq = device.uniq().to_string();
+
+        let UdevEvent::Add(node) = next_event(&mut rx, |event| {
+            matches!(event, UdevEvent::Add(node) if node.uniq.as_deref() == Some(uniq.as_str()))
+        })
+        .await
+        else {
+            unreachable!()
+        };
+        assert_eq!(node.devnode, device.hidraw_path().unwrap());
+        assert!(node.is_receiver() && node.is_hidpp());
+
+        let mut manager = DeviceManager::new(Config::default());
+        manager.handle_device_added(node.clone()).await.unwrap();
+        let key = DeviceKey {
+            devnode: node.devnode.clone(),
+            slot:    Some(DEFAULT_SLOT)
+        };
+        assert_eq!(manager.devices.keys().collect::<Vec<_>>(), [&key]);
+        assert_eq!(emulator.mouse(DEFAULT_SLOT).unwrap().unwrap().dpi, 1000);
+
+        drop(device);
+        next_event(
+            &mut rx,
+            |event| matches!(event, UdevEvent::Remove(devnode) if *devnode == node.devnode)
+        )
+        .await;
+        manager.handle_device_removed(&node.devnode).await;
+        assert!(manager.devices.is_empty());
+    }
 }
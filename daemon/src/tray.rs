@@ -10,43 +10,60 @@ use std::{
 
 #[cfg(feature = "tray")]
 use gtk4::{AlertDialog, Window, glib};
-use ksni::{Category, MenuItem, Tray, TrayMethods, menu::StandardItem};
+use ksni::{Category, MenuItem, ToolTip, Tray, TrayMethods, menu::StandardItem};
 use logi_mx_driver::prelude::*;
+use tokio::sync::mpsc::UnboundedSender;
 use tracing::{debug, error, info};
 
 #[derive(Clone)]
 pub struct DeviceStatus {
+    // The managed mouse, or the last one seen while disconnected.
+    pub name:                 String,
     pub connected:            bool,
     pub battery_level:        u8,
     pub battery_status:       String,
     pub dpi:                  u16,
     pub smartshift:           bool,
     pub smartshift_threshold: u8,
+    // Empty until the device has been queried, so everything shows by default.
+    pub unsupported:          Vec<Setting>,
     pub error:                Option<String>
 }
 
 impl Default for DeviceStatus {
     fn default() -> Self {
         Self {
+            name:                 "Logitech mouse".to_string(),
             connected:            false,
             battery_level:        0,
             battery_status:       "Unknown".to_string(),
             dpi:                  1000,
             smartshift:           false,
             smartshift_threshold: 20,
+            unsupported:          Vec::new(),
             error:                None
         }
     }
 }
 
+impl DeviceStatus {
+    pub fn supports(&self, setting: Setting) -> bool {
+        !self.unsupported.contains(&setting)
+    }
+}
+
+// Shows what the daemon last read from its managed mouse; the daemon is the
+// only one talking to the device.
 pub struct LogiTrayIcon {
-    status: Arc<Mutex<DeviceStatus>>
+    status:  Arc<Mutex<DeviceStatus>>,
+    refresh: UnboundedSender<()>
 }
 
 impl LogiTrayIcon {
-    pub fn new() -> Self {
+    pub fn new(refresh: UnboundedSender<()>) -> Self {
         Self {
-            status: Arc::new(Mutex::new(DeviceStatus::default()))
+            status: Arc::new(Mutex::new(DeviceStatus::default())),
+            refresh
         }
     }
 
@@ -101,33 +118,6 @@ impl LogiTrayIcon {
         info!("Daemon shutdown complete");
         exit(0);
     }
-
-    pub fn update_status(&self) {
-        if let Ok(mut device) = MxMaster3s::open_bolt_receiver(2) {
-            let mut status = self.status.lock().unwrap();
-            status.connected = true;
-
-            if let Ok(battery) = device.get_battery_info() {
-                status.battery_level = battery.level;
-                status.battery_status = format!("{:?}", battery.status);
-            }
-
-            if let Ok(dpi) = device.get_dpi() {
-                status.dpi = dpi;
-            }
-
-            if let Ok(ss_config) = device.get_smartshift() {
-                status.smartshift = ss_config.enabled;
-                status.smartshift_threshold = ss_config.threshold;
-            }
-
-            debug!("Tray status updated: battery={}%", status.battery_level);
-        } else {
-            let mut status = self.status.lock().unwrap();
-            status.connected = false;
-            debug!("Device not connected");
-        }
-    }
 }
 
 impl Tray for LogiTrayIcon {
@@ -145,14 +135,46 @@ impl Tray for LogiTrayIcon {
     fn title(&self) -> String {
         let status = self.status.lock().unwrap();
         if let Some(ref error) = status.error {
-            format!("MX Master 3S - Error: {}", error)
+            format!("{} - Error: {}", status.name, error)
+        } else if status.connected && status.supports(Setting::Dpi) {
+            format!(
+                "{} - Battery: {}% ({}), DPI: {}",
+                status.name, status.battery_level, status.battery_status, status.dpi
+            )
         } else if status.connected {
             format!(
-                "MX Master 3S - Battery: {}% ({}), DPI: {}",
-                status.battery_level, status.battery_status, status.dpi
+                "{} - Battery: {}% ({})",
+                status.name, status.battery_level, status.battery_status
             )
         } else {
-            "MX Master 3S - Disconnected".to_string()
+            format!("{} - Disconnected", status.name)
+        }
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        let (title, description) = {
+            let status = self.status.lock().unwrap();
+            let description = if status.connected && status.supports(Setting::Dpi) {
+                format!(
+                    "Battery: {}% ({})\nDPI: {}",
+                    status.battery_level, status.battery_status, status.dpi
+                )
+            } else if status.connected {
+                format!(
+                    "Battery: {}% ({})",
+                    status.battery_level, status.battery_status
+                )
+            } else {
+                "Disconnected".to_string()
+            };
+            (status.name.clone(), description)
+        };
+
+        ToolTip {
+            icon_name: self.icon_name(),
+            title,
+            description,
+            ..Default::default()
         }
     }
 
@@ -169,7 +191,7 @@ impl Tray for LogiTrayIcon {
 
         let mut menu = vec![
             StandardItem {
-                label: "Logitech MX Master 3S".into(),
+                label: status.name.clone(),
                 icon_name: "input-mouse".into(),
                 activate: Box::new(|_| {}),
                 enabled: false,
@@ -193,7 +215,11 @@ impl Tray for LogiTrayIcon {
                 }
                 .into(),
                 StandardItem {
-                    label: format!("DPI: {}", status.dpi),
+                    label: if status.supports(Setting::Dpi) {
+                        format!("DPI: {}", status.dpi)
+                    } else {
+                        "DPI: not adjustable on this device".to_string()
+                    },
                     icon_name: "preferences-desktop".into(),
                     activate: Box::new(|_| {}),
                     enabled: false,
@@ -201,11 +227,15 @@ impl Tray for LogiTrayIcon {
                 }
                 .into(),
                 StandardItem {
-                    label: format!(
-                        "SmartShift: {} ({})",
-                        if status.smartshift { "On" } else { "Off" },
-                        status.smartshift_threshold
-                    ),
+                    label: if status.supports(Setting::SmartShift) {
+                        format!(
+                            "SmartShift: {} ({})",
+                            if status.smartshift { "On" } else { "Off" },
+                            status.smartshift_threshold
+                        )
+                    } else {
+                        "SmartShift: not available on this device".to_string()
+                    },
                     icon_name: "preferences-system".into(),
                     activate: Box::new(|_| {}),
                     enabled: false,
@@ -217,7 +247,9 @@ impl Tray for LogiTrayIcon {
                     label: "Refresh Status".into(),
                     icon_name: "view-refresh".into(),
                     activate: Box::new(|this: &mut Self| {
-                        this.update_status();
+                        if this.refresh.send(()).is_err() {
+                            debug!("Daemon no longer takes refresh requests");
+                        }
                     }),
                     enabled: true,
                     ..Default::default()
@@ -299,10 +331,11 @@ impl Tray for LogiTrayIcon {
     }
 }
 
-pub async fn spawn_tray() -> std::result::Result<Arc<Mutex<DeviceStatus>>, String> {
-    let tray_icon = LogiTrayIcon::new();
+pub async fn spawn_tray(
+    refresh: UnboundedSender<()>
+) -> std::result::Result<Arc<Mutex<DeviceStatus>>, String> {
+    let tray_icon = LogiTrayIcon::new(refresh);
     let status_handle = tray_icon.get_status_handle();
-    tray_icon.update_status();
 
     tray_icon
         .spawn()
@@ -315,6 +348,10 @@ pub async fn spawn_tray() -> std::result::Result<Arc<Mutex<DeviceStatus>>, Strin
 mod tests {
     use super::*;
 
+    fn new_tray() -> LogiTrayIcon {
+        LogiTrayIcon::new(tokio::sync::mpsc::unbounded_channel().0)
+    }
+
     #[test]
     fn test_device_status_default_with_error() {
         let status = DeviceStatus::default();
@@ -324,12 +361,14 @@ mod tests {
     #[test]
     fn test_device_status_with_error() {
         let status = DeviceStatus {
+            name:                 "MX Master 3S".to_string(),
             connected:            false,
             battery_level:        0,
             battery_status:       "Unknown".to_string(),
             dpi:                  1000,
             smartshift:           false,
             smartshift_threshold: 20,
+            unsupported:          Vec::new(),
             error:                Some("Test error".to_string())
         };
         assert_eq!(status.error, Some("Test error".to_string()));
@@ -337,13 +376,13 @@ mod tests {
 
     #[test]
     fn test_tray_icon_name_disconnected() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         assert_eq!(tray.icon_name(), "input-mouse-symbolic");
     }
 
     #[test]
     fn test_tray_icon_name_connected() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         {
             let mut status = tray.status.lock().unwrap();
             status.connected = true;
@@ -353,7 +392,7 @@ mod tests {
 
     #[test]
     fn test_tray_icon_name_error() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         {
             let mut status = tray.status.lock().unwrap();
             status.error = Some("Test error".to_string());
@@ -363,7 +402,7 @@ mod tests {
 
     #[test]
     fn test_tray_title_with_error() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         {
             let mut status = tray.status.lock().unwrap();
             status.error = Some("Device failure".to_string());
@@ -374,7 +413,7 @@ mod tests {
 
     #[test]
     fn test_tray_title_connected_with_details() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         {
             let mut status = tray.status.lock().unwrap();
             status.connected = true;
@@ -390,26 +429,70 @@ mod tests {
 
     #[test]
     fn test_tray_title_disconnected() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         let title = tray.title();
-        assert_eq!(title, "MX Master 3S - Disconnected");
+        assert_eq!(title, "Logitech mouse - Disconnected");
+    }
+
+    #[test]
+    fn test_tray_shows_managed_device_name() {
+        let tray = new_tray();
+        {
+            let mut status = tray.status.lock().unwrap();
+            status.name = "MX Anywhere 3S".to_string();
+            status.connected = true;
+        }
+        assert!(tray.title().starts_with("MX Anywhere 3S - Battery"));
+        assert_eq!(tray.tool_tip().title, "MX Anywhere 3S");
+    }
+
+    #[test]
+    fn test_tray_tool_tip_shows_dpi() {
+        let tray = new_tray();
+        {
+            let mut status = tray.status.lock().unwrap();
+            status.connected = true;
+            status.dpi = 1600;
+        }
+        let tool_tip = tray.tool_tip();
+        assert!(tool_tip.description.contains("DPI: 1600"));
+        assert_eq!(tool_tip.icon_name, "input-mouse");
+    }
+
+    #[test]
+    fn test_tray_hides_unsupported_dpi() {
+        let tray = new_tray();
+        {
+            let mut status = tray.status.lock().unwrap();
+            status.connected = true;
+            status.dpi = 1600;
+            status.unsupported = vec![Setting::Dpi];
+        }
+        assert!(!tray.title().contains("DPI"));
+        assert!(!tray.tool_tip().description.contains("DPI"));
+    }
+
+    #[test]
+    fn test_tray_tool_tip_disconnected() {
+        let tray = new_tray();
+        assert_eq!(tray.tool_tip().description, "Disconnected");
     }
 
     #[test]
     fn test_tray_id() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         assert_eq!(tray.id(), "logi-mx-daemon");
     }
 
     #[test]
     fn test_tray_category() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         assert_eq!(tray.category(), Category::Hardware);
     }
 
     #[test]
     fn test_get_status_handle() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         let handle1 = tray.get_status_handle();
         let handle2 = tray.get_status_handle();
 
@@ -431,27 +514,32 @@ mod tests {
     #[test]
     fn test_device_status_all_fields() {
         let status = DeviceStatus {
+            name:                 "MX Master 3S".to_string(),
             connected:            true,
             battery_level:        95,
             battery_status:       "Discharging".to_string(),
             dpi:                  3200,
             smartshift:           true,
             smartshift_threshold: 30,
+            unsupported:          vec![Setting::SmartShift],
             error:                None
         };
 
+        assert_eq!(status.name, "MX Master 3S");
         assert!(status.connected);
         assert_eq!(status.battery_level, 95);
         assert_eq!(status.battery_status, "Discharging");
         assert_eq!(status.dpi, 3200);
         assert!(status.smartshift);
         assert_eq!(status.smartshift_threshold, 30);
+        assert!(status.supports(Setting::Dpi));
+        assert!(!status.supports(Setting::SmartShift));
         assert!(status.error.is_none());
     }
 
     #[test]
     fn test_icon_name_priority_error_over_connected() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         {
             let mut status = tray.status.lock().unwrap();
             status.connected = true;
@@ -463,7 +551,7 @@ mod tests {
 
     #[test]
     fn test_title_priority_error_over_connected() {
-        let tray = LogiTrayIcon::new();
+        let tray = new_tray();
         {
             let mut status = tray.status.lock().unwrap();
             status.connected = true;
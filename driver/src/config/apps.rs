@@ -0,0 +1,171 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    matching::glob_match,
+    schema::{Config, DeviceConfig}
+};
+use crate::devices::{Action, ButtonId};
+
+// `[[apps]]`: settings that hold while a window of the application has
+// focus. `app` is matched against the Wayland app id and the X11 WM class,
+// case-insensitively and with `*` and `?` wildcards.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppRule {
+    pub app: String,
+
+    // Takes the place of the active profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    // Merged over the profile's buttons on every device.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub buttons: HashMap<ButtonId, Action>
+}
+
+// What the window watcher reports. Both names are missing when nothing has
+// focus, such as on an empty workspace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FocusedWindow {
+    pub app_id:   Option<String>,
+    pub wm_class: Option<String>
+}
+
+// Where the daemon learns about the focused window. `auto` picks from the
+// session's environment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FocusBackend {
+    #[default]
+    Auto,
+    #[serde(alias = "i3")]
+    Sway,
+    Hyprland,
+    Kde,
+    Gnome,
+    X11,
+    None
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FocusConfig {
+    #[serde(default)]
+    pub backend: FocusBackend
+}
+
+impl FocusConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl AppRule {
+    pub fn matches(&self, window: &FocusedWindow) -> bool {
+        [&window.app_id, &window.wm_class]
+            .into_iter()
+            .flatten()
+            .any(|name| glob_match(&self.app, name))
+    }
+}
+
+impl Config {
+    // The first rule that matches, with its position in `apps`.
+    pub fn app_rule(&self, window: &FocusedWindow) -> Option<(usize, &AppRule)> {
+        self.apps
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(window))
+    }
+}
+
+impl DeviceConfig {
+    // `resolve_profile` with the focused application's rule on top.
+    pub fn resolve_for_app(&self, profile: &str, rule: Option<&AppRule>) -> DeviceConfig {
+        let profile = rule
+            .and_then(|rule| rule.profile.as_deref())
+            .unwrap_or(profile);
+        let mut resolved = self.resolve_profile(profile);
+        if let Some(rule) = rule {
+            resolved.buttons.extend(
+                rule.buttons
+                    .iter()
+                    .map(|(button, action)| (*button, action.clone()))
+            );
+        }
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APPS: &str = r#"
+        [[devices]]
+        name = "MX Master 3S"
+        dpi = 1000
+
+        [devices.profiles.design]
+        dpi = 1600
+
+        [[apps]]
+        app = "org.inkscape.Inkscape"
+        profile = "design"
+
+        [[apps]]
+        app = "firefox*"
+
+        [apps.buttons.Back]
+        type = "Keypress"
+        keys = ["KEY_LEFTALT", "KEY_LEFT"]
+
+        [focus]
+        backend = "i3"
+    "#;
+
+    fn window(app_id: Option<&str>, wm_class: Option<&str>) -> FocusedWindow {
+        FocusedWindow {
+            app_id:   app_id.map(String::from),
+            wm_class: wm_class.map(String::from)
+        }
+    }
+
+    #[test]
+    fn test_rules_match_app_id_or_wm_class() {
+        let config: Config = toml::from_str(APPS).unwrap();
+        assert_eq!(config.focus.backend, FocusBackend::Sway);
+
+        let inkscape = window(Some("org.inkscape.Inkscape"), None);
+        assert_eq!(config.app_rule(&inkscape).map(|(index, _)| index), Some(0));
+        let firefox = window(None, Some("Firefox-esr"));
+        assert_eq!(config.app_rule(&firefox).map(|(index, _)| index), Some(1));
+        assert!(config.app_rule(&window(Some("foot"), None)).is_none());
+        assert!(config.app_rule(&FocusedWindow::default()).is_none());
+    }
+
+    #[test]
+    fn test_rule_settings_apply_over_the_profile() {
+        let config: Config = toml::from_str(APPS).unwrap();
+        let device = &config.devices[0];
+
+        let design = device.resolve_for_app("default", Some(&config.apps[0]));
+        assert_eq!(design.dpi, 1600);
+
+        let firefox = device.resolve_for_app("design", Some(&config.apps[1]));
+        assert_eq!(firefox.dpi, 1600);
+        assert_eq!(
+            firefox.buttons[&ButtonId::Back],
+            Action::Keypress {
+                keys: vec!["KEY_LEFTALT".to_string(), "KEY_LEFT".to_string()]
+            }
+        );
+        assert_eq!(
+            device.resolve_for_app("default", None).buttons,
+            device.buttons
+        );
+    }
+}
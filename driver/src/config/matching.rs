@@ -0,0 +1,418 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::schema::{Config, DeviceConfig};
+use crate::devices::{ConnectionType, DeviceIdentity};
+
+// Optional `[devices.match]` block. Every criterion that is set must hold for
+// the entry to apply; criteria left out are ignored. An entry without the
+// block matches on its `name` alone, exactly as before.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceMatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_id: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wpid: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection: Option<ConnectionType>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receiver_slot: Option<u8>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>
+}
+
+// Listed from most to least specific. When several entries apply, the one
+// whose strongest criterion ranks highest wins, then the next strongest, and
+// so on; a remaining tie goes to the entry listed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchCriterion {
+    Serial,
+    ModelId,
+    Wpid,
+    ReceiverSlot,
+    Connection,
+    Name,
+    NameGlob
+}
+
+impl fmt::Display for MatchCriterion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MatchCriterion::Serial => "serial",
+            MatchCriterion::ModelId => "model id",
+            MatchCriterion::Wpid => "wpid",
+            MatchCriterion::ReceiverSlot => "receiver slot",
+            MatchCriterion::Connection => "connection",
+            MatchCriterion::Name => "name",
+            MatchCriterion::NameGlob => "name pattern"
+        })
+    }
+}
+
+impl DeviceMatch {
+    // The criteria that matched, best first, or `None` when any set criterion
+    // does not hold for the device.
+    pub fn evaluate(&self, identity: &DeviceIdentity) -> Option<Vec<MatchCriterion>> {
+        let checks = [
+            (
+                MatchCriterion::Serial,
+                self.serial.as_ref().map(|serial| {
+                    identity
+                        .serial
+                        .as_ref()
+                        .is_some_and(|s| s.eq_ignore_ascii_case(serial))
+                })
+            ),
+            (
+                MatchCriterion::ModelId,
+                self.model_id.map(|id| identity.model_ids.contains(&id))
+            ),
+            (
+                MatchCriterion::Wpid,
+                self.wpid.map(|wpid| identity.wpid == Some(wpid))
+            ),
+            (
+                MatchCriterion::ReceiverSlot,
+                self.receiver_slot
+                    .map(|slot| identity.receiver_slot == Some(slot))
+            ),
+            (
+                MatchCriterion::Connection,
+                self.connection
+                    .map(|connection| identity.connection == Some(connection))
+            ),
+            (
+                // A pattern without wildcards names the device exactly.
+                match &self.name {
+                    Some(pattern) if pattern.contains(['*', '?']) => MatchCriterion::NameGlob,
+                    _ => MatchCriterion::Name
+                },
+                self.name
+                    .as_ref()
+                    .map(|pattern| glob_match(pattern, &identity.name))
+            )
+        ];
+
+        let mut matched = Vec::new();
+        for (criterion, outcome) in checks {
+            match outcome {
+                Some(true) => matched.push(criterion),
+                Some(false) => return None,
+                None => {}
+            }
+        }
+        Some(matched)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigMatch<'a> {
+    pub config:  &'a DeviceConfig,
+    pub index:   usize,
+    pub matched: Vec<MatchCriterion>
+}
+
+impl fmt::Display for ConfigMatch<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry #{} \"{}\"", self.index + 1, self.config.name)?;
+        if self.matched.is_empty() {
+            return f.write_str(" (catch-all)");
+        }
+
+        let criteria: Vec<String> = self.matched.iter().map(ToString::to_string).collect();
+        write!(f, " matched by {}", criteria.join(", "))
+    }
+}
+
+impl DeviceConfig {
+    pub fn evaluate(&self, identity: &DeviceIdentity) -> Option<Vec<MatchCriterion>> {
+        match &self.matcher {
+            Some(matcher) => matcher.evaluate(identity),
+            None => (self.name == identity.name).then(|| vec![MatchCriterion::Name])
+        }
+    }
+}
+
+impl Config {
+    pub fn find_device_config(&self, identity: &DeviceIdentity) -> Option<ConfigMatch<'_>> {
+        self.devices
+            .iter()
+            .enumerate()
+            .filter_map(|(index, config)| {
+                config.evaluate(identity).map(|matched| ConfigMatch {
+                    config,
+                    index,
+                    matched
+                })
+            })
+            // `min_by` keeps the first of equal elements, so earlier entries
+            // win ties. A criterion that sorts first is stronger, and a
+            // longer list beats its own prefix.
+            .min_by(|a, b| {
+                a.matched
+                    .iter()
+                    .zip(&b.matched)
+                    .map(|(x, y)| x.cmp(y))
+                    .find(|order| order.is_ne())
+                    .unwrap_or_else(|| b.matched.len().cmp(&a.matched.len()))
+            })
+    }
+}
+
+// Case-insensitive glob with `*` for any run of characters and `?` for one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CONFIG_VERSION, FocusConfig};
+
+    fn identity() -> DeviceIdentity {
+        DeviceIdentity {
+            name:          "MX Master 3S".to_string(),
+            serial:        Some("A1B2C3D4".to_string()),
+            model_ids:     vec![0xB034],
+            wpid:          Some(0xB034),
+            connection:    Some(ConnectionType::Receiver),
+            receiver_slot: Some(2)
+        }
+    }
+
+    fn entry(name: &str, matcher: Option<DeviceMatch>) -> DeviceConfig {
+        DeviceConfig {
+            name: name.to_string(),
+            matcher,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("MX Master*", "MX Master 3S"));
+        assert!(glob_match("mx master 3?", "MX Master 3S"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*3S", "MX Master 3S"));
+        assert!(glob_match("M*a*3S", "MX Master 3S"));
+        assert!(!glob_match("MX Anywhere*", "MX Master 3S"));
+        assert!(!glob_match("MX Master", "MX Master 3S"));
+    }
+
+    #[test]
+    fn test_unset_criteria_are_ignored() {
+        let matcher = DeviceMatch {
+            wpid: Some(0xB034),
+            name: Some("MX*".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            matcher.evaluate(&identity()),
+            Some(vec![MatchCriterion::Wpid, MatchCriterion::NameGlob])
+        );
+
+        let other_slot = DeviceMatch {
+            receiver_slot: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(other_slot.evaluate(&identity()), None);
+        assert_eq!(DeviceMatch::default().evaluate(&identity()), Some(vec![]));
+    }
+
+    #[test]
+    fn test_legacy_entry_matches_by_name() {
+        let config = Config {
+            version:   CONFIG_VERSION,
+            devices:   vec![entry("MX Master 3S", None), entry("Other", None)],
+            keyboards: Vec::new(),
+            apps:      Vec::new(),
+            focus:     FocusConfig::default()
+        };
+
+        let found = config.find_device_config(&identity()).unwrap();
+        assert_eq!(found.index, 0);
+        assert_eq!(found.matched, [MatchCriterion::Name]);
+    }
+
+    #[test]
+    fn test_most_specific_entry_wins() {
+        let config = Config {
+            version:   CONFIG_VERSION,
+            devices:   vec![
+                entry("Any mouse", Some(DeviceMatch::default())),
+                entry("MX Master 3S", None),
+                entry(
+                    "Slot 2",
+                    Some(DeviceMatch {
+                        receiver_slot: Some(2),
+                        name: Some("MX*".to_string()),
+                        ..Default::default()
+                    })
+                ),
+                entry(
+                    "Desk mouse",
+                    Some(DeviceMatch {
+                        serial: Some("a1b2c3d4".to_string()),
+                        ..Default::default()
+                    })
+                ),
+                entry(
+                    "Other desk",
+                    Some(DeviceMatch {
+                        serial: Some("FFFFFFFF".to_string()),
+                        ..Default::default()
+                    })
+                ),
+            ],
+            keyboards: Vec::new(),
+            apps:      Vec::new(),
+            focus:     FocusConfig::default()
+        };
+
+        let found = config.find_device_config(&identity()).unwrap();
+        assert_eq!(found.config.name, "Desk mouse");
+        assert_eq!(
+            found.to_string(),
+            "entry #4 \"Desk mouse\" matched by serial"
+        );
+
+        let unknown = DeviceIdentity {
+            serial: None,
+            receiver_slot: Some(5),
+            ..identity()
+        };
+        let found = config.find_device_config(&unknown).unwrap();
+        assert_eq!(found.config.name, "MX Master 3S");
+
+        let renamed = DeviceIdentity {
+            name: "Office".to_string(),
+            ..unknown
+        };
+        let found = config.find_device_config(&renamed).unwrap();
+        assert_eq!(found.to_string(), "entry #1 \"Any mouse\" (catch-all)");
+    }
+
+    #[test]
+    fn test_exact_name_beats_name_pattern() {
+        let catch_all = DeviceMatch {
+            name: Some("*".to_string()),
+            ..Default::default()
+        };
+        let exact = DeviceMatch {
+            name: Some("mx master 3s".to_string()),
+            ..Default::default()
+        };
+        let config = Config {
+            version:   CONFIG_VERSION,
+            devices:   vec![
+                entry("Any", Some(catch_all.clone())),
+                entry("MX Master 3S", None),
+            ],
+            keyboards: Vec::new(),
+            apps:      Vec::new(),
+            focus:     FocusConfig::default()
+        };
+        let found = config.find_device_config(&identity()).unwrap();
+        assert_eq!(found.index, 1);
+        assert_eq!(found.matched, [MatchCriterion::Name]);
+
+        let config = Config {
+            devices: vec![entry("Any", Some(catch_all)), entry("Exact", Some(exact))],
+            ..config
+        };
+        assert_eq!(config.find_device_config(&identity()).unwrap().index, 1);
+    }
+
+    #[test]
+    fn test_more_criteria_break_ties() {
+        let config = Config {
+            version:   CONFIG_VERSION,
+            devices:   vec![
+                entry(
+                    "Receiver",
+                    Some(DeviceMatch {
+                        wpid: Some(0xB034),
+                        ..Default::default()
+                    })
+                ),
+                entry(
+                    "Receiver slot 2",
+                    Some(DeviceMatch {
+                        wpid: Some(0xB034),
+                        receiver_slot: Some(2),
+                        ..Default::default()
+                    })
+                ),
+            ],
+            keyboards: Vec::new(),
+            apps:      Vec::new(),
+            focus:     FocusConfig::default()
+        };
+
+        let found = config.find_device_config(&identity()).unwrap();
+        assert_eq!(found.config.name, "Receiver slot 2");
+    }
+
+    #[test]
+    fn test_match_block_parses() {
+        let config: Config = toml::from_str(
+            r#"
+            [[devices]]
+            name = "Desk mouse"
+
+            [devices.match]
+            serial = "A1B2C3D4"
+            connection = "receiver"
+            receiver_slot = 2
+            "#
+        )
+        .unwrap();
+
+        let matcher = config.devices[0].matcher.as_ref().unwrap();
+        assert_eq!(matcher.serial.as_deref(), Some("A1B2C3D4"));
+        assert_eq!(matcher.connection, Some(ConnectionType::Receiver));
+        assert_eq!(matcher.receiver_slot, Some(2));
+        assert_eq!(matcher.wpid, None);
+
+        let round_trip: Config = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(round_trip.devices[0].matcher, config.devices[0].matcher);
+    }
+}
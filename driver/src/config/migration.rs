@@ -0,0 +1,318 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{fmt, path::PathBuf};
+
+use masterror::{field, prelude::*};
+use toml::{Table, Value};
+
+use crate::error::Result;
+
+// Bumped with every schema change that needs a step in `MIGRATIONS`.
+pub const CONFIG_VERSION: u32 = 1;
+
+const VERSION_KEY: &str = "version";
+
+// Upgrades a file from `from` to `from + 1`, recording each change it makes
+// for the user.
+struct Migration {
+    from:  u32,
+    apply: fn(&mut Table, &mut Vec<String>)
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    from:  0,
+    apply: tag_actions
+}];
+
+// Adds the current `version` to a file that needs no other change, keeping
+// its comments and layout.
+pub(crate) fn stamp_version(content: &str) -> Result<String> {
+    let mut document: toml_edit::DocumentMut = content
+        .parse()
+        .map_err(|e| AppError::bad_request("Invalid config format").with_source(e))?;
+    document.insert(VERSION_KEY, toml_edit::value(i64::from(CONFIG_VERSION)));
+    Ok(document.to_string())
+}
+
+// Action variants and, for those that used to be newtypes, the field their
+// value moved into.
+const ACTIONS: &[(&str, Option<&str>)] = &[
+    ("Keypress", Some("keys")),
+    ("Gestures", Some("gestures")),
+    ("ToggleSmartShift", None),
+    ("DpiCycle", Some("steps")),
+    ("DpiShift", Some("dpi")),
+    ("ChangeHost", Some("target")),
+    ("None", None)
+];
+
+// Sections early versions documented but never applied.
+const UNUSED_DEVICE_KEYS: &[&str] = &["scroll_wheel", "thumbwheel"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from:    u32,
+    pub to:      u32,
+    pub changes: Vec<String>,
+    pub backup:  Option<PathBuf>
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Config migrated from version {} to {}",
+            self.from, self.to
+        )?;
+        if let Some(backup) = &self.backup {
+            write!(f, " (previous file kept as {})", backup.display())?;
+        }
+        for change in &self.changes {
+            write!(f, "\n  - {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+// Files without a `version` key predate versioning and count as version 0.
+// Returns `None` when the file is already current.
+pub fn migrate(table: &mut Table) -> Result<Option<MigrationReport>> {
+    let from = match table.get(VERSION_KEY) {
+        None => 0,
+        Some(Value::Integer(version)) => {
+            u32::try_from(*version).map_err(|_| AppError::bad_request("Invalid config version"))?
+        }
+        Some(_) => return Err(AppError::bad_request("Config version must be an integer"))
+    };
+    if from > CONFIG_VERSION {
+        return Err(
+            AppError::bad_request("Config was written by a newer version of logi-mx")
+                .with_field(field::u64("version", from as u64))
+        );
+    }
+    if from == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    let mut changes = Vec::new();
+    for version in from..CONFIG_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or_else(|| {
+                AppError::internal("No config migration from version")
+                    .with_field(field::u64("version", version as u64))
+            })?;
+        (migration.apply)(table, &mut changes);
+    }
+    table.insert(
+        VERSION_KEY.to_string(),
+        Value::Integer(CONFIG_VERSION.into())
+    );
+
+    Ok(Some(MigrationReport {
+        from,
+        to: CONFIG_VERSION,
+        changes,
+        backup: None
+    }))
+}
+
+// Version 0 to 1: actions written as `{ Keypress = { keys = [...] } }` or
+// a bare "ToggleSmartShift" take the `type` key, and unused sections go.
+fn tag_actions(table: &mut Table, changes: &mut Vec<String>) {
+    let Some(Value::Array(devices)) = table.get_mut("devices") else {
+        return;
+    };
+
+    for (index, device) in devices.iter_mut().enumerate() {
+        let Some(device) = device.as_table_mut() else {
+            continue;
+        };
+        for key in UNUSED_DEVICE_KEYS {
+            if device.remove(*key).is_some() {
+                changes.push(format!(
+                    "devices[{}]: removed `{}`, which was never applied",
+                    index, key
+                ));
+            }
+        }
+
+        let Some(Value::Table(buttons)) = device.get_mut("buttons") else {
+            continue;
+        };
+        for (button, action) in buttons.iter_mut() {
+            if tag_action(action) {
+                changes.push(format!(
+                    "devices[{}].buttons.{}: action rewritten with a `type` key",
+                    index, button
+                ));
+            }
+        }
+    }
+}
+
+// Returns whether the action, or one nested in its gestures, was rewritten.
+fn tag_action(action: &mut Value) -> bool {
+    let mut changed = match action {
+        Value::String(variant) if is_action(variant) => {
+            let tagged = Table::from_iter([("type".to_string(), Value::String(variant.clone()))]);
+            *action = Value::Table(tagged);
+            true
+        }
+        Value::Table(table) if table.len() == 1 && !table.contains_key("type") => {
+            match untagged(table) {
+                Some(tagged) => {
+                    *table = tagged;
+                    true
+                }
+                None => false
+            }
+        }
+        _ => false
+    };
+
+    if let Some(Value::Array(gestures)) = action
+        .as_table_mut()
+        .and_then(|table| table.get_mut("gestures"))
+    {
+        for gesture in gestures.iter_mut().filter_map(Value::as_table_mut) {
+            if let Some(nested) = gesture.get_mut("action") {
+                changed |= tag_action(nested);
+            }
+        }
+    }
+
+    changed
+}
+
+fn untagged(table: &Table) -> Option<Table> {
+    let (variant, value) = table.iter().next()?;
+    let &(_, field) = ACTIONS.iter().find(|(name, _)| name == variant)?;
+
+    let mut tagged = match (value, field) {
+        (Value::Table(fields), _) => fields.clone(),
+        (value, Some(field)) => Table::from_iter([(field.to_string(), value.clone())]),
+        (_, None) => Table::new()
+    };
+    tagged.insert("type".to_string(), Value::String(variant.clone()));
+    Some(tagged)
+}
+
+fn is_action(variant: &str) -> bool {
+    ACTIONS.iter().any(|(name, _)| *name == variant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        devices::{Action, ButtonId, GestureDirection}
+    };
+
+    // The layout the README used to document.
+    const UNVERSIONED: &str = r#"
+        [[devices]]
+        name = "MX Master 3S"
+        dpi = 1600
+
+        [devices.scroll_wheel]
+        vertical_speed = 3
+
+        [devices.thumbwheel]
+        speed = 5
+
+        [devices.buttons]
+        WheelModeShift = "ToggleSmartShift"
+        Back = { DpiShift = 400 }
+        Forward = { type = "DpiCycle", steps = [800, 1600] }
+
+        [devices.buttons.ThumbGesture]
+        Gestures = [
+            { direction = "Up", mode = "OnRelease", action = { Keypress = { keys = ["KEY_UP"] } } },
+        ]
+    "#;
+
+    #[test]
+    fn test_unversioned_file_is_migrated() {
+        let mut table: Table = toml::from_str(UNVERSIONED).unwrap();
+        let report = migrate(&mut table).unwrap().unwrap();
+        assert_eq!((report.from, report.to), (0, CONFIG_VERSION));
+        assert_eq!(
+            report.changes,
+            [
+                "devices[0]: removed `scroll_wheel`, which was never applied",
+                "devices[0]: removed `thumbwheel`, which was never applied",
+                "devices[0].buttons.Back: action rewritten with a `type` key",
+                "devices[0].buttons.ThumbGesture: action rewritten with a `type` key",
+                "devices[0].buttons.WheelModeShift: action rewritten with a `type` key"
+            ]
+        );
+
+        let config: Config = table.try_into().unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        let buttons = &config.devices[0].buttons;
+        assert_eq!(buttons[&ButtonId::WheelModeShift], Action::ToggleSmartShift);
+        assert_eq!(
+            buttons[&ButtonId::Back],
+            Action::DpiShift {
+                dpi: 400
+            }
+        );
+        let Action::Gestures {
+            gestures
+        } = &buttons[&ButtonId::ThumbGesture]
+        else {
+            panic!("gestures not migrated");
+        };
+        assert_eq!(gestures[0].direction, GestureDirection::Up);
+        assert_eq!(
+            *gestures[0].action,
+            Action::Keypress {
+                keys: vec!["KEY_UP".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn test_current_file_is_left_alone() {
+        let mut table: Table =
+            toml::from_str(&toml::to_string(&Config::default()).unwrap()).unwrap();
+        let before = table.clone();
+        assert_eq!(migrate(&mut table).unwrap(), None);
+        assert_eq!(table, before);
+    }
+
+    #[test]
+    fn test_newer_or_invalid_version_is_refused() {
+        let mut newer: Table = toml::from_str("version = 99").unwrap();
+        let err = migrate(&mut newer).unwrap_err();
+        assert_eq!(
+            err.render_message(),
+            "Config was written by a newer version of logi-mx"
+        );
+
+        let mut invalid: Table = toml::from_str("version = \"1\"").unwrap();
+        assert!(migrate(&mut invalid).is_err());
+        let mut negative: Table = toml::from_str("version = -1").unwrap();
+        assert!(migrate(&mut negative).is_err());
+    }
+
+    #[test]
+    fn test_report_lists_changes() {
+        let report = MigrationReport {
+            from:    0,
+            to:      1,
+            changes: vec!["devices[0]: removed `thumbwheel`, which was never applied".to_string()],
+            backup:  Some(PathBuf::from("/home/u/.config/logi-mx.toml.v0.bak"))
+        };
+        assert_eq!(
+            report.to_string(),
+            "Config migrated from version 0 to 1 (previous file kept as \
+             /home/u/.config/logi-mx.toml.v0.bak)\n  - devices[0]: removed `thumbwheel`, which \
+             was never applied"
+        );
+    }
+}
@@ -1,13 +1,23 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
+pub mod apps;
+pub mod matching;
+pub mod migration;
+pub mod profiles;
 pub mod schema;
+pub mod validation;
 
 use std::path::{Path, PathBuf};
 
+pub use apps::*;
 use masterror::prelude::*;
+pub use matching::*;
+pub use migration::*;
+pub use profiles::*;
 pub use schema::*;
 use tracing::{debug, info};
+pub use validation::*;
 
 use crate::error::Result;
 
@@ -35,32 +45,87 @@ where
 }
 
 pub fn load_config() -> Result<Config> {
+    load_config_migrating().map(|(config, _)| config)
+}
+
+// Like `load_config`, also returning what was changed when the file
+// predated the current schema and had to be upgraded.
+pub fn load_config_migrating() -> Result<(Config, Option<MigrationReport>)> {
     let path = get_config_path()?;
 
     if !path.exists() {
         info!("Config file not found, creating default: {:?}", path);
         let config = Config::default();
         save_config(&config)?;
-        return Ok(config);
+        return Ok((config, None));
     }
 
-    load_config_from_path(&path)
+    upgrade_config_file(&path)
 }
 
+// Files are only rewritten by `load_config_migrating`; an imported file is
+// upgraded in memory.
 pub fn load_config_from_path(path: &Path) -> Result<Config> {
-    let content = std::fs::read_to_string(path)
-        .map_err(|e| AppError::internal("Failed to read config file").with_source(e))?;
-
-    let config: Config = toml::from_str(&content)
-        .map_err(|e| AppError::bad_request("Invalid config format").with_source(e))?;
+    let (config, report) = parse_config(&read_config_file(path)?)?;
+    if let Some(report) = report {
+        info!("{:?}: {}", path, report);
+    }
 
     debug!("Loaded config from {:?}", path);
     Ok(config)
 }
 
+pub fn parse_config(content: &str) -> Result<(Config, Option<MigrationReport>)> {
+    let mut table: toml::Table = toml::from_str(content).map_err(invalid_format)?;
+    let Some(report) = migrate(&mut table)? else {
+        // Parsed from the text again so errors point at a line.
+        let config = toml::from_str(content).map_err(invalid_format)?;
+        return Ok((config, None));
+    };
+
+    let config = table.try_into().map_err(invalid_format)?;
+    Ok((config, Some(report)))
+}
+
 pub fn save_config(config: &Config) -> Result<()> {
-    let path = get_config_path()?;
+    write_config_file(&get_config_path()?, config)
+}
 
+// A file that only lacks its version gets it added in place. Otherwise it is
+// rewritten and the file as it was is kept as `<name>.v<from>.bak`.
+fn upgrade_config_file(path: &Path) -> Result<(Config, Option<MigrationReport>)> {
+    let content = read_config_file(path)?;
+    let (config, report) = parse_config(&content)?;
+    let Some(mut report) = report else {
+        debug!("Loaded config from {:?}", path);
+        return Ok((config, None));
+    };
+
+    if report.changes.is_empty() {
+        std::fs::write(path, stamp_version(&content)?)
+            .map_err(|e| AppError::internal("Failed to write config file").with_source(e))?;
+        info!("{}", report);
+        return Ok((config, Some(report)));
+    }
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", report.from));
+    let backup = PathBuf::from(backup);
+    std::fs::copy(path, &backup)
+        .map_err(|e| AppError::internal("Failed to back up config file").with_source(e))?;
+    write_config_file(path, &config)?;
+
+    report.backup = Some(backup);
+    info!("{}", report);
+    Ok((config, Some(report)))
+}
+
+fn read_config_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| AppError::internal("Failed to read config file").with_source(e))
+}
+
+fn write_config_file(path: &Path, config: &Config) -> Result<()> {
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent)
             .map_err(|e| AppError::internal("Failed to create config directory").with_source(e))?;
@@ -69,18 +134,23 @@ pub fn save_config(config: &Config) -> Result<()> {
     let content = toml::to_string_pretty(config)
         .map_err(|e| AppError::internal("Failed to serialize config").with_source(e))?;
 
-    std::fs::write(&path, content)
+    std::fs::write(path, content)
         .map_err(|e| AppError::internal("Failed to write config file").with_source(e))?;
 
     info!("Saved config to {:?}", path);
     Ok(())
 }
 
+fn invalid_format(e: toml::de::Error) -> AppError {
+    AppError::bad_request("Invalid config format").with_source(e)
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
 
     use super::*;
+    use crate::devices::{BacklightConfig, BacklightMode, DisableableKey};
 
     #[test]
     fn test_default_config() {
@@ -143,6 +213,38 @@ mod tests {
         std::fs::remove_dir_all(&temp_dir).ok();
     }
 
+    #[test]
+    fn test_keyboard_config_parses() {
+        let config: Config = toml::from_str(
+            r#"
+            [[keyboards]]
+            name = "MX Keys"
+            fn_inversion = true
+            disabled_keys = ["capslock", "insert"]
+
+            [keyboards.backlight]
+            mode = "manual"
+            level = 4
+            "#
+        )
+        .unwrap();
+
+        assert!(config.devices.is_empty());
+        let keyboard = &config.keyboards[0];
+        assert_eq!(keyboard.fn_inversion, Some(true));
+        assert_eq!(
+            keyboard.backlight,
+            Some(BacklightConfig {
+                mode:  BacklightMode::Manual,
+                level: 4
+            })
+        );
+        assert_eq!(
+            keyboard.disabled_keys,
+            Some(vec![DisableableKey::CapsLock, DisableableKey::Insert])
+        );
+    }
+
     #[test]
     fn test_load_config_invalid_format() {
         let temp_path = std::env::temp_dir().join("invalid_logi_mx.toml");
@@ -154,6 +256,58 @@ mod tests {
         std::fs::remove_file(&temp_path).ok();
     }
 
+    #[test]
+    fn test_old_file_is_upgraded_and_backed_up() {
+        let temp_dir = std::env::temp_dir().join("logi-mx-test-migration");
+        let config_path = temp_dir.join("logi-mx.toml");
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let original = "[[devices]]\nname = \"MX Master 3S\"\n\n[devices.buttons]\nBack = \"ToggleSmartShift\"\n";
+        std::fs::write(&config_path, original).unwrap();
+
+        let (config, report) = upgrade_config_file(&config_path).unwrap();
+        let report = report.unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(report.changes.len(), 1);
+
+        let backup = temp_dir.join("logi-mx.toml.v0.bak");
+        assert_eq!(report.backup.as_deref(), Some(backup.as_path()));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), original);
+
+        // The rewritten file is current and loads without another upgrade.
+        let (reloaded, report) = upgrade_config_file(&config_path).unwrap();
+        assert!(report.is_none());
+        assert_eq!(reloaded.devices[0].buttons, config.devices[0].buttons);
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_unversioned_file_keeps_its_comments() {
+        let temp_dir = std::env::temp_dir().join("logi-mx-test-stamp");
+        let config_path = temp_dir.join("logi-mx.toml");
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let original =
+            "# Office mouse\n[[devices]]\nname = \"MX Master 3S\"\ndpi = 1600 # precise\n";
+        std::fs::write(&config_path, original).unwrap();
+
+        let (config, report) = upgrade_config_file(&config_path).unwrap();
+        let report = report.unwrap();
+        assert!(report.changes.is_empty());
+        assert_eq!(report.backup, None);
+        assert_eq!(config.devices[0].dpi, 1600);
+        assert!(!temp_dir.join("logi-mx.toml.v0.bak").exists());
+
+        let upgraded = std::fs::read_to_string(&config_path).unwrap();
+        assert_eq!(
+            upgraded,
+            format!("version = {}\n{}", CONFIG_VERSION, original)
+        );
+        let (_, report) = upgrade_config_file(&config_path).unwrap();
+        assert!(report.is_none());
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
     #[test]
     fn test_config_path_no_env() {
         // Mock environment with no variables set
@@ -0,0 +1,194 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::schema::{ButtonMode, Config, DeviceConfig};
+use crate::devices::{Action, ButtonId, HiResScrollConfig, SmartShiftConfig};
+
+// The entry's own settings; every other profile is layered on top of them.
+pub const DEFAULT_PROFILE: &str = "default";
+
+// `[devices.profiles.<name>]`. Settings left out keep the entry's own
+// values; buttons are merged, so a profile only lists the ones it changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpi: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smartshift: Option<SmartShiftConfig>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hiresscroll: Option<HiResScrollConfig>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub buttons: HashMap<ButtonId, Action>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub button_modes: HashMap<ButtonId, ButtonMode>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_rate_hz: Option<u16>
+}
+
+impl ProfileConfig {
+    fn apply_to(&self, device: &mut DeviceConfig) {
+        if let Some(dpi) = self.dpi {
+            device.dpi = dpi;
+        }
+        if let Some(smartshift) = self.smartshift {
+            device.smartshift = smartshift;
+        }
+        if let Some(hiresscroll) = self.hiresscroll {
+            device.hiresscroll = hiresscroll;
+        }
+        if self.report_rate_hz.is_some() {
+            device.report_rate_hz = self.report_rate_hz;
+        }
+        device.buttons.extend(
+            self.buttons
+                .iter()
+                .map(|(button, action)| (*button, action.clone()))
+        );
+        device.button_modes.extend(&self.button_modes);
+    }
+}
+
+impl DeviceConfig {
+    // `default` first, then the entry's profiles by name.
+    pub fn profile_names(&self) -> Vec<&str> {
+        std::iter::once(DEFAULT_PROFILE)
+            .chain(
+                self.profiles
+                    .keys()
+                    .map(String::as_str)
+                    .filter(|name| *name != DEFAULT_PROFILE)
+            )
+            .collect()
+    }
+
+    pub fn has_profile(&self, name: &str) -> bool {
+        name == DEFAULT_PROFILE || self.profiles.contains_key(name)
+    }
+
+    // The settings to apply while `name` is active. Entries without that
+    // profile stay on their own settings.
+    pub fn resolve_profile(&self, name: &str) -> DeviceConfig {
+        let mut resolved = DeviceConfig {
+            profiles: BTreeMap::new(),
+            ..self.clone()
+        };
+        if name != DEFAULT_PROFILE
+            && let Some(profile) = self.profiles.get(name)
+        {
+            profile.apply_to(&mut resolved);
+        }
+        resolved
+    }
+}
+
+impl Config {
+    // Every profile some entry defines, `default` included.
+    pub fn profile_names(&self) -> BTreeSet<&str> {
+        std::iter::once(DEFAULT_PROFILE)
+            .chain(self.devices.iter().flat_map(DeviceConfig::profile_names))
+            .collect()
+    }
+
+    pub fn has_profile(&self, name: &str) -> bool {
+        name == DEFAULT_PROFILE || self.devices.iter().any(|device| device.has_profile(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+        [[devices]]
+        name = "MX Master 3S"
+        dpi = 1000
+
+        [devices.buttons.Back]
+        type = "Keypress"
+        keys = ["KEY_BACK"]
+
+        [devices.profiles.gaming]
+        dpi = 3200
+        smartshift = { enabled = false, threshold = 20 }
+
+        [devices.profiles.gaming.buttons.Forward]
+        type = "DpiShift"
+        dpi = 400
+
+        [devices.profiles.design.buttons.Back]
+        type = "Keypress"
+        keys = ["KEY_LEFTCTRL", "KEY_Z"]
+
+        [[devices]]
+        name = "MX Anywhere 3S"
+
+        [devices.profiles.travel]
+        dpi = 800
+    "#;
+
+    #[test]
+    fn test_profile_overrides_entry_settings() {
+        let config: Config = toml::from_str(PROFILES).unwrap();
+        let device = &config.devices[0];
+
+        let gaming = device.resolve_profile("gaming");
+        assert_eq!(gaming.dpi, 3200);
+        assert!(!gaming.smartshift.enabled);
+        assert!(gaming.profiles.is_empty());
+        assert_eq!(
+            gaming.buttons[&ButtonId::Back],
+            device.buttons[&ButtonId::Back]
+        );
+        assert_eq!(
+            gaming.buttons[&ButtonId::Forward],
+            Action::DpiShift {
+                dpi: 400
+            }
+        );
+
+        let design = device.resolve_profile("design");
+        assert_eq!(design.dpi, 1000);
+        assert_eq!(
+            design.buttons[&ButtonId::Back],
+            Action::Keypress {
+                keys: vec!["KEY_LEFTCTRL".to_string(), "KEY_Z".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_profile_keeps_entry_settings() {
+        let config: Config = toml::from_str(PROFILES).unwrap();
+        let device = &config.devices[1];
+        assert!(!device.has_profile("gaming"));
+        assert_eq!(device.resolve_profile("gaming").dpi, device.dpi);
+        assert_eq!(
+            device.resolve_profile(DEFAULT_PROFILE).buttons,
+            device.buttons
+        );
+    }
+
+    #[test]
+    fn test_profile_names() {
+        let config: Config = toml::from_str(PROFILES).unwrap();
+        assert_eq!(
+            config.devices[0].profile_names(),
+            ["default", "design", "gaming"]
+        );
+        assert_eq!(
+            config.profile_names().into_iter().collect::<Vec<_>>(),
+            ["default", "design", "gaming", "travel"]
+        );
+        assert!(config.has_profile("travel"));
+        assert!(!config.has_profile("office"));
+    }
+}
@@ -1,24 +1,47 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde::{Deserialize, Serialize};
 
+use super::{
+    apps::{AppRule, FocusConfig},
+    matching::DeviceMatch,
+    migration::CONFIG_VERSION,
+    profiles::ProfileConfig
+};
 use crate::devices::{
-    Action, ButtonId, GestureDirection, GestureMode, HiResScrollConfig, SmartShiftConfig
+    Action, BacklightConfig, ButtonId, DisableableKey, GestureDirection, GestureMode,
+    HiResScrollConfig, SmartShiftConfig
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    // Schema version; older files are upgraded by `migration::migrate`.
+    #[serde(default)]
+    pub version: u32,
+
     #[serde(default)]
-    pub devices: Vec<DeviceConfig>
+    pub devices: Vec<DeviceConfig>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keyboards: Vec<KeyboardConfig>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub apps: Vec<AppRule>,
+
+    #[serde(default, skip_serializing_if = "FocusConfig::is_default")]
+    pub focus: FocusConfig
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceConfig {
     pub name: String,
 
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub matcher: Option<DeviceMatch>,
+
     #[serde(default = "default_dpi")]
     pub dpi: u16,
 
@@ -29,7 +52,47 @@ pub struct DeviceConfig {
     pub hiresscroll: HiResScrollConfig,
 
     #[serde(default)]
-    pub buttons: HashMap<ButtonId, Action>
+    pub buttons: HashMap<ButtonId, Action>,
+
+    #[serde(default)]
+    pub button_modes: HashMap<ButtonId, ButtonMode>,
+
+    #[serde(default)]
+    pub report_rate_hz: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ProfileConfig>
+}
+
+// Settings left out are not touched on the keyboard.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyboardConfig {
+    pub name: String,
+
+    #[serde(default)]
+    pub backlight: Option<BacklightConfig>,
+
+    #[serde(default)]
+    pub fn_inversion: Option<bool>,
+
+    #[serde(default)]
+    pub disabled_keys: Option<Vec<DisableableKey>>
+}
+
+// `Persistent` buttons are written to the device (0x1C00) and keep working
+// without the daemon; only key presses and `None` can be stored that way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ButtonMode {
+    #[default]
+    Daemon,
+    Persistent
+}
+
+impl DeviceConfig {
+    pub fn button_mode(&self, button: ButtonId) -> ButtonMode {
+        self.button_modes.get(&button).copied().unwrap_or_default()
+    }
 }
 
 fn default_dpi() -> u16 {
@@ -39,7 +102,11 @@ fn default_dpi() -> u16 {
 impl Default for Config {
     fn default() -> Self {
         Self {
-            devices: vec![DeviceConfig::default()]
+            version:   CONFIG_VERSION,
+            devices:   vec![DeviceConfig::default()],
+            keyboards: Vec::new(),
+            apps:      Vec::new(),
+            focus:     FocusConfig::default()
         }
     }
 }
@@ -95,6 +162,7 @@ impl Default for DeviceConfig {
 
         Self {
             name: "MX Master 3S".to_string(),
+            matcher: None,
             dpi: 1000,
             smartshift: SmartShiftConfig {
                 enabled:   true,
@@ -104,7 +172,10 @@ impl Default for DeviceConfig {
                 enabled:  true,
                 inverted: false
             },
-            buttons
+            buttons,
+            button_modes: HashMap::new(),
+            report_rate_hz: None,
+            profiles: BTreeMap::new()
         }
     }
 }
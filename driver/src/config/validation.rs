@@ -0,0 +1,744 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::Path
+};
+
+use masterror::prelude::*;
+use toml::{
+    Table,
+    de::{DeTable, DeValue}
+};
+
+use super::{
+    apps::{AppRule, FocusBackend},
+    migration::migrate,
+    profiles::DEFAULT_PROFILE,
+    schema::{ButtonMode, Config, DeviceConfig}
+};
+use crate::{
+    devices::{
+        Action, ButtonId, DeviceSpec, DpiRange, PersistentAction, SmartShiftConfig, registry
+    },
+    error::Result
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        })
+    }
+}
+
+// 1-based, counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line:   usize,
+    pub column: usize
+}
+
+// One problem found in a config. `path` names the offending value as in
+// `devices[0].buttons.Back`; `location` is where it sits in the file, when
+// checked from its text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub path:     String,
+    pub message:  String,
+    pub location: Option<Location>
+}
+
+impl Diagnostic {
+    // Prefixed with the file, as in `logi-mx.toml:5:7: error: ...`.
+    pub fn in_file(&self, file: &Path) -> String {
+        match self.location {
+            Some(_) => format!("{}:{}", file.display(), self),
+            None => format!("{}: {}", file.display(), self)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = self.location {
+            write!(f, "{}:{}: ", location.line, location.column)?;
+        }
+        write!(f, "{}: ", self.severity)?;
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Key(String),
+    Index(usize)
+}
+
+fn render(path: &[Segment]) -> String {
+    let mut rendered = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(key) if rendered.is_empty() => rendered.push_str(key),
+            Segment::Key(key) => {
+                rendered.push('.');
+                rendered.push_str(key);
+            }
+            Segment::Index(index) => rendered.push_str(&format!("[{}]", index))
+        }
+    }
+    rendered
+}
+
+fn key(key: &str) -> Segment {
+    Segment::Key(key.to_string())
+}
+
+// Buttons are keyed by their variant name in the file.
+fn button_key(button: ButtonId) -> Segment {
+    Segment::Key(format!("{:?}", button))
+}
+
+struct Finding {
+    severity: Severity,
+    path:     Vec<Segment>,
+    message:  String
+}
+
+#[derive(Default)]
+struct Checker {
+    findings: Vec<Finding>,
+    profiles: BTreeSet<String>
+}
+
+impl Checker {
+    fn error(&mut self, path: &[Segment], message: impl Into<String>) {
+        self.push(Severity::Error, path, message.into());
+    }
+
+    fn warning(&mut self, path: &[Segment], message: impl Into<String>) {
+        self.push(Severity::Warning, path, message.into());
+    }
+
+    fn push(&mut self, severity: Severity, path: &[Segment], message: String) {
+        self.findings.push(Finding {
+            severity,
+            path: path.to_vec(),
+            message
+        });
+    }
+
+    fn device(&mut self, index: usize, device: &DeviceConfig) {
+        let path = [key("devices"), Segment::Index(index)];
+        let spec = spec_for(device);
+        let dpi = DpiLimits::for_spec(spec);
+
+        dpi.check(self, &[&path[..], &[key("dpi")]].concat(), device.dpi);
+        self.smartshift(&path, device.smartshift);
+        self.buttons(&path, spec, &dpi, &device.buttons, |button| {
+            device.button_mode(button)
+        });
+        self.button_modes(&path, spec, &device.button_modes);
+
+        for (name, profile) in &device.profiles {
+            let profile_path = [&path[..], &[key("profiles"), key(name)]].concat();
+            if name == DEFAULT_PROFILE {
+                self.warning(
+                    &profile_path,
+                    "the entry's own settings are the default profile, so this one is never used"
+                );
+                continue;
+            }
+
+            if let Some(value) = profile.dpi {
+                dpi.check(self, &[&profile_path[..], &[key("dpi")]].concat(), value);
+            }
+            if let Some(smartshift) = profile.smartshift {
+                self.smartshift(&profile_path, smartshift);
+            }
+            self.buttons(&profile_path, spec, &dpi, &profile.buttons, |button| {
+                profile
+                    .button_modes
+                    .get(&button)
+                    .copied()
+                    .unwrap_or_else(|| device.button_mode(button))
+            });
+            self.button_modes(&profile_path, spec, &profile.button_modes);
+        }
+    }
+
+    // Rules apply to every device, so their buttons are checked against no
+    // particular model.
+    fn app(&mut self, index: usize, rule: &AppRule) {
+        let path = [key("apps"), Segment::Index(index)];
+        if rule.app.is_empty() {
+            self.error(
+                &[&path[..], &[key("app")]].concat(),
+                "an empty pattern matches no application"
+            );
+        }
+
+        match &rule.profile {
+            Some(profile) if !self.profiles.contains(profile) => self.error(
+                &[&path[..], &[key("profile")]].concat(),
+                format!("no device defines a profile named {}", profile)
+            ),
+            None if rule.buttons.is_empty() => {
+                self.warning(&path, "the rule sets neither a profile nor buttons")
+            }
+            _ => {}
+        }
+
+        self.buttons(
+            &path,
+            None,
+            &DpiLimits::for_spec(None),
+            &rule.buttons,
+            |_| ButtonMode::Daemon
+        );
+    }
+
+    fn smartshift(&mut self, path: &[Segment], smartshift: SmartShiftConfig) {
+        if smartshift.enabled && smartshift.threshold == 0 {
+            self.warning(
+                &[path, &[key("smartshift"), key("threshold")]].concat(),
+                "SmartShift is enabled with threshold 0, which keeps the wheel in ratchet mode; \
+                 use 1 to 50"
+            );
+        }
+    }
+
+    fn buttons(
+        &mut self,
+        path: &[Segment],
+        spec: Option<&DeviceSpec>,
+        dpi: &DpiLimits,
+        buttons: &HashMap<ButtonId, Action>,
+        mode: impl Fn(ButtonId) -> ButtonMode
+    ) {
+        let mut buttons: Vec<_> = buttons.iter().collect();
+        buttons.sort_by_key(|(button, _)| format!("{:?}", button));
+        for (button, action) in buttons {
+            let button_path = [path, &[key("buttons"), button_key(*button)]].concat();
+            self.button_exists(&button_path, spec, *button);
+            self.action(&button_path, action, dpi);
+
+            if mode(*button) == ButtonMode::Persistent
+                && let Err(e) = PersistentAction::from_action(action)
+            {
+                self.error(
+                    &button_path,
+                    format!("cannot be stored on the device: {}", e.render_message())
+                );
+            }
+        }
+    }
+
+    fn button_modes(
+        &mut self,
+        path: &[Segment],
+        spec: Option<&DeviceSpec>,
+        modes: &HashMap<ButtonId, ButtonMode>
+    ) {
+        let mut modes: Vec<_> = modes.keys().collect();
+        modes.sort_by_key(|button| format!("{:?}", button));
+        for button in modes {
+            self.button_exists(
+                &[path, &[key("button_modes"), button_key(*button)]].concat(),
+                spec,
+                *button
+            );
+        }
+    }
+
+    fn button_exists(&mut self, path: &[Segment], spec: Option<&DeviceSpec>, button: ButtonId) {
+        if let Some(spec) = spec
+            && spec.control_id(button).is_none()
+        {
+            self.error(path, format!("{} has no {:?} button", spec.name, button));
+        }
+    }
+
+    fn action(&mut self, path: &[Segment], action: &Action, dpi: &DpiLimits) {
+        match action {
+            Action::Gestures {
+                gestures
+            } => {
+                for (index, gesture) in gestures.iter().enumerate() {
+                    let gesture_path = [path, &[key("gestures"), Segment::Index(index)]].concat();
+                    if gestures[..index]
+                        .iter()
+                        .any(|earlier| earlier.direction == gesture.direction)
+                    {
+                        self.error(
+                            &[&gesture_path[..], &[key("direction")]].concat(),
+                            format!(
+                                "a gesture for direction {:?} is already defined; only the \
+                                 first one fires",
+                                gesture.direction
+                            )
+                        );
+                    }
+                    self.action(
+                        &[&gesture_path[..], &[key("action")]].concat(),
+                        &gesture.action,
+                        dpi
+                    );
+                }
+            }
+            Action::DpiCycle {
+                steps
+            } => {
+                if steps.is_empty() {
+                    self.warning(path, "DPI cycle has no steps");
+                }
+                for (index, step) in steps.iter().enumerate() {
+                    dpi.check(
+                        self,
+                        &[path, &[key("steps"), Segment::Index(index)]].concat(),
+                        *step
+                    );
+                }
+            }
+            Action::DpiShift {
+                dpi: value
+            } => dpi.check(self, &[path, &[key("dpi")]].concat(), *value),
+            Action::SwitchProfile {
+                profile
+            } if !self.profiles.contains(profile) => self.error(
+                &[path, &[key("profile")]].concat(),
+                format!("no device defines a profile named {}", profile)
+            ),
+            _ => {}
+        }
+    }
+}
+
+// The model's own range when it is known, otherwise what any known model
+// accepts.
+struct DpiLimits {
+    range:  DpiRange,
+    device: Option<String>
+}
+
+impl DpiLimits {
+    fn for_spec(spec: Option<&DeviceSpec>) -> Self {
+        if let Some((spec, range)) =
+            spec.and_then(|spec| spec.dpi.as_ref().map(|range| (spec, range)))
+        {
+            return Self {
+                range:  range.clone(),
+                device: Some(spec.name.clone())
+            };
+        }
+
+        let ranges = registry()
+            .devices()
+            .iter()
+            .filter_map(|spec| spec.dpi.as_ref());
+        let min = ranges.clone().map(|range| range.min).min().unwrap_or(1);
+        let max = ranges.map(|range| range.max).max().unwrap_or(u16::MAX);
+        Self {
+            range:  DpiRange {
+                min,
+                max,
+                step: 0,
+                values: Vec::new()
+            },
+            device: None
+        }
+    }
+
+    fn check(&self, checker: &mut Checker, path: &[Segment], dpi: u16) {
+        if self.range.contains(dpi) {
+            return;
+        }
+        let target = match &self.device {
+            Some(name) => name.clone(),
+            None => "any known device".to_string()
+        };
+        let message = if !(self.range.min..=self.range.max).contains(&dpi) {
+            format!(
+                "DPI {} is outside {}-{} supported by {}",
+                dpi, self.range.min, self.range.max, target
+            )
+        } else if !self.range.values.is_empty() {
+            format!(
+                "DPI {} is not one of {:?} supported by {}",
+                dpi, self.range.values, target
+            )
+        } else {
+            format!(
+                "DPI {} is not a multiple of {} from {} as {} requires",
+                dpi, self.range.step, self.range.min, target
+            )
+        };
+        checker.error(path, message);
+    }
+}
+
+// The registry entry an entry configures: from the model or wireless id it
+// matches, else its name. Entries matched only by serial, slot or a pattern
+// can apply to any model and are checked against none.
+fn spec_for(device: &DeviceConfig) -> Option<&'static DeviceSpec> {
+    let registry = registry();
+    let Some(matcher) = &device.matcher else {
+        return registry.by_name(&device.name);
+    };
+
+    if let Some(model_id) = matcher.model_id {
+        return registry.by_model_ids(&[model_id]);
+    }
+    if let Some(wpid) = matcher.wpid {
+        return registry.by_wpid(wpid);
+    }
+    matcher
+        .name
+        .as_deref()
+        .filter(|name| !name.contains(['*', '?']))
+        .and_then(|name| registry.by_name(name))
+}
+
+fn findings(config: &Config) -> Vec<Finding> {
+    let mut checker = Checker {
+        profiles: config
+            .profile_names()
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    };
+    for (index, device) in config.devices.iter().enumerate() {
+        checker.device(index, device);
+    }
+    for (index, rule) in config.apps.iter().enumerate() {
+        checker.app(index, rule);
+    }
+    if !config.apps.is_empty() && config.focus.backend == FocusBackend::None {
+        checker.warning(
+            &[key("focus"), key("backend")],
+            "the rules in `apps` never apply without a window watcher"
+        );
+    }
+    checker.findings
+}
+
+pub fn validate(config: &Config) -> Vec<Diagnostic> {
+    findings(config)
+        .into_iter()
+        .map(|finding| Diagnostic {
+            severity: finding.severity,
+            path:     render(&finding.path),
+            message:  finding.message,
+            location: None
+        })
+        .collect()
+}
+
+// Parses, upgrades and validates config text. Syntax and schema errors are
+// reported like the rest, so this never fails. Values a migration moved
+// have no location.
+pub fn check_config(content: &str) -> Vec<Diagnostic> {
+    let config = match deserialize(content) {
+        Ok(config) => config,
+        Err(diagnostic) => return vec![diagnostic]
+    };
+
+    let root = DeTable::parse(content)
+        .ok()
+        .map(|table| DeValue::Table(table.into_inner()));
+    let mut diagnostics: Vec<_> = findings(&config)
+        .into_iter()
+        .map(|finding| Diagnostic {
+            severity: finding.severity,
+            location: root
+                .as_ref()
+                .and_then(|root| locate(content, root, &finding.path)),
+            path:     render(&finding.path),
+            message:  finding.message
+        })
+        .collect();
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.location, diagnostic.severity));
+    diagnostics
+}
+
+pub fn check_config_file(path: &Path) -> Result<Vec<Diagnostic>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| AppError::internal("Failed to read config file").with_source(e))?;
+    Ok(check_config(&content))
+}
+
+// Stamped files are read from the text again so errors keep their spans;
+// only a file the migration rewrote is read from the upgraded table.
+fn deserialize(content: &str) -> std::result::Result<Config, Diagnostic> {
+    let syntax = |e: toml::de::Error| Diagnostic {
+        severity: Severity::Error,
+        path:     String::new(),
+        message:  e.message().to_string(),
+        location: e.span().map(|span| location(content, span.start))
+    };
+
+    let mut table: Table = toml::from_str(content).map_err(syntax)?;
+    let report = migrate(&mut table).map_err(|e| Diagnostic {
+        severity: Severity::Error,
+        path:     "version".to_string(),
+        message:  e.render_message().to_string(),
+        location: None
+    })?;
+
+    match report {
+        Some(report) if !report.changes.is_empty() => table.try_into().map_err(syntax),
+        _ => toml::from_str(content).map_err(syntax)
+    }
+}
+
+fn locate(content: &str, root: &DeValue<'_>, path: &[Segment]) -> Option<Location> {
+    let mut value = None;
+    for segment in path {
+        let parent = value.map_or(root, |spanned: &toml::Spanned<DeValue<'_>>| {
+            spanned.get_ref()
+        });
+        value = Some(match segment {
+            Segment::Key(key) => parent.get(key.as_str())?,
+            Segment::Index(index) => parent.get(*index)?
+        });
+    }
+    value.map(|spanned| location(content, spanned.span().start))
+}
+
+fn location(content: &str, offset: usize) -> Location {
+    let before = &content[..offset.min(content.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    Location {
+        line:   before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBLEMS: &str = r#"version = 1
+
+[[devices]]
+name = "MX Master 3S"
+dpi = 50000
+
+[devices.smartshift]
+enabled = true
+threshold = 0
+
+[devices.buttons.ThumbGesture]
+type = "Gestures"
+gestures = [
+    { direction = "Up", mode = "OnRelease", action = { type = "Keypress", keys = ["KEY_UP"] } },
+    { direction = "Up", mode = "OnPress", action = { type = "DpiShift", dpi = 425 } },
+]
+
+[[devices]]
+name = "MX Anywhere 3S"
+
+[devices.buttons.ThumbGesture]
+type = "ToggleSmartShift"
+"#;
+
+    #[test]
+    fn test_diagnostic_in_file() {
+        let mut diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            path:     "devices[0].dpi".to_string(),
+            message:  "DPI 425".to_string(),
+            location: None
+        };
+        let file = Path::new("logi-mx.toml");
+        assert_eq!(
+            diagnostic.in_file(file),
+            "logi-mx.toml: warning: devices[0].dpi: DPI 425"
+        );
+        diagnostic.location = Some(Location {
+            line:   5,
+            column: 7
+        });
+        assert_eq!(
+            diagnostic.in_file(file),
+            "logi-mx.toml:5:7: warning: devices[0].dpi: DPI 425"
+        );
+    }
+
+    fn summary(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_problems_are_reported_with_locations() {
+        assert_eq!(
+            summary(&check_config(PROBLEMS)),
+            [
+                "5:7: error: devices[0].dpi: DPI 50000 is outside 200-8000 supported by MX \
+                 Master 3S",
+                "9:13: warning: devices[0].smartshift.threshold: SmartShift is enabled with \
+                 threshold 0, which keeps the wheel in ratchet mode; use 1 to 50",
+                "15:19: error: devices[0].buttons.ThumbGesture.gestures[1].direction: a gesture \
+                 for direction Up is already defined; only the first one fires",
+                "15:79: error: devices[0].buttons.ThumbGesture.gestures[1].action.dpi: DPI 425 \
+                 is not a multiple of 50 from 200 as MX Master 3S requires",
+                "21:1: error: devices[1].buttons.ThumbGesture: MX Anywhere 3S has no \
+                 ThumbGesture button"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_config_is_clean() {
+        assert_eq!(validate(&Config::default()), []);
+        let content = toml::to_string_pretty(&Config::default()).unwrap();
+        assert_eq!(check_config(&content), []);
+    }
+
+    #[test]
+    fn test_unknown_devices_use_the_widest_range() {
+        let config = r#"
+            [[devices]]
+            name = "Some other mouse"
+            dpi = 50000
+
+            [devices.buttons.Forward]
+            type = "DpiCycle"
+            steps = [425]
+
+            [devices.buttons.ThumbGesture]
+            type = "DpiCycle"
+            steps = []
+        "#;
+        assert_eq!(
+            summary(&check_config(config)),
+            [
+                "4:19: error: devices[0].dpi: DPI 50000 is outside 200-8000 supported by any \
+                 known device",
+                "10:13: warning: devices[0].buttons.ThumbGesture: DPI cycle has no steps"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_persistent_buttons_need_storable_actions() {
+        let config = r#"
+            [[devices]]
+            name = "MX Master 3S"
+
+            [devices.buttons.MiddleClick]
+            type = "ToggleSmartShift"
+
+            [devices.button_modes]
+            MiddleClick = "persistent"
+        "#;
+        let diagnostics = check_config(config);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, "devices[0].buttons.MiddleClick");
+        assert!(
+            diagnostics[0]
+                .message
+                .starts_with("cannot be stored on the device")
+        );
+    }
+
+    #[test]
+    fn test_profiles_are_checked() {
+        let config = r#"
+            [[devices]]
+            name = "MX Master 3S"
+
+            [devices.buttons.Back]
+            type = "SwitchProfile"
+            profile = "gaming"
+
+            [devices.buttons.Forward]
+            type = "SwitchProfile"
+            profile = "gamming"
+
+            [devices.profiles.gaming]
+            dpi = 9000
+
+            [devices.profiles.default]
+            dpi = 800
+        "#;
+        assert_eq!(
+            summary(&check_config(config)),
+            [
+                "11:23: error: devices[0].buttons.Forward.profile: no device defines a profile \
+                 named gamming",
+                "14:19: error: devices[0].profiles.gaming.dpi: DPI 9000 is outside 200-8000 \
+                 supported by MX Master 3S",
+                "16:13: warning: devices[0].profiles.default: the entry's own settings are the \
+                 default profile, so this one is never used"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_app_rules_are_checked() {
+        let config = r#"
+            [[devices]]
+            name = "MX Master 3S"
+
+            [[apps]]
+            app = "firefox"
+            profile = "browsing"
+
+            [[apps]]
+            app = "foot"
+
+            [[apps]]
+            app = "org.inkscape.Inkscape"
+
+            [apps.buttons.Back]
+            type = "DpiShift"
+            dpi = 9000
+
+            [focus]
+            backend = "none"
+        "#;
+        assert_eq!(
+            summary(&check_config(config)),
+            [
+                "7:23: error: apps[0].profile: no device defines a profile named browsing",
+                "9:13: warning: apps[1]: the rule sets neither a profile nor buttons",
+                "17:19: error: apps[2].buttons.Back.dpi: DPI 9000 is outside 200-8000 supported \
+                 by any known device",
+                "20:23: warning: focus.backend: the rules in `apps` never apply without a window \
+                 watcher"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_syntax_and_version_errors() {
+        let diagnostics = check_config("[[devices]]\nname = \"MX\"\ndpi = \"high\"\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[0].location,
+            Some(Location {
+                line:   3,
+                column: 7
+            })
+        );
+
+        let diagnostics = check_config("version = 99\n");
+        assert_eq!(diagnostics[0].path, "version");
+    }
+}
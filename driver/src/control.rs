@@ -0,0 +1,207 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// The daemon's control socket. Each connection carries one request and one
+// response, both a single line of JSON.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    time::Duration
+};
+
+use masterror::{field, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+const CONTROL_SOCKET_NAME: &str = "logi-mx.sock";
+
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum ControlRequest {
+    ActiveProfile,
+    UseProfile { name: String }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum ControlResponse {
+    // `app` is the `[[apps]]` rule in effect for the focused window, whose
+    // settings apply over the active profile.
+    Profile {
+        active: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        app:    Option<String>
+    },
+    Error {
+        message: String
+    }
+}
+
+impl ControlRequest {
+    pub fn parse(line: &str) -> Result<Self> {
+        serde_json::from_str(line)
+            .map_err(|e| AppError::bad_request("Invalid control request").with_source(e))
+    }
+}
+
+impl ControlResponse {
+    pub fn to_line(&self) -> String {
+        // Both variants hold plain strings, which always serialize.
+        let mut line = serde_json::to_string(self).unwrap_or_default();
+        line.push('\n');
+        line
+    }
+}
+
+// In the user's runtime dir. Without one it falls back to the shared temp
+// dir under a per-user name; the daemon then only answers its own user.
+pub fn control_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join(CONTROL_SOCKET_NAME),
+        None => {
+            // SAFETY: getuid has no preconditions and cannot fail.
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir().join(format!("logi-mx-{}.sock", uid))
+        }
+    }
+}
+
+// An `Error` response from the daemon comes back as `Err`.
+pub fn send_control_request(path: &Path, request: &ControlRequest) -> Result<ControlResponse> {
+    let mut stream = UnixStream::connect(path).map_err(|e| {
+        AppError::not_found("logi-mx-daemon is not running")
+            .with_source(e)
+            .with_field(field::str("socket", path.display().to_string()))
+    })?;
+    stream
+        .set_read_timeout(Some(CONTROL_TIMEOUT))
+        .map_err(|e| AppError::internal("Failed to configure control socket").with_source(e))?;
+
+    let mut line = serde_json::to_string(request)
+        .map_err(|e| AppError::internal("Failed to encode control request").with_source(e))?;
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .map_err(|e| AppError::internal("Failed to send control request").with_source(e))?;
+
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .map_err(|e| AppError::internal("No reply from logi-mx-daemon").with_source(e))?;
+    let response: ControlResponse = serde_json::from_str(&reply)
+        .map_err(|e| AppError::internal("Invalid reply from logi-mx-daemon").with_source(e))?;
+
+    match response {
+        ControlResponse::Error {
+            message
+        } => Err(AppError::bad_request(message)),
+        response => Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    // Answers a single request the way the daemon would.
+    fn stand_in_daemon(
+        path: &Path,
+        respond: impl FnOnce(ControlRequest) -> ControlResponse + Send + 'static
+    ) -> std::thread::JoinHandle<()> {
+        std::fs::remove_file(path).ok();
+        let listener = UnixListener::bind(path).unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            let response = respond(ControlRequest::parse(&line).unwrap());
+            (&stream).write_all(response.to_line().as_bytes()).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_request_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("logi-mx-control-{}.sock", std::process::id()));
+        let daemon = stand_in_daemon(&path, |request| {
+            assert_eq!(
+                request,
+                ControlRequest::UseProfile {
+                    name: "gaming".to_string()
+                }
+            );
+            ControlResponse::Profile {
+                active: "gaming".to_string(),
+                app:    None
+            }
+        });
+
+        let response = send_control_request(
+            &path,
+            &ControlRequest::UseProfile {
+                name: "gaming".to_string()
+            }
+        )
+        .unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Profile {
+                active: "gaming".to_string(),
+                app:    None
+            }
+        );
+        daemon.join().unwrap();
+
+        let daemon = stand_in_daemon(&path, |_| ControlResponse::Error {
+            message: "Unknown profile".to_string()
+        });
+        let err = send_control_request(&path, &ControlRequest::ActiveProfile).unwrap_err();
+        assert_eq!(err.render_message(), "Unknown profile");
+        daemon.join().unwrap();
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_missing_daemon() {
+        let path = std::env::temp_dir().join("logi-mx-control-missing.sock");
+        let err = send_control_request(&path, &ControlRequest::ActiveProfile).unwrap_err();
+        assert_eq!(err.render_message(), "logi-mx-daemon is not running");
+    }
+
+    #[test]
+    fn test_wire_format() {
+        assert_eq!(
+            ControlRequest::parse(r#"{"request":"use_profile","name":"design"}"#).unwrap(),
+            ControlRequest::UseProfile {
+                name: "design".to_string()
+            }
+        );
+        assert_eq!(
+            ControlResponse::Profile {
+                active: "default".to_string(),
+                app:    None
+            }
+            .to_line(),
+            "{\"response\":\"profile\",\"active\":\"default\"}\n"
+        );
+        assert_eq!(
+            serde_json::from_str::<ControlResponse>(
+                r#"{"response":"profile","active":"default","app":"firefox*"}"#
+            )
+            .unwrap(),
+            ControlResponse::Profile {
+                active: "default".to_string(),
+                app:    Some("firefox*".to_string())
+            }
+        );
+        assert!(ControlRequest::parse("use design").is_err());
+    }
+}
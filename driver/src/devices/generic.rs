@@ -0,0 +1,1770 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+
+use masterror::{field, prelude::*};
+use tracing::{debug, info};
+
+use super::{
+    identity::{ConnectionType, DeviceIdentity},
+    receiver::parse_link_change,
+    registry::{DeviceKind, DeviceSpec, DpiRange, Quirk, registry},
+    remap::*,
+    traits::*
+};
+use crate::{
+    error::{DeviceErrorKind, Result},
+    firmware::{self, DeviceFirmware},
+    hidpp::{
+        BatteryFunction, BatteryVoltageFunction, ChangeHostFunction, DEVICE_INDEX_RECEIVER,
+        DpiFunction, ExtendedDpiFunction, ExtendedReportRateFunction, FEATURE_ADJUSTABLE_DPI,
+        FEATURE_BATTERY_STATUS, FEATURE_BATTERY_VOLTAGE, FEATURE_CHANGE_HOST, FEATURE_DEVICE_NAME,
+        FEATURE_EXTENDED_ADJUSTABLE_DPI, FEATURE_EXTENDED_REPORT_RATE, FEATURE_HIRES_WHEEL,
+        FEATURE_HOSTS_INFO, FEATURE_PERSISTENT_REMAPPABLE_ACTION, FEATURE_REPORT_RATE,
+        FEATURE_REPROG_CONTROLS, FEATURE_SMART_SHIFT, FEATURE_UNIFIED_BATTERY,
+        FEATURE_WIRELESS_DEVICE_STATUS, HidppDevice, HidppPacket, HiresWheelFunction,
+        HostsInfoFunction, PersistentRemappableActionFunction, ReportRateFunction,
+        ReprogControlsFunction, SmartShiftFunction, UnifiedBatteryFunction
+    },
+    onboard::OnboardProfiles
+};
+
+pub const VID_LOGITECH: u16 = 0x046D;
+pub const PID_BOLT_RECEIVER: u16 = 0xC548;
+pub const PID_UNIFYING_RECEIVERS: [u16; 2] = [0xC52B, 0xC532];
+pub const RECEIVER_SLOTS: std::ops::RangeInclusive<u8> = 1..=6;
+
+const REPORTING_FLAG_DIVERT: u8 = 0x01;
+const REPORTING_FLAG_DIVERT_VALID: u8 = 0x02;
+const DIVERTED_BUTTONS_EVENT: u8 = 0x00;
+const WIRELESS_STATUS_EVENT: u8 = 0x00;
+const WIRELESS_STATUS_RECONNECTION: u8 = 0x01;
+const WIRELESS_REQUEST_RECONFIGURE: u8 = 0x01;
+const HOST_NAME_CHUNK_SIZE: usize = 14;
+const REMAP_ALL_HOSTS: u8 = 0xFF;
+const BATTERY_VOLTAGE_CHARGING: u8 = 0x80;
+const DPI_LIST_STEP_MARKER: u16 = 0xE000;
+const WHEEL_FLAG_INVERT: u8 = 0x08;
+const WHEEL_FLAG_RATCHET_SWITCH: u8 = 0x04;
+
+// Discharge curve of a single Li-ion cell, in millivolts and percent.
+const BATTERY_VOLTAGE_LEVELS: [(u16, u8); 13] = [
+    (4186, 100),
+    (4067, 90),
+    (3989, 80),
+    (3922, 70),
+    (3859, 60),
+    (3811, 50),
+    (3778, 40),
+    (3751, 30),
+    (3717, 20),
+    (3671, 10),
+    (3646, 5),
+    (3579, 2),
+    (3500, 0)
+];
+
+const EXTENDED_REPORT_RATES_HZ: [u16; 7] = [125, 250, 500, 1000, 2000, 4000, 8000];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryFeature {
+    Unified(u8),
+    Status(u8),
+    Voltage(u8)
+}
+
+impl BatteryFeature {
+    pub fn discover(hidpp: &mut HidppDevice, spec: Option<&DeviceSpec>) -> Option<Self> {
+        let mut find = |feature_id| hidpp.get_feature_index(feature_id).ok();
+
+        let legacy_battery = spec.is_some_and(|s| s.has_quirk(Quirk::LegacyBattery));
+        let unified = if legacy_battery {
+            None
+        } else {
+            find(FEATURE_UNIFIED_BATTERY)
+        };
+        unified
+            .map(BatteryFeature::Unified)
+            .or_else(|| find(FEATURE_BATTERY_STATUS).map(BatteryFeature::Status))
+            .or_else(|| find(FEATURE_BATTERY_VOLTAGE).map(BatteryFeature::Voltage))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DpiFeature {
+    Adjustable(u8),
+    Extended(u8)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportRateFeature {
+    Legacy(u8),
+    Extended(u8)
+}
+
+// Feature indices found at open time. Where several features cover the same
+// function the newest one the device offers is kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseFeatures {
+    pub device_name:      Option<u8>,
+    pub battery:          Option<BatteryFeature>,
+    pub dpi:              Option<DpiFeature>,
+    pub smart_shift:      Option<u8>,
+    pub hires_wheel:      Option<u8>,
+    pub reprog_controls:  Option<u8>,
+    pub persistent_remap: Option<u8>,
+    pub wireless_status:  Option<u8>,
+    pub report_rate:      Option<ReportRateFeature>,
+    pub change_host:      Option<u8>,
+    pub hosts_info:       Option<u8>
+}
+
+impl MouseFeatures {
+    pub fn discover(hidpp: &mut HidppDevice, spec: Option<&DeviceSpec>) -> Self {
+        let battery = BatteryFeature::discover(hidpp, spec);
+        let mut find = |feature_id| hidpp.get_feature_index(feature_id).ok();
+
+        let dpi = find(FEATURE_EXTENDED_ADJUSTABLE_DPI)
+            .map(DpiFeature::Extended)
+            .or_else(|| find(FEATURE_ADJUSTABLE_DPI).map(DpiFeature::Adjustable));
+
+        let report_rate = find(FEATURE_EXTENDED_REPORT_RATE)
+            .map(ReportRateFeature::Extended)
+            .or_else(|| find(FEATURE_REPORT_RATE).map(ReportRateFeature::Legacy));
+
+        Self {
+            device_name: find(FEATURE_DEVICE_NAME),
+            battery,
+            dpi,
+            smart_shift: find(FEATURE_SMART_SHIFT),
+            hires_wheel: find(FEATURE_HIRES_WHEEL),
+            reprog_controls: find(FEATURE_REPROG_CONTROLS),
+            persistent_remap: find(FEATURE_PERSISTENT_REMAPPABLE_ACTION),
+            wireless_status: find(FEATURE_WIRELESS_DEVICE_STATUS),
+            report_rate,
+            change_host: find(FEATURE_CHANGE_HOST),
+            hosts_info: find(FEATURE_HOSTS_INFO)
+        }
+    }
+}
+
+impl BatteryFeature {
+    pub fn feature_id(self) -> u16 {
+        match self {
+            BatteryFeature::Unified(_) => FEATURE_UNIFIED_BATTERY,
+            BatteryFeature::Status(_) => FEATURE_BATTERY_STATUS,
+            BatteryFeature::Voltage(_) => FEATURE_BATTERY_VOLTAGE
+        }
+    }
+}
+
+impl DpiFeature {
+    pub fn feature_id(self) -> u16 {
+        match self {
+            DpiFeature::Adjustable(_) => FEATURE_ADJUSTABLE_DPI,
+            DpiFeature::Extended(_) => FEATURE_EXTENDED_ADJUSTABLE_DPI
+        }
+    }
+}
+
+impl ReportRateFeature {
+    pub fn feature_id(self) -> u16 {
+        match self {
+            ReportRateFeature::Legacy(_) => FEATURE_REPORT_RATE,
+            ReportRateFeature::Extended(_) => FEATURE_EXTENDED_REPORT_RATE
+        }
+    }
+}
+
+impl MouseFeatures {
+    pub fn feature_ids(&self) -> Vec<u16> {
+        let single = [
+            (self.device_name, FEATURE_DEVICE_NAME),
+            (self.smart_shift, FEATURE_SMART_SHIFT),
+            (self.hires_wheel, FEATURE_HIRES_WHEEL),
+            (self.reprog_controls, FEATURE_REPROG_CONTROLS),
+            (self.persistent_remap, FEATURE_PERSISTENT_REMAPPABLE_ACTION),
+            (self.wireless_status, FEATURE_WIRELESS_DEVICE_STATUS),
+            (self.change_host, FEATURE_CHANGE_HOST),
+            (self.hosts_info, FEATURE_HOSTS_INFO)
+        ];
+
+        self.battery
+            .map(BatteryFeature::feature_id)
+            .into_iter()
+            .chain(self.dpi.map(DpiFeature::feature_id))
+            .chain(self.report_rate.map(ReportRateFeature::feature_id))
+            .chain(
+                single
+                    .into_iter()
+                    .filter(|(index, _)| index.is_some())
+                    .map(|(_, feature_id)| feature_id)
+            )
+            .collect()
+    }
+
+    // Unknown devices count as mice only if they report a pointer feature,
+    // so a keyboard in a receiver slot is not picked up as a mouse.
+    pub fn is_mouse(&self) -> bool {
+        self.dpi.is_some() || self.hires_wheel.is_some() || self.smart_shift.is_some()
+    }
+}
+
+pub fn is_receiver_pid(pid: u16) -> bool {
+    pid == PID_BOLT_RECEIVER || PID_UNIFYING_RECEIVERS.contains(&pid)
+}
+
+pub(crate) fn require<T>(feature: Option<T>, feature_id: u16) -> Result<T> {
+    feature.ok_or_else(|| {
+        AppError::from(DeviceErrorKind::UnsupportedFeature)
+            .with_field(field::u64("feature_id", feature_id as u64))
+    })
+}
+
+fn legacy_rates_from_mask(mask: u8) -> Vec<u16> {
+    (0..8)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(|bit| 1000 / (bit as u16 + 1))
+        .collect()
+}
+
+fn extended_rates_from_mask(mask: u16) -> Vec<u16> {
+    EXTENDED_REPORT_RATES_HZ
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, &hz)| hz)
+        .collect()
+}
+
+// The list holds DPI values, where an entry of 0xE000 + n means "every n
+// DPI between its neighbours", and ends at the first zero. Without a step
+// the values themselves are the only ones accepted.
+fn dpi_range_from_list(params: &[u8]) -> Option<DpiRange> {
+    let values: Vec<u16> = params
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .take_while(|&value| value != 0)
+        .collect();
+
+    let step = values
+        .iter()
+        .find(|&&value| value >= DPI_LIST_STEP_MARKER)
+        .map_or(0, |&value| value - DPI_LIST_STEP_MARKER);
+    let mut dpis: Vec<u16> = values
+        .into_iter()
+        .filter(|&value| value < DPI_LIST_STEP_MARKER)
+        .collect();
+    dpis.sort_unstable();
+
+    Some(DpiRange {
+        min: *dpis.first()?,
+        max: *dpis.last()?,
+        step,
+        values: if step == 0 { dpis } else { Vec::new() }
+    })
+}
+
+fn battery_level_from_voltage(millivolts: u16) -> u8 {
+    BATTERY_VOLTAGE_LEVELS
+        .iter()
+        .find(|&&(threshold, _)| millivolts >= threshold)
+        .map(|&(_, level)| level)
+        .unwrap_or(0)
+}
+
+// Level, level flags, charging status, external power.
+fn read_battery_unified(hidpp: &mut HidppDevice, feature_index: u8) -> Result<BatteryInfo> {
+    let response =
+        hidpp.send_command(feature_index, UnifiedBatteryFunction::GetStatus as u8, &[])?;
+    let params = response.parameters();
+
+    let status = match params[2] {
+        0 => BatteryStatus::Discharging,
+        1 | 2 => BatteryStatus::Charging,
+        3 => BatteryStatus::Full,
+        _ => BatteryStatus::Unknown
+    };
+
+    Ok(BatteryInfo {
+        level: params[0],
+        status
+    })
+}
+
+// Level, next level, status.
+fn read_battery_status(hidpp: &mut HidppDevice, feature_index: u8) -> Result<BatteryInfo> {
+    let response = hidpp.send_command(feature_index, BatteryFunction::GetStatus as u8, &[])?;
+    let params = response.parameters();
+
+    let status = match params[2] {
+        1 => BatteryStatus::Discharging,
+        2 => BatteryStatus::Charging,
+        3 => BatteryStatus::Full,
+        _ => BatteryStatus::Unknown
+    };
+
+    Ok(BatteryInfo {
+        level: params[0],
+        status
+    })
+}
+
+fn read_battery_voltage(hidpp: &mut HidppDevice, feature_index: u8) -> Result<BatteryInfo> {
+    let response = hidpp.send_command(
+        feature_index,
+        BatteryVoltageFunction::GetBatteryVoltage as u8,
+        &[]
+    )?;
+    let params = response.parameters();
+    let millivolts = u16::from_be_bytes([params[0], params[1]]);
+    let flags = params[2];
+
+    let status = if flags & BATTERY_VOLTAGE_CHARGING == 0 {
+        BatteryStatus::Discharging
+    } else {
+        match flags & 0x07 {
+            0 => BatteryStatus::Charging,
+            1 | 2 => BatteryStatus::Full,
+            _ => BatteryStatus::Unknown
+        }
+    };
+
+    debug!("Battery voltage: {} mV", millivolts);
+    Ok(BatteryInfo {
+        level: battery_level_from_voltage(millivolts),
+        status
+    })
+}
+
+pub(crate) fn read_battery(
+    hidpp: &mut HidppDevice,
+    feature: BatteryFeature
+) -> Result<BatteryInfo> {
+    match feature {
+        BatteryFeature::Unified(index) => read_battery_unified(hidpp, index),
+        BatteryFeature::Status(index) => read_battery_status(hidpp, index),
+        BatteryFeature::Voltage(index) => read_battery_voltage(hidpp, index)
+    }
+}
+
+// Returns the name as reported, which is empty on some firmware.
+pub(crate) fn read_device_name(hidpp: &mut HidppDevice, feature_index: u8) -> Result<String> {
+    let mut name = String::new();
+    let mut offset = 0u8;
+
+    loop {
+        let response = hidpp.send_command(feature_index, 0x00, &[offset])?;
+        let params = response.parameters();
+        let name_len = params[0] as usize;
+        let chunk = &params[1..];
+
+        for &byte in chunk.iter().take(name_len.saturating_sub(offset as usize)) {
+            if byte == 0 {
+                break;
+            }
+            name.push(byte as char);
+        }
+
+        if offset as usize >= name_len {
+            break;
+        }
+
+        offset += chunk.len() as u8;
+    }
+
+    Ok(name)
+}
+
+fn standard_control_id(button: ButtonId) -> u16 {
+    registry()
+        .standard_controls()
+        .iter()
+        .find(|c| c.button == button)
+        .map(|c| c.cid)
+        .unwrap_or(0)
+}
+
+fn button_for_control(spec: Option<&DeviceSpec>, cid: u16) -> Option<ButtonId> {
+    match spec {
+        Some(spec) => spec.button_for_control(cid),
+        None => registry()
+            .standard_controls()
+            .iter()
+            .find(|c| c.cid == cid)
+            .map(|c| c.button)
+    }
+}
+
+pub(crate) fn parse_event(
+    packet: &HidppPacket,
+    spec: Option<&DeviceSpec>,
+    reprog_index: Option<u8>,
+    status_index: Option<u8>
+) -> Option<DeviceEvent> {
+    let params = packet.parameters();
+
+    // HID++ 1.0 receiver notification, sent when the link is made or lost.
+    if let Some(change) = parse_link_change(packet) {
+        return Some(DeviceEvent::LinkChanged {
+            connected: change.connected
+        });
+    }
+
+    if Some(packet.feature_index()) == status_index
+        && packet.function_id() == WIRELESS_STATUS_EVENT
+    {
+        return (params[0] == WIRELESS_STATUS_RECONNECTION
+            && params[1] == WIRELESS_REQUEST_RECONFIGURE)
+            .then_some(DeviceEvent::ReconfigurationNeeded);
+    }
+
+    if Some(packet.feature_index()) == reprog_index
+        && packet.function_id() == DIVERTED_BUTTONS_EVENT
+    {
+        let pressed = params
+            .chunks_exact(2)
+            .take(4)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .filter_map(|cid| button_for_control(spec, cid))
+            .collect();
+        return Some(DeviceEvent::ButtonsPressed(pressed));
+    }
+
+    None
+}
+
+// Drives any HID++ 2.0 mouse through the features it reports, using the
+// device registry only for the model name, buttons, DPI range and quirks.
+pub struct GenericHidppMouse {
+    hidpp:           HidppDevice,
+    spec:            Option<&'static DeviceSpec>,
+    features:        MouseFeatures,
+    button_mappings: HashMap<ButtonId, Action>,
+    connection:      Option<ConnectionType>,
+    receiver_slot:   Option<u8>
+}
+
+impl GenericHidppMouse {
+    pub fn open_bolt_receiver(device_index: u8) -> Result<Self> {
+        info!(
+            "Opening mouse via Bolt receiver, device index: {}",
+            device_index
+        );
+
+        let hidpp = HidppDevice::open_vid_pid(VID_LOGITECH, PID_BOLT_RECEIVER, device_index)?;
+        let mut mouse = Self::open(hidpp, None)?;
+        mouse.connection = Some(ConnectionType::Receiver);
+        mouse.receiver_slot = Some(device_index);
+        Ok(mouse)
+    }
+
+    // Direct USB or Bluetooth connection to the mouse itself.
+    pub fn open_product(pid: u16) -> Result<Self> {
+        info!("Opening mouse {:04x}:{:04x}", VID_LOGITECH, pid);
+
+        let hidpp = HidppDevice::open_vid_pid(VID_LOGITECH, pid, 0xFF)?;
+        let (fallback, connection) = match registry().by_bluetooth_pid(pid) {
+            Some(spec) => (Some(spec), ConnectionType::Bluetooth),
+            None => (registry().by_usb_pid(pid), ConnectionType::Usb)
+        };
+        let mut mouse = Self::open(hidpp, fallback)?;
+        mouse.connection = Some(connection);
+        Ok(mouse)
+    }
+
+    // Opens a hidraw node resolved by the caller. `receiver_slot` selects the
+    // paired device behind a receiver; without it the node is the mouse.
+    pub fn open_path(
+        path: &str,
+        connection: ConnectionType,
+        receiver_slot: Option<u8>
+    ) -> Result<Self> {
+        info!("Opening mouse at {} (slot {:?})", path, receiver_slot);
+
+        let hidpp = HidppDevice::open_path(path, receiver_slot.unwrap_or(DEVICE_INDEX_RECEIVER))?;
+        let mouse = Self::open(hidpp, None)?;
+        if !mouse.is_mouse() {
+            return Err(AppError::bad_request("Device is not a mouse")
+                .with_field(field::str("path", path.to_string())));
+        }
+
+        Ok(Self {
+            connection: Some(connection),
+            receiver_slot,
+            ..mouse
+        })
+    }
+
+    // The first mouse reachable from this host: paired with a Bolt receiver,
+    // else connected directly with a product id from the registry.
+    pub fn open_any() -> Result<Self> {
+        for slot in RECEIVER_SLOTS {
+            match Self::open_bolt_receiver(slot) {
+                Ok(mouse) if mouse.is_mouse() => return Ok(mouse),
+                Ok(_) => debug!("Receiver slot {} holds no mouse", slot),
+                Err(e) => debug!("No device in receiver slot {}: {}", slot, e)
+            }
+        }
+
+        let pids = registry()
+            .devices()
+            .iter()
+            .filter(|spec| spec.kind == DeviceKind::Mouse)
+            .flat_map(|spec| spec.usb_pids.iter().chain(&spec.bluetooth_pids));
+        for &pid in pids {
+            match Self::open_product(pid) {
+                Ok(mouse) => return Ok(mouse),
+                Err(e) => debug!("No mouse {:04x}:{:04x}: {}", VID_LOGITECH, pid, e)
+            }
+        }
+
+        Err(AppError::not_found("No mouse found"))
+    }
+
+    fn open(mut hidpp: HidppDevice, fallback: Option<&'static DeviceSpec>) -> Result<Self> {
+        hidpp.ping()?;
+
+        Ok(Self::identify(hidpp, fallback))
+    }
+
+    // Looks the model up by the ids it reports and discovers its features.
+    // Models missing from the registry keep `fallback`, if any.
+    pub fn identify(mut hidpp: HidppDevice, fallback: Option<&'static DeviceSpec>) -> Self {
+        let spec = match hidpp.read_device_ids() {
+            Ok(ids) => {
+                if let Err(e) = hidpp.load_feature_cache(&ids) {
+                    debug!("Feature cache unavailable: {}", e);
+                }
+                registry().by_model_ids(&ids.model_ids).or_else(|| {
+                    debug!("Model ids {:04x?} are not in the registry", ids.model_ids);
+                    fallback
+                })
+            }
+            Err(e) => {
+                debug!("Model ids unavailable: {}", e);
+                fallback
+            }
+        };
+        match spec {
+            Some(spec) => info!("Identified {}", spec.name),
+            None => info!("Unknown model, relying on feature discovery")
+        }
+
+        let features = MouseFeatures::discover(&mut hidpp, spec);
+        debug!("Discovered features: {:?}", features);
+        hidpp.save_feature_cache();
+
+        Self {
+            hidpp,
+            spec,
+            features,
+            button_mappings: HashMap::new(),
+            connection: None,
+            receiver_slot: None
+        }
+    }
+
+    pub fn spec(&self) -> Option<&'static DeviceSpec> {
+        self.spec
+    }
+
+    fn is_mouse(&self) -> bool {
+        match self.spec {
+            Some(spec) => spec.kind == DeviceKind::Mouse,
+            None => self.features.is_mouse()
+        }
+    }
+
+    pub fn features(&self) -> &MouseFeatures {
+        &self.features
+    }
+
+    // Lookups that timed out while the mouse slept are retried once it is
+    // back; indices already known, or known missing, come from the cache.
+    pub fn rediscover_features(&mut self) {
+        self.features = MouseFeatures::discover(&mut self.hidpp, self.spec);
+        self.hidpp.save_feature_cache();
+    }
+
+    // Fields the device cannot report are left empty rather than failing, so
+    // config matching can still fall back to the name.
+    pub fn identity(&mut self) -> Result<DeviceIdentity> {
+        let name = self.get_device_name()?;
+
+        let ids = self
+            .hidpp
+            .read_device_ids()
+            .inspect_err(|e| debug!("Device ids unavailable: {}", e))
+            .ok();
+        let serial = ids
+            .as_ref()
+            .and_then(|ids| {
+                self.hidpp
+                    .read_serial_number(ids)
+                    .inspect_err(|e| debug!("Serial number unavailable: {}", e))
+                    .ok()
+            })
+            .filter(|serial| !serial.is_empty());
+
+        Ok(DeviceIdentity {
+            name,
+            serial,
+            model_ids: ids
+                .as_ref()
+                .map(|ids| ids.model_ids.clone())
+                .unwrap_or_default(),
+            wpid: ids.and_then(|ids| ids.wpid),
+            connection: self.connection,
+            receiver_slot: self.receiver_slot
+        })
+    }
+
+    pub fn onboard_profiles(&mut self) -> Result<OnboardProfiles<'_>> {
+        OnboardProfiles::open(&mut self.hidpp)
+    }
+
+    pub fn firmware_info(&mut self) -> Result<DeviceFirmware> {
+        firmware::read_device_firmware(&mut self.hidpp)
+    }
+
+    fn control_id(&self, button: ButtonId) -> Result<u16> {
+        match self.spec {
+            Some(spec) => spec.control_id(button).ok_or_else(|| {
+                AppError::bad_request("Button not present on this device")
+                    .with_field(field::str("button", format!("{:?}", button)))
+                    .with_field(field::str("model", spec.name.clone()))
+            }),
+            None => Ok(standard_control_id(button))
+        }
+    }
+
+    fn read_host_entry(&mut self, feature_index: u8, index: u8) -> Result<HostEntry> {
+        let response = self.hidpp.send_command(
+            feature_index,
+            HostsInfoFunction::GetHostInfo as u8,
+            &[index]
+        )?;
+        let params = response.parameters();
+        if params.len() < 6 {
+            return Err(DeviceErrorKind::InvalidResponse.into());
+        }
+        let paired = params[1] != 0;
+        let name_len = params[4] as usize;
+
+        let mut name_bytes = Vec::with_capacity(name_len);
+        while name_bytes.len() < name_len {
+            let response = self.hidpp.send_command(
+                feature_index,
+                HostsInfoFunction::GetHostFriendlyName as u8,
+                &[index, name_bytes.len() as u8]
+            )?;
+            let chunk = &response.parameters()[2..];
+            if chunk.is_empty() {
+                break;
+            }
+            let take = chunk
+                .len()
+                .min(HOST_NAME_CHUNK_SIZE)
+                .min(name_len - name_bytes.len());
+            name_bytes.extend_from_slice(&chunk[..take]);
+        }
+
+        let name = String::from_utf8_lossy(&name_bytes)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+
+        let os = self
+            .hidpp
+            .send_command(
+                feature_index,
+                HostsInfoFunction::GetHostOsVersion as u8,
+                &[index]
+            )
+            .map(|r| HostOs::from(r.parameters()[1]))
+            .unwrap_or(HostOs::Unknown);
+
+        Ok(HostEntry {
+            index,
+            name: (!name.is_empty()).then_some(name),
+            os,
+            paired
+        })
+    }
+
+    fn read_control_ids(&mut self, feature_index: u8) -> Result<Vec<u16>> {
+        let response = self.hidpp.send_command(
+            feature_index,
+            ReprogControlsFunction::GetControlCount as u8,
+            &[]
+        )?;
+        let count = response.parameters()[0];
+
+        (0..count)
+            .map(|index| {
+                let response = self.hidpp.send_command(
+                    feature_index,
+                    ReprogControlsFunction::GetControlInfo as u8,
+                    &[index]
+                )?;
+                let params = response.parameters();
+                Ok(u16::from_be_bytes([params[0], params[1]]))
+            })
+            .collect()
+    }
+
+    // The registry range wins; otherwise 0x2201 reports its own list. 0x2202
+    // describes ranges per axis and is left to the registry.
+    fn read_dpi_range(&mut self) -> Result<Option<DpiRange>> {
+        if let Some(range) = self.spec.and_then(|s| s.dpi.clone()) {
+            return Ok(Some(range));
+        }
+
+        match self.features.dpi {
+            Some(DpiFeature::Adjustable(feature_index)) => {
+                let response = self.hidpp.send_command(
+                    feature_index,
+                    DpiFunction::GetSensorDpiList as u8,
+                    &[0x00]
+                )?;
+                Ok(dpi_range_from_list(&response.parameters()[1..]))
+            }
+            Some(DpiFeature::Extended(_)) | None => Ok(None)
+        }
+    }
+
+    fn read_wheel_capabilities(&mut self, feature_index: u8) -> Result<WheelCapabilities> {
+        let response = self.hidpp.send_command(
+            feature_index,
+            HiresWheelFunction::GetCapabilities as u8,
+            &[]
+        )?;
+        let params = response.parameters();
+
+        Ok(WheelCapabilities {
+            multiplier:     params[0],
+            invertible:     params[1] & WHEEL_FLAG_INVERT != 0,
+            ratchet_switch: params[1] & WHEEL_FLAG_RATCHET_SWITCH != 0
+        })
+    }
+
+    fn read_persistent_action(&mut self, feature_index: u8, cid: u16) -> Result<PersistentAction> {
+        let [cid_hi, cid_lo] = cid.to_be_bytes();
+
+        let response = self.hidpp.send_command(
+            feature_index,
+            PersistentRemappableActionFunction::GetCidReporting as u8,
+            &[cid_hi, cid_lo, REMAP_ALL_HOSTS]
+        )?;
+
+        PersistentAction::from_report(&response.parameters()[3..])
+    }
+}
+
+impl MouseDevice for GenericHidppMouse {
+    fn capabilities(&mut self) -> Result<DeviceCapabilities> {
+        let features = self
+            .features
+            .feature_ids()
+            .into_iter()
+            .map(|feature_id| {
+                let version = self
+                    .hidpp
+                    .read_feature_version(feature_id)
+                    .unwrap_or_else(|e| {
+                        debug!("Version of feature {:04x} unavailable: {}", feature_id, e);
+                        0
+                    });
+                FeatureVersion {
+                    feature_id,
+                    version
+                }
+            })
+            .collect();
+
+        // Like feature versions above, a read that fails only leaves its part
+        // of the answer empty.
+        let dpi = match self.features.dpi {
+            Some(_) => self.read_dpi_range().unwrap_or_else(|e| {
+                debug!("DPI range unavailable: {}", e);
+                None
+            }),
+            None => None
+        };
+
+        let wheel = self.features.hires_wheel.and_then(|feature_index| {
+            self.read_wheel_capabilities(feature_index)
+                .inspect_err(|e| debug!("Wheel capabilities unavailable: {}", e))
+                .ok()
+        });
+
+        let hosts = match self.features.change_host {
+            Some(feature_index) => self
+                .hidpp
+                .send_command(feature_index, ChangeHostFunction::GetHostInfo as u8, &[])
+                .map(|response| response.parameters()[0])
+                .unwrap_or_else(|e| {
+                    debug!("Host count unavailable: {}", e);
+                    1
+                }),
+            None => 1
+        };
+
+        let mut controls: Vec<ButtonId> = match self.features.reprog_controls {
+            Some(feature_index) => self
+                .read_control_ids(feature_index)
+                .unwrap_or_else(|e| {
+                    debug!("Control list unavailable: {}", e);
+                    Vec::new()
+                })
+                .into_iter()
+                .filter_map(|cid| button_for_control(self.spec, cid))
+                .collect(),
+            None => Vec::new()
+        };
+        controls.dedup();
+
+        let capabilities = DeviceCapabilities {
+            features,
+            dpi,
+            wheel,
+            hosts,
+            controls
+        };
+        debug!("Capabilities: {:?}", capabilities);
+        Ok(capabilities)
+    }
+
+    fn get_device_name(&mut self) -> Result<String> {
+        let feature_index = require(self.features.device_name, FEATURE_DEVICE_NAME)?;
+
+        let mut name = read_device_name(&mut self.hidpp, feature_index)?;
+        if name.trim().is_empty() {
+            name = match self.spec {
+                Some(spec) => format!("Logitech {}", spec.name),
+                None => "Logitech mouse".to_string()
+            };
+        }
+
+        debug!("Device name: {}", name);
+        Ok(name)
+    }
+
+    fn get_battery_info(&mut self) -> Result<BatteryInfo> {
+        let feature = require(self.features.battery, FEATURE_UNIFIED_BATTERY)?;
+        read_battery(&mut self.hidpp, feature)
+    }
+
+    fn set_dpi(&mut self, dpi: u16) -> Result<()> {
+        if let Some(range) = self.spec.and_then(|s| s.dpi.as_ref())
+            && !range.contains(dpi)
+        {
+            return Err(AppError::bad_request("DPI not supported by this device")
+                .with_field(field::u64("dpi", dpi as u64))
+                .with_field(field::u64("dpi_min", range.min as u64))
+                .with_field(field::u64("dpi_max", range.max as u64)));
+        }
+
+        let [dpi_hi, dpi_lo] = dpi.to_be_bytes();
+        match require(self.features.dpi, FEATURE_ADJUSTABLE_DPI)? {
+            DpiFeature::Adjustable(feature_index) => {
+                self.hidpp.send_command(
+                    feature_index,
+                    DpiFunction::SetSensorDpi as u8,
+                    &[0x00, dpi_hi, dpi_lo]
+                )?;
+            }
+            DpiFeature::Extended(feature_index) => {
+                // X and Y are set together; the lift-off distance is kept.
+                let response = self.hidpp.send_command(
+                    feature_index,
+                    ExtendedDpiFunction::GetSensorDpi as u8,
+                    &[0x00]
+                )?;
+                let lod = response.parameters()[9];
+                self.hidpp.send_command(
+                    feature_index,
+                    ExtendedDpiFunction::SetSensorDpi as u8,
+                    &[0x00, dpi_hi, dpi_lo, dpi_hi, dpi_lo, lod]
+                )?;
+            }
+        }
+
+        info!("DPI set to {}", dpi);
+        Ok(())
+    }
+
+    fn get_dpi(&mut self) -> Result<u16> {
+        let response = match require(self.features.dpi, FEATURE_ADJUSTABLE_DPI)? {
+            DpiFeature::Adjustable(feature_index) => {
+                self.hidpp
+                    .send_command(feature_index, DpiFunction::GetSensorDpi as u8, &[0x00])?
+            }
+            DpiFeature::Extended(feature_index) => self.hidpp.send_command(
+                feature_index,
+                ExtendedDpiFunction::GetSensorDpi as u8,
+                &[0x00]
+            )?
+        };
+        let params = response.parameters();
+        let dpi = u16::from_be_bytes([params[1], params[2]]);
+
+        debug!("Current DPI: {}", dpi);
+        Ok(dpi)
+    }
+
+    fn set_smartshift(&mut self, config: SmartShiftConfig) -> Result<()> {
+        let feature_index = require(self.features.smart_shift, FEATURE_SMART_SHIFT)?;
+
+        let wheel_mode = 0x02;
+        let auto_disengage = if config.enabled && config.threshold > 0 {
+            config.threshold
+        } else {
+            0xFF
+        };
+        let auto_disengage_default = 0x00;
+
+        let params = [wheel_mode, auto_disengage, auto_disengage_default];
+
+        self.hidpp.send_command(
+            feature_index,
+            SmartShiftFunction::SetRatchetControlMode as u8,
+            &params
+        )?;
+
+        info!(
+            "SmartShift configured: enabled={}, threshold={}",
+            config.enabled, config.threshold
+        );
+        Ok(())
+    }
+
+    fn get_smartshift(&mut self) -> Result<SmartShiftConfig> {
+        let feature_index = require(self.features.smart_shift, FEATURE_SMART_SHIFT)?;
+
+        let response = self.hidpp.send_command(
+            feature_index,
+            SmartShiftFunction::GetRatchetControlMode as u8,
+            &[]
+        )?;
+        let auto_disengage = response.parameters()[1];
+
+        Ok(SmartShiftConfig {
+            enabled:   auto_disengage > 0 && auto_disengage < 0xFF,
+            threshold: if auto_disengage > 0 && auto_disengage < 0xFF {
+                auto_disengage
+            } else {
+                20
+            }
+        })
+    }
+
+    fn set_hires_scroll(&mut self, config: HiResScrollConfig) -> Result<()> {
+        let feature_index = require(self.features.hires_wheel, FEATURE_HIRES_WHEEL)?;
+
+        let mode = if config.enabled { 0x02 } else { 0x00 };
+        let params = [mode, 0x00, 0x00];
+
+        self.hidpp
+            .send_command(feature_index, HiresWheelFunction::SetMode as u8, &params)?;
+
+        info!(
+            "Hi-res scroll configured: enabled={}, inverted={}",
+            config.enabled, config.inverted
+        );
+        Ok(())
+    }
+
+    fn get_hires_scroll(&mut self) -> Result<HiResScrollConfig> {
+        let feature_index = require(self.features.hires_wheel, FEATURE_HIRES_WHEEL)?;
+
+        let response =
+            self.hidpp
+                .send_command(feature_index, HiresWheelFunction::GetMode as u8, &[])?;
+        let mode = response.parameters()[0];
+
+        Ok(HiResScrollConfig {
+            enabled:  mode == 0x02,
+            inverted: false
+        })
+    }
+
+    fn set_button_action(&mut self, button: ButtonId, action: Action) -> Result<()> {
+        self.button_mappings.insert(button, action);
+        debug!("Button {:?} action configured", button);
+        Ok(())
+    }
+
+    fn get_button_action(&mut self, button: ButtonId) -> Result<Action> {
+        self.button_mappings
+            .get(&button)
+            .cloned()
+            .ok_or_else(|| AppError::not_found("Button action not configured"))
+    }
+
+    fn set_button_diverted(&mut self, button: ButtonId, diverted: bool) -> Result<()> {
+        let feature_index = require(self.features.reprog_controls, FEATURE_REPROG_CONTROLS)?;
+
+        let cid = self.control_id(button)?;
+        let mut flags = REPORTING_FLAG_DIVERT_VALID;
+        if diverted {
+            flags |= REPORTING_FLAG_DIVERT;
+        }
+        let params = [(cid >> 8) as u8, (cid & 0xFF) as u8, flags];
+
+        self.hidpp.send_command(
+            feature_index,
+            ReprogControlsFunction::SetControlReporting as u8,
+            &params
+        )?;
+
+        debug!(
+            "Button {:?} (CID {:04x}) diverted={}",
+            button, cid, diverted
+        );
+        Ok(())
+    }
+
+    fn read_event(&mut self, timeout_ms: i32) -> Result<Option<DeviceEvent>> {
+        while let Some(packet) = self.hidpp.read_notification(timeout_ms)? {
+            if let Some(event) = parse_event(
+                &packet,
+                self.spec,
+                self.features.reprog_controls,
+                self.features.wireless_status
+            ) {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn get_report_rate_list(&mut self) -> Result<Vec<u16>> {
+        let rates = match require(self.features.report_rate, FEATURE_REPORT_RATE)? {
+            ReportRateFeature::Extended(feature_index) => {
+                let response = self.hidpp.send_command(
+                    feature_index,
+                    ExtendedReportRateFunction::GetReportRateList as u8,
+                    &[]
+                )?;
+                let params = response.parameters();
+                extended_rates_from_mask(u16::from_be_bytes([params[0], params[1]]))
+            }
+            ReportRateFeature::Legacy(feature_index) => {
+                let response = self.hidpp.send_command(
+                    feature_index,
+                    ReportRateFunction::GetReportRateList as u8,
+                    &[]
+                )?;
+                legacy_rates_from_mask(response.parameters()[0])
+            }
+        };
+
+        debug!("Supported report rates: {:?} Hz", rates);
+        Ok(rates)
+    }
+
+    fn get_report_rate(&mut self) -> Result<u16> {
+        let hz = match require(self.features.report_rate, FEATURE_REPORT_RATE)? {
+            ReportRateFeature::Extended(feature_index) => {
+                let response = self.hidpp.send_command(
+                    feature_index,
+                    ExtendedReportRateFunction::GetReportRate as u8,
+                    &[]
+                )?;
+                *EXTENDED_REPORT_RATES_HZ
+                    .get(response.parameters()[0] as usize)
+                    .ok_or(DeviceErrorKind::InvalidResponse)?
+            }
+            ReportRateFeature::Legacy(feature_index) => {
+                let response = self.hidpp.send_command(
+                    feature_index,
+                    ReportRateFunction::GetReportRate as u8,
+                    &[]
+                )?;
+                match response.parameters()[0] {
+                    0 => return Err(DeviceErrorKind::InvalidResponse.into()),
+                    ms => 1000 / ms as u16
+                }
+            }
+        };
+
+        debug!("Current report rate: {} Hz", hz);
+        Ok(hz)
+    }
+
+    fn set_report_rate(&mut self, hz: u16) -> Result<()> {
+        if !self.get_report_rate_list()?.contains(&hz) {
+            return Err(
+                AppError::bad_request("Report rate not supported by this device")
+                    .with_field(field::u64("report_rate_hz", hz as u64))
+            );
+        }
+
+        match require(self.features.report_rate, FEATURE_REPORT_RATE)? {
+            ReportRateFeature::Extended(feature_index) => {
+                let rate_index = EXTENDED_REPORT_RATES_HZ
+                    .iter()
+                    .position(|&rate| rate == hz)
+                    .unwrap_or_default() as u8;
+                self.hidpp.send_command(
+                    feature_index,
+                    ExtendedReportRateFunction::SetReportRate as u8,
+                    &[rate_index]
+                )?;
+            }
+            ReportRateFeature::Legacy(feature_index) => {
+                self.hidpp.send_command(
+                    feature_index,
+                    ReportRateFunction::SetReportRate as u8,
+                    &[(1000 / hz) as u8]
+                )?;
+            }
+        }
+
+        info!("Report rate set to {} Hz", hz);
+        Ok(())
+    }
+
+    fn get_host_info(&mut self) -> Result<HostInfo> {
+        let feature_index = require(self.features.change_host, FEATURE_CHANGE_HOST)?;
+
+        let response =
+            self.hidpp
+                .send_command(feature_index, ChangeHostFunction::GetHostInfo as u8, &[])?;
+        let count = response.parameters()[0];
+        let current = response.parameters()[1];
+
+        let hosts_info = self.features.hosts_info;
+        let hosts = (0..count)
+            .map(|index| {
+                require(hosts_info, FEATURE_HOSTS_INFO)
+                    .and_then(|feature_index| self.read_host_entry(feature_index, index))
+                    .unwrap_or_else(|e| {
+                        debug!("Host {} details unavailable: {}", index, e);
+                        HostEntry {
+                            index,
+                            name: None,
+                            os: HostOs::Unknown,
+                            paired: index == current
+                        }
+                    })
+            })
+            .collect();
+
+        debug!("Easy-Switch: host {} of {}", current + 1, count);
+        Ok(HostInfo {
+            current,
+            count,
+            hosts
+        })
+    }
+
+    fn change_host(&mut self, host: u8) -> Result<()> {
+        let feature_index = require(self.features.change_host, FEATURE_CHANGE_HOST)?;
+
+        // The device drops the link as soon as it switches, so no reply arrives.
+        self.hidpp.send_command_no_reply(
+            feature_index,
+            ChangeHostFunction::SetCurrentHost as u8,
+            &[host]
+        )?;
+
+        info!("Switched to host {}", host + 1);
+        Ok(())
+    }
+
+    fn list_remappable_controls(&mut self) -> Result<Vec<RemappableControl>> {
+        let feature_index = require(
+            self.features.persistent_remap,
+            FEATURE_PERSISTENT_REMAPPABLE_ACTION
+        )?;
+
+        let response = self.hidpp.send_command(
+            feature_index,
+            PersistentRemappableActionFunction::GetCount as u8,
+            &[]
+        )?;
+        let count = response.parameters()[0];
+
+        (0..count)
+            .map(|index| {
+                let response = self.hidpp.send_command(
+                    feature_index,
+                    PersistentRemappableActionFunction::GetCidInfo as u8,
+                    &[index, REMAP_ALL_HOSTS]
+                )?;
+                let params = response.parameters();
+                let cid = u16::from_be_bytes([params[0], params[1]]);
+
+                Ok(RemappableControl {
+                    control_id: cid,
+                    button:     button_for_control(self.spec, cid),
+                    action:     self.read_persistent_action(feature_index, cid)?
+                })
+            })
+            .collect()
+    }
+
+    fn get_persistent_action(&mut self, button: ButtonId) -> Result<PersistentAction> {
+        let feature_index = require(
+            self.features.persistent_remap,
+            FEATURE_PERSISTENT_REMAPPABLE_ACTION
+        )?;
+
+        let cid = self.control_id(button)?;
+        self.read_persistent_action(feature_index, cid)
+    }
+
+    fn set_persistent_action(&mut self, button: ButtonId, action: PersistentAction) -> Result<()> {
+        let feature_index = require(
+            self.features.persistent_remap,
+            FEATURE_PERSISTENT_REMAPPABLE_ACTION
+        )?;
+
+        let [cid_hi, cid_lo] = self.control_id(button)?.to_be_bytes();
+
+        match action.to_report() {
+            Some(report) => {
+                let mut params = vec![cid_hi, cid_lo, REMAP_ALL_HOSTS];
+                params.extend_from_slice(&report);
+                self.hidpp.send_command(
+                    feature_index,
+                    PersistentRemappableActionFunction::SetCidReporting as u8,
+                    &params
+                )?;
+            }
+            None => {
+                self.hidpp.send_command(
+                    feature_index,
+                    PersistentRemappableActionFunction::ResetToFactory as u8,
+                    &[cid_hi, cid_lo, REMAP_ALL_HOSTS]
+                )?;
+            }
+        }
+
+        info!("Button {:?} persistently remapped to {:?}", button, action);
+        Ok(())
+    }
+
+    fn ping(&mut self) -> Result<()> {
+        self.hidpp.ping()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering}
+    };
+
+    use super::*;
+    use crate::hidpp::{
+        ERROR_REPORT_HIDPP20, FEATURE_DEVICE_INFO, HidppPacket, ROOT_INDEX, RootFunction,
+        ScriptedTransport
+    };
+
+    // A mouse with 0x2202 DPI, voltage battery and no SmartShift, reporting
+    // the given model id.
+    fn scripted_mouse(model_id: u16, dpi: Arc<Mutex<[u8; 6]>>) -> HidppDevice {
+        let features = [
+            (FEATURE_DEVICE_INFO, 0x01),
+            (FEATURE_EXTENDED_ADJUSTABLE_DPI, 0x02),
+            (FEATURE_BATTERY_VOLTAGE, 0x03),
+            (FEATURE_REPROG_CONTROLS, 0x04),
+            (FEATURE_PERSISTENT_REMAPPABLE_ACTION, 0x05)
+        ];
+
+        let transport = ScriptedTransport::new(move |report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let sent = request.parameters();
+            let mut params = [0u8; 16];
+
+            match (request.feature_index(), request.function_id()) {
+                (ROOT_INDEX, f) if f == RootFunction::GetFeature as u8 => {
+                    let id = u16::from_be_bytes([sent[0], sent[1]]);
+                    params[0] = features
+                        .iter()
+                        .find(|(feature, _)| *feature == id)
+                        .map_or(0, |(_, index)| *index);
+                    params[2] = if params[0] == 0 { 0 } else { 0x01 };
+                }
+                (0x01, 0x00) => params[7..9].copy_from_slice(&model_id.to_be_bytes()),
+                (0x02, 0x05) => {
+                    let current = dpi.lock().unwrap();
+                    params[1..3].copy_from_slice(&current[1..3]);
+                    params[5..7].copy_from_slice(&current[3..5]);
+                    params[9] = current[5];
+                }
+                (0x02, 0x06) => dpi.lock().unwrap().copy_from_slice(&sent[..6]),
+                (0x03, 0x00) => params[..3].copy_from_slice(&[0x0F, 0x1E, 0x80]),
+                // Back and Forward; Forward sends Alt+Right.
+                (0x05, 0x01) => params[0] = 2,
+                (0x05, 0x02) => params[1] = [0x53, 0x56][sent[0] as usize],
+                (0x05, 0x03) => {
+                    params[..3].copy_from_slice(&sent[..3]);
+                    if sent[1] == 0x56 {
+                        params[3..7].copy_from_slice(&[0x01, 0x00, 0x4F, 0x04]);
+                    }
+                }
+                _ => params[..3].copy_from_slice(&sent[..3])
+            }
+
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    params
+                )
+                .to_bytes(),
+            ])
+        });
+
+        HidppDevice::with_transport(Box::new(transport), 1)
+    }
+
+    #[test]
+    fn test_discovers_feature_variants() {
+        let dpi = Arc::new(Mutex::new([0, 0x03, 0xE8, 0x03, 0xE8, 0x02]));
+        let mouse = GenericHidppMouse::identify(scripted_mouse(0x4090, dpi), None);
+
+        assert_eq!(mouse.spec().map(|s| s.name.as_str()), Some("MX Anywhere 3"));
+        let features = mouse.features();
+        assert_eq!(features.dpi, Some(DpiFeature::Extended(0x02)));
+        assert_eq!(features.battery, Some(BatteryFeature::Voltage(0x03)));
+        assert_eq!(features.smart_shift, None);
+        assert_eq!(features.report_rate, None);
+    }
+
+    #[test]
+    fn test_events_use_indices_from_discovery() {
+        let awake = Arc::new(AtomicBool::new(false));
+        let writes = Arc::new(AtomicUsize::new(0));
+
+        let (awake_t, writes_t) = (awake.clone(), writes.clone());
+        let transport = ScriptedTransport::new(move |report| {
+            writes_t.fetch_add(1, Ordering::SeqCst);
+            let request = HidppPacket::from_bytes(report)?;
+            if !awake_t.load(Ordering::SeqCst) {
+                return Ok(vec![]);
+            }
+
+            let mut params = [0u8; 16];
+            if request.feature_index() == ROOT_INDEX
+                && request.parameters()[..2] == FEATURE_REPROG_CONTROLS.to_be_bytes()
+            {
+                params[0] = 0x04;
+            }
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    params
+                )
+                .to_bytes(),
+            ])
+        });
+        let hidpp = HidppDevice::with_transport(Box::new(transport), 1);
+
+        // Asleep at open: nothing is found, and polling never asks again.
+        let mut mouse = GenericHidppMouse::identify(hidpp, None);
+        assert_eq!(mouse.features().reprog_controls, None);
+        let sent = writes.load(Ordering::SeqCst);
+        for _ in 0..10 {
+            assert_eq!(mouse.read_event(0).unwrap(), None);
+        }
+        assert_eq!(writes.load(Ordering::SeqCst), sent);
+
+        awake.store(true, Ordering::SeqCst);
+        mouse.rediscover_features();
+        assert_eq!(mouse.features().reprog_controls, Some(0x04));
+    }
+
+    #[test]
+    fn test_extended_dpi_keeps_lift_off_distance() {
+        let dpi = Arc::new(Mutex::new([0, 0x03, 0xE8, 0x03, 0xE8, 0x02]));
+        let mut mouse = GenericHidppMouse::identify(scripted_mouse(0x4090, dpi.clone()), None);
+
+        assert_eq!(mouse.get_dpi().unwrap(), 1000);
+        mouse.set_dpi(1600).unwrap();
+        assert_eq!(*dpi.lock().unwrap(), [0, 0x06, 0x40, 0x06, 0x40, 0x02]);
+        assert_eq!(mouse.get_dpi().unwrap(), 1600);
+
+        assert!(mouse.set_dpi(8000).is_err());
+    }
+
+    #[test]
+    fn test_missing_feature_is_unsupported() {
+        let dpi = Arc::new(Mutex::new([0; 6]));
+        let mut mouse = GenericHidppMouse::identify(scripted_mouse(0x1234, dpi), None);
+
+        assert!(mouse.spec().is_none());
+        let err = mouse.get_smartshift().unwrap_err();
+        assert_eq!(
+            err.render_message(),
+            AppError::from(DeviceErrorKind::UnsupportedFeature).render_message()
+        );
+        // Unknown models accept every standard control.
+        assert_eq!(mouse.control_id(ButtonId::ThumbGesture).unwrap(), 0x00C3);
+    }
+
+    #[test]
+    fn test_identity_reads_device_info_once() {
+        let info_reads = Arc::new(AtomicUsize::new(0));
+        let reads = info_reads.clone();
+        let transport = ScriptedTransport::new(move |report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let sent = request.parameters();
+            let mut params = [0u8; 16];
+
+            match (request.feature_index(), request.function_id()) {
+                (ROOT_INDEX, f) if f == RootFunction::GetFeature as u8 => {
+                    params[0] = match u16::from_be_bytes([sent[0], sent[1]]) {
+                        FEATURE_DEVICE_INFO => 0x01,
+                        FEATURE_DEVICE_NAME => 0x02,
+                        _ => 0
+                    };
+                }
+                (0x01, 0x00) => {
+                    reads.fetch_add(1, Ordering::SeqCst);
+                    params[1..5].copy_from_slice(&[0xA1, 0xB2, 0xC3, 0xD4]);
+                    params[7..9].copy_from_slice(&0xB034u16.to_be_bytes());
+                }
+                (0x02, 0x00) => {
+                    params[0] = 4;
+                    params[1..5].copy_from_slice(b"Test");
+                }
+                _ => {}
+            }
+
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    params
+                )
+                .to_bytes(),
+            ])
+        });
+        let hidpp = HidppDevice::with_transport(Box::new(transport), 1);
+        let mut mouse = GenericHidppMouse::identify(hidpp, None);
+
+        info_reads.store(0, Ordering::SeqCst);
+        let identity = mouse.identity().unwrap();
+        assert_eq!(identity.serial.as_deref(), Some("A1B2C3D4"));
+        assert_eq!(identity.model_ids, [0xB034]);
+        assert_eq!(info_reads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_capabilities_survive_failed_reads() {
+        let features = [(FEATURE_REPROG_CONTROLS, 0x04), (FEATURE_CHANGE_HOST, 0x06)];
+        let transport = ScriptedTransport::new(move |report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let mut params = [0u8; 16];
+
+            if request.feature_index() == ROOT_INDEX {
+                let id = u16::from_be_bytes([request.parameters()[0], request.parameters()[1]]);
+                if let Some((_, index)) = features.iter().find(|(feature, _)| *feature == id) {
+                    params[0] = *index;
+                    params[2] = 0x01;
+                }
+            } else {
+                // Every other request fails with a hardware error.
+                params[0] = (request.function_id() << 4) | request.software_id();
+                params[1] = 0x04;
+                return Ok(vec![
+                    HidppPacket::new_long(
+                        request.device_index(),
+                        ERROR_REPORT_HIDPP20,
+                        request.feature_index() >> 4,
+                        request.feature_index() & 0x0F,
+                        params
+                    )
+                    .to_bytes(),
+                ]);
+            }
+
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    params
+                )
+                .to_bytes(),
+            ])
+        });
+        let hidpp = HidppDevice::with_transport(Box::new(transport), 1);
+        let mut mouse = GenericHidppMouse::identify(hidpp, None);
+
+        let capabilities = mouse.capabilities().unwrap();
+        assert!(capabilities.has_feature(FEATURE_REPROG_CONTROLS));
+        assert_eq!(capabilities.hosts, 1);
+        assert!(capabilities.controls.is_empty());
+    }
+
+    #[test]
+    fn test_capabilities_follow_discovery() {
+        let dpi = Arc::new(Mutex::new([0; 6]));
+        let mut mouse = GenericHidppMouse::identify(scripted_mouse(0x4090, dpi), None);
+
+        let capabilities = mouse.capabilities().unwrap();
+        assert_eq!(
+            capabilities.features,
+            [
+                FeatureVersion {
+                    feature_id: FEATURE_BATTERY_VOLTAGE,
+                    version:    1
+                },
+                FeatureVersion {
+                    feature_id: FEATURE_EXTENDED_ADJUSTABLE_DPI,
+                    version:    1
+                },
+                FeatureVersion {
+                    feature_id: FEATURE_REPROG_CONTROLS,
+                    version:    1
+                },
+                FeatureVersion {
+                    feature_id: FEATURE_PERSISTENT_REMAPPABLE_ACTION,
+                    version:    1
+                }
+            ]
+        );
+        assert_eq!(capabilities.dpi.as_ref().map(|r| r.max), Some(4000));
+        assert_eq!(capabilities.wheel, None);
+        assert!(capabilities.supports(Setting::Dpi));
+        assert!(!capabilities.supports(Setting::SmartShift));
+        assert!(!capabilities.supports(Setting::EasySwitch));
+    }
+
+    #[test]
+    fn test_remappable_controls_from_persistent_remap() {
+        let dpi = Arc::new(Mutex::new([0; 6]));
+        let mut mouse = GenericHidppMouse::identify(scripted_mouse(0x4090, dpi), None);
+
+        assert_eq!(
+            mouse.list_remappable_controls().unwrap(),
+            [
+                RemappableControl {
+                    control_id: 0x0053,
+                    button:     Some(ButtonId::Back),
+                    action:     PersistentAction::Disabled
+                },
+                RemappableControl {
+                    control_id: 0x0056,
+                    button:     Some(ButtonId::Forward),
+                    action:     PersistentAction::Key {
+                        key:       0x4F,
+                        modifiers: 0x04
+                    }
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dpi_range_from_list() {
+        // 200, step 50, 4000
+        let stepped = [0x00, 0xC8, 0xE0, 0x32, 0x0F, 0xA0, 0x00, 0x00];
+        assert_eq!(
+            dpi_range_from_list(&stepped),
+            Some(DpiRange {
+                min:    200,
+                max:    4000,
+                step:   50,
+                values: Vec::new()
+            })
+        );
+
+        let discrete = [0x01, 0x90, 0x03, 0x20, 0x06, 0x40, 0x00, 0x00, 0x07, 0xD0];
+        assert_eq!(
+            dpi_range_from_list(&discrete),
+            Some(DpiRange {
+                min:    400,
+                max:    1600,
+                step:   0,
+                values: vec![400, 800, 1600]
+            })
+        );
+        assert_eq!(dpi_range_from_list(&[0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn test_voltage_battery() {
+        let dpi = Arc::new(Mutex::new([0; 6]));
+        let mut mouse = GenericHidppMouse::identify(scripted_mouse(0x4090, dpi), None);
+
+        assert_eq!(
+            mouse.get_battery_info().unwrap(),
+            BatteryInfo {
+                level:  60,
+                status: BatteryStatus::Charging
+            }
+        );
+    }
+
+    #[test]
+    fn test_battery_replies_as_devices_send_them() {
+        // Full GetStatus reports: 0x1004 at 55%, level good, charging on
+        // external power; 0x1000 at 50%, next level 20%, discharging.
+        let replies = [
+            (
+                FEATURE_UNIFIED_BATTERY,
+                [0x11, 0x01, 0x03, 0x10, 0x37, 0x04, 0x01, 0x01],
+                BatteryInfo {
+                    level:  55,
+                    status: BatteryStatus::Charging
+                }
+            ),
+            (
+                FEATURE_BATTERY_STATUS,
+                [0x11, 0x01, 0x03, 0x00, 0x32, 0x14, 0x01, 0x00],
+                BatteryInfo {
+                    level:  50,
+                    status: BatteryStatus::Discharging
+                }
+            )
+        ];
+
+        for (feature, reply, expected) in replies {
+            let transport = ScriptedTransport::new(move |report| {
+                let request = HidppPacket::from_bytes(report)?;
+                assert_eq!(request.function_id(), reply[3] >> 4);
+                let mut reply = reply.to_vec();
+                reply[3] |= request.software_id();
+                reply.resize(20, 0);
+                Ok(vec![reply])
+            });
+            let mut hidpp = HidppDevice::with_transport(Box::new(transport), 1);
+            let battery = match feature {
+                FEATURE_UNIFIED_BATTERY => BatteryFeature::Unified(0x03),
+                _ => BatteryFeature::Status(0x03)
+            };
+            assert_eq!(read_battery(&mut hidpp, battery).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_battery_status_conversion() {
+        let status = BatteryStatus::Charging;
+        assert_eq!(status, BatteryStatus::Charging);
+    }
+
+    #[test]
+    fn test_all_battery_statuses() {
+        let statuses = [
+            BatteryStatus::Discharging,
+            BatteryStatus::Charging,
+            BatteryStatus::Full,
+            BatteryStatus::Unknown
+        ];
+        assert_eq!(statuses.len(), 4);
+        assert_eq!(statuses[0], BatteryStatus::Discharging);
+        assert_eq!(statuses[1], BatteryStatus::Charging);
+        assert_eq!(statuses[2], BatteryStatus::Full);
+        assert_eq!(statuses[3], BatteryStatus::Unknown);
+    }
+
+    #[test]
+    fn test_battery_level_from_voltage() {
+        assert_eq!(battery_level_from_voltage(4200), 100);
+        assert_eq!(battery_level_from_voltage(3860), 60);
+        assert_eq!(battery_level_from_voltage(3000), 0);
+    }
+
+    #[test]
+    fn test_known_model_restricts_controls() {
+        let spec = registry().by_name("MX Anywhere 3").unwrap();
+        assert_eq!(button_for_control(Some(spec), 0x00C3), None);
+        assert_eq!(
+            button_for_control(None, 0x00C3),
+            Some(ButtonId::ThumbGesture)
+        );
+    }
+
+    #[test]
+    fn test_parse_diverted_buttons_event() {
+        let packet = HidppPacket::new_long(2, 0x08, 0x00, 0x00, {
+            let mut params = [0u8; 16];
+            params[..4].copy_from_slice(&[0x00, 0x53, 0x00, 0xC3]);
+            params
+        });
+        assert_eq!(
+            parse_event(&packet, None, Some(0x08), Some(0x09)),
+            Some(DeviceEvent::ButtonsPressed(vec![
+                ButtonId::Back,
+                ButtonId::ThumbGesture
+            ]))
+        );
+        assert_eq!(parse_event(&packet, None, None, Some(0x09)), None);
+    }
+
+    #[test]
+    fn test_parse_wireless_status_event() {
+        let reconfigure = HidppPacket::new_short(2, 0x09, 0x00, 0x00, [0x01, 0x01, 0x01]);
+        assert_eq!(
+            parse_event(&reconfigure, None, Some(0x08), Some(0x09)),
+            Some(DeviceEvent::ReconfigurationNeeded)
+        );
+
+        let no_request = HidppPacket::new_short(2, 0x09, 0x00, 0x00, [0x01, 0x00, 0x00]);
+        assert_eq!(parse_event(&no_request, None, Some(0x08), Some(0x09)), None);
+    }
+
+    #[test]
+    fn test_parse_receiver_connection_event() {
+        let connected = HidppPacket::new_short(2, 0x41, 0x01, 0x00, [0x10, 0x34, 0xB0]);
+        assert_eq!(
+            parse_event(&connected, None, None, None),
+            Some(DeviceEvent::LinkChanged {
+                connected: true
+            })
+        );
+
+        let lost = HidppPacket::new_short(2, 0x41, 0x01, 0x00, [0x50, 0x34, 0xB0]);
+        assert_eq!(
+            parse_event(&lost, None, None, None),
+            Some(DeviceEvent::LinkChanged {
+                connected: false
+            })
+        );
+    }
+
+    #[test]
+    fn test_legacy_rates_from_mask() {
+        assert_eq!(
+            legacy_rates_from_mask(0b1000_1011),
+            vec![1000, 500, 250, 125]
+        );
+        assert!(legacy_rates_from_mask(0).is_empty());
+    }
+
+    #[test]
+    fn test_extended_rates_from_mask() {
+        assert_eq!(extended_rates_from_mask(0x0008), vec![1000]);
+        assert_eq!(
+            extended_rates_from_mask(0x007F),
+            EXTENDED_REPORT_RATES_HZ.to_vec()
+        );
+        assert_eq!(extended_rates_from_mask(0xFF80), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn test_button_mapping() {
+        let mut mappings = HashMap::new();
+        mappings.insert(ButtonId::ThumbGesture, Action::ToggleSmartShift);
+        assert!(mappings.contains_key(&ButtonId::ThumbGesture));
+    }
+}
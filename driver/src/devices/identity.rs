@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionType {
+    Receiver,
+    Usb,
+    Bluetooth
+}
+
+impl fmt::Display for ConnectionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConnectionType::Receiver => "receiver",
+            ConnectionType::Usb => "usb",
+            ConnectionType::Bluetooth => "bluetooth"
+        })
+    }
+}
+
+// What is known about one physical device, used to tell apart two units of
+// the same model. `serial` falls back to the unit id on devices that do not
+// report a serial number; both stay the same across renames and reconnects.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    pub name:          String,
+    pub serial:        Option<String>,
+    pub model_ids:     Vec<u16>,
+    pub wpid:          Option<u16>,
+    pub connection:    Option<ConnectionType>,
+    pub receiver_slot: Option<u8>
+}
+
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(serial) = &self.serial {
+            write!(f, " [{}]", serial)?;
+        }
+        match (self.connection, self.receiver_slot) {
+            (Some(connection), Some(slot)) => write!(f, " via {} slot {}", connection, slot),
+            (Some(connection), None) => write!(f, " via {}", connection),
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_display() {
+        let identity = DeviceIdentity {
+            name: "MX Master 3S".to_string(),
+            serial: Some("A1B2C3D4".to_string()),
+            connection: Some(ConnectionType::Receiver),
+            receiver_slot: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            identity.to_string(),
+            "MX Master 3S [A1B2C3D4] via receiver slot 2"
+        );
+
+        let bare = DeviceIdentity {
+            name: "Logitech mouse".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(bare.to_string(), "Logitech mouse");
+    }
+}
@@ -0,0 +1,540 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use masterror::{field, prelude::*};
+use tracing::{debug, info};
+
+use super::{
+    generic::{
+        BatteryFeature, PID_BOLT_RECEIVER, VID_LOGITECH, parse_event, read_battery,
+        read_device_name, require
+    },
+    registry::{DeviceKind, DeviceSpec, registry},
+    traits::*
+};
+use crate::{
+    error::{DeviceErrorKind, Result},
+    hidpp::{
+        BacklightFunction, DEVICE_INDEX_RECEIVER, DisableKeysFunction, FEATURE_BACKLIGHT,
+        FEATURE_DEVICE_NAME, FEATURE_DISABLE_KEYS, FEATURE_FN_INVERSION, FEATURE_UNIFIED_BATTERY,
+        FEATURE_WIRELESS_DEVICE_STATUS, FnInversionFunction, HidppDevice
+    }
+};
+
+const CURRENT_HOST: u8 = 0xFF;
+const BACKLIGHT_MODE_SHIFT: u8 = 3;
+const BACKLIGHT_MODE_MASK: u8 = 0x03 << BACKLIGHT_MODE_SHIFT;
+const BACKLIGHT_MODE_AUTOMATIC: u8 = 0x01;
+const BACKLIGHT_MODE_MANUAL: u8 = 0x02;
+const BACKLIGHT_KEEP_EFFECT: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyboardFeatures {
+    pub device_name:     Option<u8>,
+    pub battery:         Option<BatteryFeature>,
+    pub backlight:       Option<u8>,
+    pub fn_inversion:    Option<u8>,
+    pub disable_keys:    Option<u8>,
+    pub wireless_status: Option<u8>
+}
+
+impl KeyboardFeatures {
+    pub fn discover(hidpp: &mut HidppDevice, spec: Option<&DeviceSpec>) -> Self {
+        let battery = BatteryFeature::discover(hidpp, spec);
+        let mut find = |feature_id| hidpp.get_feature_index(feature_id).ok();
+
+        Self {
+            device_name: find(FEATURE_DEVICE_NAME),
+            battery,
+            backlight: find(FEATURE_BACKLIGHT),
+            fn_inversion: find(FEATURE_FN_INVERSION),
+            disable_keys: find(FEATURE_DISABLE_KEYS),
+            wireless_status: find(FEATURE_WIRELESS_DEVICE_STATUS)
+        }
+    }
+
+    fn is_keyboard(&self) -> bool {
+        self.backlight.is_some() || self.fn_inversion.is_some() || self.disable_keys.is_some()
+    }
+}
+
+fn backlight_mode(enabled: u8, options: u8) -> BacklightMode {
+    if enabled == 0 {
+        return BacklightMode::Off;
+    }
+
+    match (options & BACKLIGHT_MODE_MASK) >> BACKLIGHT_MODE_SHIFT {
+        BACKLIGHT_MODE_MANUAL => BacklightMode::Manual,
+        _ => BacklightMode::Automatic
+    }
+}
+
+fn backlight_options(options: u8, mode: BacklightMode) -> u8 {
+    let bits = match mode {
+        BacklightMode::Manual => BACKLIGHT_MODE_MANUAL,
+        BacklightMode::Off | BacklightMode::Automatic => BACKLIGHT_MODE_AUTOMATIC
+    };
+    (options & !BACKLIGHT_MODE_MASK) | (bits << BACKLIGHT_MODE_SHIFT)
+}
+
+// Drives HID++ 2.0 keyboards such as the MX Keys family.
+pub struct GenericHidppKeyboard {
+    hidpp:    HidppDevice,
+    spec:     Option<&'static DeviceSpec>,
+    features: KeyboardFeatures
+}
+
+impl GenericHidppKeyboard {
+    pub fn open_bolt_receiver(device_index: u8) -> Result<Self> {
+        info!(
+            "Opening keyboard via Bolt receiver, device index: {}",
+            device_index
+        );
+
+        let hidpp = HidppDevice::open_vid_pid(VID_LOGITECH, PID_BOLT_RECEIVER, device_index)?;
+        Self::open(hidpp, None)
+    }
+
+    pub fn open_path(path: &str, receiver_slot: Option<u8>) -> Result<Self> {
+        info!("Opening keyboard at {} (slot {:?})", path, receiver_slot);
+
+        let hidpp = HidppDevice::open_path(path, receiver_slot.unwrap_or(DEVICE_INDEX_RECEIVER))?;
+        Self::open(hidpp, None)
+    }
+
+    pub fn open_product(pid: u16) -> Result<Self> {
+        info!("Opening keyboard {:04x}:{:04x}", VID_LOGITECH, pid);
+
+        let hidpp = HidppDevice::open_vid_pid(VID_LOGITECH, pid, 0xFF)?;
+        let fallback = registry()
+            .by_usb_pid(pid)
+            .or_else(|| registry().by_bluetooth_pid(pid));
+        Self::open(hidpp, fallback)
+    }
+
+    fn open(mut hidpp: HidppDevice, fallback: Option<&'static DeviceSpec>) -> Result<Self> {
+        hidpp.ping()?;
+
+        Self::identify(hidpp, fallback)
+    }
+
+    // Fails for devices the registry lists as mice and for unknown devices
+    // without any keyboard feature, so receiver slots can be probed safely.
+    pub fn identify(
+        mut hidpp: HidppDevice,
+        fallback: Option<&'static DeviceSpec>
+    ) -> Result<Self> {
+        let spec = match hidpp.read_device_ids() {
+            Ok(ids) => {
+                if let Err(e) = hidpp.load_feature_cache(&ids) {
+                    debug!("Feature cache unavailable: {}", e);
+                }
+                registry().by_model_ids(&ids.model_ids).or(fallback)
+            }
+            Err(e) => {
+                debug!("Model ids unavailable: {}", e);
+                fallback
+            }
+        };
+
+        if let Some(spec) = spec
+            && spec.kind != DeviceKind::Keyboard
+        {
+            return Err(AppError::bad_request("Device is not a keyboard")
+                .with_field(field::str("model", spec.name.clone())));
+        }
+
+        let features = KeyboardFeatures::discover(&mut hidpp, spec);
+        hidpp.save_feature_cache();
+        if spec.is_none() && !features.is_keyboard() {
+            return Err(AppError::bad_request("Device is not a keyboard"));
+        }
+        debug!("Discovered keyboard features: {:?}", features);
+
+        Ok(Self {
+            hidpp,
+            spec,
+            features
+        })
+    }
+
+    pub fn spec(&self) -> Option<&'static DeviceSpec> {
+        self.spec
+    }
+
+    pub fn features(&self) -> &KeyboardFeatures {
+        &self.features
+    }
+
+    // Lookups that timed out while the keyboard slept are retried once it
+    // is back, as for mice.
+    pub fn rediscover_features(&mut self) {
+        self.features = KeyboardFeatures::discover(&mut self.hidpp, self.spec);
+        self.hidpp.save_feature_cache();
+    }
+
+    fn backlight_level_count(&mut self, feature_index: u8) -> Result<u8> {
+        let response = self.hidpp.send_command(
+            feature_index,
+            BacklightFunction::GetBacklightInfo as u8,
+            &[]
+        )?;
+        Ok(response.parameters()[0])
+    }
+}
+
+impl KeyboardDevice for GenericHidppKeyboard {
+    fn get_device_name(&mut self) -> Result<String> {
+        let feature_index = require(self.features.device_name, FEATURE_DEVICE_NAME)?;
+
+        let mut name = read_device_name(&mut self.hidpp, feature_index)?;
+        if name.trim().is_empty() {
+            name = match self.spec {
+                Some(spec) => format!("Logitech {}", spec.name),
+                None => "Logitech keyboard".to_string()
+            };
+        }
+
+        debug!("Device name: {}", name);
+        Ok(name)
+    }
+
+    fn get_battery_info(&mut self) -> Result<BatteryInfo> {
+        let feature = require(self.features.battery, FEATURE_UNIFIED_BATTERY)?;
+        read_battery(&mut self.hidpp, feature)
+    }
+
+    fn get_backlight(&mut self) -> Result<BacklightConfig> {
+        let feature_index = require(self.features.backlight, FEATURE_BACKLIGHT)?;
+
+        let response = self.hidpp.send_command(
+            feature_index,
+            BacklightFunction::GetBacklightConfig as u8,
+            &[]
+        )?;
+        let params = response.parameters();
+
+        Ok(BacklightConfig {
+            mode:  backlight_mode(params[0], params[1]),
+            level: params[5]
+        })
+    }
+
+    fn set_backlight(&mut self, config: BacklightConfig) -> Result<()> {
+        let feature_index = require(self.features.backlight, FEATURE_BACKLIGHT)?;
+
+        if config.mode == BacklightMode::Manual {
+            let levels = self.backlight_level_count(feature_index)?;
+            if config.level >= levels {
+                return Err(AppError::bad_request("Backlight level out of range")
+                    .with_field(field::u64("level", config.level as u64))
+                    .with_field(field::u64("levels", levels as u64)));
+            }
+        }
+
+        // The timeouts are written back unchanged.
+        let response = self.hidpp.send_command(
+            feature_index,
+            BacklightFunction::GetBacklightConfig as u8,
+            &[]
+        )?;
+        let current = response.parameters();
+        if current.len() < 12 {
+            return Err(DeviceErrorKind::InvalidResponse.into());
+        }
+
+        let mut params = vec![
+            (config.mode != BacklightMode::Off) as u8,
+            backlight_options(current[1], config.mode),
+            BACKLIGHT_KEEP_EFFECT,
+            if config.mode == BacklightMode::Manual {
+                config.level
+            } else {
+                current[5]
+            },
+        ];
+        params.extend_from_slice(&current[6..12]);
+
+        self.hidpp.send_command(
+            feature_index,
+            BacklightFunction::SetBacklightConfig as u8,
+            &params
+        )?;
+
+        info!("Backlight set to {:?}, level {}", config.mode, config.level);
+        Ok(())
+    }
+
+    fn get_fn_inversion(&mut self) -> Result<bool> {
+        let feature_index = require(self.features.fn_inversion, FEATURE_FN_INVERSION)?;
+
+        let response = self.hidpp.send_command(
+            feature_index,
+            FnInversionFunction::GetGlobalFnInversion as u8,
+            &[CURRENT_HOST]
+        )?;
+        Ok(response.parameters()[1] != 0)
+    }
+
+    fn set_fn_inversion(&mut self, inverted: bool) -> Result<()> {
+        let feature_index = require(self.features.fn_inversion, FEATURE_FN_INVERSION)?;
+
+        self.hidpp.send_command(
+            feature_index,
+            FnInversionFunction::SetGlobalFnInversion as u8,
+            &[CURRENT_HOST, inverted as u8]
+        )?;
+
+        info!("Fn inversion set to {}", inverted);
+        Ok(())
+    }
+
+    fn get_disableable_keys(&mut self) -> Result<Vec<DisableableKey>> {
+        let feature_index = require(self.features.disable_keys, FEATURE_DISABLE_KEYS)?;
+
+        let response = self.hidpp.send_command(
+            feature_index,
+            DisableKeysFunction::GetCapabilities as u8,
+            &[]
+        )?;
+        Ok(DisableableKey::from_mask(response.parameters()[0]))
+    }
+
+    fn get_disabled_keys(&mut self) -> Result<Vec<DisableableKey>> {
+        let feature_index = require(self.features.disable_keys, FEATURE_DISABLE_KEYS)?;
+
+        let response = self.hidpp.send_command(
+            feature_index,
+            DisableKeysFunction::GetDisabledKeys as u8,
+            &[]
+        )?;
+        Ok(DisableableKey::from_mask(response.parameters()[0]))
+    }
+
+    fn set_disabled_keys(&mut self, keys: &[DisableableKey]) -> Result<()> {
+        let supported = self.get_disableable_keys()?;
+        if let Some(key) = keys.iter().find(|key| !supported.contains(key)) {
+            return Err(
+                AppError::bad_request("Key cannot be disabled on this keyboard")
+                    .with_field(field::str("key", format!("{:?}", key)))
+            );
+        }
+
+        let feature_index = require(self.features.disable_keys, FEATURE_DISABLE_KEYS)?;
+        self.hidpp.send_command(
+            feature_index,
+            DisableKeysFunction::SetDisabledKeys as u8,
+            &[DisableableKey::to_mask(keys)]
+        )?;
+
+        info!("Disabled keys: {:?}", keys);
+        Ok(())
+    }
+
+    // Keyboards divert no buttons, so only link and wake-up events come out.
+    fn read_event(&mut self, timeout_ms: i32) -> Result<Option<DeviceEvent>> {
+        while let Some(packet) = self.hidpp.read_notification(timeout_ms)? {
+            if let Some(event) =
+                parse_event(&packet, self.spec, None, self.features.wireless_status)
+            {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn ping(&mut self) -> Result<()> {
+        self.hidpp.ping()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::hidpp::{
+        FEATURE_DEVICE_INFO, HidppPacket, ROOT_INDEX, RootFunction, ScriptedTransport
+    };
+
+    // An MX Keys with backlight, Fn inversion and DisableKeys, whose state
+    // lives in `state`: [enabled, options, level, fn inversion, disabled mask].
+    // A ping is answered as if the keyboard had just woken up.
+    fn scripted_keyboard(model_id: u16, state: Arc<Mutex<[u8; 5]>>) -> HidppDevice {
+        let features = [
+            (FEATURE_DEVICE_INFO, 0x01),
+            (FEATURE_BACKLIGHT, 0x02),
+            (FEATURE_FN_INVERSION, 0x03),
+            (FEATURE_DISABLE_KEYS, 0x04),
+            (FEATURE_WIRELESS_DEVICE_STATUS, 0x05)
+        ];
+
+        let transport = ScriptedTransport::new(move |report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let sent = request.parameters();
+            let mut params = [0u8; 16];
+            let mut state = state.lock().unwrap();
+
+            match (request.feature_index(), request.function_id()) {
+                (ROOT_INDEX, f) if f == RootFunction::GetFeature as u8 => {
+                    let id = u16::from_be_bytes([sent[0], sent[1]]);
+                    params[0] = features
+                        .iter()
+                        .find(|(feature, _)| *feature == id)
+                        .map_or(0, |(_, index)| *index);
+                }
+                (0x01, 0x00) => params[7..9].copy_from_slice(&model_id.to_be_bytes()),
+                (0x02, 0x00) => {
+                    params[..2].copy_from_slice(&state[..2]);
+                    params[5] = state[2];
+                    params[6..12].copy_from_slice(&[0x2C, 0x01, 0x58, 0x02, 0x10, 0x0E]);
+                }
+                (0x02, 0x01) => {
+                    assert_eq!(&sent[4..10], &[0x2C, 0x01, 0x58, 0x02, 0x10, 0x0E]);
+                    state[0] = sent[0];
+                    state[1] = sent[1];
+                    state[2] = sent[3];
+                }
+                (0x02, 0x02) => params[0] = 8,
+                (0x03, 0x00) => params[..2].copy_from_slice(&[sent[0], state[3]]),
+                (0x03, 0x01) => state[3] = sent[1],
+                (0x04, 0x00) => params[0] = 0x19,
+                (0x04, 0x01) => params[0] = state[4],
+                (0x04, 0x02) => state[4] = sent[0],
+                (ROOT_INDEX, f) if f == RootFunction::Ping as u8 => {
+                    let mut wake = [0u8; 16];
+                    wake[..2].copy_from_slice(&[0x01, 0x01]);
+                    params[..3].copy_from_slice(&sent[..3]);
+                    return Ok(vec![
+                        HidppPacket::new_long(request.device_index(), 0x05, 0x00, 0x00, wake)
+                            .to_bytes(),
+                        HidppPacket::new_long(
+                            request.device_index(),
+                            ROOT_INDEX,
+                            request.function_id(),
+                            request.software_id(),
+                            params
+                        )
+                        .to_bytes(),
+                    ]);
+                }
+                _ => params[..3].copy_from_slice(&sent[..3])
+            }
+
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    params
+                )
+                .to_bytes(),
+            ])
+        });
+
+        HidppDevice::with_transport(Box::new(transport), 1)
+    }
+
+    #[test]
+    fn test_backlight_roundtrip() {
+        let state = Arc::new(Mutex::new([1, 0x08, 3, 0, 0]));
+        let mut keyboard =
+            GenericHidppKeyboard::identify(scripted_keyboard(0x408A, state.clone()), None)
+                .unwrap();
+        assert_eq!(keyboard.spec().map(|s| s.name.as_str()), Some("MX Keys"));
+
+        assert_eq!(
+            keyboard.get_backlight().unwrap(),
+            BacklightConfig {
+                mode:  BacklightMode::Automatic,
+                level: 3
+            }
+        );
+
+        let manual = BacklightConfig {
+            mode:  BacklightMode::Manual,
+            level: 6
+        };
+        keyboard.set_backlight(manual).unwrap();
+        assert_eq!(keyboard.get_backlight().unwrap(), manual);
+
+        assert!(
+            keyboard
+                .set_backlight(BacklightConfig {
+                    mode:  BacklightMode::Manual,
+                    level: 8
+                })
+                .is_err()
+        );
+
+        keyboard
+            .set_backlight(BacklightConfig {
+                mode:  BacklightMode::Off,
+                level: 0
+            })
+            .unwrap();
+        assert_eq!(keyboard.get_backlight().unwrap().mode, BacklightMode::Off);
+    }
+
+    #[test]
+    fn test_wake_up_asks_for_reconfiguration() {
+        let state = Arc::new(Mutex::new([1, 0x08, 3, 0, 0]));
+        let mut keyboard =
+            GenericHidppKeyboard::identify(scripted_keyboard(0x408A, state), None).unwrap();
+        assert_eq!(keyboard.features().wireless_status, Some(0x05));
+        assert_eq!(keyboard.read_event(0).unwrap(), None);
+
+        keyboard.ping().unwrap();
+        assert_eq!(
+            keyboard.read_event(0).unwrap(),
+            Some(DeviceEvent::ReconfigurationNeeded)
+        );
+        assert_eq!(keyboard.read_event(0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_fn_inversion_and_disabled_keys() {
+        let state = Arc::new(Mutex::new([1, 0x08, 3, 0, 0]));
+        let mut keyboard =
+            GenericHidppKeyboard::identify(scripted_keyboard(0x408A, state.clone()), None)
+                .unwrap();
+
+        assert!(!keyboard.get_fn_inversion().unwrap());
+        keyboard.set_fn_inversion(true).unwrap();
+        assert!(keyboard.get_fn_inversion().unwrap());
+
+        assert_eq!(
+            keyboard.get_disableable_keys().unwrap(),
+            vec![
+                DisableableKey::CapsLock,
+                DisableableKey::Insert,
+                DisableableKey::Super
+            ]
+        );
+        keyboard
+            .set_disabled_keys(&[DisableableKey::CapsLock, DisableableKey::Insert])
+            .unwrap();
+        assert_eq!(state.lock().unwrap()[4], 0x09);
+        assert!(
+            keyboard
+                .set_disabled_keys(&[DisableableKey::NumLock])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_identify_rejects_mouse() {
+        let state = Arc::new(Mutex::new([0; 5]));
+        assert!(GenericHidppKeyboard::identify(scripted_keyboard(0xB034, state), None).is_err());
+    }
+
+    #[test]
+    fn test_backlight_options_keep_other_bits() {
+        assert_eq!(backlight_options(0x0F, BacklightMode::Manual), 0x17);
+        assert_eq!(backlight_mode(1, 0x17), BacklightMode::Manual);
+        assert_eq!(backlight_mode(0, 0x17), BacklightMode::Off);
+        assert_eq!(backlight_mode(1, 0x08), BacklightMode::Automatic);
+    }
+}
@@ -1,8 +1,25 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
+pub mod generic;
+pub mod identity;
+pub mod keyboard;
 pub mod mx_master_3s;
+pub mod receiver;
+pub mod registry;
+pub mod remap;
 pub mod traits;
 
+pub use generic::{
+    BatteryFeature, DpiFeature, GenericHidppMouse, MouseFeatures, PID_BOLT_RECEIVER,
+    PID_UNIFYING_RECEIVERS, RECEIVER_SLOTS, ReportRateFeature, VID_LOGITECH, is_receiver_pid
+};
+pub use identity::{ConnectionType, DeviceIdentity};
+pub use keyboard::{GenericHidppKeyboard, KeyboardFeatures};
 pub use mx_master_3s::MxMaster3s;
+pub use receiver::{LinkChange, ReceiverWatch};
+pub use registry::{
+    ControlSpec, DeviceKind, DeviceRegistry, DeviceSpec, DpiRange, Quirk, registry
+};
+pub use remap::*;
 pub use traits::*;
@@ -0,0 +1,93 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use tracing::info;
+
+use crate::{
+    error::Result,
+    hidpp::{
+        CONNECTION_FLAG_LINK_LOST, DEVICE_INDEX_RECEIVER, HidppDevice, HidppPacket,
+        NOTIFICATION_DEVICE_CONNECTION
+    }
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkChange {
+    pub slot:      u8,
+    pub connected: bool
+}
+
+pub(crate) fn parse_link_change(packet: &HidppPacket) -> Option<LinkChange> {
+    (matches!(packet, HidppPacket::Short(_))
+        && packet.feature_index() == NOTIFICATION_DEVICE_CONNECTION)
+        .then(|| LinkChange {
+            slot:      packet.device_index(),
+            connected: packet.parameters()[0] & CONNECTION_FLAG_LINK_LOST == 0
+        })
+}
+
+// Listens on a receiver for the link notifications of all its slots, so a
+// paired device that was asleep when the receiver appeared is found once it
+// wakes up.
+pub struct ReceiverWatch {
+    hidpp: HidppDevice
+}
+
+impl ReceiverWatch {
+    pub fn open_path(path: &str) -> Result<Self> {
+        info!("Watching receiver at {}", path);
+
+        Ok(Self::new(HidppDevice::open_path(
+            path,
+            DEVICE_INDEX_RECEIVER
+        )?))
+    }
+
+    pub fn new(hidpp: HidppDevice) -> Self {
+        Self {
+            hidpp
+        }
+    }
+
+    pub fn read_link_change(&mut self, timeout_ms: i32) -> Result<Option<LinkChange>> {
+        while let Some(packet) = self.hidpp.read_report(timeout_ms)? {
+            if let Some(change) = parse_link_change(&packet) {
+                return Ok(Some(change));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{DEFAULT_SLOT, Emulator, MouseState};
+
+    #[test]
+    fn test_link_changes_of_every_slot() {
+        let emulator = Emulator::with_mouse(DEFAULT_SLOT, MouseState::mx_master_3s());
+        let mut watch = ReceiverWatch::new(emulator.open(DEVICE_INDEX_RECEIVER).unwrap());
+        assert_eq!(watch.read_link_change(0).unwrap(), None);
+
+        emulator.set_connected(DEFAULT_SLOT, false).unwrap();
+        emulator.set_connected(DEFAULT_SLOT, true).unwrap();
+
+        assert_eq!(
+            watch.read_link_change(0).unwrap(),
+            Some(LinkChange {
+                slot:      DEFAULT_SLOT,
+                connected: false
+            })
+        );
+        assert_eq!(
+            watch.read_link_change(0).unwrap(),
+            Some(LinkChange {
+                slot:      DEFAULT_SLOT,
+                connected: true
+            })
+        );
+        assert_eq!(watch.read_link_change(0).unwrap(), None);
+    }
+}
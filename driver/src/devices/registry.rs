@@ -0,0 +1,280 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::sync::LazyLock;
+
+use masterror::{field, prelude::*};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::traits::ButtonId;
+use crate::error::Result;
+
+const BUILTIN_REGISTRY: &str = include_str!("registry.toml");
+
+static BUILTIN: LazyLock<DeviceRegistry> = LazyLock::new(|| {
+    DeviceRegistry::parse(BUILTIN_REGISTRY).unwrap_or_else(|e| {
+        warn!("Built-in device registry is invalid: {}", e);
+        DeviceRegistry::default()
+    })
+});
+
+pub fn registry() -> &'static DeviceRegistry {
+    &BUILTIN
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceKind {
+    #[default]
+    Mouse,
+    Keyboard
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quirk {
+    LegacyBattery
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ControlSpec {
+    pub button: ButtonId,
+    pub cid:    u16
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DpiRange {
+    pub min:    u16,
+    pub max:    u16,
+    pub step:   u16,
+    // The only values a sensor without a step accepts. When empty, any value
+    // between the bounds is fine, on `step` if there is one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<u16>
+}
+
+impl DpiRange {
+    pub fn contains(&self, dpi: u16) -> bool {
+        if !self.values.is_empty() {
+            return self.values.contains(&dpi);
+        }
+        (self.min..=self.max).contains(&dpi)
+            && (self.step == 0 || (dpi - self.min).is_multiple_of(self.step))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DeviceSpec {
+    pub name:           String,
+    #[serde(default)]
+    pub kind:           DeviceKind,
+    #[serde(default)]
+    pub usb_pids:       Vec<u16>,
+    #[serde(default)]
+    pub bluetooth_pids: Vec<u16>,
+    #[serde(default)]
+    pub wpids:          Vec<u16>,
+    #[serde(default)]
+    pub model_ids:      Vec<u16>,
+    #[serde(default)]
+    pub controls:       Vec<ControlSpec>,
+    pub dpi:            Option<DpiRange>,
+    #[serde(default)]
+    pub quirks:         Vec<Quirk>
+}
+
+impl DeviceSpec {
+    pub fn control_id(&self, button: ButtonId) -> Option<u16> {
+        self.controls
+            .iter()
+            .find(|c| c.button == button)
+            .map(|c| c.cid)
+    }
+
+    pub fn button_for_control(&self, cid: u16) -> Option<ButtonId> {
+        self.controls
+            .iter()
+            .find(|c| c.cid == cid)
+            .map(|c| c.button)
+    }
+
+    pub fn has_quirk(&self, quirk: Quirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeviceRegistry {
+    #[serde(default)]
+    standard_controls: Vec<ControlSpec>,
+    #[serde(default, rename = "device")]
+    devices:           Vec<DeviceSpec>
+}
+
+impl DeviceRegistry {
+    pub fn parse(content: &str) -> Result<Self> {
+        let registry: Self = toml::from_str(content)
+            .map_err(|e| AppError::bad_request("Invalid device registry").with_source(e))?;
+
+        for spec in &registry.devices {
+            if let Some(dpi) = &spec.dpi
+                && dpi.min > dpi.max
+            {
+                return Err(AppError::bad_request("Device DPI range is empty")
+                    .with_field(field::str("device", spec.name.clone())));
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub fn devices(&self) -> &[DeviceSpec] {
+        &self.devices
+    }
+
+    // Controls of unknown models, which share Logitech's control id numbering.
+    pub fn standard_controls(&self) -> &[ControlSpec] {
+        &self.standard_controls
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&DeviceSpec> {
+        self.devices
+            .iter()
+            .find(|d| d.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn by_usb_pid(&self, pid: u16) -> Option<&DeviceSpec> {
+        self.devices.iter().find(|d| d.usb_pids.contains(&pid))
+    }
+
+    pub fn by_bluetooth_pid(&self, pid: u16) -> Option<&DeviceSpec> {
+        self.devices
+            .iter()
+            .find(|d| d.bluetooth_pids.contains(&pid))
+    }
+
+    pub fn by_wpid(&self, wpid: u16) -> Option<&DeviceSpec> {
+        self.devices.iter().find(|d| d.wpids.contains(&wpid))
+    }
+
+    // Model ids are reported in order of preference, so the first known one wins.
+    pub fn by_model_ids(&self, model_ids: &[u16]) -> Option<&DeviceSpec> {
+        model_ids
+            .iter()
+            .find_map(|id| self.devices.iter().find(|d| d.model_ids.contains(id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_registry_parses() {
+        let registry = DeviceRegistry::parse(BUILTIN_REGISTRY).unwrap();
+        assert!(!registry.devices().is_empty());
+
+        for spec in registry.devices() {
+            assert_eq!(registry.by_name(&spec.name), Some(spec));
+        }
+    }
+
+    #[test]
+    fn test_lookup_mx_master_3s() {
+        let spec = registry().by_name("mx master 3s").unwrap();
+        assert_eq!(registry().by_usb_pid(0x4082), None);
+        assert_eq!(registry().by_bluetooth_pid(0xB034), Some(spec));
+        assert_eq!(registry().by_wpid(0xB034), Some(spec));
+        assert_eq!(registry().by_model_ids(&[0xB034]), Some(spec));
+
+        assert_eq!(spec.control_id(ButtonId::ThumbGesture), Some(0x00C3));
+        assert_eq!(spec.button_for_control(0x0056), Some(ButtonId::Forward));
+        assert_eq!(spec.button_for_control(0x0000), None);
+    }
+
+    #[test]
+    fn test_standard_controls_cover_known_mice() {
+        let standard = registry().standard_controls();
+        assert_eq!(standard.len(), 7);
+
+        for spec in registry()
+            .devices()
+            .iter()
+            .filter(|spec| spec.kind == DeviceKind::Mouse)
+        {
+            assert!(spec.controls.iter().all(|c| standard.contains(c)));
+        }
+    }
+
+    #[test]
+    fn test_lookup_by_model_ids_skips_unknown() {
+        let spec = registry().by_model_ids(&[0x1234, 0x4090]).unwrap();
+        assert_eq!(spec.name, "MX Anywhere 3");
+        assert!(registry().by_model_ids(&[0x1234]).is_none());
+    }
+
+    #[test]
+    fn test_lookup_keyboard() {
+        let spec = registry().by_wpid(0x408A).unwrap();
+        assert_eq!(spec.name, "MX Keys");
+        assert_eq!(spec.kind, DeviceKind::Keyboard);
+        assert!(spec.controls.is_empty());
+    }
+
+    #[test]
+    fn test_dpi_range_contains() {
+        let range = DpiRange {
+            min:    200,
+            max:    4000,
+            step:   50,
+            values: Vec::new()
+        };
+        assert!(range.contains(200));
+        assert!(range.contains(1650));
+        assert!(!range.contains(1625));
+        assert!(!range.contains(100));
+        assert!(!range.contains(4050));
+
+        let discrete = DpiRange {
+            min:    400,
+            max:    1600,
+            step:   0,
+            values: vec![400, 800, 1600]
+        };
+        assert!(discrete.contains(800));
+        assert!(!discrete.contains(1000));
+        assert!(!discrete.contains(200));
+    }
+
+    #[test]
+    fn test_parse_quirks_and_defaults() {
+        let registry = DeviceRegistry::parse(
+            r#"
+            [[device]]
+            name   = "Test Mouse"
+            wpids  = [0x1234]
+            quirks = ["legacy_battery"]
+            "#
+        )
+        .unwrap();
+
+        let spec = registry.by_wpid(0x1234).unwrap();
+        assert!(spec.has_quirk(Quirk::LegacyBattery));
+        assert_eq!(spec.kind, DeviceKind::Mouse);
+        assert!(spec.controls.is_empty());
+        assert_eq!(spec.dpi, None);
+    }
+
+    #[test]
+    fn test_parse_rejects_inverted_dpi_range() {
+        let result = DeviceRegistry::parse(
+            r#"
+            [[device]]
+            name = "Broken"
+            dpi  = { min = 4000, max = 200, step = 50 }
+            "#
+        );
+        assert!(result.is_err());
+    }
+}
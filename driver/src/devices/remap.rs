@@ -0,0 +1,405 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use masterror::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::traits::{Action, ButtonId};
+use crate::error::Result;
+
+const ACTION_ID_DISABLED: u8 = 0x00;
+const ACTION_ID_KEY: u8 = 0x01;
+const ACTION_ID_MOUSE: u8 = 0x02;
+const ACTION_ID_CONSUMER: u8 = 0x07;
+
+const MODIFIERS: [(&str, u8); 8] = [
+    ("KEY_LEFTCTRL", 0x01),
+    ("KEY_LEFTSHIFT", 0x02),
+    ("KEY_LEFTALT", 0x04),
+    ("KEY_LEFTMETA", 0x08),
+    ("KEY_RIGHTCTRL", 0x10),
+    ("KEY_RIGHTSHIFT", 0x20),
+    ("KEY_RIGHTALT", 0x40),
+    ("KEY_RIGHTMETA", 0x80)
+];
+
+const MOUSE_BUTTONS: [(&str, u16); 5] = [
+    ("BTN_LEFT", 0x0001),
+    ("BTN_RIGHT", 0x0002),
+    ("BTN_MIDDLE", 0x0004),
+    ("BTN_SIDE", 0x0008),
+    ("BTN_EXTRA", 0x0010)
+];
+
+const CONSUMER_USAGES: [(&str, u16); 15] = [
+    ("KEY_BRIGHTNESSUP", 0x006F),
+    ("KEY_BRIGHTNESSDOWN", 0x0070),
+    ("KEY_NEXTSONG", 0x00B5),
+    ("KEY_PREVIOUSSONG", 0x00B6),
+    ("KEY_STOPCD", 0x00B7),
+    ("KEY_PLAYPAUSE", 0x00CD),
+    ("KEY_MUTE", 0x00E2),
+    ("KEY_VOLUMEUP", 0x00E9),
+    ("KEY_VOLUMEDOWN", 0x00EA),
+    ("KEY_MAIL", 0x018A),
+    ("KEY_CALC", 0x0192),
+    ("KEY_SEARCH", 0x0221),
+    ("KEY_HOMEPAGE", 0x0223),
+    ("KEY_BACK", 0x0224),
+    ("KEY_FORWARD", 0x0225)
+];
+
+const KEYBOARD_USAGES: [(&str, u8); 34] = [
+    ("KEY_ENTER", 0x28),
+    ("KEY_ESC", 0x29),
+    ("KEY_BACKSPACE", 0x2A),
+    ("KEY_TAB", 0x2B),
+    ("KEY_SPACE", 0x2C),
+    ("KEY_MINUS", 0x2D),
+    ("KEY_EQUAL", 0x2E),
+    ("KEY_LEFTBRACE", 0x2F),
+    ("KEY_RIGHTBRACE", 0x30),
+    ("KEY_BACKSLASH", 0x31),
+    ("KEY_SEMICOLON", 0x33),
+    ("KEY_APOSTROPHE", 0x34),
+    ("KEY_GRAVE", 0x35),
+    ("KEY_COMMA", 0x36),
+    ("KEY_DOT", 0x37),
+    ("KEY_SLASH", 0x38),
+    ("KEY_CAPSLOCK", 0x39),
+    ("KEY_SYSRQ", 0x46),
+    ("KEY_SCROLLLOCK", 0x47),
+    ("KEY_PAUSE", 0x48),
+    ("KEY_INSERT", 0x49),
+    ("KEY_HOME", 0x4A),
+    ("KEY_PAGEUP", 0x4B),
+    ("KEY_DELETE", 0x4C),
+    ("KEY_END", 0x4D),
+    ("KEY_PAGEDOWN", 0x4E),
+    ("KEY_RIGHT", 0x4F),
+    ("KEY_LEFT", 0x50),
+    ("KEY_DOWN", 0x51),
+    ("KEY_UP", 0x52),
+    ("KEY_COMPOSE", 0x65),
+    ("KEY_F13", 0x68),
+    ("KEY_F14", 0x69),
+    ("KEY_F15", 0x6A)
+];
+
+// Reading never yields `Default`; writing it resets the control to its
+// factory behaviour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PersistentAction {
+    Default,
+    Disabled,
+    Key { key: u8, modifiers: u8 },
+    MouseButton { mask: u16 },
+    Consumer { usage: u16 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemappableControl {
+    pub control_id: u16,
+    pub button:     Option<ButtonId>,
+    pub action:     PersistentAction
+}
+
+fn keyboard_usage(name: &str) -> Option<u8> {
+    let suffix = name.strip_prefix("KEY_")?;
+
+    if let [c] = suffix.as_bytes()
+        && c.is_ascii_uppercase()
+    {
+        return Some(0x04 + (c - b'A'));
+    }
+    if let [c] = suffix.as_bytes()
+        && c.is_ascii_digit()
+    {
+        return Some(if *c == b'0' { 0x27 } else { 0x1E + (c - b'1') });
+    }
+    if let Some(n) = suffix.strip_prefix('F').and_then(|n| n.parse::<u8>().ok())
+        && (1..=12).contains(&n)
+    {
+        return Some(0x3A + n - 1);
+    }
+
+    KEYBOARD_USAGES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, usage)| *usage)
+}
+
+fn keyboard_name(usage: u8) -> Option<String> {
+    match usage {
+        0x04..=0x1D => Some(format!("KEY_{}", (b'A' + usage - 0x04) as char)),
+        0x1E..=0x26 => Some(format!("KEY_{}", usage - 0x1E + 1)),
+        0x27 => Some("KEY_0".to_string()),
+        0x3A..=0x45 => Some(format!("KEY_F{}", usage - 0x3A + 1)),
+        _ => KEYBOARD_USAGES
+            .iter()
+            .find(|(_, u)| *u == usage)
+            .map(|(n, _)| n.to_string())
+    }
+}
+
+impl PersistentAction {
+    pub fn from_report(params: &[u8]) -> Result<Self> {
+        if params.len() < 4 {
+            return Err(AppError::bad_request("Persistent action report too short"));
+        }
+
+        let value = u16::from_be_bytes([params[1], params[2]]);
+        Ok(match params[0] {
+            ACTION_ID_DISABLED => PersistentAction::Disabled,
+            ACTION_ID_KEY => PersistentAction::Key {
+                key:       value as u8,
+                modifiers: params[3]
+            },
+            ACTION_ID_MOUSE => PersistentAction::MouseButton {
+                mask: value
+            },
+            ACTION_ID_CONSUMER => PersistentAction::Consumer {
+                usage: value
+            },
+            _ => PersistentAction::Default
+        })
+    }
+
+    pub fn to_report(self) -> Option<[u8; 4]> {
+        let (action_id, value, modifiers) = match self {
+            PersistentAction::Default => return None,
+            PersistentAction::Disabled => (ACTION_ID_DISABLED, 0, 0),
+            PersistentAction::Key {
+                key,
+                modifiers
+            } => (ACTION_ID_KEY, key as u16, modifiers),
+            PersistentAction::MouseButton {
+                mask
+            } => (ACTION_ID_MOUSE, mask, 0),
+            PersistentAction::Consumer {
+                usage
+            } => (ACTION_ID_CONSUMER, usage, 0)
+        };
+
+        let [value_hi, value_lo] = value.to_be_bytes();
+        Some([action_id, value_hi, value_lo, modifiers])
+    }
+
+    pub fn from_keys(keys: &[String]) -> Result<Self> {
+        let mut modifiers = 0u8;
+        let mut target = None;
+
+        for name in keys {
+            if let Some((_, bit)) = MODIFIERS.iter().find(|(n, _)| n == name) {
+                modifiers |= bit;
+                continue;
+            }
+
+            let action = if let Some(usage) = keyboard_usage(name) {
+                PersistentAction::Key {
+                    key:       usage,
+                    modifiers: 0
+                }
+            } else if let Some((_, mask)) = MOUSE_BUTTONS.iter().find(|(n, _)| n == name) {
+                PersistentAction::MouseButton {
+                    mask: *mask
+                }
+            } else if let Some((_, usage)) = CONSUMER_USAGES.iter().find(|(n, _)| n == name) {
+                PersistentAction::Consumer {
+                    usage: *usage
+                }
+            } else {
+                return Err(AppError::bad_request(format!(
+                    "Key {} cannot be stored on the device",
+                    name
+                )));
+            };
+
+            if target.replace(action).is_some() {
+                return Err(AppError::bad_request(
+                    "A persistent remap holds a single key plus modifiers"
+                ));
+            }
+        }
+
+        match target {
+            Some(PersistentAction::Key {
+                key, ..
+            }) => Ok(PersistentAction::Key {
+                key,
+                modifiers
+            }),
+            Some(_) if modifiers != 0 => Err(AppError::bad_request(
+                "Modifiers can only be combined with keyboard keys"
+            )),
+            Some(action) => Ok(action),
+            None if modifiers != 0 => Ok(PersistentAction::Key {
+                key: 0,
+                modifiers
+            }),
+            None => Err(AppError::bad_request("No keys given for persistent remap"))
+        }
+    }
+
+    pub fn from_action(action: &Action) -> Result<Self> {
+        match action {
+            Action::Keypress {
+                keys
+            } => Self::from_keys(keys),
+            Action::None => Ok(PersistentAction::Disabled),
+            _ => Err(AppError::bad_request(
+                "Only key presses can be stored on the device"
+            ))
+        }
+    }
+
+    pub fn to_keys(self) -> Option<Vec<String>> {
+        match self {
+            PersistentAction::Key {
+                key,
+                modifiers
+            } => {
+                let mut keys: Vec<String> = MODIFIERS
+                    .iter()
+                    .filter(|(_, bit)| modifiers & bit != 0)
+                    .map(|(n, _)| n.to_string())
+                    .collect();
+                if key != 0 {
+                    keys.push(keyboard_name(key)?);
+                }
+                Some(keys)
+            }
+            PersistentAction::MouseButton {
+                mask
+            } => MOUSE_BUTTONS
+                .iter()
+                .find(|(_, m)| *m == mask)
+                .map(|(n, _)| vec![n.to_string()]),
+            PersistentAction::Consumer {
+                usage
+            } => CONSUMER_USAGES
+                .iter()
+                .find(|(_, u)| *u == usage)
+                .map(|(n, _)| vec![n.to_string()]),
+            PersistentAction::Default | PersistentAction::Disabled => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_keys_with_modifiers() {
+        let action = PersistentAction::from_keys(&keys(&["KEY_LEFTCTRL", "KEY_C"])).unwrap();
+        assert_eq!(
+            action,
+            PersistentAction::Key {
+                key:       0x06,
+                modifiers: 0x01
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_keys_digits_and_function_keys() {
+        assert_eq!(keyboard_usage("KEY_1"), Some(0x1E));
+        assert_eq!(keyboard_usage("KEY_0"), Some(0x27));
+        assert_eq!(keyboard_usage("KEY_F1"), Some(0x3A));
+        assert_eq!(keyboard_usage("KEY_F12"), Some(0x45));
+        assert_eq!(keyboard_usage("KEY_F13"), Some(0x68));
+        assert_eq!(keyboard_usage("KEY_UNKNOWN"), None);
+    }
+
+    #[test]
+    fn test_from_keys_mouse_and_consumer() {
+        assert_eq!(
+            PersistentAction::from_keys(&keys(&["BTN_MIDDLE"])).unwrap(),
+            PersistentAction::MouseButton {
+                mask: 0x0004
+            }
+        );
+        assert_eq!(
+            PersistentAction::from_keys(&keys(&["KEY_VOLUMEUP"])).unwrap(),
+            PersistentAction::Consumer {
+                usage: 0x00E9
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_keys_rejects_invalid_combinations() {
+        assert!(PersistentAction::from_keys(&keys(&["KEY_A", "KEY_B"])).is_err());
+        assert!(PersistentAction::from_keys(&keys(&["KEY_LEFTCTRL", "BTN_LEFT"])).is_err());
+        assert!(PersistentAction::from_keys(&keys(&["KEY_NOSUCHKEY"])).is_err());
+        assert!(PersistentAction::from_keys(&[]).is_err());
+    }
+
+    #[test]
+    fn test_from_action() {
+        assert_eq!(
+            PersistentAction::from_action(&Action::None).unwrap(),
+            PersistentAction::Disabled
+        );
+        assert!(PersistentAction::from_action(&Action::ToggleSmartShift).is_err());
+    }
+
+    #[test]
+    fn test_report_roundtrip() {
+        let actions = [
+            PersistentAction::Disabled,
+            PersistentAction::Key {
+                key:       0x3A,
+                modifiers: 0x05
+            },
+            PersistentAction::MouseButton {
+                mask: 0x0010
+            },
+            PersistentAction::Consumer {
+                usage: 0x00CD
+            }
+        ];
+
+        for action in actions {
+            let report = action.to_report().unwrap();
+            assert_eq!(PersistentAction::from_report(&report).unwrap(), action);
+        }
+        assert_eq!(PersistentAction::Default.to_report(), None);
+        assert_eq!(
+            PersistentAction::from_report(&[0x05, 0x00, 0x10, 0x00]).unwrap(),
+            PersistentAction::Default
+        );
+    }
+
+    #[test]
+    fn test_to_keys_roundtrip() {
+        let names = keys(&["KEY_LEFTCTRL", "KEY_LEFTSHIFT", "KEY_T"]);
+        let action = PersistentAction::from_keys(&names).unwrap();
+        assert_eq!(action.to_keys(), Some(names));
+        assert_eq!(
+            PersistentAction::Consumer {
+                usage: 0x00E2
+            }
+            .to_keys(),
+            Some(keys(&["KEY_MUTE"]))
+        );
+    }
+
+    #[test]
+    fn test_persistent_action_serde() {
+        let action = PersistentAction::Key {
+            key:       0x04,
+            modifiers: 0x08
+        };
+        let json = serde_json::to_string(&action).unwrap();
+        assert!(json.contains("\"type\":\"Key\""));
+        let parsed: PersistentAction = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, action);
+    }
+}
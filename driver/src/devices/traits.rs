@@ -1,9 +1,24 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
+use std::str::FromStr;
+
+use masterror::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::error::Result;
+use super::{
+    registry::DpiRange,
+    remap::{PersistentAction, RemappableControl}
+};
+use crate::{
+    error::Result,
+    hidpp::{
+        FEATURE_ADJUSTABLE_DPI, FEATURE_BATTERY_STATUS, FEATURE_BATTERY_VOLTAGE,
+        FEATURE_CHANGE_HOST, FEATURE_EXTENDED_ADJUSTABLE_DPI, FEATURE_EXTENDED_REPORT_RATE,
+        FEATURE_HIRES_WHEEL, FEATURE_PERSISTENT_REMAPPABLE_ACTION, FEATURE_REPORT_RATE,
+        FEATURE_REPROG_CONTROLS, FEATURE_SMART_SHIFT, FEATURE_UNIFIED_BATTERY
+    }
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BatteryInfo {
@@ -31,6 +46,87 @@ pub struct HiResScrollConfig {
     pub inverted: bool
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HostOs {
+    Unknown,
+    Windows,
+    WindowsEmbedded,
+    Linux,
+    ChromeOs,
+    Android,
+    MacOs,
+    Ios
+}
+
+impl From<u8> for HostOs {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => HostOs::Windows,
+            2 => HostOs::WindowsEmbedded,
+            3 => HostOs::Linux,
+            4 => HostOs::ChromeOs,
+            5 => HostOs::Android,
+            6 => HostOs::MacOs,
+            7 => HostOs::Ios,
+            _ => HostOs::Unknown
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostEntry {
+    pub index:  u8,
+    pub name:   Option<String>,
+    pub os:     HostOs,
+    pub paired: bool
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostInfo {
+    pub current: u8,
+    pub count:   u8,
+    pub hosts:   Vec<HostEntry>
+}
+
+// `Index` counts channels from 1, matching the labels on the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HostTarget {
+    Next,
+    Prev,
+    Index(u8)
+}
+
+impl HostTarget {
+    pub fn resolve(self, current: u8, count: u8) -> Option<u8> {
+        if count == 0 {
+            return None;
+        }
+
+        // In u16 so hosts near 255 wrap instead of overflowing.
+        let (current, count16) = (u16::from(current), u16::from(count));
+        match self {
+            HostTarget::Next => Some(((current + 1) % count16) as u8),
+            HostTarget::Prev => Some(((current + count16 - 1) % count16) as u8),
+            HostTarget::Index(channel) if (1..=count).contains(&channel) => Some(channel - 1),
+            HostTarget::Index(_) => None
+        }
+    }
+}
+
+impl FromStr for HostTarget {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "next" => Ok(HostTarget::Next),
+            "prev" | "previous" => Ok(HostTarget::Prev),
+            other => other.parse::<u8>().map(HostTarget::Index).map_err(|e| {
+                AppError::bad_request("Expected next, prev or a channel number").with_source(e)
+            })
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ButtonId {
     LeftClick,
@@ -42,15 +138,129 @@ pub enum ButtonId {
     WheelModeShift
 }
 
+impl FromStr for ButtonId {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "leftclick" | "left" => Ok(ButtonId::LeftClick),
+            "rightclick" | "right" => Ok(ButtonId::RightClick),
+            "middleclick" | "middle" => Ok(ButtonId::MiddleClick),
+            "back" => Ok(ButtonId::Back),
+            "forward" => Ok(ButtonId::Forward),
+            "thumbgesture" | "thumb" => Ok(ButtonId::ThumbGesture),
+            "wheelmodeshift" | "wheelmode" => Ok(ButtonId::WheelModeShift),
+            _ => Err(AppError::bad_request(format!("Unknown button: {}", s)))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BacklightMode {
+    Off,
+    #[default]
+    Automatic,
+    Manual
+}
+
+impl FromStr for BacklightMode {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(BacklightMode::Off),
+            "automatic" | "auto" => Ok(BacklightMode::Automatic),
+            "manual" => Ok(BacklightMode::Manual),
+            _ => Err(AppError::bad_request(format!(
+                "Unknown backlight mode: {}",
+                s
+            )))
+        }
+    }
+}
+
+// `level` only applies in manual mode; the device picks it otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BacklightConfig {
+    pub mode:  BacklightMode,
+    #[serde(default)]
+    pub level: u8
+}
+
+// Keys that DisableKeys (0x4521) can turn off, in the order of its bitmask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisableableKey {
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    Insert,
+    Super
+}
+
+impl DisableableKey {
+    pub const ALL: [DisableableKey; 5] = [
+        DisableableKey::CapsLock,
+        DisableableKey::NumLock,
+        DisableableKey::ScrollLock,
+        DisableableKey::Insert,
+        DisableableKey::Super
+    ];
+
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn from_mask(mask: u8) -> Vec<DisableableKey> {
+        Self::ALL
+            .into_iter()
+            .filter(|key| mask & key.mask() != 0)
+            .collect()
+    }
+
+    pub fn to_mask(keys: &[DisableableKey]) -> u8 {
+        keys.iter().fold(0, |mask, key| mask | key.mask())
+    }
+}
+
+impl FromStr for DisableableKey {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "capslock" | "caps" => Ok(DisableableKey::CapsLock),
+            "numlock" | "num" => Ok(DisableableKey::NumLock),
+            "scrolllock" | "scroll" => Ok(DisableableKey::ScrollLock),
+            "insert" | "ins" => Ok(DisableableKey::Insert),
+            "super" | "win" | "windows" => Ok(DisableableKey::Super),
+            _ => Err(AppError::bad_request(format!("Unknown key: {}", s)))
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Action {
     Keypress { keys: Vec<String> },
     Gestures { gestures: Vec<Gesture> },
     ToggleSmartShift,
+    DpiCycle { steps: Vec<u16> },
+    DpiShift { dpi: u16 },
+    ChangeHost { target: HostTarget },
+    // Activates the named profile, or returns to `default` when it is
+    // already active.
+    SwitchProfile { profile: String },
     None
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    ButtonsPressed(Vec<ButtonId>),
+    ReconfigurationNeeded,
+    LinkChanged { connected: bool }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Gesture {
     pub direction: GestureDirection,
@@ -73,7 +283,138 @@ pub enum GestureMode {
     OnPress
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeatureVersion {
+    pub feature_id: u16,
+    pub version:    u8
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WheelCapabilities {
+    pub multiplier:     u8,
+    pub invertible:     bool,
+    pub ratchet_switch: bool
+}
+
+// Settings a front-end may offer, each backed by one or more features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Setting {
+    Battery,
+    Dpi,
+    SmartShift,
+    HiResScroll,
+    ScrollInversion,
+    ReportRate,
+    EasySwitch,
+    ButtonDiversion,
+    PersistentRemap
+}
+
+impl Setting {
+    pub const ALL: [Setting; 9] = [
+        Setting::Battery,
+        Setting::Dpi,
+        Setting::SmartShift,
+        Setting::HiResScroll,
+        Setting::ScrollInversion,
+        Setting::ReportRate,
+        Setting::EasySwitch,
+        Setting::ButtonDiversion,
+        Setting::PersistentRemap
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Setting::Battery => "Battery",
+            Setting::Dpi => "DPI",
+            Setting::SmartShift => "SmartShift",
+            Setting::HiResScroll => "Hi-res scrolling",
+            Setting::ScrollInversion => "Natural scrolling",
+            Setting::ReportRate => "Report rate",
+            Setting::EasySwitch => "Easy-Switch",
+            Setting::ButtonDiversion => "Button actions",
+            Setting::PersistentRemap => "On-device remapping"
+        }
+    }
+
+    // Any one of these features is enough to provide the setting.
+    pub fn features(self) -> &'static [u16] {
+        match self {
+            Setting::Battery => &[
+                FEATURE_UNIFIED_BATTERY,
+                FEATURE_BATTERY_STATUS,
+                FEATURE_BATTERY_VOLTAGE
+            ],
+            Setting::Dpi => &[FEATURE_EXTENDED_ADJUSTABLE_DPI, FEATURE_ADJUSTABLE_DPI],
+            Setting::SmartShift => &[FEATURE_SMART_SHIFT],
+            Setting::HiResScroll | Setting::ScrollInversion => &[FEATURE_HIRES_WHEEL],
+            Setting::ReportRate => &[FEATURE_EXTENDED_REPORT_RATE, FEATURE_REPORT_RATE],
+            Setting::EasySwitch => &[FEATURE_CHANGE_HOST],
+            Setting::ButtonDiversion => &[FEATURE_REPROG_CONTROLS],
+            Setting::PersistentRemap => &[FEATURE_PERSISTENT_REMAPPABLE_ACTION]
+        }
+    }
+}
+
+// What a connected device offers, so front-ends only show settings that
+// will work. `dpi` is `None` when the range is unknown, even if DPI can be set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceCapabilities {
+    pub features: Vec<FeatureVersion>,
+    pub dpi:      Option<DpiRange>,
+    pub wheel:    Option<WheelCapabilities>,
+    pub hosts:    u8,
+    pub controls: Vec<ButtonId>
+}
+
+impl DeviceCapabilities {
+    pub fn has_feature(&self, feature_id: u16) -> bool {
+        self.features.iter().any(|f| f.feature_id == feature_id)
+    }
+
+    pub fn feature_version(&self, feature_id: u16) -> Option<u8> {
+        self.features
+            .iter()
+            .find(|f| f.feature_id == feature_id)
+            .map(|f| f.version)
+    }
+
+    pub fn supports(&self, setting: Setting) -> bool {
+        self.unsupported_reason(setting).is_none()
+    }
+
+    // A sentence suitable for a tooltip or error message, or `None` when the
+    // setting is available.
+    pub fn unsupported_reason(&self, setting: Setting) -> Option<String> {
+        let features = setting.features();
+        if !features.iter().any(|&id| self.has_feature(id)) {
+            let ids: Vec<String> = features.iter().map(|id| format!("0x{:04X}", id)).collect();
+            return Some(format!(
+                "{} is not supported: the device does not report feature {}",
+                setting.label(),
+                ids.join(" or ")
+            ));
+        }
+
+        match setting {
+            Setting::ScrollInversion if !self.wheel.is_some_and(|w| w.invertible) => {
+                Some(format!(
+                    "{} is not supported: the wheel cannot be inverted",
+                    setting.label()
+                ))
+            }
+            Setting::EasySwitch if self.hosts < 2 => Some(format!(
+                "{} is not supported: the device has a single host channel",
+                setting.label()
+            )),
+            _ => None
+        }
+    }
+}
+
 pub trait MouseDevice {
+    fn capabilities(&mut self) -> Result<DeviceCapabilities>;
+
     fn get_device_name(&mut self) -> Result<String>;
 
     fn get_battery_info(&mut self) -> Result<BatteryInfo>;
@@ -94,6 +435,50 @@ pub trait MouseDevice {
 
     fn get_button_action(&mut self, button: ButtonId) -> Result<Action>;
 
+    fn set_button_diverted(&mut self, button: ButtonId, diverted: bool) -> Result<()>;
+
+    fn read_event(&mut self, timeout_ms: i32) -> Result<Option<DeviceEvent>>;
+
+    fn get_report_rate_list(&mut self) -> Result<Vec<u16>>;
+
+    fn get_report_rate(&mut self) -> Result<u16>;
+
+    fn set_report_rate(&mut self, hz: u16) -> Result<()>;
+
+    fn get_host_info(&mut self) -> Result<HostInfo>;
+
+    fn change_host(&mut self, host: u8) -> Result<()>;
+
+    fn list_remappable_controls(&mut self) -> Result<Vec<RemappableControl>>;
+
+    fn get_persistent_action(&mut self, button: ButtonId) -> Result<PersistentAction>;
+
+    fn set_persistent_action(&mut self, button: ButtonId, action: PersistentAction) -> Result<()>;
+
+    fn ping(&mut self) -> Result<()>;
+}
+
+pub trait KeyboardDevice {
+    fn get_device_name(&mut self) -> Result<String>;
+
+    fn get_battery_info(&mut self) -> Result<BatteryInfo>;
+
+    fn get_backlight(&mut self) -> Result<BacklightConfig>;
+
+    fn set_backlight(&mut self, config: BacklightConfig) -> Result<()>;
+
+    fn get_fn_inversion(&mut self) -> Result<bool>;
+
+    fn set_fn_inversion(&mut self, inverted: bool) -> Result<()>;
+
+    fn get_disableable_keys(&mut self) -> Result<Vec<DisableableKey>>;
+
+    fn get_disabled_keys(&mut self) -> Result<Vec<DisableableKey>>;
+
+    fn set_disabled_keys(&mut self, keys: &[DisableableKey]) -> Result<()>;
+
+    fn read_event(&mut self, timeout_ms: i32) -> Result<Option<DeviceEvent>>;
+
     fn ping(&mut self) -> Result<()>;
 }
 
@@ -199,6 +584,97 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_action_dpi_cycle_serde() {
+        let action = Action::DpiCycle {
+            steps: vec![800, 1600, 3200]
+        };
+        let toml_str = toml::to_string(&action).unwrap();
+        assert!(toml_str.contains("type = \"DpiCycle\""));
+        let deserialized: Action = toml::from_str(&toml_str).unwrap();
+        assert_eq!(action, deserialized);
+    }
+
+    #[test]
+    fn test_action_dpi_shift_serde() {
+        let action = Action::DpiShift {
+            dpi: 400
+        };
+        let json = serde_json::to_string(&action).unwrap();
+        let deserialized: Action = serde_json::from_str(&json).unwrap();
+        assert_eq!(action, deserialized);
+    }
+
+    #[test]
+    fn test_host_os_from_u8() {
+        assert_eq!(HostOs::from(3), HostOs::Linux);
+        assert_eq!(HostOs::from(6), HostOs::MacOs);
+        assert_eq!(HostOs::from(0), HostOs::Unknown);
+        assert_eq!(HostOs::from(0x42), HostOs::Unknown);
+    }
+
+    #[test]
+    fn test_host_target_resolve() {
+        assert_eq!(HostTarget::Next.resolve(0, 3), Some(1));
+        assert_eq!(HostTarget::Next.resolve(2, 3), Some(0));
+        assert_eq!(HostTarget::Prev.resolve(0, 3), Some(2));
+        assert_eq!(HostTarget::Index(1).resolve(2, 3), Some(0));
+        assert_eq!(HostTarget::Index(3).resolve(0, 3), Some(2));
+        assert_eq!(HostTarget::Index(0).resolve(0, 3), None);
+        assert_eq!(HostTarget::Index(4).resolve(0, 3), None);
+        assert_eq!(HostTarget::Next.resolve(0, 0), None);
+    }
+
+    #[test]
+    fn test_host_target_resolve_at_u8_boundary() {
+        assert_eq!(HostTarget::Next.resolve(254, 255), Some(0));
+        assert_eq!(HostTarget::Next.resolve(255, 255), Some(1));
+        assert_eq!(HostTarget::Prev.resolve(0, 255), Some(254));
+        assert_eq!(HostTarget::Prev.resolve(255, 255), Some(254));
+        assert_eq!(HostTarget::Index(255).resolve(0, 255), Some(254));
+    }
+
+    #[test]
+    fn test_host_target_from_str() {
+        assert_eq!("next".parse::<HostTarget>().unwrap(), HostTarget::Next);
+        assert_eq!("Prev".parse::<HostTarget>().unwrap(), HostTarget::Prev);
+        assert_eq!("2".parse::<HostTarget>().unwrap(), HostTarget::Index(2));
+        assert!("sideways".parse::<HostTarget>().is_err());
+    }
+
+    #[test]
+    fn test_action_change_host_serde() {
+        let action = Action::ChangeHost {
+            target: HostTarget::Index(2)
+        };
+        let toml_str = toml::to_string(&action).unwrap();
+        let deserialized: Action = toml::from_str(&toml_str).unwrap();
+        assert_eq!(action, deserialized);
+    }
+
+    #[test]
+    fn test_action_switch_profile_serde() {
+        let action: Action =
+            toml::from_str("type = \"SwitchProfile\"\nprofile = \"gaming\"").unwrap();
+        assert_eq!(
+            action,
+            Action::SwitchProfile {
+                profile: "gaming".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_button_id_from_str() {
+        assert_eq!("Forward".parse::<ButtonId>().unwrap(), ButtonId::Forward);
+        assert_eq!("thumb".parse::<ButtonId>().unwrap(), ButtonId::ThumbGesture);
+        assert_eq!(
+            "WheelModeShift".parse::<ButtonId>().unwrap(),
+            ButtonId::WheelModeShift
+        );
+        assert!("scroll".parse::<ButtonId>().is_err());
+    }
+
     #[test]
     fn test_gesture_direction_variants() {
         let directions = [
@@ -247,4 +723,67 @@ mod tests {
         let deserialized: BatteryInfo = serde_json::from_str(&json).unwrap();
         assert_eq!(battery, deserialized);
     }
+
+    #[test]
+    fn test_unsupported_reason() {
+        let capabilities = DeviceCapabilities {
+            features: vec![
+                FeatureVersion {
+                    feature_id: FEATURE_HIRES_WHEEL,
+                    version:    1
+                },
+                FeatureVersion {
+                    feature_id: FEATURE_CHANGE_HOST,
+                    version:    1
+                },
+            ],
+            wheel: Some(WheelCapabilities::default()),
+            hosts: 1,
+            ..Default::default()
+        };
+
+        assert!(capabilities.supports(Setting::HiResScroll));
+        assert_eq!(capabilities.feature_version(FEATURE_HIRES_WHEEL), Some(1));
+        assert_eq!(
+            capabilities.unsupported_reason(Setting::Dpi).unwrap(),
+            "DPI is not supported: the device does not report feature 0x2202 or 0x2201"
+        );
+        assert!(
+            capabilities
+                .unsupported_reason(Setting::ScrollInversion)
+                .unwrap()
+                .contains("cannot be inverted")
+        );
+        assert!(
+            capabilities
+                .unsupported_reason(Setting::EasySwitch)
+                .unwrap()
+                .contains("single host")
+        );
+    }
+
+    #[test]
+    fn test_backlight_mode_from_str() {
+        assert_eq!(
+            "auto".parse::<BacklightMode>().unwrap(),
+            BacklightMode::Automatic
+        );
+        assert_eq!(
+            "Manual".parse::<BacklightMode>().unwrap(),
+            BacklightMode::Manual
+        );
+        assert!("dim".parse::<BacklightMode>().is_err());
+    }
+
+    #[test]
+    fn test_disableable_key_mask() {
+        let keys = [DisableableKey::CapsLock, DisableableKey::Insert];
+        assert_eq!(DisableableKey::to_mask(&keys), 0x09);
+        assert_eq!(DisableableKey::from_mask(0x09), keys);
+        assert_eq!(DisableableKey::from_mask(0xE0), Vec::new());
+        assert_eq!(
+            "win".parse::<DisableableKey>().unwrap(),
+            DisableableKey::Super
+        );
+    }
 }
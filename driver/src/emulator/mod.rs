@@ -0,0 +1,463 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+pub mod mouse;
+pub mod uhid;
+
+use std::{
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError}
+    },
+    time::Duration
+};
+
+use masterror::prelude::*;
+pub use mouse::{MX_MASTER_3S_MODEL_ID, MouseState};
+use tracing::debug;
+
+use self::mouse::Reply;
+use crate::{
+    devices::{PID_BOLT_RECEIVER, RECEIVER_SLOTS, VID_LOGITECH},
+    error::Result,
+    hidpp::{
+        CONNECTION_FLAG_LINK_LOST, DEVICE_INDEX_RECEIVER, ERROR_REPORT_HIDPP10,
+        ERROR_REPORT_HIDPP20, Hidpp10Error, Hidpp20Error, HidppDevice, HidppPacket,
+        NOTIFICATION_DEVICE_CONNECTION, Transport, capture_if_enabled
+    }
+};
+
+pub const EMULATOR_ENV_VAR: &str = "LOGI_MX_EMULATOR";
+
+// Stands in for a hidraw node wherever a path is expected.
+pub const EMULATOR_PATH: &str = "emulator:bolt";
+
+// The slot the emulated mouse is paired in.
+pub const DEFAULT_SLOT: u8 = 2;
+
+// Interface 2 of a Bolt receiver: the short and long HID++ reports on the
+// vendor page 0xFF00.
+pub const RECEIVER_REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x00, 0xFF, 0x09, 0x01, 0xA1, 0x01, 0x85, 0x10, 0x75, 0x08, 0x95, 0x06, 0x15, 0x00,
+    0x26, 0xFF, 0x00, 0x09, 0x01, 0x81, 0x00, 0x09, 0x01, 0x91, 0x00, 0xC0, 0x06, 0x00, 0xFF,
+    0x09, 0x02, 0xA1, 0x01, 0x85, 0x11, 0x75, 0x08, 0x95, 0x13, 0x15, 0x00, 0x26, 0xFF, 0x00,
+    0x09, 0x02, 0x81, 0x00, 0x09, 0x02, 0x91, 0x00, 0xC0
+];
+
+const DEVICE_TYPE_MOUSE: u8 = 0x02;
+const CONNECTION_PROTOCOL: u8 = 0x04;
+
+static SHARED: OnceLock<Emulator> = OnceLock::new();
+
+// Any value except an empty one or "0" switches every front-end over.
+pub fn emulator_enabled() -> bool {
+    std::env::var(EMULATOR_ENV_VAR).is_ok_and(|value| !value.is_empty() && value != "0")
+}
+
+// The process-wide receiver every handle opened through `HidppDevice`
+// talks to, so the daemon, tray and tests all see the same mouse.
+pub fn shared() -> &'static Emulator {
+    SHARED.get_or_init(|| Emulator::with_mouse(DEFAULT_SLOT, MouseState::mx_master_3s()))
+}
+
+// Only the Bolt receiver exists in the emulator; every other product is
+// reported as absent, like unplugged hardware.
+pub(crate) fn open_vid_pid(
+    vendor_id: u16,
+    product_id: u16,
+    device_index: u8
+) -> Result<HidppDevice> {
+    if (vendor_id, product_id) != (VID_LOGITECH, PID_BOLT_RECEIVER) {
+        return Err(AppError::not_found("HID++ interface not found for device"));
+    }
+    shared().open(device_index)
+}
+
+// A Bolt receiver with up to six paired mice. Replies go back to the handle
+// that asked; notifications reach every open handle, as on a hidraw node.
+#[derive(Debug, Default)]
+pub struct ReceiverEmulator {
+    slots:       [Option<MouseState>; 6],
+    subscribers: Vec<Sender<Vec<u8>>>
+}
+
+impl ReceiverEmulator {
+    pub fn handle(&mut self, report: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let request = HidppPacket::from_bytes(report)?;
+        let slot = request.device_index();
+
+        if slot == DEVICE_INDEX_RECEIVER {
+            return Ok(vec![hidpp10_error(&request, Hidpp10Error::InvalidSubId)]);
+        }
+        let Some(mouse) = self.mouse_mut(slot) else {
+            return Ok(vec![hidpp10_error(&request, Hidpp10Error::UnknownDevice)]);
+        };
+        if !mouse.connected {
+            return Ok(vec![hidpp10_error(&request, Hidpp10Error::ResourceError)]);
+        }
+
+        let reply = match mouse.handle(&request) {
+            Reply::Params(params) => {
+                let mut parameters = [0u8; 16];
+                let len = params.len().min(parameters.len());
+                parameters[..len].copy_from_slice(&params[..len]);
+                HidppPacket::new_long(
+                    slot,
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    parameters
+                )
+                .to_bytes()
+            }
+            Reply::Error(error) => hidpp20_error(&request, error),
+            Reply::Silent => {
+                if !mouse.connected {
+                    debug!("Emulated mouse in slot {} left for another host", slot);
+                    self.broadcast(link_event(slot, false));
+                }
+                return Ok(vec![]);
+            }
+        };
+
+        Ok(vec![reply])
+    }
+
+    fn mouse_mut(&mut self, slot: u8) -> Option<&mut MouseState> {
+        RECEIVER_SLOTS
+            .contains(&slot)
+            .then(|| self.slots[slot as usize - 1].as_mut())
+            .flatten()
+    }
+
+    fn broadcast(&mut self, report: Vec<u8>) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(report.clone()).is_ok());
+    }
+}
+
+// Cloneable handle on one emulated receiver.
+#[derive(Debug, Clone, Default)]
+pub struct Emulator {
+    receiver: Arc<Mutex<ReceiverEmulator>>
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Slots outside 1..=6 leave the receiver empty.
+    pub fn with_mouse(slot: u8, mouse: MouseState) -> Self {
+        let emulator = Self::new();
+        if let Err(e) = emulator.pair(slot, mouse) {
+            debug!("Emulated mouse not paired: {}", e);
+        }
+        emulator
+    }
+
+    pub fn connect(&self) -> Result<EmulatorTransport> {
+        let (sender, reports) = mpsc::channel();
+        self.lock()?.subscribers.push(sender.clone());
+
+        Ok(EmulatorTransport {
+            receiver: self.receiver.clone(),
+            sender,
+            reports
+        })
+    }
+
+    pub fn open(&self, device_index: u8) -> Result<HidppDevice> {
+        let transport = capture_if_enabled(Box::new(self.connect()?))?;
+        debug!("Opened emulated receiver with index {}", device_index);
+        Ok(HidppDevice::with_transport(transport, device_index))
+    }
+
+    pub fn pair(&self, slot: u8, mouse: MouseState) -> Result<()> {
+        if !RECEIVER_SLOTS.contains(&slot) {
+            return Err(AppError::bad_request("Receiver slot out of range"));
+        }
+        self.lock()?.slots[slot as usize - 1] = Some(mouse);
+        Ok(())
+    }
+
+    pub fn mouse(&self, slot: u8) -> Result<Option<MouseState>> {
+        Ok(self.lock()?.mouse_mut(slot).map(|mouse| mouse.clone()))
+    }
+
+    pub fn update_mouse(&self, slot: u8, update: impl FnOnce(&mut MouseState)) -> Result<()> {
+        let mut receiver = self.lock()?;
+        let mouse = receiver
+            .mouse_mut(slot)
+            .ok_or_else(|| AppError::not_found("No emulated mouse in slot"))?;
+        update(mouse);
+        Ok(())
+    }
+
+    // Reports the diverted controls among `cids` as held; an empty list
+    // releases them all.
+    pub fn press_buttons(&self, slot: u8, cids: &[u16]) -> Result<()> {
+        let mut receiver = self.lock()?;
+        let event = receiver
+            .mouse_mut(slot)
+            .and_then(|mouse| mouse.pressed_event(slot, cids));
+        if let Some(event) = event {
+            receiver.broadcast(event);
+        }
+        Ok(())
+    }
+
+    // Makes or drops the wireless link. A reconnecting mouse announces
+    // itself and asks to be reconfigured, as the real one does.
+    pub fn set_connected(&self, slot: u8, connected: bool) -> Result<()> {
+        let mut receiver = self.lock()?;
+        let mouse = receiver
+            .mouse_mut(slot)
+            .ok_or_else(|| AppError::not_found("No emulated mouse in slot"))?;
+        mouse.connected = connected;
+        let reconfigure = connected.then(|| mouse.reconfigure_event(slot)).flatten();
+
+        receiver.broadcast(link_event(slot, connected));
+        if let Some(event) = reconfigure {
+            receiver.broadcast(event);
+        }
+        Ok(())
+    }
+
+    pub fn inject(&self, report: Vec<u8>) -> Result<()> {
+        self.lock()?.broadcast(report);
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, ReceiverEmulator>> {
+        self.receiver
+            .lock()
+            .map_err(|_| AppError::internal("Device emulator state poisoned"))
+    }
+}
+
+pub struct EmulatorTransport {
+    receiver: Arc<Mutex<ReceiverEmulator>>,
+    sender:   Sender<Vec<u8>>,
+    reports:  Receiver<Vec<u8>>
+}
+
+impl Transport for EmulatorTransport {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let replies = self
+            .receiver
+            .lock()
+            .map_err(|_| AppError::internal("Device emulator state poisoned"))?
+            .handle(data)?;
+        for reply in replies {
+            let _ = self.sender.send(reply);
+        }
+        Ok(())
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        let report = match timeout_ms {
+            0 => self
+                .reports
+                .try_recv()
+                .map_err(|e| e == TryRecvError::Empty),
+            ms if ms < 0 => self.reports.recv().map_err(|_| false),
+            ms => self
+                .reports
+                .recv_timeout(Duration::from_millis(ms as u64))
+                .map_err(|e| e == RecvTimeoutError::Timeout)
+        };
+
+        match report {
+            Ok(report) => {
+                let size = report.len().min(buf.len());
+                buf[..size].copy_from_slice(&report[..size]);
+                Ok(size)
+            }
+            Err(true) => Ok(0),
+            Err(false) => Err(AppError::internal("Device emulator disconnected"))
+        }
+    }
+}
+
+// HID++ 1.0 error: the failed sub id goes in the function byte and the
+// failed address in parameter 0.
+fn hidpp10_error(request: &HidppPacket, error: Hidpp10Error) -> Vec<u8> {
+    let failed = request.feature_index();
+    HidppPacket::new_short(
+        request.device_index(),
+        ERROR_REPORT_HIDPP10,
+        failed >> 4,
+        failed & 0x0F,
+        [
+            (request.function_id() << 4) | request.software_id(),
+            error.code(),
+            0
+        ]
+    )
+    .to_bytes()
+}
+
+fn hidpp20_error(request: &HidppPacket, error: Hidpp20Error) -> Vec<u8> {
+    let failed = request.feature_index();
+    let mut params = [0u8; 16];
+    params[0] = (request.function_id() << 4) | request.software_id();
+    params[1] = error.code();
+    HidppPacket::new_long(
+        request.device_index(),
+        ERROR_REPORT_HIDPP20,
+        failed >> 4,
+        failed & 0x0F,
+        params
+    )
+    .to_bytes()
+}
+
+// Receiver notification 0x41; the wireless id follows little-endian.
+fn link_event(slot: u8, connected: bool) -> Vec<u8> {
+    let mut flags = DEVICE_TYPE_MOUSE;
+    if !connected {
+        flags |= CONNECTION_FLAG_LINK_LOST;
+    }
+    let [wpid_lo, wpid_hi] = MX_MASTER_3S_MODEL_ID.to_le_bytes();
+    HidppPacket::new_short(
+        slot,
+        NOTIFICATION_DEVICE_CONNECTION,
+        CONNECTION_PROTOCOL >> 4,
+        CONNECTION_PROTOCOL & 0x0F,
+        [flags, wpid_lo, wpid_hi]
+    )
+    .to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{
+        BatteryStatus, ButtonId, DeviceEvent, GenericHidppMouse, MouseDevice, SmartShiftConfig
+    };
+
+    fn mouse(emulator: &Emulator, slot: u8) -> GenericHidppMouse {
+        GenericHidppMouse::identify(emulator.open(slot).unwrap(), None)
+    }
+
+    #[test]
+    fn test_emulated_mouse_is_identified() {
+        let emulator = Emulator::with_mouse(2, MouseState::mx_master_3s());
+        let mut mouse = mouse(&emulator, 2);
+
+        assert_eq!(mouse.spec().map(|s| s.name.as_str()), Some("MX Master 3S"));
+        let identity = mouse.identity().unwrap();
+        assert_eq!(identity.name, "MX Master 3S");
+        assert_eq!(identity.serial.as_deref(), Some("EMU0B0340001"));
+        assert_eq!(identity.wpid, Some(MX_MASTER_3S_MODEL_ID));
+
+        let capabilities = mouse.capabilities().unwrap();
+        assert_eq!(capabilities.hosts, 3);
+        assert!(capabilities.wheel.is_some_and(|wheel| wheel.invertible));
+    }
+
+    #[test]
+    fn test_settings_are_stored() {
+        let emulator = Emulator::with_mouse(2, MouseState::mx_master_3s());
+        let mut mouse = mouse(&emulator, 2);
+
+        mouse.set_dpi(1600).unwrap();
+        assert_eq!(mouse.get_dpi().unwrap(), 1600);
+        mouse
+            .set_smartshift(SmartShiftConfig {
+                enabled:   true,
+                threshold: 30
+            })
+            .unwrap();
+        assert_eq!(mouse.get_smartshift().unwrap().threshold, 30);
+
+        emulator
+            .update_mouse(2, |state| {
+                state.battery_level = 15;
+                state.battery_status = BatteryStatus::Charging;
+            })
+            .unwrap();
+        let battery = mouse.get_battery_info().unwrap();
+        assert_eq!(
+            (battery.level, battery.status),
+            (15, BatteryStatus::Charging)
+        );
+
+        let state = emulator.mouse(2).unwrap().unwrap();
+        assert_eq!((state.dpi, state.auto_disengage), (1600, 30));
+    }
+
+    #[test]
+    fn test_errors_follow_the_protocol() {
+        let emulator = Emulator::with_mouse(2, MouseState::mx_master_3s());
+
+        // Off-step DPI is refused by the device with a HID++ 2.0 error.
+        let mut hidpp = emulator.open(2).unwrap();
+        let dpi_index = hidpp
+            .get_feature_index(crate::hidpp::FEATURE_ADJUSTABLE_DPI)
+            .unwrap();
+        let err = hidpp
+            .send_command(dpi_index, 0x03, &[0, 0x03, 0xE9])
+            .unwrap_err();
+        assert_eq!(err.render_message(), "Invalid argument");
+
+        // Empty slots answer with the receiver's HID++ 1.0 error.
+        let mut empty = emulator.open(3).unwrap();
+        assert!(empty.ping().is_err());
+
+        emulator.set_connected(2, false).unwrap();
+        assert!(hidpp.ping().is_err());
+    }
+
+    #[test]
+    fn test_notifications_reach_every_handle() {
+        let emulator = Emulator::with_mouse(2, MouseState::mx_master_3s());
+        let mut daemon = mouse(&emulator, 2);
+        let mut other = mouse(&emulator, 2);
+
+        daemon
+            .set_button_diverted(ButtonId::ThumbGesture, true)
+            .unwrap();
+        emulator.press_buttons(2, &[0x00C3, 0x0050]).unwrap();
+
+        let expected = DeviceEvent::ButtonsPressed(vec![ButtonId::ThumbGesture]);
+        assert_eq!(daemon.read_event(0).unwrap(), Some(expected.clone()));
+        assert_eq!(other.read_event(0).unwrap(), Some(expected));
+
+        emulator.set_connected(2, false).unwrap();
+        assert_eq!(
+            daemon.read_event(0).unwrap(),
+            Some(DeviceEvent::LinkChanged {
+                connected: false
+            })
+        );
+        emulator.set_connected(2, true).unwrap();
+        assert_eq!(
+            daemon.read_event(0).unwrap(),
+            Some(DeviceEvent::LinkChanged {
+                connected: true
+            })
+        );
+        assert_eq!(
+            daemon.read_event(0).unwrap(),
+            Some(DeviceEvent::ReconfigurationNeeded)
+        );
+    }
+
+    #[test]
+    fn test_host_switch_drops_the_link() {
+        let emulator = Emulator::with_mouse(2, MouseState::mx_master_3s());
+        let mut mouse = mouse(&emulator, 2);
+
+        mouse.change_host(1).unwrap();
+        let state = emulator.mouse(2).unwrap().unwrap();
+        assert_eq!((state.current_host, state.connected), (1, false));
+        assert_eq!(
+            mouse.read_event(0).unwrap(),
+            Some(DeviceEvent::LinkChanged {
+                connected: false
+            })
+        );
+    }
+}
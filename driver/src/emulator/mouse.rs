@@ -0,0 +1,434 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use crate::{
+    devices::BatteryStatus,
+    hidpp::{
+        ChangeHostFunction, DeviceInfoFunction, DpiFunction, FEATURE_ADJUSTABLE_DPI,
+        FEATURE_CHANGE_HOST, FEATURE_DEVICE_INFO, FEATURE_DEVICE_NAME, FEATURE_FEATURE_SET,
+        FEATURE_HIRES_WHEEL, FEATURE_REPROG_CONTROLS, FEATURE_ROOT, FEATURE_SMART_SHIFT,
+        FEATURE_UNIFIED_BATTERY, FEATURE_WIRELESS_DEVICE_STATUS, FeatureSetFunction, Hidpp20Error,
+        HidppPacket, HiresWheelFunction, ReprogControlsFunction, RootFunction, SmartShiftFunction,
+        UnifiedBatteryFunction
+    }
+};
+
+pub const MX_MASTER_3S_MODEL_ID: u16 = 0xB034;
+
+// Feature table of the emulated MX Master 3S; the position is the index.
+const FEATURES: [(u16, u8); 11] = [
+    (FEATURE_ROOT, 2),
+    (FEATURE_FEATURE_SET, 2),
+    (FEATURE_DEVICE_INFO, 4),
+    (FEATURE_DEVICE_NAME, 1),
+    (FEATURE_UNIFIED_BATTERY, 3),
+    (FEATURE_CHANGE_HOST, 1),
+    (FEATURE_REPROG_CONTROLS, 5),
+    (FEATURE_WIRELESS_DEVICE_STATUS, 0),
+    (FEATURE_ADJUSTABLE_DPI, 2),
+    (FEATURE_SMART_SHIFT, 0),
+    (FEATURE_HIRES_WHEEL, 1)
+];
+
+// Control ids with their 0x1B04 flags: left and right click can only be
+// reported as mouse buttons, the rest are reprogrammable and divertable.
+const CONTROLS: [(u16, u8); 7] = [
+    (0x0050, CONTROL_FLAG_MOUSE),
+    (0x0051, CONTROL_FLAG_MOUSE),
+    (
+        0x0052,
+        CONTROL_FLAG_MOUSE | CONTROL_FLAG_REPROGRAMMABLE | CONTROL_FLAG_DIVERTABLE
+    ),
+    (
+        0x0053,
+        CONTROL_FLAG_MOUSE | CONTROL_FLAG_REPROGRAMMABLE | CONTROL_FLAG_DIVERTABLE
+    ),
+    (
+        0x0056,
+        CONTROL_FLAG_MOUSE | CONTROL_FLAG_REPROGRAMMABLE | CONTROL_FLAG_DIVERTABLE
+    ),
+    (
+        0x00C3,
+        CONTROL_FLAG_REPROGRAMMABLE | CONTROL_FLAG_DIVERTABLE | CONTROL_FLAG_VIRTUAL
+    ),
+    (
+        0x00C4,
+        CONTROL_FLAG_REPROGRAMMABLE | CONTROL_FLAG_DIVERTABLE | CONTROL_FLAG_VIRTUAL
+    )
+];
+
+const CONTROL_FLAG_MOUSE: u8 = 0x01;
+const CONTROL_FLAG_REPROGRAMMABLE: u8 = 0x10;
+const CONTROL_FLAG_DIVERTABLE: u8 = 0x20;
+const CONTROL_FLAG_VIRTUAL: u8 = 0x80;
+const REPORTING_FLAG_DIVERT: u8 = 0x01;
+const REPORTING_FLAG_DIVERT_VALID: u8 = 0x02;
+const MAX_PRESSED_CONTROLS: usize = 4;
+
+const DPI_MIN: u16 = 200;
+const DPI_MAX: u16 = 8000;
+const DPI_STEP: u16 = 50;
+const DPI_LIST_STEP_MARKER: u16 = 0xE000;
+const DPI_DEFAULT: u16 = 1000;
+
+const WHEEL_MULTIPLIER: u8 = 15;
+const WHEEL_FLAGS: u8 = 0x0C;
+const SMARTSHIFT_MODE_RATCHET: u8 = 0x02;
+const NAME_CHUNK_SIZE: usize = 15;
+const SERIAL_NUMBER_LEN: usize = 12;
+const TRANSPORT_BLUETOOTH_AND_RECEIVER: u8 = 0x05;
+const CAPABILITY_SERIAL_NUMBER: u8 = 0x01;
+
+// Everything the emulated mouse remembers between requests. Tests and
+// front-ends can read and change it through `Emulator`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MouseState {
+    pub name:                   String,
+    pub serial:                 String,
+    pub unit_id:                [u8; 4],
+    pub connected:              bool,
+    pub battery_level:          u8,
+    pub battery_status:         BatteryStatus,
+    pub dpi:                    u16,
+    pub smartshift_mode:        u8,
+    pub auto_disengage:         u8,
+    pub auto_disengage_default: u8,
+    pub hires_mode:             u8,
+    pub diverted:               Vec<u16>,
+    pub host_count:             u8,
+    pub current_host:           u8
+}
+
+impl MouseState {
+    pub fn mx_master_3s() -> Self {
+        Self {
+            name:                   "MX Master 3S".to_string(),
+            serial:                 "EMU0B0340001".to_string(),
+            unit_id:                [0xE0, 0x0B, 0x03, 0x40],
+            connected:              true,
+            battery_level:          80,
+            battery_status:         BatteryStatus::Discharging,
+            dpi:                    DPI_DEFAULT,
+            smartshift_mode:        SMARTSHIFT_MODE_RATCHET,
+            auto_disengage:         20,
+            auto_disengage_default: 20,
+            hires_mode:             0x00,
+            diverted:               Vec::new(),
+            host_count:             3,
+            current_host:           0
+        }
+    }
+}
+
+// Outcome of a request to the mouse. `Silent` covers requests the device
+// acts on without answering, such as a host switch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reply {
+    Params(Vec<u8>),
+    Error(Hidpp20Error),
+    Silent
+}
+
+pub(crate) fn feature_index(feature_id: u16) -> Option<u8> {
+    FEATURES
+        .iter()
+        .position(|&(id, _)| id == feature_id)
+        .map(|index| index as u8)
+}
+
+impl MouseState {
+    pub(crate) fn handle(&mut self, request: &HidppPacket) -> Reply {
+        let Some(&(feature_id, _)) = FEATURES.get(request.feature_index() as usize) else {
+            return Reply::Error(Hidpp20Error::InvalidFeatureIndex);
+        };
+        let function = request.function_id();
+        let params = request.parameters();
+
+        let reply = match feature_id {
+            FEATURE_ROOT => self.root(function, params),
+            FEATURE_FEATURE_SET => self.feature_set(function, params),
+            FEATURE_DEVICE_INFO => self.device_info(function),
+            FEATURE_DEVICE_NAME if function == 0x00 => {
+                let offset = (params[0] as usize).min(self.name.len());
+                let mut reply = vec![self.name.len() as u8];
+                reply.extend(self.name.bytes().skip(offset).take(NAME_CHUNK_SIZE));
+                Ok(reply)
+            }
+            FEATURE_UNIFIED_BATTERY => self.unified_battery(function),
+            FEATURE_CHANGE_HOST => return self.change_host(function, params),
+            FEATURE_REPROG_CONTROLS => self.reprog_controls(function, params),
+            FEATURE_ADJUSTABLE_DPI => self.dpi(function, params),
+            FEATURE_SMART_SHIFT => self.smartshift(function, params),
+            FEATURE_HIRES_WHEEL => self.hires_wheel(function, params),
+            _ => Err(Hidpp20Error::InvalidFunctionId)
+        };
+
+        match reply {
+            Ok(params) => Reply::Params(params),
+            Err(error) => Reply::Error(error)
+        }
+    }
+
+    fn root(&self, function: u8, params: &[u8]) -> Result<Vec<u8>, Hidpp20Error> {
+        match function {
+            f if f == RootFunction::GetFeature as u8 => {
+                let feature_id = u16::from_be_bytes([params[0], params[1]]);
+                Ok(match feature_index(feature_id) {
+                    Some(index) => vec![index, 0, FEATURES[index as usize].1],
+                    None => vec![0, 0, 0]
+                })
+            }
+            // HID++ 4.5, echoing the ping byte.
+            f if f == RootFunction::Ping as u8 => Ok(vec![4, 5, params[2]]),
+            _ => Err(Hidpp20Error::InvalidFunctionId)
+        }
+    }
+
+    fn feature_set(&self, function: u8, params: &[u8]) -> Result<Vec<u8>, Hidpp20Error> {
+        match function {
+            f if f == FeatureSetFunction::GetCount as u8 => Ok(vec![FEATURES.len() as u8 - 1]),
+            f if f == FeatureSetFunction::GetFeatureId as u8 => {
+                let &(feature_id, version) = FEATURES
+                    .get(params[0] as usize)
+                    .ok_or(Hidpp20Error::OutOfRange)?;
+                let [id_hi, id_lo] = feature_id.to_be_bytes();
+                Ok(vec![id_hi, id_lo, 0, version])
+            }
+            _ => Err(Hidpp20Error::InvalidFunctionId)
+        }
+    }
+
+    fn device_info(&self, function: u8) -> Result<Vec<u8>, Hidpp20Error> {
+        let mut reply = vec![0u8; 16];
+        match function {
+            f if f == DeviceInfoFunction::GetDeviceInfo as u8 => {
+                reply[0] = 1;
+                reply[1..5].copy_from_slice(&self.unit_id);
+                reply[6] = TRANSPORT_BLUETOOTH_AND_RECEIVER;
+                reply[7..9].copy_from_slice(&MX_MASTER_3S_MODEL_ID.to_be_bytes());
+                reply[9..11].copy_from_slice(&MX_MASTER_3S_MODEL_ID.to_be_bytes());
+                reply[14] = CAPABILITY_SERIAL_NUMBER;
+            }
+            // A firmware name no real device uses, so feature caches written
+            // against the emulator never apply to hardware.
+            f if f == DeviceInfoFunction::GetFwInfo as u8 => {
+                reply[1..4].copy_from_slice(b"EMU");
+                reply[4] = 0x01;
+                reply[6..8].copy_from_slice(&0x0001u16.to_be_bytes());
+            }
+            f if f == DeviceInfoFunction::GetDeviceSerialNumber as u8 => {
+                for (slot, byte) in reply[..SERIAL_NUMBER_LEN]
+                    .iter_mut()
+                    .zip(self.serial.bytes())
+                {
+                    *slot = byte;
+                }
+            }
+            _ => return Err(Hidpp20Error::InvalidFunctionId)
+        }
+        Ok(reply)
+    }
+
+    fn change_host(&mut self, function: u8, params: &[u8]) -> Reply {
+        match function {
+            f if f == ChangeHostFunction::GetHostInfo as u8 => {
+                Reply::Params(vec![self.host_count, self.current_host])
+            }
+            f if f == ChangeHostFunction::SetCurrentHost as u8 => {
+                if params[0] >= self.host_count {
+                    return Reply::Error(Hidpp20Error::InvalidArgument);
+                }
+                if params[0] != self.current_host {
+                    self.current_host = params[0];
+                    self.connected = false;
+                }
+                Reply::Silent
+            }
+            _ => Reply::Error(Hidpp20Error::InvalidFunctionId)
+        }
+    }
+
+    fn reprog_controls(&mut self, function: u8, params: &[u8]) -> Result<Vec<u8>, Hidpp20Error> {
+        let control = |cid: u16| {
+            CONTROLS
+                .iter()
+                .find(|&&(id, _)| id == cid)
+                .copied()
+                .ok_or(Hidpp20Error::InvalidArgument)
+        };
+        let cid = u16::from_be_bytes([params[0], params[1]]);
+
+        match function {
+            f if f == ReprogControlsFunction::GetControlCount as u8 => {
+                Ok(vec![CONTROLS.len() as u8])
+            }
+            f if f == ReprogControlsFunction::GetControlInfo as u8 => {
+                let &(cid, flags) = CONTROLS
+                    .get(params[0] as usize)
+                    .ok_or(Hidpp20Error::InvalidArgument)?;
+                let [cid_hi, cid_lo] = cid.to_be_bytes();
+                Ok(vec![
+                    cid_hi,
+                    cid_lo,
+                    cid_hi,
+                    cid_lo,
+                    flags,
+                    params[0] + 1,
+                    0,
+                    0,
+                ])
+            }
+            f if f == ReprogControlsFunction::GetControlReporting as u8 => {
+                control(cid)?;
+                let flags = if self.diverted.contains(&cid) {
+                    REPORTING_FLAG_DIVERT
+                } else {
+                    0
+                };
+                Ok(vec![params[0], params[1], flags, 0, 0])
+            }
+            f if f == ReprogControlsFunction::SetControlReporting as u8 => {
+                let (_, control_flags) = control(cid)?;
+                let flags = params[2];
+                if flags & REPORTING_FLAG_DIVERT_VALID != 0 {
+                    let divert = flags & REPORTING_FLAG_DIVERT != 0;
+                    if divert && control_flags & CONTROL_FLAG_DIVERTABLE == 0 {
+                        return Err(Hidpp20Error::InvalidArgument);
+                    }
+                    self.diverted.retain(|&diverted| diverted != cid);
+                    if divert {
+                        self.diverted.push(cid);
+                    }
+                }
+                Ok(params[..3].to_vec())
+            }
+            _ => Err(Hidpp20Error::InvalidFunctionId)
+        }
+    }
+
+    // Every level flag is supported and the state of charge is reported; the
+    // status is level, level flag, charging status and external power.
+    fn unified_battery(&self, function: u8) -> Result<Vec<u8>, Hidpp20Error> {
+        match function {
+            f if f == UnifiedBatteryFunction::GetCapabilities as u8 => Ok(vec![0x0F, 0x02]),
+            f if f == UnifiedBatteryFunction::GetStatus as u8 => {
+                let level_flag = match self.battery_level {
+                    0..10 => 0x01,
+                    10..30 => 0x02,
+                    30..90 => 0x04,
+                    _ => 0x08
+                };
+                let (status, external_power) = match self.battery_status {
+                    BatteryStatus::Discharging => (0, 0),
+                    BatteryStatus::Charging => (1, 1),
+                    BatteryStatus::Full => (3, 1),
+                    BatteryStatus::Unknown => (4, 0)
+                };
+                Ok(vec![self.battery_level, level_flag, status, external_power])
+            }
+            _ => Err(Hidpp20Error::InvalidFunctionId)
+        }
+    }
+
+    fn dpi(&mut self, function: u8, params: &[u8]) -> Result<Vec<u8>, Hidpp20Error> {
+        if function != DpiFunction::GetSensorCount as u8 && params[0] != 0 {
+            return Err(Hidpp20Error::InvalidArgument);
+        }
+
+        match function {
+            f if f == DpiFunction::GetSensorCount as u8 => Ok(vec![1]),
+            f if f == DpiFunction::GetSensorDpiList as u8 => {
+                let mut reply = vec![0];
+                for value in [DPI_MIN, DPI_LIST_STEP_MARKER + DPI_STEP, DPI_MAX] {
+                    reply.extend_from_slice(&value.to_be_bytes());
+                }
+                Ok(reply)
+            }
+            f if f == DpiFunction::GetSensorDpi as u8 => {
+                let mut reply = vec![0];
+                reply.extend_from_slice(&self.dpi.to_be_bytes());
+                reply.extend_from_slice(&DPI_DEFAULT.to_be_bytes());
+                Ok(reply)
+            }
+            f if f == DpiFunction::SetSensorDpi as u8 => {
+                let dpi = u16::from_be_bytes([params[1], params[2]]);
+                if !(DPI_MIN..=DPI_MAX).contains(&dpi) || !(dpi - DPI_MIN).is_multiple_of(DPI_STEP)
+                {
+                    return Err(Hidpp20Error::InvalidArgument);
+                }
+                self.dpi = dpi;
+                Ok(params[..3].to_vec())
+            }
+            _ => Err(Hidpp20Error::InvalidFunctionId)
+        }
+    }
+
+    // A zero leaves the corresponding setting unchanged.
+    fn smartshift(&mut self, function: u8, params: &[u8]) -> Result<Vec<u8>, Hidpp20Error> {
+        match function {
+            f if f == SmartShiftFunction::GetRatchetControlMode as u8 => Ok(vec![
+                self.smartshift_mode,
+                self.auto_disengage,
+                self.auto_disengage_default,
+            ]),
+            f if f == SmartShiftFunction::SetRatchetControlMode as u8 => {
+                if params[0] > SMARTSHIFT_MODE_RATCHET {
+                    return Err(Hidpp20Error::InvalidArgument);
+                }
+                if params[0] != 0 {
+                    self.smartshift_mode = params[0];
+                }
+                if params[1] != 0 {
+                    self.auto_disengage = params[1];
+                }
+                if params[2] != 0 {
+                    self.auto_disengage_default = params[2];
+                }
+                Ok(params[..3].to_vec())
+            }
+            _ => Err(Hidpp20Error::InvalidFunctionId)
+        }
+    }
+
+    fn hires_wheel(&mut self, function: u8, params: &[u8]) -> Result<Vec<u8>, Hidpp20Error> {
+        match function {
+            f if f == HiresWheelFunction::GetCapabilities as u8 => {
+                Ok(vec![WHEEL_MULTIPLIER, WHEEL_FLAGS])
+            }
+            f if f == HiresWheelFunction::GetMode as u8 => Ok(vec![self.hires_mode]),
+            f if f == HiresWheelFunction::SetMode as u8 => {
+                self.hires_mode = params[0];
+                Ok(vec![self.hires_mode])
+            }
+            f if f == HiresWheelFunction::GetRatchetSwitchState as u8 => Ok(vec![1]),
+            _ => Err(Hidpp20Error::InvalidFunctionId)
+        }
+    }
+
+    // Only diverted controls are reported over HID++; the rest go to the
+    // mouse interface and never reach the driver.
+    pub(crate) fn pressed_event(&self, slot: u8, cids: &[u16]) -> Option<Vec<u8>> {
+        if self.diverted.is_empty() {
+            return None;
+        }
+        let reprog_index = feature_index(FEATURE_REPROG_CONTROLS)?;
+        let mut params = [0u8; 16];
+        for (pair, cid) in params.chunks_exact_mut(2).zip(
+            cids.iter()
+                .filter(|cid| self.diverted.contains(cid))
+                .take(MAX_PRESSED_CONTROLS)
+        ) {
+            pair.copy_from_slice(&cid.to_be_bytes());
+        }
+
+        Some(HidppPacket::new_long(slot, reprog_index, 0x00, 0x00, params).to_bytes())
+    }
+
+    // Sent after a reconnection, asking the host to restore its settings.
+    pub(crate) fn reconfigure_event(&self, slot: u8) -> Option<Vec<u8>> {
+        let status_index = feature_index(FEATURE_WIRELESS_DEVICE_STATUS)?;
+        let mut params = [0u8; 16];
+        params[..3].copy_from_slice(&[0x01, 0x01, 0x00]);
+        Some(HidppPacket::new_long(slot, status_index, 0x00, 0x00, params).to_bytes())
+    }
+}
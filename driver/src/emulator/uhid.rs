@@ -0,0 +1,365 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering}
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant}
+};
+
+use masterror::{field, prelude::*};
+use tracing::{debug, warn};
+
+use super::{Emulator, EmulatorTransport, RECEIVER_REPORT_DESCRIPTOR};
+use crate::{
+    devices::{PID_BOLT_RECEIVER, VID_LOGITECH},
+    error::Result,
+    hidpp::Transport
+};
+
+pub const UHID_PATH: &str = "/dev/uhid";
+
+// Event types from <linux/uhid.h>.
+const UHID_DESTROY: u32 = 1;
+const UHID_OUTPUT: u32 = 6;
+const UHID_GET_REPORT: u32 = 9;
+const UHID_GET_REPORT_REPLY: u32 = 10;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_SET_REPORT: u32 = 13;
+const UHID_SET_REPORT_REPLY: u32 = 14;
+
+// `struct uhid_event` is packed: the u32 type, then a union whose largest
+// member is `uhid_create2_req` (name, phys, uniq, six ids, descriptor).
+const UHID_DATA_MAX: usize = 4096;
+const UHID_EVENT_SIZE: usize = 4 + 128 + 64 + 64 + 2 + 2 + 4 * 4 + UHID_DATA_MAX;
+
+const BUS_USB: u16 = 0x0003;
+const RECEIVER_NAME: &str = "Logitech USB Receiver";
+const MAX_REPORT_SIZE: usize = 64;
+const POLL_INTERVAL_MS: i32 = 10;
+const NODE_TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+// Whether this process may create uhid devices; usually root only, and the
+// `uhid` module has to be loaded.
+pub fn uhid_available() -> bool {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(UHID_PATH)
+        .is_ok()
+}
+
+// A Bolt receiver created in the kernel through uhid and answered by an
+// `Emulator`. The kernel exposes it like the real one, as a hidraw node
+// with udev events, so everything above hidraw runs unchanged. Dropping it
+// unplugs the receiver.
+pub struct UhidDevice {
+    uniq:   String,
+    stop:   Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>
+}
+
+impl UhidDevice {
+    pub fn create(emulator: &Emulator) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(UHID_PATH)
+            .map_err(|e| {
+                AppError::not_found("Failed to open uhid")
+                    .with_field(field::str("path", UHID_PATH))
+                    .with_source(e)
+            })?;
+
+        let uniq = format!(
+            "logi-mx-uhid-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        file.write_all(&create_event(&uniq))
+            .map_err(|e| AppError::internal("Failed to create uhid device").with_source(e))?;
+
+        let transport = emulator.connect()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = thread::Builder::new()
+            .name("uhid".to_string())
+            .spawn({
+                let stop = stop.clone();
+                move || serve(file, transport, &stop)
+            })
+            .map_err(|e| AppError::internal("Failed to start uhid worker").with_source(e))?;
+
+        debug!("Created uhid receiver {}", uniq);
+        Ok(Self {
+            uniq,
+            stop,
+            worker: Some(worker)
+        })
+    }
+
+    // Reported as `HID_UNIQ`, which tells this receiver apart from real ones.
+    pub fn uniq(&self) -> &str {
+        &self.uniq
+    }
+
+    // The kernel adds the hidraw node asynchronously after creation.
+    pub fn hidraw_path(&self) -> Result<String> {
+        let deadline = Instant::now() + NODE_TIMEOUT;
+        loop {
+            if let Some(path) = find_hidraw(&self.uniq) {
+                return Ok(path);
+            }
+            if Instant::now() >= deadline {
+                return Err(AppError::not_found("No hidraw node for uhid device")
+                    .with_field(field::str("uniq", self.uniq.clone())));
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for UhidDevice {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn serve(mut file: File, mut transport: EmulatorTransport, stop: &AtomicBool) {
+    let mut event = vec![0u8; UHID_EVENT_SIZE];
+    while !stop.load(Ordering::Relaxed) {
+        if let Err(e) = serve_once(&mut file, &mut transport, &mut event) {
+            warn!("uhid receiver stopped: {}", e);
+            break;
+        }
+    }
+
+    if let Err(e) = file.write_all(&uhid_event(UHID_DESTROY)) {
+        debug!("Failed to destroy uhid device: {}", e);
+    }
+}
+
+// Answers what the kernel sent, then forwards the replies and every
+// notification the emulator raised as input reports.
+fn serve_once(file: &mut File, transport: &mut EmulatorTransport, event: &mut [u8]) -> Result<()> {
+    if readable(file, POLL_INTERVAL_MS)? {
+        let len = file
+            .read(event)
+            .map_err(|e| AppError::internal("Failed to read uhid event").with_source(e))?;
+        if let Some(reply) = handle_event(transport, &event[..len]) {
+            write_event(file, &reply)?;
+        }
+    }
+
+    let mut report = [0u8; MAX_REPORT_SIZE];
+    loop {
+        let size = transport.read_timeout(&mut report, 0)?;
+        if size == 0 {
+            return Ok(());
+        }
+        write_event(file, &input_event(&report[..size]))?;
+    }
+}
+
+// hidraw writes arrive as output reports, or as SET_REPORT requests the
+// kernel waits on. Start, stop, open and close need no answer.
+fn handle_event(transport: &mut EmulatorTransport, event: &[u8]) -> Option<Vec<u8>> {
+    match read_u32(event, 0)? {
+        UHID_OUTPUT => {
+            let size = (read_u16(event, 4 + UHID_DATA_MAX)? as usize).min(UHID_DATA_MAX);
+            forward(transport, event.get(4..4 + size)?);
+            None
+        }
+        UHID_SET_REPORT => {
+            let id = read_u32(event, 4)?;
+            let size = (read_u16(event, 10)? as usize).min(UHID_DATA_MAX);
+            forward(transport, event.get(12..12 + size)?);
+            Some(report_reply(UHID_SET_REPORT_REPLY, id, 0))
+        }
+        UHID_GET_REPORT => {
+            let id = read_u32(event, 4)?;
+            Some(report_reply(UHID_GET_REPORT_REPLY, id, libc::EIO as u16))
+        }
+        _ => None
+    }
+}
+
+fn forward(transport: &mut EmulatorTransport, report: &[u8]) {
+    if let Err(e) = transport.write(report) {
+        debug!("Unanswered report {:02x?}: {}", report, e);
+    }
+}
+
+fn readable(file: &File, timeout_ms: i32) -> Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd:      file.as_raw_fd(),
+        events:  libc::POLLIN,
+        revents: 0
+    };
+    // SAFETY: `pollfd` is a single valid entry for the duration of the call.
+    let ready = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+    if ready < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() == io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(AppError::internal("Failed to poll uhid").with_source(e));
+    }
+    Ok(ready > 0)
+}
+
+fn write_event(file: &mut File, event: &[u8]) -> Result<()> {
+    file.write_all(event)
+        .map_err(|e| AppError::internal("Failed to write uhid event").with_source(e))
+}
+
+fn uhid_event(kind: u32) -> Vec<u8> {
+    let mut event = vec![0u8; UHID_EVENT_SIZE];
+    event[..4].copy_from_slice(&kind.to_ne_bytes());
+    event
+}
+
+fn put(event: &mut [u8], offset: usize, bytes: &[u8]) {
+    event[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn create_event(uniq: &str) -> Vec<u8> {
+    let mut event = uhid_event(UHID_CREATE2);
+    put(&mut event, 4, RECEIVER_NAME.as_bytes());
+    put(&mut event, 132, b"logi-mx-uhid");
+    put(&mut event, 196, uniq.as_bytes());
+    put(
+        &mut event,
+        260,
+        &(RECEIVER_REPORT_DESCRIPTOR.len() as u16).to_ne_bytes()
+    );
+    put(&mut event, 262, &BUS_USB.to_ne_bytes());
+    put(&mut event, 264, &(VID_LOGITECH as u32).to_ne_bytes());
+    put(&mut event, 268, &(PID_BOLT_RECEIVER as u32).to_ne_bytes());
+    put(&mut event, 280, RECEIVER_REPORT_DESCRIPTOR);
+    event
+}
+
+fn input_event(report: &[u8]) -> Vec<u8> {
+    let mut event = uhid_event(UHID_INPUT2);
+    put(&mut event, 4, &(report.len() as u16).to_ne_bytes());
+    put(&mut event, 6, report);
+    event
+}
+
+fn report_reply(kind: u32, id: u32, err: u16) -> Vec<u8> {
+    let mut event = uhid_event(kind);
+    put(&mut event, 4, &id.to_ne_bytes());
+    put(&mut event, 8, &err.to_ne_bytes());
+    event
+}
+
+fn read_u32(event: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(
+        event.get(offset..offset + 4)?.try_into().ok()?
+    ))
+}
+
+fn read_u16(event: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(
+        event.get(offset..offset + 2)?.try_into().ok()?
+    ))
+}
+
+// Child devices a receiver driver creates share neither the ids nor the
+// unique string, so both together pick out the receiver node.
+fn find_hidraw(uniq: &str) -> Option<String> {
+    let hid_id = format!(
+        "HID_ID={:04X}:{:08X}:{:08X}",
+        BUS_USB, VID_LOGITECH, PID_BOLT_RECEIVER
+    );
+    let hid_uniq = format!("HID_UNIQ={}", uniq);
+
+    fs::read_dir("/sys/class/hidraw")
+        .ok()?
+        .flatten()
+        .find_map(|entry| {
+            let uevent = fs::read_to_string(entry.path().join("device/uevent")).ok()?;
+            let matches = uevent.lines().any(|line| line == hid_id)
+                && uevent.lines().any(|line| line == hid_uniq);
+            let devnode = format!("/dev/{}", entry.file_name().to_string_lossy());
+            (matches && Path::new(&devnode).exists()).then_some(devnode)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{DEFAULT_SLOT, MouseState};
+
+    #[test]
+    fn test_create_event_layout() {
+        let event = create_event("logi-mx-uhid-1-0");
+        assert_eq!(event.len(), 4376);
+        assert_eq!(read_u32(&event, 0), Some(UHID_CREATE2));
+        assert_eq!(&event[4..25], RECEIVER_NAME.as_bytes());
+        assert_eq!(&event[196..212], b"logi-mx-uhid-1-0");
+        assert_eq!(
+            read_u16(&event, 260),
+            Some(RECEIVER_REPORT_DESCRIPTOR.len() as u16)
+        );
+        assert_eq!(read_u16(&event, 262), Some(BUS_USB));
+        assert_eq!(read_u32(&event, 264), Some(0x046D));
+        assert_eq!(read_u32(&event, 268), Some(0xC548));
+        assert_eq!(
+            &event[280..280 + RECEIVER_REPORT_DESCRIPTOR.len()],
+            RECEIVER_REPORT_DESCRIPTOR
+        );
+    }
+
+    #[test]
+    fn test_kernel_requests_reach_the_emulator() {
+        let emulator = Emulator::with_mouse(DEFAULT_SLOT, MouseState::mx_master_3s());
+        let mut transport = emulator.connect().unwrap();
+        let ping = [0x10, DEFAULT_SLOT, 0x00, 0x1A, 0x00, 0x00, 0x5A];
+
+        let mut output = uhid_event(UHID_OUTPUT);
+        put(&mut output, 4, &ping);
+        put(
+            &mut output,
+            4 + UHID_DATA_MAX,
+            &(ping.len() as u16).to_ne_bytes()
+        );
+        assert_eq!(handle_event(&mut transport, &output), None);
+
+        let mut reply = [0u8; MAX_REPORT_SIZE];
+        let size = transport.read_timeout(&mut reply, 0).unwrap();
+        assert_eq!(&reply[..3], &[0x11, DEFAULT_SLOT, 0x00]);
+        assert_eq!(reply[6], 0x5A);
+        assert_eq!(size, 20);
+
+        // SET_REPORT is answered so the kernel does not wait it out.
+        let mut set_report = uhid_event(UHID_SET_REPORT);
+        put(&mut set_report, 4, &7u32.to_ne_bytes());
+        put(&mut set_report, 10, &(ping.len() as u16).to_ne_bytes());
+        put(&mut set_report, 12, &ping);
+        let answer = handle_event(&mut transport, &set_report).unwrap();
+        assert_eq!(read_u32(&answer, 0), Some(UHID_SET_REPORT_REPLY));
+        assert_eq!(read_u32(&answer, 4), Some(7));
+        assert_eq!(read_u16(&answer, 8), Some(0));
+        assert_eq!(transport.read_timeout(&mut reply, 0).unwrap(), 20);
+
+        let mut get_report = uhid_event(UHID_GET_REPORT);
+        put(&mut get_report, 4, &8u32.to_ne_bytes());
+        let answer = handle_event(&mut transport, &get_report).unwrap();
+        assert_eq!(read_u32(&answer, 0), Some(UHID_GET_REPORT_REPLY));
+        assert_eq!(read_u16(&answer, 8), Some(libc::EIO as u16));
+    }
+}
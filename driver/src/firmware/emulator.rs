@@ -0,0 +1,269 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::sync::{Arc, Mutex};
+
+use masterror::prelude::*;
+
+use super::{DeviceFirmware, DfuStatus, FirmwareEntityKind};
+use crate::{
+    error::Result,
+    hidpp::{
+        DeviceInfoFunction, DfuControlFunction, DfuFunction, FEATURE_DEVICE_INFO, FEATURE_DFU,
+        FEATURE_DFU_CONTROL_BOLT, HidppPacket, ROOT_INDEX, RootFunction, ScriptedTransport
+    }
+};
+
+pub const EMULATED_INDEX: u8 = 0xFF;
+
+const DEVICE_INFO_INDEX: u8 = 0x01;
+const DFU_INDEX: u8 = 0x02;
+const DFU_CONTROL_INDEX: u8 = 0x03;
+const STATUS_DFU_NOT_STARTED: u8 = 0x14;
+const STATUS_UNSUPPORTED_COMMAND: u8 = 0x16;
+const STATUS_FIRMWARE_CHECK_FAILED: u8 = 0x1F;
+
+// Models a device that starts in its application, exposing 0x00C3 to reboot
+// into the bootloader, which then offers the DFU feature and validates block
+// order the way the real one does. A real bootloader learns the image length
+// from the image itself; the emulator is told it.
+#[derive(Debug, Clone, Default)]
+pub struct DfuEmulator {
+    firmware:        DeviceFirmware,
+    total_blocks:    usize,
+    in_bootloader:   bool,
+    accepted:        usize,
+    started:         bool,
+    restarted:       bool,
+    packets:         usize,
+    sessions:        usize,
+    busy_block:      Option<usize>,
+    lost_block:      Option<usize>,
+    dropped_replies: Option<(usize, usize)>
+}
+
+impl DfuEmulator {
+    pub fn new(firmware: &DeviceFirmware, total_blocks: usize) -> Self {
+        Self {
+            firmware: firmware.clone(),
+            total_blocks,
+            ..Self::default()
+        }
+    }
+
+    pub fn transport(emulator: Arc<Mutex<Self>>) -> ScriptedTransport {
+        ScriptedTransport::new(move |report| {
+            emulator
+                .lock()
+                .map_err(|_| AppError::internal("Firmware emulator state poisoned"))?
+                .handle(report)
+        })
+    }
+
+    pub fn packets(&self) -> usize {
+        self.packets
+    }
+
+    pub fn restarted(&self) -> bool {
+        self.restarted
+    }
+
+    pub fn in_bootloader(&self) -> bool {
+        self.in_bootloader
+    }
+
+    pub fn sessions(&self) -> usize {
+        self.sessions
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_busy_block(mut self, block: usize) -> Self {
+        self.busy_block = Some(block);
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_lost_block(mut self, block: usize) -> Self {
+        self.lost_block = Some(block);
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_dropped_replies(mut self, block: usize, count: usize) -> Self {
+        self.dropped_replies = Some((block, count));
+        self
+    }
+
+    pub fn handle(&mut self, report: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let request = HidppPacket::from_bytes(report)?;
+        self.packets += 1;
+
+        let params = request.parameters();
+        let reply = match (request.feature_index(), request.function_id()) {
+            (ROOT_INDEX, f) if f == RootFunction::GetFeature as u8 => {
+                let index = match u16::from_be_bytes([params[0], params[1]]) {
+                    FEATURE_DEVICE_INFO => DEVICE_INFO_INDEX,
+                    FEATURE_DFU if self.in_bootloader => DFU_INDEX,
+                    FEATURE_DFU_CONTROL_BOLT if !self.in_bootloader => DFU_CONTROL_INDEX,
+                    _ => 0
+                };
+                vec![index]
+            }
+            (DFU_CONTROL_INDEX, f)
+                if f == DfuControlFunction::SetDfuControl as u8 && !self.in_bootloader =>
+            {
+                if params[0] == 0x01 && &params[4..7] == b"DFU" {
+                    self.in_bootloader = true;
+                }
+                return Ok(vec![]);
+            }
+            (ROOT_INDEX, f) if f == RootFunction::Ping as u8 => params.to_vec(),
+            (DEVICE_INFO_INDEX, f) if f == DeviceInfoFunction::GetDeviceInfo as u8 => {
+                let mut info = vec![0u8; 16];
+                info[0] = self.firmware.entities.len() as u8;
+                for (slot, pid) in info[7..13]
+                    .chunks_exact_mut(2)
+                    .zip(&self.firmware.model_ids)
+                {
+                    slot.copy_from_slice(&pid.to_be_bytes());
+                }
+                info
+            }
+            (DEVICE_INFO_INDEX, f) if f == DeviceInfoFunction::GetFwInfo as u8 => {
+                let mut info = vec![0u8; 16];
+                if let Some(entity) = self.firmware.entities.get(params[0] as usize) {
+                    info[0] = match entity.kind {
+                        FirmwareEntityKind::MainApplication => 0,
+                        FirmwareEntityKind::Bootloader => 1,
+                        FirmwareEntityKind::Hardware => 2,
+                        FirmwareEntityKind::Other(kind) => kind
+                    };
+                    let prefix = entity.version.prefix.as_bytes();
+                    let len = prefix.len().min(3);
+                    info[1..1 + len].copy_from_slice(&prefix[..len]);
+                    info[4] = entity.version.major;
+                    info[5] = entity.version.minor;
+                    info[6..8].copy_from_slice(&entity.version.build.to_be_bytes());
+                }
+                info
+            }
+            (DFU_INDEX, _) if !self.in_bootloader => {
+                self.status(DfuStatus::Error(STATUS_UNSUPPORTED_COMMAND))
+            }
+            (DFU_INDEX, f) if f == DfuFunction::Restart as u8 => {
+                self.restarted = true;
+                return Ok(vec![]);
+            }
+            (DFU_INDEX, f) if f == DfuFunction::DfuStart as u8 => self.start(params),
+            (DFU_INDEX, f) if f < DfuFunction::DfuStart as u8 => {
+                let Some(reply) = self.data(f) else {
+                    return Ok(vec![]);
+                };
+                reply
+            }
+            _ => self.status(DfuStatus::Error(STATUS_UNSUPPORTED_COMMAND))
+        };
+
+        let mut response = reply.clone();
+        response.resize(16, 0);
+        let mut parameters = [0u8; 16];
+        parameters.copy_from_slice(&response);
+        let mut replies = vec![
+            HidppPacket::new_long(
+                request.device_index(),
+                request.feature_index(),
+                request.function_id(),
+                request.software_id(),
+                parameters
+            )
+            .to_bytes(),
+        ];
+
+        if request.feature_index() == DFU_INDEX && reply.get(4) == Some(&0x03) {
+            // The deferred result arrives as a notification (software id 0).
+            let mut event = [0u8; 16];
+            event[..4].copy_from_slice(&(self.accepted as u32).to_be_bytes());
+            event[4] = u8::from(self.block_status());
+            replies.push(
+                HidppPacket::new_long(
+                    request.device_index(),
+                    DFU_INDEX,
+                    request.function_id(),
+                    0,
+                    event
+                )
+                .to_bytes()
+            );
+        }
+
+        Ok(replies)
+    }
+
+    fn start(&mut self, header: &[u8]) -> Vec<u8> {
+        self.sessions += 1;
+        self.accepted = 0;
+        self.started = false;
+
+        if !self
+            .firmware
+            .entities
+            .iter()
+            .any(|entity| entity.index == header[0])
+        {
+            return self.status(DfuStatus::Error(STATUS_FIRMWARE_CHECK_FAILED));
+        }
+
+        self.started = true;
+        self.status(DfuStatus::PacketSuccess)
+    }
+
+    fn data(&mut self, function: u8) -> Option<Vec<u8>> {
+        if !self.started {
+            return Some(self.status(DfuStatus::Error(STATUS_DFU_NOT_STARTED)));
+        }
+
+        let expected = (self.accepted % 4) as u8;
+        let previous = ((self.accepted + 3) % 4) as u8;
+        if function == expected {
+            if self.lost_block.take_if(|b| *b == self.accepted).is_some() {
+                return Some(self.status(DfuStatus::PacketSuccess));
+            }
+
+            self.accepted += 1;
+        } else if function != previous || self.accepted == 0 {
+            return Some(self.status(DfuStatus::BadSequence));
+        }
+
+        if let Some((block, count)) = self.dropped_replies
+            && block + 1 == self.accepted
+            && count > 0
+        {
+            self.dropped_replies = Some((block, count - 1));
+            return None;
+        }
+
+        if self
+            .busy_block
+            .take_if(|b| *b + 1 == self.accepted)
+            .is_some()
+        {
+            return Some(self.status(DfuStatus::WaitForEvent));
+        }
+
+        Some(self.status(self.block_status()))
+    }
+
+    fn block_status(&self) -> DfuStatus {
+        if self.accepted == self.total_blocks {
+            DfuStatus::DfuSuccess
+        } else {
+            DfuStatus::PacketSuccess
+        }
+    }
+
+    fn status(&self, status: DfuStatus) -> Vec<u8> {
+        let mut reply = (self.accepted as u32).to_be_bytes().to_vec();
+        reply.push(u8::from(status));
+        reply
+    }
+}
@@ -0,0 +1,179 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+
+use masterror::{field, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use super::DeviceFirmware;
+use crate::error::Result;
+
+pub const DFU_BLOCK_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareVersion {
+    pub prefix: String,
+    pub major:  u8,
+    pub minor:  u8,
+    pub build:  u16
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{:02x}.{:02x}_B{:04x}",
+            self.prefix, self.major, self.minor, self.build
+        )
+    }
+}
+
+// A .dfu file as Logitech ships it (and fwupd flashes it) is streamed to the
+// device unchanged, 16 bytes at a time. The first block opens the session:
+// the firmware entity it replaces, the encryption flag, then the firmware
+// prefix (three ASCII bytes) and the model id it was built for, which are
+// checked against the device before it is rebooted. The signature is inside
+// the blob and verified by the bootloader itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareImage {
+    pub entity:   u8,
+    pub prefix:   String,
+    pub model_id: u16,
+    data:         Vec<u8>
+}
+
+impl FirmwareImage {
+    pub fn parse(data: Vec<u8>) -> Result<Self> {
+        if data.len() < 2 * DFU_BLOCK_SIZE || !data.len().is_multiple_of(DFU_BLOCK_SIZE) {
+            return Err(AppError::bad_request("Firmware image has an invalid size")
+                .with_field(field::u64("image_size", data.len() as u64)));
+        }
+
+        Ok(Self {
+            entity: data[0],
+            prefix: String::from_utf8_lossy(&data[2..5])
+                .trim_end_matches('\0')
+                .to_string(),
+            model_id: u16::from_be_bytes([data[5], data[6]]),
+            data
+        })
+    }
+
+    pub fn check_device(&self, firmware: &DeviceFirmware) -> Result<()> {
+        if !firmware.model_ids.contains(&self.model_id) {
+            return Err(
+                AppError::bad_request("Firmware image is built for another model")
+                    .with_field(field::u64("image_model_id", self.model_id as u64))
+            );
+        }
+
+        let Some(entity) = firmware
+            .entities
+            .iter()
+            .find(|entity| entity.index == self.entity)
+        else {
+            return Err(AppError::bad_request(
+                "Firmware image targets an entity the device does not have"
+            )
+            .with_field(field::u64("entity", self.entity as u64)));
+        };
+
+        if entity.version.prefix != self.prefix {
+            return Err(AppError::bad_request(
+                "Firmware image does not match the installed firmware"
+            )
+            .with_field(field::str("image_prefix", self.prefix.clone()))
+            .with_field(field::str("device_prefix", entity.version.prefix.clone())));
+        }
+
+        Ok(())
+    }
+
+    pub fn header(&self) -> &[u8] {
+        &self.data[..DFU_BLOCK_SIZE]
+    }
+
+    // Blocks after the one sent with dfuStart.
+    pub fn block_count(&self) -> usize {
+        self.data.len() / DFU_BLOCK_SIZE - 1
+    }
+
+    pub fn block(&self, index: usize) -> &[u8] {
+        let start = (index + 1) * DFU_BLOCK_SIZE;
+        &self.data[start..start + DFU_BLOCK_SIZE]
+    }
+}
+
+// An image for the test devices: model 0xB034 running "MPM" firmware.
+#[cfg(test)]
+pub(crate) fn build_test_image(entity: u8, blocks: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = (0..blocks * DFU_BLOCK_SIZE).map(|i| i as u8).collect();
+    data[0] = entity;
+    data[2..5].copy_from_slice(b"MPM");
+    data[5..7].copy_from_slice(&0xB034u16.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::{FirmwareEntity, FirmwareEntityKind};
+
+    fn device_firmware(entities: u8) -> DeviceFirmware {
+        DeviceFirmware {
+            model_ids: vec![0xB034],
+            entities:  (0..entities)
+                .map(|index| FirmwareEntity {
+                    index,
+                    kind: FirmwareEntityKind::from(index),
+                    version: FirmwareVersion {
+                        prefix: "MPM".to_string(),
+                        major:  0x19,
+                        minor:  0x01,
+                        build:  0x0021
+                    }
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_parse_raw_image() {
+        let data = build_test_image(0, 6);
+        let image = FirmwareImage::parse(data.clone()).unwrap();
+
+        assert_eq!(image.entity, 0);
+        assert_eq!(image.prefix, "MPM");
+        assert_eq!(image.model_id, 0xB034);
+        assert_eq!(image.header(), &data[..16]);
+        assert_eq!(image.block_count(), 5);
+        assert_eq!(image.block(4), &data[80..96]);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_size() {
+        assert!(FirmwareImage::parse(build_test_image(0, 1)).is_err());
+
+        let mut data = build_test_image(0, 4);
+        data.pop();
+        assert!(FirmwareImage::parse(data).is_err());
+    }
+
+    #[test]
+    fn test_check_device() {
+        let image = FirmwareImage::parse(build_test_image(1, 2)).unwrap();
+        assert!(image.check_device(&device_firmware(2)).is_ok());
+        assert!(image.check_device(&device_firmware(1)).is_err());
+
+        let mut other_model = build_test_image(1, 2);
+        other_model[5..7].copy_from_slice(&0x4082u16.to_be_bytes());
+        let image = FirmwareImage::parse(other_model).unwrap();
+        assert!(image.check_device(&device_firmware(2)).is_err());
+
+        let mut other_prefix = build_test_image(1, 2);
+        other_prefix[2..5].copy_from_slice(b"BOT");
+        let image = FirmwareImage::parse(other_prefix).unwrap();
+        assert!(image.check_device(&device_firmware(2)).is_err());
+    }
+}
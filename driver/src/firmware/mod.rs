@@ -0,0 +1,657 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+pub mod emulator;
+pub mod image;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant}
+};
+
+pub use emulator::DfuEmulator;
+pub use image::*;
+use masterror::{field, prelude::*};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    error::{DeviceErrorKind, Result},
+    hidpp::{
+        DEVICE_INDEX_RECEIVER, DeviceInfoFunction, DfuControlFunction, DfuFunction,
+        FEATURE_DEVICE_INFO, FEATURE_DFU, FEATURE_DFU_CONTROL_BOLT, FEATURE_DFU_CONTROL_SIGNED,
+        HidppDevice, HidppPacket
+    }
+};
+
+const VID_LOGITECH: u16 = 0x046D;
+const PID_BOLT_RECEIVER: u16 = 0xC548;
+
+const DATA_FUNCTIONS: [DfuFunction; 4] = [
+    DfuFunction::DfuCmdData0,
+    DfuFunction::DfuCmdData1,
+    DfuFunction::DfuCmdData2,
+    DfuFunction::DfuCmdData3
+];
+const MAX_SESSION_ATTEMPTS: usize = 2;
+const MAX_STALLED_REPLIES: usize = 3;
+const DFU_EVENT_TIMEOUT_MS: u64 = 10_000;
+const BOOTLOADER_TIMEOUT_MS: u64 = 10_000;
+const BOOTLOADER_POLL_MS: u64 = 250;
+// setDfuControl: enterDfu, then the "DFU" magic the device checks.
+const ENTER_DFU: [u8; 7] = [0x01, 0x00, 0x00, 0x00, b'D', b'F', b'U'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DfuStatus {
+    PacketSuccess,
+    DfuSuccess,
+    WaitForEvent,
+    EntityRestartRequired,
+    SystemRestartRequired,
+    BadSequence,
+    Error(u8)
+}
+
+impl From<u8> for DfuStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => DfuStatus::PacketSuccess,
+            0x02 => DfuStatus::DfuSuccess,
+            0x03 => DfuStatus::WaitForEvent,
+            0x05 => DfuStatus::EntityRestartRequired,
+            0x06 => DfuStatus::SystemRestartRequired,
+            0x15 => DfuStatus::BadSequence,
+            other => DfuStatus::Error(other)
+        }
+    }
+}
+
+impl From<DfuStatus> for u8 {
+    fn from(status: DfuStatus) -> Self {
+        match status {
+            DfuStatus::PacketSuccess => 0x01,
+            DfuStatus::DfuSuccess => 0x02,
+            DfuStatus::WaitForEvent => 0x03,
+            DfuStatus::EntityRestartRequired => 0x05,
+            DfuStatus::SystemRestartRequired => 0x06,
+            DfuStatus::BadSequence => 0x15,
+            DfuStatus::Error(code) => code
+        }
+    }
+}
+
+impl DfuStatus {
+    pub fn is_complete(self) -> bool {
+        matches!(
+            self,
+            DfuStatus::DfuSuccess
+                | DfuStatus::EntityRestartRequired
+                | DfuStatus::SystemRestartRequired
+        )
+    }
+
+    fn describe(code: u8) -> &'static str {
+        match code {
+            0x04 => "generic error",
+            0x11 => "bad voltage",
+            0x12 => "unsupported encryption mode",
+            0x13 => "failed to erase flash",
+            0x14 => "DFU not started",
+            0x16 => "unsupported command",
+            0x17 => "command in progress",
+            0x18 => "address out of range",
+            0x19 => "unaligned address",
+            0x1A => "bad size",
+            0x1B => "missing program data",
+            0x1C => "missing check data",
+            0x1D => "program failed to write",
+            0x1E => "program failed to verify",
+            0x1F => "firmware check failed",
+            0x20 => "blocked command, restart required",
+            _ => "unknown error"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DfuProgress {
+    pub block: usize,
+    pub total: usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FirmwareEntityKind {
+    MainApplication,
+    Bootloader,
+    Hardware,
+    Other(u8)
+}
+
+impl From<u8> for FirmwareEntityKind {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => FirmwareEntityKind::MainApplication,
+            1 => FirmwareEntityKind::Bootloader,
+            2 => FirmwareEntityKind::Hardware,
+            other => FirmwareEntityKind::Other(other)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareEntity {
+    pub index:   u8,
+    pub kind:    FirmwareEntityKind,
+    pub version: FirmwareVersion
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceFirmware {
+    pub model_ids: Vec<u16>,
+    pub entities:  Vec<FirmwareEntity>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DryRunReport {
+    pub blocks:    usize,
+    pub packets:   usize,
+    pub status:    DfuStatus,
+    pub restarted: bool
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DfuReply {
+    counter: usize,
+    status:  DfuStatus
+}
+
+impl DfuReply {
+    fn from_packet(packet: &HidppPacket) -> Result<Self> {
+        let params = packet.parameters();
+        if params.len() < 5 {
+            return Err(DeviceErrorKind::InvalidResponse.into());
+        }
+
+        Ok(Self {
+            counter: u32::from_be_bytes([params[0], params[1], params[2], params[3]]) as usize,
+            status:  DfuStatus::from(params[4])
+        })
+    }
+}
+
+pub fn read_device_firmware(hidpp: &mut HidppDevice) -> Result<DeviceFirmware> {
+    let feature_index = hidpp.get_feature_index(FEATURE_DEVICE_INFO)?;
+
+    let response =
+        hidpp.send_command(feature_index, DeviceInfoFunction::GetDeviceInfo as u8, &[])?;
+    let params = response.parameters();
+    if params.len() < 13 {
+        return Err(DeviceErrorKind::InvalidResponse.into());
+    }
+
+    let entity_count = params[0];
+    let model_ids = params[7..13]
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .filter(|&pid| pid != 0)
+        .collect();
+
+    let mut entities = Vec::with_capacity(entity_count as usize);
+    for index in 0..entity_count {
+        let response =
+            hidpp.send_command(feature_index, DeviceInfoFunction::GetFwInfo as u8, &[index])?;
+        let params = response.parameters();
+        if params.len() < 8 {
+            return Err(DeviceErrorKind::InvalidResponse.into());
+        }
+
+        entities.push(FirmwareEntity {
+            index,
+            kind: FirmwareEntityKind::from(params[0]),
+            version: FirmwareVersion {
+                prefix: String::from_utf8_lossy(&params[1..4])
+                    .trim_end_matches('\0')
+                    .to_string(),
+                major:  params[4],
+                minor:  params[5],
+                build:  u16::from_be_bytes([params[6], params[7]])
+            }
+        });
+    }
+
+    Ok(DeviceFirmware {
+        model_ids,
+        entities
+    })
+}
+
+pub struct Dfu<'a> {
+    hidpp:         &'a mut HidppDevice,
+    feature_index: u8
+}
+
+impl<'a> Dfu<'a> {
+    // A device running its application is rebooted into the bootloader
+    // first, which is the only firmware that exposes 0x00D0. Only a device
+    // that reports 0x00D0 as unsupported is rebooted; a failed lookup is
+    // returned as is.
+    pub fn open(hidpp: &'a mut HidppDevice) -> Result<Self> {
+        let feature_index = match hidpp.find_feature_index(FEATURE_DFU)? {
+            Some(index) => index,
+            None => enter_bootloader(hidpp)?
+        };
+
+        Ok(Self {
+            hidpp,
+            feature_index
+        })
+    }
+
+    pub fn update<F>(&mut self, image: &FirmwareImage, mut progress: F) -> Result<DfuStatus>
+    where
+        F: FnMut(DfuProgress)
+    {
+        let mut attempt = 0;
+        let status = loop {
+            attempt += 1;
+            match self.stream(image, &mut progress) {
+                Ok(status) => break status,
+                Err(e) if attempt < MAX_SESSION_ATTEMPTS && e.kind == AppErrorKind::Timeout => {
+                    warn!("Firmware transfer interrupted, restarting session: {}", e);
+                }
+                Err(e) => return Err(e)
+            }
+        };
+
+        // The device reboots into the new firmware and never answers.
+        self.hidpp.send_command_no_reply(
+            self.feature_index,
+            DfuFunction::Restart as u8,
+            &[image.entity]
+        )?;
+
+        info!(
+            "Firmware entity {} written, device restarting",
+            image.entity
+        );
+        Ok(status)
+    }
+
+    fn stream<F>(&mut self, image: &FirmwareImage, progress: &mut F) -> Result<DfuStatus>
+    where
+        F: FnMut(DfuProgress)
+    {
+        let total = image.block_count();
+
+        let reply = self.send_block(DfuFunction::DfuStart, image.header())?;
+        let reply = self.settle(reply)?;
+        if reply.status != DfuStatus::PacketSuccess {
+            return Err(self.status_error(reply.status, 0));
+        }
+        debug!("DFU session started for {} blocks", total);
+
+        let mut next = 0;
+        let mut stalled = 0;
+        let mut status = reply.status;
+        while next < total {
+            let function = DATA_FUNCTIONS[next % DATA_FUNCTIONS.len()];
+            let reply = self.send_block(function, image.block(next))?;
+            let reply = self.settle(reply)?;
+
+            match reply.status {
+                DfuStatus::Error(_) => return Err(self.status_error(reply.status, next)),
+                DfuStatus::BadSequence => {
+                    warn!("Device reported a sequence error at block {}", next)
+                }
+                _ => {}
+            }
+
+            // The counter is the number of blocks the device has accepted, so
+            // a lost or rejected block is resent from where the device is.
+            if reply.counter > next + 1 {
+                return Err(AppError::internal("Device acknowledged blocks never sent")
+                    .with_field(field::u64("block", next as u64))
+                    .with_field(field::u64("counter", reply.counter as u64)));
+            }
+            if reply.counter == next + 1 {
+                stalled = 0;
+            } else {
+                stalled += 1;
+                if stalled > MAX_STALLED_REPLIES {
+                    return Err(
+                        AppError::internal("Device stopped accepting firmware blocks")
+                            .with_field(field::u64("block", next as u64))
+                    );
+                }
+                warn!("Resuming firmware transfer at block {}", reply.counter);
+            }
+
+            next = reply.counter;
+            status = reply.status;
+            progress(DfuProgress {
+                block: next,
+                total
+            });
+        }
+
+        if !status.is_complete() {
+            return Err(AppError::internal(
+                "Device did not confirm the firmware after the last block"
+            ));
+        }
+
+        Ok(status)
+    }
+
+    // Never retried: a block whose reply was lost may already be written, and
+    // a timeout restarts the whole session instead.
+    fn send_block(&mut self, function: DfuFunction, block: &[u8]) -> Result<DfuReply> {
+        let response = self
+            .hidpp
+            .send_command_once(self.feature_index, function as u8, block)?;
+        DfuReply::from_packet(&response)
+    }
+
+    fn settle(&mut self, reply: DfuReply) -> Result<DfuReply> {
+        if reply.status != DfuStatus::WaitForEvent {
+            return Ok(reply);
+        }
+
+        debug!("Waiting for DFU event");
+        let deadline = Instant::now() + Duration::from_millis(DFU_EVENT_TIMEOUT_MS);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Some(packet) = self.hidpp.read_notification(remaining.as_millis() as i32)? else {
+                return Err(DeviceErrorKind::Timeout.into());
+            };
+
+            if packet.feature_index() == self.feature_index {
+                return DfuReply::from_packet(&packet);
+            }
+        }
+    }
+
+    fn status_error(&self, status: DfuStatus, block: usize) -> AppError {
+        let code = u8::from(status);
+        AppError::internal(format!(
+            "Firmware update failed: {}",
+            DfuStatus::describe(code)
+        ))
+        .with_field(field::u64("dfu_status", code as u64))
+        .with_field(field::u64("block", block as u64))
+    }
+}
+
+fn enter_bootloader(hidpp: &mut HidppDevice) -> Result<u8> {
+    let control = match hidpp.find_feature_index(FEATURE_DFU_CONTROL_BOLT)? {
+        Some(index) => index,
+        None => hidpp
+            .find_feature_index(FEATURE_DFU_CONTROL_SIGNED)?
+            .ok_or_else(|| {
+                AppError::bad_request("Device supports neither DFU nor entering the bootloader")
+            })?
+    };
+
+    info!("Restarting device into its bootloader");
+    // The device reboots before it could answer.
+    hidpp.send_command_no_reply(control, DfuControlFunction::SetDfuControl as u8, &ENTER_DFU)?;
+
+    let deadline = Instant::now() + Duration::from_millis(BOOTLOADER_TIMEOUT_MS);
+    loop {
+        hidpp.reset_feature_cache();
+        match hidpp.get_feature_index(FEATURE_DFU) {
+            Ok(index) => return Ok(index),
+            Err(e) if Instant::now() >= deadline => {
+                return Err(
+                    AppError::timeout("Device did not restart into its bootloader").with_source(e)
+                );
+            }
+            Err(e) => debug!("Bootloader not up yet: {}", e)
+        }
+        std::thread::sleep(Duration::from_millis(BOOTLOADER_POLL_MS));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DfuTarget {
+    Device(u8),
+    Receiver
+}
+
+// The Bolt receiver runs DFU on its own device index.
+pub fn open_target(target: DfuTarget) -> Result<HidppDevice> {
+    let device_index = match target {
+        DfuTarget::Device(index) => index,
+        DfuTarget::Receiver => DEVICE_INDEX_RECEIVER
+    };
+
+    let mut hidpp = HidppDevice::open_vid_pid(VID_LOGITECH, PID_BOLT_RECEIVER, device_index)?;
+    hidpp.ping()?;
+    Ok(hidpp)
+}
+
+pub fn update_firmware<F>(
+    hidpp: &mut HidppDevice,
+    image: &FirmwareImage,
+    progress: F
+) -> Result<DfuStatus>
+where
+    F: FnMut(DfuProgress)
+{
+    let firmware = read_device_firmware(hidpp)?;
+    image.check_device(&firmware)?;
+
+    Dfu::open(hidpp)?.update(image, progress)
+}
+
+// Runs the full update sequence against an emulation of `firmware`, read
+// from the real device, so the image and the transfer can be validated
+// before that device is touched.
+pub fn dry_run<F>(
+    image: &FirmwareImage,
+    firmware: &DeviceFirmware,
+    progress: F
+) -> Result<DryRunReport>
+where
+    F: FnMut(DfuProgress)
+{
+    let emulator = Arc::new(Mutex::new(DfuEmulator::new(firmware, image.block_count())));
+    dry_run_with(image, emulator, progress)
+}
+
+fn dry_run_with<F>(
+    image: &FirmwareImage,
+    emulator: Arc<Mutex<DfuEmulator>>,
+    progress: F
+) -> Result<DryRunReport>
+where
+    F: FnMut(DfuProgress)
+{
+    let transport = DfuEmulator::transport(emulator.clone());
+    let mut hidpp = HidppDevice::with_transport(Box::new(transport), emulator::EMULATED_INDEX);
+
+    let status = update_firmware(&mut hidpp, image, progress)?;
+
+    let emulator = emulator
+        .lock()
+        .map_err(|_| AppError::internal("Firmware emulator state poisoned"))?;
+
+    Ok(DryRunReport {
+        blocks: image.block_count(),
+        packets: emulator.packets(),
+        status,
+        restarted: emulator.restarted()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hidpp::{ERROR_REPORT_HIDPP20, ROOT_INDEX, ScriptedTransport};
+
+    fn test_image(blocks: usize) -> FirmwareImage {
+        FirmwareImage::parse(build_test_image(0, blocks)).unwrap()
+    }
+
+    fn device_firmware() -> DeviceFirmware {
+        let version = FirmwareVersion {
+            prefix: "MPM".to_string(),
+            major:  0x19,
+            minor:  0x01,
+            build:  0x0021
+        };
+        DeviceFirmware {
+            model_ids: vec![0xB034],
+            entities:  vec![
+                FirmwareEntity {
+                    index:   0,
+                    kind:    FirmwareEntityKind::MainApplication,
+                    version: version.clone()
+                },
+                FirmwareEntity {
+                    index: 1,
+                    kind: FirmwareEntityKind::Bootloader,
+                    version
+                },
+            ]
+        }
+    }
+
+    fn emulator(image: &FirmwareImage) -> DfuEmulator {
+        DfuEmulator::new(&device_firmware(), image.block_count())
+    }
+
+    #[test]
+    fn test_dfu_status_roundtrip() {
+        for code in [0x01, 0x02, 0x03, 0x05, 0x06, 0x15, 0x1F] {
+            assert_eq!(u8::from(DfuStatus::from(code)), code);
+        }
+        assert!(DfuStatus::DfuSuccess.is_complete());
+        assert!(!DfuStatus::PacketSuccess.is_complete());
+    }
+
+    #[test]
+    fn test_dry_run_streams_every_block() {
+        let image = test_image(8);
+        let mut updates = Vec::new();
+
+        let report = dry_run(&image, &device_firmware(), |p| updates.push(p)).unwrap();
+
+        assert_eq!(report.blocks, image.block_count());
+        assert_eq!(report.status, DfuStatus::DfuSuccess);
+        assert!(report.restarted);
+        assert_eq!(updates.len(), image.block_count());
+        assert_eq!(
+            updates.last(),
+            Some(&DfuProgress {
+                block: image.block_count(),
+                total: image.block_count()
+            })
+        );
+    }
+
+    #[test]
+    fn test_dry_run_rejects_unknown_entity() {
+        let image = FirmwareImage::parse(build_test_image(5, 2)).unwrap();
+        assert!(dry_run(&image, &device_firmware(), |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_update_checks_model_before_bootloader() {
+        let mut data = build_test_image(0, 3);
+        data[5..7].copy_from_slice(&0x4082u16.to_be_bytes());
+        let image = FirmwareImage::parse(data).unwrap();
+        let emulator = Arc::new(Mutex::new(emulator(&image)));
+
+        assert!(dry_run_with(&image, emulator.clone(), |_| {}).is_err());
+        assert!(!emulator.lock().unwrap().in_bootloader());
+    }
+
+    #[test]
+    fn test_open_does_not_reboot_on_failed_lookup() {
+        let rebooted = Arc::new(Mutex::new(false));
+        let seen = rebooted.clone();
+        let transport = ScriptedTransport::new(move |report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let index = request.feature_index();
+            let params = request.parameters();
+            if index != ROOT_INDEX {
+                *seen.lock().unwrap() = true;
+                return Ok(vec![]);
+            }
+            // The device could be rebooted, but the 0x00D0 lookup is refused
+            // with a HID++ 2.0 error, which says nothing about whether the
+            // feature is supported.
+            if u16::from_be_bytes([params[0], params[1]]) != FEATURE_DFU {
+                let reply = HidppPacket::new_short(
+                    request.device_index(),
+                    index,
+                    request.function_id(),
+                    request.software_id(),
+                    [0x05, 0x00, 0x00]
+                );
+                return Ok(vec![reply.to_bytes()]);
+            }
+            let mut reply = [0u8; 16];
+            reply[0] = (request.function_id() << 4) | request.software_id();
+            reply[1] = 0x02;
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    ERROR_REPORT_HIDPP20,
+                    index >> 4,
+                    index & 0x0F,
+                    reply
+                )
+                .to_bytes(),
+            ])
+        });
+        let mut hidpp = HidppDevice::with_transport(Box::new(transport), 1);
+
+        assert!(Dfu::open(&mut hidpp).is_err());
+        assert!(!*rebooted.lock().unwrap());
+    }
+
+    #[test]
+    fn test_dry_run_enters_bootloader() {
+        let image = test_image(3);
+        let emulator = Arc::new(Mutex::new(emulator(&image)));
+        assert!(!emulator.lock().unwrap().in_bootloader());
+
+        dry_run_with(&image, emulator.clone(), |_| {}).unwrap();
+        assert!(emulator.lock().unwrap().in_bootloader());
+    }
+
+    #[test]
+    fn test_dry_run_waits_for_event() {
+        let image = test_image(4);
+        let emulator = Arc::new(Mutex::new(emulator(&image).with_busy_block(2)));
+
+        let report = dry_run_with(&image, emulator, |_| {}).unwrap();
+        assert_eq!(report.status, DfuStatus::DfuSuccess);
+    }
+
+    #[test]
+    fn test_dry_run_resumes_after_lost_block() {
+        let image = test_image(6);
+        let emulator = Arc::new(Mutex::new(emulator(&image).with_lost_block(3)));
+
+        let mut updates = Vec::new();
+        let report = dry_run_with(&image, emulator, |p| updates.push(p.block)).unwrap();
+
+        assert_eq!(report.status, DfuStatus::DfuSuccess);
+        assert_eq!(&updates[..5], &[1, 2, 3, 3, 4]);
+    }
+
+    #[test]
+    fn test_dry_run_recovers_from_dropped_replies() {
+        let image = test_image(4);
+        // A lost reply is not retried; the session starts over instead.
+        let emulator = Arc::new(Mutex::new(emulator(&image).with_dropped_replies(1, 1)));
+
+        let report = dry_run_with(&image, emulator.clone(), |_| {}).unwrap();
+
+        assert_eq!(report.status, DfuStatus::DfuSuccess);
+        assert_eq!(emulator.lock().unwrap().sessions(), 2);
+    }
+}
@@ -0,0 +1,571 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration
+};
+
+use hidapi::HidApi;
+use masterror::prelude::*;
+use tokio::{
+    io::unix::AsyncFd,
+    sync::{Mutex as AsyncMutex, Semaphore, SemaphorePermit, mpsc, oneshot},
+    task::JoinHandle,
+    time::Instant
+};
+use tracing::{debug, trace, warn};
+
+use super::{
+    CommandContext, Direction, RootFunction, constants::*, device::find_hidpp_path, dissect,
+    feature_id_for_index, packet::HidppPacket
+};
+use crate::error::{DeviceErrorKind, Result};
+
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_millis(1000);
+
+const RETRY_COUNT: usize = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+const MAX_PENDING_NOTIFICATIONS: usize = 32;
+
+// Software id 0 is what the device uses for notifications, so every command
+// in flight gets its own id from 1..=15 and replies are routed by it. Ids are
+// handed out in rotation, so one given up on is the last to be reused.
+const SOFTWARE_IDS: std::ops::RangeInclusive<u8> = 1..=15;
+
+type PendingKey = (u8, u8);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Shared {
+    fd:            AsyncFd<File>,
+    device_index:  u8,
+    pending:       Mutex<HashMap<PendingKey, oneshot::Sender<HidppPacket>>>,
+    // Commands given up on before their reply; a late one is dropped.
+    abandoned:     Mutex<HashSet<PendingKey>>,
+    feature_cache: Mutex<HashMap<u16, u8>>
+}
+
+impl Shared {
+    async fn write(&self, data: &[u8]) -> Result<()> {
+        loop {
+            let mut guard = self
+                .fd
+                .writable()
+                .await
+                .map_err(|e| AppError::internal("Failed to write to device").with_source(e))?;
+
+            if let Ok(result) = guard.try_io(|fd| fd.get_ref().write(data)) {
+                result
+                    .map_err(|e| AppError::internal("Failed to write to device").with_source(e))?;
+                return Ok(());
+            }
+        }
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            if let Ok(result) = guard.try_io(|fd| fd.get_ref().read(buf)) {
+                return result;
+            }
+        }
+    }
+
+    fn dissect(&self, packet: &HidppPacket, direction: Direction) -> String {
+        let feature_id = feature_id_for_index(&lock(&self.feature_cache), packet.feature_index());
+        dissect(packet, feature_id, direction)
+    }
+
+    fn take_waiter(&self, packet: &HidppPacket) -> Option<oneshot::Sender<HidppPacket>> {
+        lock(&self.pending).remove(&Self::reply_key(packet))
+    }
+
+    fn is_abandoned_reply(&self, packet: &HidppPacket) -> bool {
+        lock(&self.abandoned).remove(&Self::reply_key(packet))
+    }
+
+    fn reply_key(packet: &HidppPacket) -> PendingKey {
+        packet
+            .error_request()
+            .unwrap_or((packet.feature_index(), packet.software_id()))
+    }
+}
+
+// Asynchronous counterpart of `HidppDevice` talking to `/dev/hidrawN`
+// directly. A single reader task owns the receive side: replies go to the
+// command waiting on them and everything else is queued as a notification,
+// so any number of commands can be awaited concurrently through `&self`.
+//
+// Only the transport is async so far: the device types and the daemon still
+// go through the blocking `HidppDevice`.
+pub struct AsyncHidppDevice {
+    shared:        Arc<Shared>,
+    slots:         Semaphore,
+    software_ids:  Mutex<VecDeque<u8>>,
+    notifications: AsyncMutex<mpsc::Receiver<HidppPacket>>,
+    reader:        JoinHandle<()>
+}
+
+// Releases the software id and forgets the waiter when a command completes
+// or its future is dropped.
+struct InFlight<'a> {
+    device:  &'a AsyncHidppDevice,
+    key:     PendingKey,
+    _permit: SemaphorePermit<'a>
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if lock(&self.device.shared.pending)
+            .remove(&self.key)
+            .is_some()
+        {
+            lock(&self.device.shared.abandoned).insert(self.key);
+        }
+        lock(&self.device.software_ids).push_back(self.key.1);
+    }
+}
+
+impl AsyncHidppDevice {
+    pub async fn open_path(path: impl AsRef<Path>, device_index: u8) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .map_err(|e| AppError::not_found("Failed to open device").with_source(e))?;
+
+        debug!(
+            "Opened async HID++ device at {:?} with index {}",
+            path, device_index
+        );
+
+        Self::with_file(file, device_index)
+    }
+
+    pub async fn open_vid_pid(vendor_id: u16, product_id: u16, device_index: u8) -> Result<Self> {
+        let api = HidApi::new()
+            .map_err(|e| AppError::internal("Failed to initialize HID API").with_source(e))?;
+
+        let path = find_hidpp_path(&api, vendor_id, product_id)?;
+        let path = path
+            .to_str()
+            .map_err(|e| AppError::bad_request("Invalid path").with_source(e))?;
+
+        Self::open_path(path, device_index).await
+    }
+
+    // `file` must be in non-blocking mode and deliver one report per read.
+    // Must be called from within a tokio runtime.
+    pub fn with_file(file: File, device_index: u8) -> Result<Self> {
+        // SAFETY: the `File` owns its descriptor and is never closed or
+        // replaced while registered.
+        let fd = unsafe { AsyncFd::register(file) }.map_err(|e| {
+            AppError::internal("Failed to register device with the runtime").with_source(e)
+        })?;
+
+        let shared = Arc::new(Shared {
+            fd,
+            device_index,
+            pending: Mutex::new(HashMap::new()),
+            abandoned: Mutex::new(HashSet::new()),
+            feature_cache: Mutex::new(HashMap::new())
+        });
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_NOTIFICATIONS);
+        let reader = tokio::spawn(read_loop(shared.clone(), sender));
+
+        Ok(Self {
+            shared,
+            slots: Semaphore::new(SOFTWARE_IDS.count()),
+            software_ids: Mutex::new(SOFTWARE_IDS.collect()),
+            notifications: AsyncMutex::new(receiver),
+            reader
+        })
+    }
+
+    pub fn device_index(&self) -> u8 {
+        self.shared.device_index
+    }
+
+    pub async fn send_command(
+        &self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8]
+    ) -> Result<HidppPacket> {
+        self.send_command_with_timeout(feature_index, function_id, params, DEFAULT_COMMAND_TIMEOUT)
+            .await
+    }
+
+    // The timeout covers the whole command including busy retries. Dropping
+    // the returned future cancels the command.
+    pub async fn send_command_with_timeout(
+        &self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8],
+        timeout: Duration
+    ) -> Result<HidppPacket> {
+        let context = CommandContext::new(
+            self.shared.device_index,
+            feature_index,
+            function_id,
+            &lock(&self.shared.feature_cache)
+        );
+        self.send_command_until(feature_index, function_id, params, timeout)
+            .await
+            .map_err(|e| context.attach(e))
+    }
+
+    async fn send_command_until(
+        &self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8],
+        timeout: Duration
+    ) -> Result<HidppPacket> {
+        let deadline = Instant::now() + timeout;
+
+        for attempt in 0..RETRY_COUNT {
+            let response = tokio::time::timeout_at(
+                deadline,
+                self.request(feature_index, function_id, params)
+            )
+            .await
+            .map_err(|_| AppError::from(DeviceErrorKind::Timeout))??;
+
+            if let Some(error) = response.error() {
+                if error.is_busy() && attempt < RETRY_COUNT - 1 {
+                    warn!("Device busy, retrying... (attempt {})", attempt + 1);
+                    tokio::time::sleep_until(deadline.min(Instant::now() + RETRY_DELAY)).await;
+                    continue;
+                }
+                return Err(error.into());
+            }
+
+            return Ok(response);
+        }
+
+        Err(DeviceErrorKind::CommandFailed.into())
+    }
+
+    async fn request(
+        &self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8]
+    ) -> Result<HidppPacket> {
+        let permit = self
+            .slots
+            .acquire()
+            .await
+            .map_err(|_| AppError::from(DeviceErrorKind::ConnectionFailed))?;
+        let software_id = lock(&self.software_ids)
+            .pop_front()
+            .ok_or_else(|| AppError::internal("No free HID++ software id"))?;
+
+        let packet = HidppPacket::new_request(
+            self.shared.device_index,
+            feature_index,
+            function_id,
+            software_id,
+            params
+        );
+        let key = (feature_index, software_id);
+        let (sender, receiver) = oneshot::channel();
+        lock(&self.shared.abandoned).remove(&key);
+        lock(&self.shared.pending).insert(key, sender);
+        let _in_flight = InFlight {
+            device: self,
+            key,
+            _permit: permit
+        };
+
+        let packet = packet?;
+        trace!("Sending {}", self.shared.dissect(&packet, Direction::Sent));
+        self.shared.write(&packet.to_bytes()).await?;
+
+        receiver
+            .await
+            .map_err(|_| AppError::from(DeviceErrorKind::ConnectionFailed))
+    }
+
+    pub async fn get_feature_index(&self, feature_id: u16) -> Result<u8> {
+        let context = CommandContext::lookup(self.shared.device_index, feature_id);
+        self.lookup_feature_index(feature_id)
+            .await
+            .map_err(|e| context.attach(e))
+    }
+
+    async fn lookup_feature_index(&self, feature_id: u16) -> Result<u8> {
+        match lock(&self.shared.feature_cache).get(&feature_id) {
+            Some(0) => return Err(DeviceErrorKind::UnsupportedFeature.into()),
+            Some(&index) => return Ok(index),
+            None => {}
+        }
+
+        let params = [(feature_id >> 8) as u8, (feature_id & 0xFF) as u8, 0x00];
+        let response = self
+            .send_command_until(
+                ROOT_INDEX,
+                RootFunction::GetFeature as u8,
+                &params,
+                DEFAULT_COMMAND_TIMEOUT
+            )
+            .await?;
+        let index = response.parameters()[0];
+
+        lock(&self.shared.feature_cache).insert(feature_id, index);
+        if index == 0 {
+            return Err(DeviceErrorKind::UnsupportedFeature.into());
+        }
+
+        debug!("Feature {:04x} mapped to index {}", feature_id, index);
+
+        Ok(index)
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        let response = self
+            .send_command(ROOT_INDEX, RootFunction::Ping as u8, &[0, 0, 0])
+            .await?;
+        trace!("Ping response: {:?}", response);
+        Ok(())
+    }
+
+    // Returns `None` when nothing arrived before the timeout.
+    pub async fn read_notification(&self, timeout: Duration) -> Result<Option<HidppPacket>> {
+        let mut notifications = self.notifications.lock().await;
+
+        match tokio::time::timeout(timeout, notifications.recv()).await {
+            Ok(Some(packet)) => Ok(Some(packet)),
+            Ok(None) => Err(DeviceErrorKind::ConnectionFailed.into()),
+            Err(_) => Ok(None)
+        }
+    }
+}
+
+impl Drop for AsyncHidppDevice {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+async fn read_loop(shared: Arc<Shared>, notifications: mpsc::Sender<HidppPacket>) {
+    let mut buf = [0u8; 64];
+
+    loop {
+        let size = match shared.read(&mut buf).await {
+            Ok(0) => {
+                debug!("HID++ device closed");
+                break;
+            }
+            Ok(size) => size,
+            Err(e) => {
+                warn!("HID++ reader stopped: {}", e);
+                break;
+            }
+        };
+
+        let packet = match HidppPacket::from_bytes(&buf[..size]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                trace!("Ignoring non HID++ report: {}", e);
+                continue;
+            }
+        };
+
+        if packet.device_index() != shared.device_index {
+            continue;
+        }
+
+        trace!("Received {}", shared.dissect(&packet, Direction::Received));
+
+        if let Some(waiter) = shared.take_waiter(&packet) {
+            let _ = waiter.send(packet);
+            continue;
+        }
+        if shared.is_abandoned_reply(&packet) {
+            trace!("Dropping the late reply to an abandoned command");
+            continue;
+        }
+
+        if notifications.try_send(packet).is_err() {
+            trace!("Notification queue full, dropping report");
+        }
+    }
+
+    // Wakes every pending command with a connection error.
+    lock(&shared.pending).clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::{fd::OwnedFd, unix::net::UnixDatagram},
+        thread
+    };
+
+    use super::*;
+
+    fn connected_device() -> (AsyncHidppDevice, UnixDatagram) {
+        let (ours, theirs) = UnixDatagram::pair().unwrap();
+        ours.set_nonblocking(true).unwrap();
+        let device = AsyncHidppDevice::with_file(File::from(OwnedFd::from(ours)), 1).unwrap();
+        (device, theirs)
+    }
+
+    fn recv_packet(peer: &UnixDatagram) -> HidppPacket {
+        let mut buf = [0u8; 64];
+        let size = peer.recv(&mut buf).unwrap();
+        HidppPacket::from_bytes(&buf[..size]).unwrap()
+    }
+
+    fn reply_to(request: &HidppPacket, value: u8) -> Vec<u8> {
+        HidppPacket::new_short(
+            request.device_index(),
+            request.feature_index(),
+            request.function_id(),
+            request.software_id(),
+            [value, 0, 0]
+        )
+        .to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_commands_get_their_own_replies() {
+        let (device, peer) = connected_device();
+
+        let responder = thread::spawn(move || {
+            let first = recv_packet(&peer);
+            let second = recv_packet(&peer);
+            assert_ne!(first.software_id(), second.software_id());
+            peer.send(&reply_to(&second, second.feature_index()))
+                .unwrap();
+            peer.send(&reply_to(&first, first.feature_index())).unwrap();
+            peer
+        });
+
+        let (a, b) = tokio::join!(
+            device.send_command(0x02, 0x01, &[]),
+            device.send_command(0x03, 0x01, &[])
+        );
+        assert_eq!(a.unwrap().parameters()[0], 0x02);
+        assert_eq!(b.unwrap().parameters()[0], 0x03);
+
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timeout_releases_software_id() {
+        let (device, _peer) = connected_device();
+
+        let err = device
+            .send_command_with_timeout(0x02, 0x01, &[], Duration::from_millis(20))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.kind, AppErrorKind::Timeout);
+        assert!(lock(&device.shared.pending).is_empty());
+        assert_eq!(lock(&device.software_ids).len(), SOFTWARE_IDS.count());
+    }
+
+    #[tokio::test]
+    async fn test_late_reply_does_not_answer_the_next_command() {
+        let (device, peer) = connected_device();
+
+        let err = device
+            .send_command_with_timeout(0x02, 0x01, &[], Duration::from_millis(20))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, AppErrorKind::Timeout);
+
+        let responder = thread::spawn(move || {
+            let late = recv_packet(&peer);
+            let next = recv_packet(&peer);
+            assert_ne!(late.software_id(), next.software_id());
+            peer.send(&reply_to(&late, 0xAA)).unwrap();
+            peer.send(&reply_to(&next, 0xBB)).unwrap();
+            peer
+        });
+
+        let response = device.send_command(0x02, 0x01, &[]).await.unwrap();
+        assert_eq!(response.parameters()[0], 0xBB);
+        assert_eq!(
+            device
+                .read_notification(Duration::from_millis(20))
+                .await
+                .unwrap(),
+            None
+        );
+
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_notifications_are_queued() {
+        let (device, peer) = connected_device();
+
+        let notification = HidppPacket::new_short(1, 0x04, 0x00, 0x00, [0x01, 0, 0]);
+        peer.send(&notification.to_bytes()).unwrap();
+        peer.send(&HidppPacket::new_short(2, 0x04, 0x00, 0x00, [0; 3]).to_bytes())
+            .unwrap();
+
+        let received = device
+            .read_notification(Duration::from_millis(500))
+            .await
+            .unwrap();
+        assert_eq!(received, Some(notification));
+        assert_eq!(
+            device
+                .read_notification(Duration::from_millis(20))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_reply_routed_to_request() {
+        let (device, peer) = connected_device();
+
+        let responder = thread::spawn(move || {
+            let request = recv_packet(&peer);
+            let function_byte = (request.function_id() << 4) | request.software_id();
+            peer.send(&[
+                0x10,
+                request.device_index(),
+                0xFF,
+                request.feature_index(),
+                function_byte,
+                0x03,
+                0
+            ])
+            .unwrap();
+            peer
+        });
+
+        let err = device.send_command(0x02, 0x01, &[]).await.unwrap_err();
+        assert_eq!(err.render_message(), "Argument out of range");
+        assert!(lock(&device.shared.pending).is_empty());
+
+        responder.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_closed_device_fails_pending_commands() {
+        let (device, peer) = connected_device();
+        drop(peer);
+
+        assert!(device.send_command(0x02, 0x01, &[]).await.is_err());
+    }
+}
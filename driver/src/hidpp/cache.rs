@@ -0,0 +1,187 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf}
+};
+
+use masterror::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+const CACHE_DIR_NAME: &str = "logi-mx";
+const FEATURE_CACHE_DIR_NAME: &str = "features";
+
+pub fn get_feature_cache_dir() -> Result<PathBuf> {
+    get_feature_cache_dir_from_env(|key| std::env::var(key))
+}
+
+fn get_feature_cache_dir_from_env<F>(env_fn: F) -> Result<PathBuf>
+where
+    F: Fn(&str) -> std::result::Result<String, std::env::VarError>
+{
+    if let Ok(cache_home) = env_fn("XDG_CACHE_HOME") {
+        return Ok(PathBuf::from(cache_home)
+            .join(CACHE_DIR_NAME)
+            .join(FEATURE_CACHE_DIR_NAME));
+    }
+
+    if let Ok(home) = env_fn("HOME") {
+        return Ok(PathBuf::from(home)
+            .join(".cache")
+            .join(CACHE_DIR_NAME)
+            .join(FEATURE_CACHE_DIR_NAME));
+    }
+
+    Err(AppError::internal("Cannot determine cache directory"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureCacheKey {
+    pub model_id: u16,
+    pub firmware: String
+}
+
+impl FeatureCacheKey {
+    pub fn file_name(&self) -> String {
+        let firmware: String = self
+            .firmware
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        format!("{:04x}-{}.toml", self.model_id, firmware)
+    }
+}
+
+// Index 0 records a feature the device does not have. `feature_count` is
+// what FeatureSet reported when the file was written; files without it
+// never match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeatureCacheFile {
+    #[serde(default)]
+    pub feature_count: u8,
+    pub features:      BTreeMap<String, u8>
+}
+
+impl FeatureCacheFile {
+    pub fn from_map(feature_count: u8, map: &HashMap<u16, u8>) -> Self {
+        Self {
+            feature_count,
+            features: map
+                .iter()
+                .map(|(id, index)| (format!("{:04X}", id), *index))
+                .collect()
+        }
+    }
+
+    pub fn to_map(&self) -> HashMap<u16, u8> {
+        self.features
+            .iter()
+            .filter_map(|(id, index)| u16::from_str_radix(id, 16).ok().map(|id| (id, *index)))
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| AppError::internal("Failed to read feature cache").with_source(e))?;
+        let cache = toml::from_str(&content)
+            .map_err(|e| AppError::bad_request("Invalid feature cache format").with_source(e))?;
+
+        Ok(Some(cache))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                AppError::internal("Failed to create cache directory").with_source(e)
+            })?;
+        }
+
+        let content = toml::to_string(self)
+            .map_err(|e| AppError::internal("Failed to serialize feature cache").with_source(e))?;
+        std::fs::write(path, content)
+            .map_err(|e| AppError::internal("Failed to write feature cache").with_source(e))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_get_feature_cache_dir_with_xdg() {
+        let mock_env = |var: &str| {
+            if var == "XDG_CACHE_HOME" {
+                Ok("/tmp/test_cache".to_string())
+            } else {
+                Err(env::VarError::NotPresent)
+            }
+        };
+        let path = get_feature_cache_dir_from_env(mock_env).unwrap();
+        assert_eq!(path, PathBuf::from("/tmp/test_cache/logi-mx/features"));
+    }
+
+    #[test]
+    fn test_get_feature_cache_dir_with_home() {
+        let mock_env = |var: &str| {
+            if var == "HOME" {
+                Ok("/tmp/test_home".to_string())
+            } else {
+                Err(env::VarError::NotPresent)
+            }
+        };
+        let path = get_feature_cache_dir_from_env(mock_env).unwrap();
+        assert_eq!(
+            path,
+            PathBuf::from("/tmp/test_home/.cache/logi-mx/features")
+        );
+    }
+
+    #[test]
+    fn test_key_file_name_is_sanitized() {
+        let key = FeatureCacheKey {
+            model_id: 0xB034,
+            firmware: "MPM19.01_B0021/x".to_string()
+        };
+        assert_eq!(key.file_name(), "b034-MPM19.01_B0021_x.toml");
+    }
+
+    #[test]
+    fn test_cache_file_roundtrip() {
+        let path = env::temp_dir()
+            .join("logi-mx-cache-test")
+            .join("roundtrip.toml");
+        let map = HashMap::from([(0x2201, 0x07), (0x1B04, 0x0A), (0x8100, 0x00)]);
+
+        FeatureCacheFile::from_map(24, &map).save(&path).unwrap();
+        let loaded = FeatureCacheFile::load(&path).unwrap().unwrap();
+
+        assert_eq!(loaded.feature_count, 24);
+        assert_eq!(loaded.to_map(), map);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_missing_cache_file() {
+        let path = env::temp_dir().join("logi-mx-no-such-cache.toml");
+        assert_eq!(FeatureCacheFile::load(&path).unwrap(), None);
+    }
+}
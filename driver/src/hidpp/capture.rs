@@ -0,0 +1,455 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        OnceLock,
+        atomic::{AtomicU32, Ordering}
+    },
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use masterror::{field, prelude::*};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use super::transport::Transport;
+use crate::error::Result;
+
+pub const CAPTURE_ENV_VAR: &str = "LOGI_MX_CAPTURE";
+
+static CAPTURE_PATH: OnceLock<PathBuf> = OnceLock::new();
+static NEXT_SESSION: AtomicU32 = AtomicU32::new(1);
+
+// Takes precedence over `LOGI_MX_CAPTURE`; only the first call has effect.
+pub fn set_capture_path(path: PathBuf) {
+    let _ = CAPTURE_PATH.set(path);
+}
+
+pub fn capture_path() -> Option<PathBuf> {
+    CAPTURE_PATH
+        .get()
+        .cloned()
+        .or_else(|| std::env::var_os(CAPTURE_ENV_VAR).map(PathBuf::from))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received
+}
+
+// One JSON line per report. Every device handle is its own `session`
+// ("<pid>-<n>"), as several handles and processes may append to the same
+// file; `time_ms` is wall-clock time since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub session:   String,
+    pub time_ms:   u64,
+    pub direction: Direction,
+    pub report:    String
+}
+
+impl CaptureRecord {
+    pub fn new(time_ms: u64, direction: Direction, report: &[u8]) -> Self {
+        Self {
+            session: String::new(),
+            time_ms,
+            direction,
+            report: report.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        if !self.report.len().is_multiple_of(2) {
+            return Err(AppError::bad_request("Capture report has odd length"));
+        }
+
+        (0..self.report.len())
+            .step_by(2)
+            .map(|i| {
+                u8::from_str_radix(&self.report[i..i + 2], 16).map_err(|e| {
+                    AppError::bad_request("Capture report is not valid hex").with_source(e)
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn read_capture(path: &Path) -> Result<Vec<CaptureRecord>> {
+    let file = File::open(path)
+        .map_err(|e| AppError::not_found("Failed to open capture file").with_source(e))?;
+
+    let mut records = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line =
+            line.map_err(|e| AppError::internal("Failed to read capture file").with_source(e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = serde_json::from_str(&line).map_err(|e| {
+            AppError::bad_request("Invalid capture record")
+                .with_field(field::u64("line", number as u64 + 1))
+                .with_source(e)
+        })?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+// Sessions in the order they first appear.
+pub fn capture_sessions(records: &[CaptureRecord]) -> Vec<String> {
+    let mut sessions: Vec<String> = Vec::new();
+    for record in records {
+        if !sessions.contains(&record.session) {
+            sessions.push(record.session.clone());
+        }
+    }
+    sessions
+}
+
+// Wraps another transport and appends every report it carries to a capture.
+// A capture that cannot be written is given up with a warning; the device
+// keeps working.
+pub struct CapturingTransport {
+    inner:   Box<dyn Transport>,
+    output:  Option<Box<dyn Write + Send>>,
+    session: String
+}
+
+impl CapturingTransport {
+    pub fn new(inner: Box<dyn Transport>, output: Box<dyn Write + Send>) -> Self {
+        Self {
+            inner,
+            output: Some(output),
+            session: format!(
+                "{}-{}",
+                std::process::id(),
+                NEXT_SESSION.fetch_add(1, Ordering::Relaxed)
+            )
+        }
+    }
+
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    pub fn to_file(inner: Box<dyn Transport>, path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| AppError::internal("Failed to open capture file").with_source(e))?;
+
+        debug!("Capturing HID++ traffic to {:?}", path);
+
+        Ok(Self::new(inner, Box::new(file)))
+    }
+
+    fn record(&mut self, direction: Direction, report: &[u8]) {
+        let Some(output) = &mut self.output else {
+            return;
+        };
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis() as u64);
+        let record = CaptureRecord {
+            session: self.session.clone(),
+            ..CaptureRecord::new(time_ms, direction, report)
+        };
+        // Both fields are plain strings and numbers, which always serialize.
+        let mut line = serde_json::to_string(&record).unwrap_or_default();
+        line.push('\n');
+
+        // A single write per line keeps lines whole when several writers
+        // append to the same file.
+        if let Err(e) = output
+            .write_all(line.as_bytes())
+            .and_then(|_| output.flush())
+        {
+            warn!("Failed to write capture, capture stopped: {}", e);
+            self.output = None;
+        }
+    }
+}
+
+impl Transport for CapturingTransport {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.inner.write(data)?;
+        self.record(Direction::Sent, data);
+        Ok(())
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        let size = self.inner.read_timeout(buf, timeout_ms)?;
+        if size > 0 {
+            self.record(Direction::Received, &buf[..size]);
+        }
+        Ok(size)
+    }
+}
+
+// Wraps `inner` in a `CapturingTransport` when a capture path is configured.
+pub fn capture_if_enabled(inner: Box<dyn Transport>) -> Result<Box<dyn Transport>> {
+    match capture_path() {
+        Some(path) => Ok(Box::new(CapturingTransport::to_file(inner, &path)?)),
+        None => Ok(inner)
+    }
+}
+
+// Plays one session of a capture back as a device. Each write must match the
+// next sent report of the session; the received reports that followed it are
+// then returned by reads. Timing is ignored so replays are deterministic.
+pub struct ReplayTransport {
+    records: VecDeque<CaptureRecord>,
+    pending: VecDeque<Vec<u8>>,
+    sent:    usize
+}
+
+impl ReplayTransport {
+    // Replays the first session of `records`.
+    pub fn new(records: Vec<CaptureRecord>) -> Result<Self> {
+        let session = records
+            .first()
+            .map(|record| record.session.clone())
+            .unwrap_or_default();
+        Self::for_session(records, &session)
+    }
+
+    pub fn for_session(records: Vec<CaptureRecord>, session: &str) -> Result<Self> {
+        let records: VecDeque<CaptureRecord> = records
+            .into_iter()
+            .filter(|record| record.session == session)
+            .collect();
+        if records.is_empty() && !session.is_empty() {
+            return Err(AppError::not_found("Capture has no such session")
+                .with_field(field::str("session", session.to_string())));
+        }
+
+        let mut replay = Self {
+            records,
+            pending: VecDeque::new(),
+            sent: 0
+        };
+        replay.queue_received()?;
+        Ok(replay)
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::new(read_capture(path)?)
+    }
+
+    pub fn open_session(path: &Path, session: &str) -> Result<Self> {
+        Self::for_session(read_capture(path)?, session)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.records.is_empty() && self.pending.is_empty()
+    }
+
+    fn queue_received(&mut self) -> Result<()> {
+        while self
+            .records
+            .front()
+            .is_some_and(|r| r.direction == Direction::Received)
+        {
+            if let Some(record) = self.records.pop_front() {
+                self.pending.push_back(record.bytes()?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.sent += 1;
+
+        let Some(expected) = self
+            .records
+            .pop_front()
+            .filter(|r| r.direction == Direction::Sent)
+        else {
+            return Err(AppError::conflict("Replay has no more sent reports")
+                .with_field(field::u64("sent_report", self.sent as u64)));
+        };
+
+        if expected.bytes()? != data {
+            return Err(AppError::conflict("Replay diverged from capture")
+                .with_field(field::u64("sent_report", self.sent as u64))
+                .with_field(field::str("expected", expected.report)));
+        }
+
+        self.queue_received()
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize> {
+        let Some(report) = self.pending.pop_front() else {
+            return Ok(0);
+        };
+
+        let size = report.len().min(buf.len());
+        buf[..size].copy_from_slice(&report[..size]);
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::hidpp::{
+        FEATURE_DEVICE_NAME, HidppDevice, HidppPacket, ROOT_INDEX, ScriptedTransport
+    };
+
+    fn scripted_device() -> Box<dyn Transport> {
+        Box::new(ScriptedTransport::new(|report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let value = if request.feature_index() == ROOT_INDEX {
+                0x03
+            } else {
+                0x0A
+            };
+            let notification =
+                HidppPacket::new_short(request.device_index(), 0x04, 0, 0, [1, 0, 0]);
+            let reply = HidppPacket::new_short(
+                request.device_index(),
+                request.feature_index(),
+                request.function_id(),
+                request.software_id(),
+                [value, 0, 0]
+            );
+            Ok(vec![notification.to_bytes(), reply.to_bytes()])
+        }))
+    }
+
+    fn exercise(device: &mut HidppDevice) -> Result<u8> {
+        device.ping()?;
+        let index = device.get_feature_index(FEATURE_DEVICE_NAME)?;
+        let response = device.send_command(index, 0x00, &[])?;
+        Ok(response.parameters()[0])
+    }
+
+    #[test]
+    fn test_record_hex_roundtrip() {
+        let record = CaptureRecord::new(5, Direction::Sent, &[0x10, 0x01, 0xFF]);
+        assert_eq!(record.report, "1001ff");
+        assert_eq!(record.bytes().unwrap(), vec![0x10, 0x01, 0xFF]);
+
+        let line = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            line,
+            r#"{"time_ms":5,"direction":"sent","report":"1001ff"}"#
+        );
+    }
+
+    #[test]
+    fn test_capture_then_replay() {
+        let path = env::temp_dir().join("logi-mx-capture-test.jsonl");
+        std::fs::remove_file(&path).ok();
+
+        let transport = CapturingTransport::to_file(scripted_device(), &path).unwrap();
+        let mut device = HidppDevice::with_transport(Box::new(transport), 1);
+        assert_eq!(exercise(&mut device).unwrap(), 0x0A);
+        drop(device);
+
+        let records = read_capture(&path).unwrap();
+        assert_eq!(records.len(), 9);
+        assert_eq!(records[0].direction, Direction::Sent);
+
+        let replay = ReplayTransport::open(&path).unwrap();
+        let mut device = HidppDevice::with_transport(Box::new(replay), 1);
+        assert_eq!(exercise(&mut device).unwrap(), 0x0A);
+        assert!(device.read_notification(0).unwrap().is_some());
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_interleaved_sessions_replay_separately() {
+        let path = env::temp_dir().join("logi-mx-capture-sessions-test.jsonl");
+        std::fs::remove_file(&path).ok();
+
+        let first = CapturingTransport::to_file(scripted_device(), &path).unwrap();
+        let second = CapturingTransport::to_file(scripted_device(), &path).unwrap();
+        let sessions = [first.session().to_string(), second.session().to_string()];
+        assert_ne!(sessions[0], sessions[1]);
+
+        let mut first = HidppDevice::with_transport(Box::new(first), 1);
+        let mut second = HidppDevice::with_transport(Box::new(second), 1);
+        first.ping().unwrap();
+        assert_eq!(exercise(&mut second).unwrap(), 0x0A);
+        assert_eq!(exercise(&mut first).unwrap(), 0x0A);
+        drop((first, second));
+
+        let records = read_capture(&path).unwrap();
+        assert_eq!(capture_sessions(&records), sessions);
+        assert!(records.iter().all(|record| record.time_ms > 0));
+
+        let replay = ReplayTransport::open_session(&path, &sessions[1]).unwrap();
+        let mut device = HidppDevice::with_transport(Box::new(replay), 1);
+        assert_eq!(exercise(&mut device).unwrap(), 0x0A);
+
+        let replay = ReplayTransport::open(&path).unwrap();
+        let mut device = HidppDevice::with_transport(Box::new(replay), 1);
+        device.ping().unwrap();
+        assert_eq!(exercise(&mut device).unwrap(), 0x0A);
+
+        assert!(ReplayTransport::open_session(&path, "0-0").is_err());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_failed_capture_keeps_device_working() {
+        struct Broken;
+
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::StorageFull.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let transport = CapturingTransport::new(scripted_device(), Box::new(Broken));
+        let mut device = HidppDevice::with_transport(Box::new(transport), 1);
+        assert_eq!(exercise(&mut device).unwrap(), 0x0A);
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        let records = vec![
+            CaptureRecord::new(0, Direction::Sent, &[0x10, 0x01, 0x00, 0x15, 0, 0, 0]),
+            CaptureRecord::new(1, Direction::Received, &[0x10, 0x01, 0x00, 0x15, 0, 0, 0]),
+        ];
+        let mut replay = ReplayTransport::new(records).unwrap();
+
+        assert!(replay.write(&[0x10, 0x02, 0x00, 0x15, 0, 0, 0]).is_err());
+        assert!(replay.write(&[0x10, 0x01, 0x00, 0x15, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_replay_queues_leading_notifications() {
+        let records = vec![CaptureRecord::new(
+            0,
+            Direction::Received,
+            &[0x10, 0x01, 0x04, 0x00, 1, 0, 0]
+        )];
+        let mut replay = ReplayTransport::new(records).unwrap();
+
+        let mut buf = [0u8; 20];
+        assert_eq!(replay.read_timeout(&mut buf, 0).unwrap(), 7);
+        assert!(replay.is_finished());
+    }
+}
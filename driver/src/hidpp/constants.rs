@@ -11,6 +11,9 @@ pub const VERY_LONG_PACKET_SIZE: usize = 64;
 
 pub const DEVICE_INDEX_RECEIVER: u8 = 0xFF;
 
+pub const NOTIFICATION_DEVICE_CONNECTION: u8 = 0x41;
+pub const CONNECTION_FLAG_LINK_LOST: u8 = 0x40;
+
 pub const ERROR_SUCCESS: u8 = 0x00;
 pub const ERROR_INVALID_SUBID: u8 = 0x01;
 pub const ERROR_INVALID_ADDRESS: u8 = 0x02;
@@ -28,13 +31,28 @@ pub const ERROR_WRONG_PIN_CODE: u8 = 0x0C;
 pub const FEATURE_ROOT: u16 = 0x0000;
 pub const FEATURE_FEATURE_SET: u16 = 0x0001;
 pub const FEATURE_FEATURE_INFO: u16 = 0x0002;
+pub const FEATURE_DEVICE_INFO: u16 = 0x0003;
 pub const FEATURE_DEVICE_NAME: u16 = 0x0005;
+pub const FEATURE_DFU_CONTROL_SIGNED: u16 = 0x00C2;
+pub const FEATURE_DFU_CONTROL_BOLT: u16 = 0x00C3;
+pub const FEATURE_DFU: u16 = 0x00D0;
 pub const FEATURE_BATTERY_STATUS: u16 = 0x1000;
 pub const FEATURE_BATTERY_VOLTAGE: u16 = 0x1001;
 pub const FEATURE_UNIFIED_BATTERY: u16 = 0x1004;
+pub const FEATURE_CHANGE_HOST: u16 = 0x1814;
+pub const FEATURE_HOSTS_INFO: u16 = 0x1815;
+pub const FEATURE_BACKLIGHT: u16 = 0x1982;
 pub const FEATURE_ADJUSTABLE_DPI: u16 = 0x2201;
+pub const FEATURE_EXTENDED_ADJUSTABLE_DPI: u16 = 0x2202;
 pub const FEATURE_SMART_SHIFT: u16 = 0x2110;
 pub const FEATURE_HIRES_WHEEL: u16 = 0x2121;
 pub const FEATURE_REPROG_CONTROLS: u16 = 0x1B04;
+pub const FEATURE_PERSISTENT_REMAPPABLE_ACTION: u16 = 0x1C00;
+pub const FEATURE_WIRELESS_DEVICE_STATUS: u16 = 0x1D4B;
+pub const FEATURE_FN_INVERSION: u16 = 0x40A3;
+pub const FEATURE_DISABLE_KEYS: u16 = 0x4521;
+pub const FEATURE_REPORT_RATE: u16 = 0x8060;
+pub const FEATURE_EXTENDED_REPORT_RATE: u16 = 0x8061;
+pub const FEATURE_ONBOARD_PROFILES: u16 = 0x8100;
 
 pub const ROOT_INDEX: u8 = 0x00;
@@ -0,0 +1,176 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{fs, path::Path};
+
+use masterror::{field, prelude::*};
+
+use super::constants::{REPORT_ID_LONG, REPORT_ID_SHORT};
+use crate::error::Result;
+
+pub const USAGE_PAGE_HIDPP: u16 = 0xFF00;
+pub const USAGE_PAGE_HIDPP_BLUETOOTH: u16 = 0xFF43;
+
+const ITEM_LONG: u8 = 0xFE;
+const ITEM_TYPE_GLOBAL: u8 = 1;
+const TAG_USAGE_PAGE: u8 = 0x0;
+const TAG_REPORT_ID: u8 = 0x8;
+const TAG_PUSH: u8 = 0xA;
+const TAG_POP: u8 = 0xB;
+
+// The report ids a HID report descriptor declares, each with the usage page
+// in effect where it appears. Only global items are tracked; that is enough
+// to tell the HID++ node of a device apart from its mouse and keyboard ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReportDescriptor {
+    pub reports: Vec<(u8, u16)>
+}
+
+impl ReportDescriptor {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reports = Vec::new();
+        let mut usage_page = 0u16;
+        let mut stack = Vec::new();
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            let prefix = bytes[pos];
+            if prefix == ITEM_LONG {
+                let size = *bytes.get(pos + 1).ok_or_else(truncated)? as usize;
+                pos += 3 + size;
+                continue;
+            }
+
+            let size = match prefix & 0x03 {
+                3 => 4,
+                size => size as usize
+            };
+            let data = bytes.get(pos + 1..pos + 1 + size).ok_or_else(truncated)?;
+            let value = data
+                .iter()
+                .rev()
+                .fold(0u32, |acc, &byte| (acc << 8) | byte as u32);
+            pos += 1 + size;
+
+            if (prefix >> 2) & 0x03 != ITEM_TYPE_GLOBAL {
+                continue;
+            }
+            match prefix >> 4 {
+                TAG_USAGE_PAGE => usage_page = value as u16,
+                TAG_REPORT_ID => reports.push((value as u8, usage_page)),
+                TAG_PUSH => stack.push(usage_page),
+                TAG_POP => usage_page = stack.pop().unwrap_or(usage_page),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            reports
+        })
+    }
+
+    // Reads the descriptor the kernel exposes for a hidraw node, e.g.
+    // `/dev/hidraw3` -> `/sys/class/hidraw/hidraw3/device/report_descriptor`.
+    pub fn read_for_hidraw(devnode: &str) -> Result<Self> {
+        let name = Path::new(devnode)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| AppError::bad_request("Invalid hidraw path"))?;
+        let path = Path::new("/sys/class/hidraw")
+            .join(name)
+            .join("device/report_descriptor");
+        let bytes = fs::read(&path).map_err(|e| {
+            AppError::not_found("Failed to read report descriptor")
+                .with_field(field::str("path", path.display().to_string()))
+                .with_source(e)
+        })?;
+
+        Self::parse(&bytes)
+    }
+
+    // A vendor-defined HID++ collection carrying the short or long report.
+    pub fn is_hidpp(&self) -> bool {
+        self.reports.iter().any(|&(report_id, usage_page)| {
+            matches!(report_id, REPORT_ID_SHORT | REPORT_ID_LONG)
+                && matches!(usage_page, USAGE_PAGE_HIDPP | USAGE_PAGE_HIDPP_BLUETOOTH)
+        })
+    }
+}
+
+fn truncated() -> AppError {
+    AppError::bad_request("Truncated report descriptor")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Interface 2 of a Bolt receiver.
+    const RECEIVER_HIDPP: &[u8] = &[
+        0x06, 0x00, 0xFF, 0x09, 0x01, 0xA1, 0x01, 0x85, 0x10, 0x75, 0x08, 0x95, 0x06, 0x15, 0x00,
+        0x26, 0xFF, 0x00, 0x09, 0x01, 0x81, 0x00, 0x09, 0x01, 0x91, 0x00, 0xC0, 0x06, 0x00, 0xFF,
+        0x09, 0x02, 0xA1, 0x01, 0x85, 0x11, 0x75, 0x08, 0x95, 0x13, 0x15, 0x00, 0x26, 0xFF, 0x00,
+        0x09, 0x02, 0x81, 0x00, 0x09, 0x02, 0x91, 0x00, 0xC0
+    ];
+
+    // The HID++ collection of an MX Master 3S over Bluetooth, after its
+    // mouse collection.
+    const BLUETOOTH_MOUSE: &[u8] = &[
+        0x05, 0x01, 0x09, 0x02, 0xA1, 0x01, 0x85, 0x02, 0x09, 0x01, 0xA1, 0x00, 0x05, 0x09, 0x19,
+        0x01, 0x29, 0x10, 0x15, 0x00, 0x25, 0x01, 0x95, 0x10, 0x75, 0x01, 0x81, 0x02, 0xC0, 0xC0,
+        0x06, 0x43, 0xFF, 0x0A, 0x02, 0x02, 0xA1, 0x01, 0x85, 0x11, 0x75, 0x08, 0x96, 0x13, 0x00,
+        0x15, 0x00, 0x26, 0xFF, 0x00, 0x0A, 0x02, 0x02, 0x81, 0x00, 0x0A, 0x02, 0x02, 0x91, 0x00,
+        0xC0
+    ];
+
+    // Interface 0 of a receiver: a boot keyboard without report ids.
+    const KEYBOARD: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, 0x05, 0x07, 0x19, 0xE0, 0x29, 0xE7, 0x15, 0x00, 0x25,
+        0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0xC0
+    ];
+
+    #[test]
+    fn test_receiver_hidpp_interface() {
+        let descriptor = ReportDescriptor::parse(RECEIVER_HIDPP).unwrap();
+        assert_eq!(
+            descriptor.reports,
+            [
+                (REPORT_ID_SHORT, USAGE_PAGE_HIDPP),
+                (REPORT_ID_LONG, USAGE_PAGE_HIDPP)
+            ]
+        );
+        assert!(descriptor.is_hidpp());
+    }
+
+    #[test]
+    fn test_bluetooth_collection_after_mouse() {
+        let descriptor = ReportDescriptor::parse(BLUETOOTH_MOUSE).unwrap();
+        assert_eq!(
+            descriptor.reports,
+            [(0x02, 0x0001), (REPORT_ID_LONG, USAGE_PAGE_HIDPP_BLUETOOTH)]
+        );
+        assert!(descriptor.is_hidpp());
+    }
+
+    #[test]
+    fn test_other_interfaces_are_not_hidpp() {
+        assert!(!ReportDescriptor::parse(KEYBOARD).unwrap().is_hidpp());
+
+        // Report id 0x10 outside a vendor page, as some mouse interfaces use.
+        let mouse = [0x05, 0x01, 0x09, 0x02, 0xA1, 0x01, 0x85, 0x10, 0xC0];
+        assert!(!ReportDescriptor::parse(&mouse).unwrap().is_hidpp());
+    }
+
+    #[test]
+    fn test_push_pop_and_truncation() {
+        let pushed = [
+            0x06, 0x00, 0xFF, 0xA4, 0x05, 0x01, 0x85, 0x02, 0xB4, 0x85, 0x10
+        ];
+        assert_eq!(
+            ReportDescriptor::parse(&pushed).unwrap().reports,
+            [(0x02, 0x0001), (REPORT_ID_SHORT, USAGE_PAGE_HIDPP)]
+        );
+
+        assert!(ReportDescriptor::parse(&[0x06, 0x00]).is_err());
+    }
+}
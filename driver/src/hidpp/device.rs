@@ -1,27 +1,153 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
-use std::{collections::HashMap, ffi::CString, time::Duration};
-
-use hidapi::{HidApi, HidDevice};
-use masterror::{field, prelude::*};
+use std::{
+    collections::{HashMap, VecDeque},
+    ffi::CString,
+    path::{Path, PathBuf},
+    time::{Duration, Instant}
+};
+
+use hidapi::HidApi;
+use masterror::prelude::*;
 use tracing::{debug, trace, warn};
 
-use super::{RootFunction, constants::*, packet::HidppPacket};
-use crate::error::{DeviceErrorKind, Result};
+use super::{
+    CommandContext, DeviceInfoFunction, Direction, FeatureSetFunction, Hidpp20Error, HidppError,
+    RootFunction,
+    cache::{FeatureCacheFile, FeatureCacheKey, get_feature_cache_dir},
+    capture_if_enabled,
+    constants::*,
+    descriptor::ReportDescriptor,
+    dissect, feature_id_for_index,
+    packet::HidppPacket,
+    transport::Transport
+};
+use crate::{
+    emulator,
+    error::{DeviceErrorKind, Result}
+};
 
 const DEFAULT_TIMEOUT_MS: i32 = 1000;
 const RETRY_COUNT: usize = 3;
+const MAX_PENDING_NOTIFICATIONS: usize = 32;
+const SERIAL_NUMBER_LEN: usize = 12;
+const TRANSPORT_RECEIVER: u8 = 0x04;
+const CAPABILITY_SERIAL_NUMBER: u8 = 0x01;
+
+// With the linux-native backend the returned path is the hidraw node. The
+// node is picked by its report descriptor; the interface number is only
+// consulted for nodes whose descriptor cannot be read.
+pub(crate) fn find_hidpp_path(api: &HidApi, vendor_id: u16, product_id: u16) -> Result<CString> {
+    let device_info = api
+        .device_list()
+        .filter(|info| info.vendor_id() == vendor_id && info.product_id() == product_id)
+        .find(|info| {
+            let path = info.path().to_string_lossy();
+            match ReportDescriptor::read_for_hidraw(&path) {
+                Ok(descriptor) => descriptor.is_hidpp(),
+                Err(e) => {
+                    debug!("No report descriptor for {}: {}", path, e);
+                    info.interface_number() == 2 || info.interface_number() == -1
+                }
+            }
+        })
+        .ok_or_else(|| AppError::not_found("HID++ interface not found for device"))?;
+
+    debug!(
+        "Found HID++ device at interface {}: {:?}",
+        device_info.interface_number(),
+        device_info.path()
+    );
+
+    Ok(device_info.path().to_owned())
+}
+
+// Identifiers from DeviceInformation (0x0003). The model id slots are filled
+// in transport order (Bluetooth, BLE, receiver, USB), so the receiver
+// wireless id can be picked out by the transport bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIds {
+    pub unit_id:          [u8; 4],
+    pub model_ids:        Vec<u16>,
+    pub wpid:             Option<u16>,
+    pub serial_supported: bool
+}
+
+impl DeviceIds {
+    pub fn parse(params: &[u8]) -> Result<Self> {
+        if params.len() < 13 {
+            return Err(DeviceErrorKind::InvalidResponse.into());
+        }
+
+        let transports = params[6];
+        let slots: Vec<u16> = params[7..13]
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        let wpid_slot = (transports & TRANSPORT_RECEIVER != 0)
+            .then(|| (transports & (TRANSPORT_RECEIVER - 1)).count_ones() as usize);
+
+        Ok(Self {
+            unit_id:          [params[1], params[2], params[3], params[4]],
+            model_ids:        slots.iter().copied().filter(|&pid| pid != 0).collect(),
+            wpid:             wpid_slot
+                .and_then(|slot| slots.get(slot).copied())
+                .filter(|&pid| pid != 0),
+            serial_supported: params
+                .get(14)
+                .is_some_and(|&caps| caps & CAPABILITY_SERIAL_NUMBER != 0)
+        })
+    }
+
+    pub fn unit_id_hex(&self) -> String {
+        self.unit_id.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+}
 
 pub struct HidppDevice {
-    device:        HidDevice,
+    device:        Box<dyn Transport>,
     device_index:  u8,
     feature_cache: HashMap<u16, u8>,
-    software_id:   u8
+    notifications: VecDeque<HidppPacket>,
+    software_id:   u8,
+    cache_dir:     Option<PathBuf>,
+    cache_path:    Option<PathBuf>,
+    cache_loaded:  bool,
+    cache_dirty:   bool,
+    feature_count: u8
 }
 
 impl HidppDevice {
+    pub fn with_transport(device: Box<dyn Transport>, device_index: u8) -> Self {
+        Self {
+            device,
+            device_index,
+            feature_cache: HashMap::new(),
+            notifications: VecDeque::new(),
+            software_id: 0x05,
+            cache_dir: None,
+            cache_path: None,
+            cache_loaded: false,
+            cache_dirty: false,
+            feature_count: 0
+        }
+    }
+
+    // Real devices keep their feature indices on disk; see
+    // `load_feature_cache`.
+    fn with_feature_cache(mut self) -> Self {
+        self.cache_dir = get_feature_cache_dir()
+            .inspect_err(|e| debug!("Feature cache disabled: {}", e))
+            .ok();
+        self
+    }
+
     pub fn open_path(path: &str, device_index: u8) -> Result<Self> {
+        if path == emulator::EMULATOR_PATH {
+            return emulator::shared().open(device_index);
+        }
+
         let api = HidApi::new()
             .map_err(|e| AppError::internal("Failed to initialize HID API").with_source(e))?;
 
@@ -37,36 +163,21 @@ impl HidppDevice {
             path, device_index
         );
 
-        Ok(Self {
-            device,
-            device_index,
-            feature_cache: HashMap::new(),
-            software_id: 0x05
-        })
+        Ok(
+            Self::with_transport(capture_if_enabled(Box::new(device))?, device_index)
+                .with_feature_cache()
+        )
     }
 
     pub fn open_vid_pid(vendor_id: u16, product_id: u16, device_index: u8) -> Result<Self> {
+        if emulator::emulator_enabled() {
+            return emulator::open_vid_pid(vendor_id, product_id, device_index);
+        }
+
         let api = HidApi::new()
             .map_err(|e| AppError::internal("Failed to initialize HID API").with_source(e))?;
 
-        let mut target_path = None;
-        for device_info in api.device_list() {
-            if device_info.vendor_id() == vendor_id
-                && device_info.product_id() == product_id
-                && (device_info.interface_number() == 2 || device_info.interface_number() == -1)
-            {
-                target_path = Some(device_info.path().to_owned());
-                debug!(
-                    "Found HID++ device at interface {}: {:?}",
-                    device_info.interface_number(),
-                    device_info.path()
-                );
-                break;
-            }
-        }
-
-        let path = target_path
-            .ok_or_else(|| AppError::not_found("HID++ interface not found for device"))?;
+        let path = find_hidpp_path(&api, vendor_id, product_id)?;
 
         let device = api
             .open_path(&path)
@@ -77,12 +188,10 @@ impl HidppDevice {
             vendor_id, product_id, device_index
         );
 
-        Ok(Self {
-            device,
-            device_index,
-            feature_cache: HashMap::new(),
-            software_id: 0x05
-        })
+        Ok(
+            Self::with_transport(capture_if_enabled(Box::new(device))?, device_index)
+                .with_feature_cache()
+        )
     }
 
     pub fn send_command(
@@ -91,48 +200,42 @@ impl HidppDevice {
         function_id: u8,
         params: &[u8]
     ) -> Result<HidppPacket> {
-        let packet = if params.len() <= 3 {
-            let mut parameters = [0u8; 3];
-            parameters[..params.len()].copy_from_slice(params);
-            HidppPacket::new_short(
-                self.device_index,
-                feature_index,
-                function_id,
-                self.software_id,
-                parameters
-            )
-        } else if params.len() <= 16 {
-            let mut parameters = [0u8; 16];
-            parameters[..params.len()].copy_from_slice(params);
-            HidppPacket::new_long(
-                self.device_index,
-                feature_index,
-                function_id,
-                self.software_id,
-                parameters
-            )
-        } else {
-            return Err(AppError::bad_request(
-                "Parameters too long for HID++ packet"
-            ));
-        };
+        let context = self.command_context(feature_index, function_id);
+        self.send_command_retrying(feature_index, function_id, params)
+            .map_err(|e| context.attach(e))
+    }
+
+    fn send_command_retrying(
+        &mut self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8]
+    ) -> Result<HidppPacket> {
+        let packet = self.build_packet(feature_index, function_id, params)?;
 
-        trace!("Sending HID++ packet: {:?}", packet);
+        trace!("Sending {}", self.dissect(&packet, Direction::Sent));
 
         for attempt in 0..RETRY_COUNT {
             match self.send_packet_with_response(&packet) {
                 Ok(response) => {
-                    if response.is_error()
-                        && let Some(error_code) = response.get_error_code()
-                    {
-                        if error_code == ERROR_BUSY && attempt < RETRY_COUNT - 1 {
+                    trace!("Received {}", self.dissect(&response, Direction::Received));
+                    if let Some(error) = response.error() {
+                        if error.is_busy() && attempt < RETRY_COUNT - 1 {
                             warn!("Device busy, retrying... (attempt {})", attempt + 1);
                             std::thread::sleep(Duration::from_millis(50));
                             continue;
                         }
-                        return Err(self.map_hidpp_error(error_code));
+                        if error == HidppError::V20(Hidpp20Error::InvalidFeatureIndex)
+                            && self.cache_loaded
+                        {
+                            return self.resend_after_stale_cache(
+                                feature_index,
+                                function_id,
+                                params
+                            );
+                        }
+                        return Err(error.into());
                     }
-                    trace!("Received response: {:?}", response);
                     return Ok(response);
                 }
                 Err(e) if attempt < RETRY_COUNT - 1 => {
@@ -151,28 +254,312 @@ impl HidppDevice {
         Err(DeviceErrorKind::CommandFailed.into())
     }
 
+    // A single attempt for commands that must not be repeated, such as a DFU
+    // block the device may already have written.
+    pub fn send_command_once(
+        &mut self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8]
+    ) -> Result<HidppPacket> {
+        let context = self.command_context(feature_index, function_id);
+        let packet = self
+            .build_packet(feature_index, function_id, params)
+            .map_err(|e| context.attach(e))?;
+
+        trace!("Sending once {}", self.dissect(&packet, Direction::Sent));
+
+        let response = self
+            .send_packet_with_response(&packet)
+            .map_err(|e| context.attach(e))?;
+        trace!("Received {}", self.dissect(&response, Direction::Received));
+        match response.error() {
+            Some(error) => Err(context.attach(error.into())),
+            None => Ok(response)
+        }
+    }
+
+    pub fn send_command_no_reply(
+        &mut self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8]
+    ) -> Result<()> {
+        let context = self.command_context(feature_index, function_id);
+        let packet = self
+            .build_packet(feature_index, function_id, params)
+            .map_err(|e| context.attach(e))?;
+
+        trace!(
+            "Sending without reply {}",
+            self.dissect(&packet, Direction::Sent)
+        );
+
+        self.device
+            .write(&packet.to_bytes())
+            .map_err(|e| context.attach(e))
+    }
+
+    fn dissect(&self, packet: &HidppPacket, direction: Direction) -> String {
+        let feature_id = feature_id_for_index(&self.feature_cache, packet.feature_index());
+        dissect(packet, feature_id, direction)
+    }
+
+    fn command_context(&self, feature_index: u8, function_id: u8) -> CommandContext {
+        CommandContext::new(
+            self.device_index,
+            feature_index,
+            function_id,
+            &self.feature_cache
+        )
+    }
+
+    fn build_packet(
+        &self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8]
+    ) -> Result<HidppPacket> {
+        HidppPacket::new_request(
+            self.device_index,
+            feature_index,
+            function_id,
+            self.software_id,
+            params
+        )
+    }
+
     pub fn get_feature_index(&mut self, feature_id: u16) -> Result<u8> {
-        if let Some(&index) = self.feature_cache.get(&feature_id) {
-            return Ok(index);
+        let context = CommandContext::lookup(self.device_index, feature_id);
+        self.find_feature_index(feature_id)?
+            .ok_or_else(|| context.attach(DeviceErrorKind::UnsupportedFeature.into()))
+    }
+
+    // `None` when the device reports the feature as unsupported, so callers
+    // can tell that apart from a lookup that failed.
+    pub fn find_feature_index(&mut self, feature_id: u16) -> Result<Option<u8>> {
+        let context = CommandContext::lookup(self.device_index, feature_id);
+        self.lookup_feature_index(feature_id)
+            .map_err(|e| context.attach(e))
+    }
+
+    fn lookup_feature_index(&mut self, feature_id: u16) -> Result<Option<u8>> {
+        // Unsupported features are cached as index 0 so pollers do not
+        // repeat the lookup.
+        match self.feature_cache.get(&feature_id) {
+            Some(0) => return Ok(None),
+            Some(&index) => return Ok(Some(index)),
+            None => {}
         }
 
         let params = [(feature_id >> 8) as u8, (feature_id & 0xFF) as u8, 0x00];
 
-        let response = self.send_command(ROOT_INDEX, RootFunction::GetFeature as u8, &params)?;
+        let response =
+            self.send_command_retrying(ROOT_INDEX, RootFunction::GetFeature as u8, &params)?;
 
         let index = match response {
             HidppPacket::Short(p) => p.parameters[0],
             HidppPacket::Long(p) => p.parameters[0]
         };
 
+        self.feature_cache.insert(feature_id, index);
+        self.cache_dirty = true;
         if index == 0 {
-            return Err(DeviceErrorKind::UnsupportedFeature.into());
+            return Ok(None);
         }
 
-        self.feature_cache.insert(feature_id, index);
         debug!("Feature {:04x} mapped to index {}", feature_id, index);
 
-        Ok(index)
+        Ok(Some(index))
+    }
+
+    // Versions are not part of the index cache, so this always asks the root.
+    pub fn read_feature_version(&mut self, feature_id: u16) -> Result<u8> {
+        let context = CommandContext::lookup(self.device_index, feature_id);
+        let params = [(feature_id >> 8) as u8, (feature_id & 0xFF) as u8, 0x00];
+
+        let response = self
+            .send_command_retrying(ROOT_INDEX, RootFunction::GetFeature as u8, &params)
+            .map_err(|e| context.attach(e))?;
+        let params = response.parameters();
+        if params[0] == 0 {
+            return Err(context.attach(DeviceErrorKind::UnsupportedFeature.into()));
+        }
+
+        Ok(params[2])
+    }
+
+    // Indices are kept per model (the receiver wireless id where there is
+    // one, which the caller has just read) and firmware version, and only
+    // trusted while FeatureSet still reports the count stored with them. An
+    // index the device rejects anyway drops the file, see
+    // `resend_after_stale_cache`. Does nothing for devices opened without a
+    // cache directory.
+    pub fn load_feature_cache(&mut self, ids: &DeviceIds) -> Result<()> {
+        let Some(dir) = self.cache_dir.clone() else {
+            return Ok(());
+        };
+        self.load_feature_cache_from(&dir, ids)
+    }
+
+    pub fn load_feature_cache_from(&mut self, dir: &Path, ids: &DeviceIds) -> Result<()> {
+        let model_id = ids
+            .wpid
+            .or_else(|| ids.model_ids.first().copied())
+            .ok_or_else(|| AppError::not_found("Device reports no model id"))?;
+        let key = FeatureCacheKey {
+            model_id,
+            firmware: self.read_firmware_name()?
+        };
+        let path = dir.join(key.file_name());
+        let feature_count = self.read_feature_count()?;
+
+        match FeatureCacheFile::load(&path) {
+            Ok(Some(cached)) if cached.feature_count == feature_count => {
+                debug!(
+                    "Loaded {} cached feature indices from {:?}",
+                    cached.features.len(),
+                    path
+                );
+                let cached = cached.to_map();
+                // Lookups made before the load, such as device information,
+                // only need writing if the file lacks them.
+                self.cache_dirty = self
+                    .feature_cache
+                    .keys()
+                    .any(|feature_id| !cached.contains_key(feature_id));
+                for (feature_id, index) in cached {
+                    self.feature_cache.entry(feature_id).or_insert(index);
+                }
+                self.cache_loaded = true;
+            }
+            Ok(Some(_)) => {
+                debug!("Feature cache {:?} is stale, rebuilding", path);
+                self.cache_dirty = true;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Ignoring feature cache {:?}: {}", path, e)
+        }
+
+        self.feature_count = feature_count;
+        self.cache_path = Some(path);
+        Ok(())
+    }
+
+    fn read_feature_count(&mut self) -> Result<u8> {
+        let feature_index = self.get_feature_index(FEATURE_FEATURE_SET)?;
+        let response =
+            self.send_command(feature_index, FeatureSetFunction::GetCount as u8, &[])?;
+        Ok(response.parameters()[0])
+    }
+
+    // Called once discovery is done; only new lookups cause a write.
+    pub fn save_feature_cache(&mut self) {
+        let Some(path) = &self.cache_path else {
+            return;
+        };
+        if !self.cache_dirty {
+            return;
+        }
+
+        match FeatureCacheFile::from_map(self.feature_count, &self.feature_cache).save(path) {
+            Ok(()) => self.cache_dirty = false,
+            Err(e) => debug!("Failed to save feature cache {:?}: {}", path, e)
+        }
+    }
+
+    // An index from the cache file that the device rejects means the file no
+    // longer matches it: the file is dropped, the feature looked up again
+    // and the command resent once.
+    fn resend_after_stale_cache(
+        &mut self,
+        feature_index: u8,
+        function_id: u8,
+        params: &[u8]
+    ) -> Result<HidppPacket> {
+        warn!(
+            "Device rejected cached feature index {}, discarding {:?}",
+            feature_index, self.cache_path
+        );
+        let feature_id = feature_id_for_index(&self.feature_cache, feature_index);
+        self.feature_cache.clear();
+        self.cache_loaded = false;
+        self.cache_dirty = true;
+        if let Some(path) = &self.cache_path {
+            std::fs::remove_file(path).ok();
+        }
+
+        let Some(feature_id) = feature_id else {
+            return Err(HidppError::V20(Hidpp20Error::InvalidFeatureIndex).into());
+        };
+        let index = self.get_feature_index(feature_id)?;
+        self.send_command_retrying(index, function_id, params)
+    }
+
+    // After a reboot into another firmware (the DFU bootloader) every index
+    // may have moved, and the cache file belongs to the old firmware.
+    pub fn reset_feature_cache(&mut self) {
+        self.feature_cache.clear();
+        self.feature_count = 0;
+        self.cache_path = None;
+        self.cache_loaded = false;
+        self.cache_dirty = false;
+    }
+
+    pub fn read_device_ids(&mut self) -> Result<DeviceIds> {
+        let feature_index = self.get_feature_index(FEATURE_DEVICE_INFO)?;
+
+        let response =
+            self.send_command(feature_index, DeviceInfoFunction::GetDeviceInfo as u8, &[])?;
+        DeviceIds::parse(response.parameters())
+    }
+
+    pub fn read_model_ids(&mut self) -> Result<Vec<u16>> {
+        self.read_device_ids().map(|ids| ids.model_ids)
+    }
+
+    // Devices without a serial number still report a unique unit id, which
+    // is returned as hex instead.
+    // Takes the ids already read, as they decide where the serial comes from.
+    pub fn read_serial_number(&mut self, ids: &DeviceIds) -> Result<String> {
+        if !ids.serial_supported {
+            return Ok(ids.unit_id_hex());
+        }
+
+        let feature_index = self.get_feature_index(FEATURE_DEVICE_INFO)?;
+        let response = self.send_command(
+            feature_index,
+            DeviceInfoFunction::GetDeviceSerialNumber as u8,
+            &[]
+        )?;
+        let params = response.parameters();
+        let serial: String = params[..params.len().min(SERIAL_NUMBER_LEN)]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| byte as char)
+            .collect();
+
+        Ok(serial)
+    }
+
+    // Version of the running firmware, entity 0, e.g. "MPM19.01_B0021".
+    fn read_firmware_name(&mut self) -> Result<String> {
+        let feature_index = self.get_feature_index(FEATURE_DEVICE_INFO)?;
+
+        let response =
+            self.send_command(feature_index, DeviceInfoFunction::GetFwInfo as u8, &[0])?;
+        let params = response.parameters();
+        if params.len() < 8 {
+            return Err(DeviceErrorKind::InvalidResponse.into());
+        }
+
+        Ok(format!(
+            "{}{:02x}.{:02x}_B{:04x}",
+            String::from_utf8_lossy(&params[1..4]).trim_end_matches('\0'),
+            params[4],
+            params[5],
+            u16::from_be_bytes([params[6], params[7]])
+        ))
     }
 
     pub fn ping(&mut self) -> Result<()> {
@@ -181,48 +568,83 @@ impl HidppDevice {
         Ok(())
     }
 
+    pub fn read_notification(&mut self, timeout_ms: i32) -> Result<Option<HidppPacket>> {
+        if let Some(packet) = self.notifications.pop_front() {
+            return Ok(Some(packet));
+        }
+
+        match self.read_packet(timeout_ms)? {
+            Some(packet) if packet.device_index() == self.device_index => Ok(Some(packet)),
+            _ => Ok(None)
+        }
+    }
+
+    // Reports for any device index, as a receiver handle sees them.
+    pub fn read_report(&mut self, timeout_ms: i32) -> Result<Option<HidppPacket>> {
+        self.read_packet(timeout_ms)
+    }
+
     fn send_packet_with_response(&mut self, packet: &HidppPacket) -> Result<HidppPacket> {
-        let bytes = packet.to_bytes();
-        self.device
-            .write(&bytes)
-            .map_err(|e| AppError::internal("Failed to write to device").with_source(e))?;
+        self.device.write(&packet.to_bytes())?;
+
+        let deadline = Instant::now() + Duration::from_millis(DEFAULT_TIMEOUT_MS as u64);
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(DeviceErrorKind::Timeout.into());
+            }
 
+            let Some(response) = self.read_packet(remaining.as_millis() as i32)? else {
+                return Err(DeviceErrorKind::Timeout.into());
+            };
+
+            if self.is_response_to(packet, &response) {
+                return Ok(response);
+            }
+
+            if response.device_index() == self.device_index {
+                trace!("Queued {}", self.dissect(&response, Direction::Received));
+                if self.notifications.len() == MAX_PENDING_NOTIFICATIONS {
+                    self.notifications.pop_front();
+                }
+                self.notifications.push_back(response);
+            }
+        }
+    }
+
+    fn read_packet(&mut self, timeout_ms: i32) -> Result<Option<HidppPacket>> {
         let mut buf = [0u8; 64];
-        let size = self
-            .device
-            .read_timeout(&mut buf, DEFAULT_TIMEOUT_MS)
-            .map_err(|e| AppError::internal("Failed to read from device").with_source(e))?;
+        let size = self.device.read_timeout(&mut buf, timeout_ms)?;
 
         if size == 0 {
-            return Err(DeviceErrorKind::Timeout.into());
+            return Ok(None);
         }
 
-        HidppPacket::from_bytes(&buf[..size])
-    }
-
-    fn map_hidpp_error(&self, error_code: u8) -> AppError {
-        match error_code {
-            ERROR_INVALID_SUBID => AppError::bad_request("Invalid function ID"),
-            ERROR_INVALID_ADDRESS => AppError::bad_request("Invalid address"),
-            ERROR_INVALID_VALUE => AppError::bad_request("Invalid value"),
-            ERROR_CONNECT_FAIL => AppError::internal("Connection failed"),
-            ERROR_TOO_MANY_DEVICES => AppError::conflict("Too many devices"),
-            ERROR_ALREADY_EXISTS => AppError::conflict("Already exists"),
-            ERROR_BUSY => AppError::internal("Device busy"),
-            ERROR_UNKNOWN_DEVICE => AppError::not_found("Unknown device"),
-            ERROR_RESOURCE_ERROR => AppError::internal("Resource error"),
-            ERROR_REQUEST_UNAVAILABLE => AppError::bad_request("Request unavailable"),
-            ERROR_UNSUPPORTED_PARAM => AppError::bad_request("Unsupported parameter"),
-            ERROR_WRONG_PIN_CODE => AppError::unauthorized("Wrong PIN code"),
-            _ => AppError::internal("Unknown HID++ error")
-                .with_field(field::u64("error_code", error_code as u64))
-        }
+        HidppPacket::from_bytes(&buf[..size]).map(Some)
+    }
+
+    fn is_response_to(&self, request: &HidppPacket, response: &HidppPacket) -> bool {
+        response.device_index() == request.device_index()
+            && match response.error_request() {
+                Some(failed) => failed == (request.feature_index(), self.software_id),
+                None => {
+                    response.feature_index() == request.feature_index()
+                        && response.software_id() == self.software_id
+                }
+            }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering}
+    };
+
     use super::*;
+    use crate::hidpp::{ERROR_REPORT_HIDPP20, Hidpp10Error, ScriptedTransport};
 
     #[test]
     fn test_feature_cache() {
@@ -231,6 +653,281 @@ mod tests {
         assert_eq!(cache.get(&FEATURE_ROOT), Some(&ROOT_INDEX));
     }
 
+    #[test]
+    fn test_device_ids_pick_wpid_by_transport() {
+        // Bluetooth and receiver transports of an MX Master 3: B023 is the
+        // Bluetooth pid and 4082 the wireless id.
+        let mut params = [0u8; 16];
+        params[1..5].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        params[6] = 0x05;
+        params[7..11].copy_from_slice(&[0xB0, 0x23, 0x40, 0x82]);
+        params[14] = 0x01;
+
+        let ids = DeviceIds::parse(&params).unwrap();
+        assert_eq!(ids.model_ids, [0xB023, 0x4082]);
+        assert_eq!(ids.wpid, Some(0x4082));
+        assert!(ids.serial_supported);
+        assert_eq!(ids.unit_id_hex(), "DEADBEEF");
+
+        params[6] = 0x08;
+        assert_eq!(DeviceIds::parse(&params).unwrap().wpid, None);
+        assert!(DeviceIds::parse(&params[..12]).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_feature_is_cached() {
+        let transport = ScriptedTransport::new(|report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let index = if request.parameters()[1] == 0x05 {
+                0x03
+            } else {
+                0x00
+            };
+            Ok(vec![
+                HidppPacket::new_short(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    [index, 0, 0]
+                )
+                .to_bytes(),
+            ])
+        });
+        let mut device = HidppDevice::with_transport(Box::new(transport), 1);
+
+        assert_eq!(device.get_feature_index(FEATURE_DEVICE_NAME).unwrap(), 0x03);
+        assert!(device.get_feature_index(FEATURE_ADJUSTABLE_DPI).is_err());
+        assert!(device.get_feature_index(FEATURE_ADJUSTABLE_DPI).is_err());
+        assert_eq!(device.feature_cache.get(&FEATURE_ADJUSTABLE_DPI), Some(&0));
+    }
+
+    #[test]
+    fn test_notification_queued_while_waiting_for_reply() {
+        let transport = ScriptedTransport::new(|report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let notification =
+                HidppPacket::new_short(request.device_index(), 0x04, 0x00, 0x00, [0x01, 0, 0]);
+            let reply = HidppPacket::new_short(
+                request.device_index(),
+                request.feature_index(),
+                request.function_id(),
+                request.software_id(),
+                [0, 0, 0]
+            );
+            Ok(vec![notification.to_bytes(), reply.to_bytes()])
+        });
+        let mut device = HidppDevice::with_transport(Box::new(transport), 1);
+
+        device.ping().unwrap();
+
+        let notification = device.read_notification(0).unwrap().unwrap();
+        assert_eq!(notification.feature_index(), 0x04);
+        assert!(device.read_notification(0).unwrap().is_none());
+    }
+
+    fn cached_device(
+        dpi_index: u8,
+        build: u16,
+        writes: Arc<AtomicUsize>,
+        lookups: Arc<AtomicUsize>
+    ) -> HidppDevice {
+        counted_device(dpi_index, build, 12, writes, lookups)
+    }
+
+    // Counts every report and every DPI feature lookup; any index other than
+    // feature set, device info and `dpi_index` is rejected as invalid.
+    fn counted_device(
+        dpi_index: u8,
+        build: u16,
+        feature_count: u8,
+        writes: Arc<AtomicUsize>,
+        lookups: Arc<AtomicUsize>
+    ) -> HidppDevice {
+        let transport = ScriptedTransport::new(move |report| {
+            writes.fetch_add(1, Ordering::SeqCst);
+            let request = HidppPacket::from_bytes(report)?;
+            let params = request.parameters();
+            let mut reply = [0u8; 16];
+            match (request.feature_index(), request.function_id()) {
+                (ROOT_INDEX, 0x00) => {
+                    reply[0] = match u16::from_be_bytes([params[0], params[1]]) {
+                        FEATURE_FEATURE_SET => 0x01,
+                        FEATURE_DEVICE_INFO => 0x02,
+                        FEATURE_ADJUSTABLE_DPI => {
+                            lookups.fetch_add(1, Ordering::SeqCst);
+                            dpi_index
+                        }
+                        _ => 0x00
+                    };
+                }
+                (0x01, 0x00) => reply[0] = feature_count,
+                (0x02, 0x00) => reply[7..9].copy_from_slice(&0xB034u16.to_be_bytes()),
+                (0x02, 0x01) => {
+                    reply[1..4].copy_from_slice(b"MPM");
+                    reply[4] = 0x19;
+                    reply[5] = 0x01;
+                    reply[6..8].copy_from_slice(&build.to_be_bytes());
+                }
+                (index, _) if index == dpi_index => {}
+                (index, function) => {
+                    reply[0] = (function << 4) | request.software_id();
+                    reply[1] = 0x06;
+                    return Ok(vec![
+                        HidppPacket::new_long(
+                            request.device_index(),
+                            ERROR_REPORT_HIDPP20,
+                            index >> 4,
+                            index & 0x0F,
+                            reply
+                        )
+                        .to_bytes(),
+                    ]);
+                }
+            }
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    reply
+                )
+                .to_bytes(),
+            ])
+        });
+        HidppDevice::with_transport(Box::new(transport), 1)
+    }
+
+    #[test]
+    fn test_feature_cache_persisted_across_opens() {
+        let dir = std::env::temp_dir().join("logi-mx-device-cache-test");
+        std::fs::remove_dir_all(&dir).ok();
+        let path = dir.join("b034-MPM19.01_B0021.toml");
+        let writes = Arc::new(AtomicUsize::new(0));
+        let lookups = Arc::new(AtomicUsize::new(0));
+
+        let mut device = cached_device(0x07, 0x0021, writes.clone(), lookups.clone());
+        let ids = device.read_device_ids().unwrap();
+        device.load_feature_cache_from(&dir, &ids).unwrap();
+        assert_eq!(
+            device.get_feature_index(FEATURE_ADJUSTABLE_DPI).unwrap(),
+            0x07
+        );
+        assert!(!path.exists());
+        device.save_feature_cache();
+        assert!(path.exists());
+
+        // Trusting the file costs a GetFwInfo and the feature set lookup
+        // and count, and an unchanged cache is not written again.
+        let mut device = cached_device(0x07, 0x0021, writes.clone(), lookups.clone());
+        let ids = device.read_device_ids().unwrap();
+        let before = writes.load(Ordering::SeqCst);
+        device.load_feature_cache_from(&dir, &ids).unwrap();
+        assert_eq!(writes.load(Ordering::SeqCst), before + 3);
+        assert_eq!(
+            device.get_feature_index(FEATURE_ADJUSTABLE_DPI).unwrap(),
+            0x07
+        );
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        std::fs::remove_file(&path).unwrap();
+        device.save_feature_cache();
+        assert!(!path.exists());
+
+        let mut device = cached_device(0x07, 0x0022, writes.clone(), lookups.clone());
+        let ids = device.read_device_ids().unwrap();
+        device.load_feature_cache_from(&dir, &ids).unwrap();
+        device.get_feature_index(FEATURE_ADJUSTABLE_DPI).unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_missing_feature_names_the_feature() {
+        let mut device = cached_device(
+            0x07,
+            0x0021,
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicUsize::new(0))
+        );
+
+        // Both the lookup and the cached answer say which feature it was.
+        for _ in 0..2 {
+            let error = device.get_feature_index(FEATURE_SMART_SHIFT).unwrap_err();
+            assert_eq!(
+                error.metadata().get("feature_id"),
+                Some(&masterror::FieldValue::U64(FEATURE_SMART_SHIFT as u64))
+            );
+            assert!(error.metadata().get("device_index").is_some());
+        }
+    }
+
+    #[test]
+    fn test_stale_feature_cache_is_a_miss() {
+        let dir = std::env::temp_dir().join("logi-mx-stale-cache-test");
+        std::fs::remove_dir_all(&dir).ok();
+        let path = dir.join("b034-MPM19.01_B0021.toml");
+        let writes = Arc::new(AtomicUsize::new(0));
+        let lookups = Arc::new(AtomicUsize::new(0));
+
+        let mut device = cached_device(0x07, 0x0021, writes.clone(), lookups.clone());
+        let ids = device.read_device_ids().unwrap();
+        device.load_feature_cache_from(&dir, &ids).unwrap();
+        device.get_feature_index(FEATURE_ADJUSTABLE_DPI).unwrap();
+        device.save_feature_cache();
+
+        // Same firmware name, but the feature has moved.
+        let mut device = cached_device(0x08, 0x0021, writes.clone(), lookups.clone());
+        let ids = device.read_device_ids().unwrap();
+        device.load_feature_cache_from(&dir, &ids).unwrap();
+        let index = device.get_feature_index(FEATURE_ADJUSTABLE_DPI).unwrap();
+        assert_eq!(index, 0x07);
+
+        let response = device.send_command(index, 0x01, &[]).unwrap();
+        assert_eq!(response.feature_index(), 0x08);
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+        assert!(!path.exists());
+        assert_eq!(
+            device.get_feature_index(FEATURE_ADJUSTABLE_DPI).unwrap(),
+            0x08
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_feature_count_change_discards_cache() {
+        let dir = std::env::temp_dir().join("logi-mx-count-cache-test");
+        std::fs::remove_dir_all(&dir).ok();
+        let path = dir.join("b034-MPM19.01_B0021.toml");
+        let writes = Arc::new(AtomicUsize::new(0));
+        let lookups = Arc::new(AtomicUsize::new(0));
+
+        let mut device = counted_device(0x07, 0x0021, 12, writes.clone(), lookups.clone());
+        let ids = device.read_device_ids().unwrap();
+        device.load_feature_cache_from(&dir, &ids).unwrap();
+        device.get_feature_index(FEATURE_ADJUSTABLE_DPI).unwrap();
+        device.save_feature_cache();
+
+        // Same firmware name, one feature more, and the old DPI index is now
+        // a valid index of something else: nothing is taken from the file.
+        let mut device = counted_device(0x08, 0x0021, 13, writes.clone(), lookups.clone());
+        let ids = device.read_device_ids().unwrap();
+        device.load_feature_cache_from(&dir, &ids).unwrap();
+        assert_eq!(
+            device.get_feature_index(FEATURE_ADJUSTABLE_DPI).unwrap(),
+            0x08
+        );
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        device.save_feature_cache();
+        let saved = FeatureCacheFile::load(&path).unwrap().unwrap();
+        assert_eq!(saved.feature_count, 13);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
     #[test]
     fn test_error_mapping() {
         let error_codes = vec![
@@ -248,47 +945,60 @@ mod tests {
             ERROR_WRONG_PIN_CODE,
         ];
 
-        let api = HidApi::new().unwrap();
-        let devices: Vec<_> = api.device_list().collect();
-
-        if devices.is_empty() {
-            return;
-        }
-
-        if let Ok(opened_device) = api.open_path(devices[0].path()) {
-            let device = HidppDevice {
-                device:        opened_device,
-                device_index:  1,
-                feature_cache: HashMap::new(),
-                software_id:   0x05
-            };
-
-            for code in error_codes {
-                let err = device.map_hidpp_error(code);
-                assert!(!err.to_string().is_empty());
-            }
+        for code in error_codes {
+            let err = AppError::from(Hidpp10Error::from(code));
+            assert!(!err.to_string().is_empty());
+            let err = AppError::from(Hidpp20Error::from(code));
+            assert!(!err.to_string().is_empty());
         }
     }
 
     #[test]
     fn test_unknown_error_code() {
-        let api = HidApi::new().unwrap();
-        let devices: Vec<_> = api.device_list().collect();
-
-        if devices.is_empty() {
-            return;
-        }
-
-        if let Ok(opened_device) = api.open_path(devices[0].path()) {
-            let device = HidppDevice {
-                device:        opened_device,
-                device_index:  1,
-                feature_cache: HashMap::new(),
-                software_id:   0x05
-            };
+        let err = AppError::from(Hidpp10Error::from(0xFF));
+        assert!(!err.to_string().is_empty());
+        let err = AppError::from(Hidpp20Error::from(0xFF));
+        assert!(!err.to_string().is_empty());
+    }
 
-            let err = device.map_hidpp_error(0xFF);
-            assert!(!err.to_string().is_empty());
+    #[test]
+    fn test_hidpp20_error_carries_command_context() {
+        let transport = ScriptedTransport::new(|report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let mut reply = [0u8; 16];
+            if request.feature_index() == ROOT_INDEX {
+                reply[0] = 0x0A;
+            } else {
+                return Ok(vec![vec![
+                    0x10,
+                    request.device_index(),
+                    0xFF,
+                    request.feature_index(),
+                    (request.function_id() << 4) | request.software_id(),
+                    0x02,
+                    0x00,
+                ]]);
+            }
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    reply
+                )
+                .to_bytes(),
+            ])
+        });
+        let mut device = HidppDevice::with_transport(Box::new(transport), 2);
+
+        let index = device.get_feature_index(FEATURE_SMART_SHIFT).unwrap();
+        let err = device.send_command(index, 0x01, &[0x05]).unwrap_err();
+
+        assert_eq!(err.kind, AppErrorKind::BadRequest);
+        assert_eq!(err.render_message(), "Invalid argument");
+        for name in ["feature_id", "feature_index", "function_id", "device_index"] {
+            assert!(err.metadata().get(name).is_some(), "missing {name}");
         }
     }
 }
@@ -0,0 +1,691 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::fmt::Write;
+
+use masterror::prelude::*;
+
+use super::{
+    HidppError,
+    capture::Direction,
+    constants::*,
+    error::{ERROR_REPORT_HIDPP10, ERROR_REPORT_HIDPP20},
+    packet::HidppPacket
+};
+use crate::{devices::PersistentAction, error::Result, firmware::DfuStatus};
+
+const LONG_PARAMS_LEN: usize = 16;
+
+pub fn feature_name(feature_id: u16) -> Option<&'static str> {
+    let name = match feature_id {
+        FEATURE_ROOT => "Root",
+        FEATURE_FEATURE_SET => "FeatureSet",
+        FEATURE_FEATURE_INFO => "FeatureInfo",
+        FEATURE_DEVICE_INFO => "DeviceInfo",
+        FEATURE_DEVICE_NAME => "DeviceName",
+        FEATURE_DFU_CONTROL_SIGNED => "DfuControlSigned",
+        FEATURE_DFU_CONTROL_BOLT => "DfuControlBolt",
+        FEATURE_DFU => "Dfu",
+        FEATURE_BATTERY_STATUS => "BatteryStatus",
+        FEATURE_BATTERY_VOLTAGE => "BatteryVoltage",
+        FEATURE_UNIFIED_BATTERY => "UnifiedBattery",
+        FEATURE_CHANGE_HOST => "ChangeHost",
+        FEATURE_HOSTS_INFO => "HostsInfo",
+        FEATURE_BACKLIGHT => "Backlight",
+        FEATURE_FN_INVERSION => "FnInversion",
+        FEATURE_DISABLE_KEYS => "DisableKeys",
+        FEATURE_REPROG_CONTROLS => "ReprogControls",
+        FEATURE_PERSISTENT_REMAPPABLE_ACTION => "PersistentRemappableAction",
+        FEATURE_WIRELESS_DEVICE_STATUS => "WirelessDeviceStatus",
+        FEATURE_SMART_SHIFT => "SmartShift",
+        FEATURE_HIRES_WHEEL => "HiResWheel",
+        FEATURE_ADJUSTABLE_DPI => "AdjustableDpi",
+        FEATURE_EXTENDED_ADJUSTABLE_DPI => "ExtendedAdjustableDpi",
+        FEATURE_REPORT_RATE => "ReportRate",
+        FEATURE_EXTENDED_REPORT_RATE => "ExtendedReportRate",
+        FEATURE_ONBOARD_PROFILES => "OnboardProfiles",
+        _ => return None
+    };
+    Some(name)
+}
+
+pub fn function_name(feature_id: u16, function_id: u8) -> Option<&'static str> {
+    let names: &[&str] = match feature_id {
+        FEATURE_ROOT => &["GetFeature", "Ping"],
+        FEATURE_FEATURE_SET => &["GetCount", "GetFeatureId"],
+        FEATURE_DEVICE_INFO => &["GetDeviceInfo", "GetFwInfo", "GetDeviceSerialNumber"],
+        FEATURE_DEVICE_NAME => &["GetCount", "GetDeviceName", "GetDeviceType"],
+        FEATURE_DFU_CONTROL_SIGNED | FEATURE_DFU_CONTROL_BOLT => {
+            &["GetDfuControl", "SetDfuControl"]
+        }
+        FEATURE_DFU => &[
+            "DfuCmdData0",
+            "DfuCmdData1",
+            "DfuCmdData2",
+            "DfuCmdData3",
+            "DfuStart",
+            "Restart"
+        ],
+        FEATURE_BATTERY_STATUS => &["GetStatus", "GetCapability"],
+        FEATURE_BATTERY_VOLTAGE => &["GetBatteryVoltage"],
+        FEATURE_UNIFIED_BATTERY => &["GetCapabilities", "GetStatus"],
+        FEATURE_CHANGE_HOST => &["GetHostInfo", "SetCurrentHost"],
+        FEATURE_HOSTS_INFO => &[
+            "GetFeatureInfo",
+            "GetHostInfo",
+            "GetHostDescriptor",
+            "GetHostFriendlyName",
+            "SetHostFriendlyName",
+            "MoveHost",
+            "DeleteHost",
+            "GetHostOsVersion"
+        ],
+        FEATURE_BACKLIGHT => &[
+            "GetBacklightConfig",
+            "SetBacklightConfig",
+            "GetBacklightInfo"
+        ],
+        FEATURE_FN_INVERSION => &["GetGlobalFnInversion", "SetGlobalFnInversion"],
+        FEATURE_DISABLE_KEYS => &["GetCapabilities", "GetDisabledKeys", "SetDisabledKeys"],
+        FEATURE_REPROG_CONTROLS => &[
+            "GetControlCount",
+            "GetControlInfo",
+            "GetControlReporting",
+            "SetControlReporting"
+        ],
+        FEATURE_PERSISTENT_REMAPPABLE_ACTION => &[
+            "GetCapabilities",
+            "GetCount",
+            "GetCidInfo",
+            "GetCidReporting",
+            "SetCidReporting",
+            "ResetToFactory"
+        ],
+        FEATURE_SMART_SHIFT => &["GetRatchetControlMode", "SetRatchetControlMode"],
+        FEATURE_HIRES_WHEEL => &[
+            "GetCapabilities",
+            "GetMode",
+            "SetMode",
+            "GetRatchetSwitchState"
+        ],
+        FEATURE_ADJUSTABLE_DPI => &[
+            "GetSensorCount",
+            "GetSensorDpiList",
+            "GetSensorDpi",
+            "SetSensorDpi"
+        ],
+        FEATURE_EXTENDED_ADJUSTABLE_DPI => &[
+            "GetSensorCount",
+            "GetSensorCapabilities",
+            "GetSensorDpiRanges",
+            "GetSensorDpiList",
+            "GetSensorLodList",
+            "GetSensorDpi",
+            "SetSensorDpi"
+        ],
+        FEATURE_REPORT_RATE => &["GetReportRateList", "GetReportRate", "SetReportRate"],
+        FEATURE_EXTENDED_REPORT_RATE => &[
+            "GetDeviceCapabilities",
+            "GetReportRateList",
+            "GetReportRate",
+            "SetReportRate"
+        ],
+        FEATURE_ONBOARD_PROFILES => &[
+            "GetProfilesDescription",
+            "SetOnboardMode",
+            "GetOnboardMode",
+            "SetCurrentProfile",
+            "GetCurrentProfile",
+            "MemoryRead",
+            "MemoryAddrWrite",
+            "MemoryWrite",
+            "MemoryWriteEnd"
+        ],
+        _ => &[]
+    };
+    names.get(function_id as usize).copied()
+}
+
+// Renders a packet on one line. `feature_id` is the feature behind the
+// packet's index when the caller knows it; index 0 is always the root.
+pub fn dissect(packet: &HidppPacket, feature_id: Option<u16>, direction: Direction) -> String {
+    let mut out = String::new();
+    let kind = if direction == Direction::Received
+        && (packet.software_id() == 0 || packet.feature_index() == NOTIFICATION_DEVICE_CONNECTION)
+    {
+        "event"
+    } else {
+        match direction {
+            Direction::Sent => "request",
+            Direction::Received => "reply"
+        }
+    };
+    let _ = write!(out, "{} dev=0x{:02x}", kind, packet.device_index());
+
+    if let Some(error) = packet.error() {
+        dissect_error(&mut out, packet, error);
+        return out;
+    }
+
+    if matches!(packet, HidppPacket::Short(_))
+        && packet.feature_index() == NOTIFICATION_DEVICE_CONNECTION
+    {
+        let params = packet.parameters();
+        let link = if params[0] & CONNECTION_FLAG_LINK_LOST == 0 {
+            "established"
+        } else {
+            "lost"
+        };
+        let _ = write!(
+            out,
+            " DeviceConnection link={} wpid=0x{:02x}{:02x}",
+            link, params[2], params[1]
+        );
+        return out;
+    }
+
+    let feature_id = feature_id.or((packet.feature_index() == ROOT_INDEX).then_some(FEATURE_ROOT));
+    match feature_id {
+        Some(id) => {
+            let _ = write!(
+                out,
+                " {}(0x{:04x})@0x{:02x}",
+                feature_name(id).unwrap_or("Feature"),
+                id,
+                packet.feature_index()
+            );
+        }
+        None => {
+            let _ = write!(out, " index=0x{:02x}", packet.feature_index());
+        }
+    }
+
+    match feature_id.and_then(|id| function_name(id, packet.function_id())) {
+        Some(name) => {
+            let _ = write!(out, " {}", name);
+        }
+        None => {
+            let _ = write!(out, " fn={}", packet.function_id());
+        }
+    }
+    let _ = write!(out, " sw={}", packet.software_id());
+
+    let params = packet.parameters();
+    let event = kind == "event";
+    let decoded = feature_id
+        .and_then(|id| decode_params(id, packet.function_id(), direction, event, params));
+    let _ = write!(
+        out,
+        " | {}",
+        decoded.unwrap_or_else(|| format!("params={}", hex(params)))
+    );
+
+    out
+}
+
+// Accepts hex as printed by most tools: optional `0x` prefix and any mix of
+// spaces, colons or dashes between bytes.
+pub fn parse_report(text: &str) -> Result<HidppPacket> {
+    let digits: String = text
+        .trim()
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !matches!(c, ' ' | ':' | '-' | ','))
+        .collect();
+
+    // Checked before slicing: a multi-byte character would split a char
+    // boundary.
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::bad_request("Report is not valid hex"));
+    }
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(AppError::bad_request(
+            "Report must be an even number of hex digits"
+        ));
+    }
+
+    let bytes = (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|e| AppError::bad_request("Report is not valid hex").with_source(e))?;
+
+    HidppPacket::from_bytes(&bytes)
+}
+
+fn dissect_error(out: &mut String, packet: &HidppPacket, error: HidppError) {
+    let (failed_index, _) = packet.error_request().unwrap_or_default();
+    let failed_function = packet.parameters()[0] >> 4;
+    let code = packet.get_error_code().unwrap_or_default();
+
+    match packet.feature_index() {
+        ERROR_REPORT_HIDPP10 => {
+            let _ = write!(
+                out,
+                " HID++ 1.0 error {} (0x{:02x}) sub_id=0x{:02x} address=0x{:02x}",
+                error,
+                code,
+                failed_index,
+                packet.parameters()[0]
+            );
+        }
+        ERROR_REPORT_HIDPP20 => {
+            let _ = write!(
+                out,
+                " HID++ 2.0 error {} (0x{:02x}) index=0x{:02x} fn={}",
+                error, code, failed_index, failed_function
+            );
+        }
+        _ => {}
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn be16(params: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([params[offset], params[offset + 1]])
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+fn named_feature(feature_id: u16) -> String {
+    match feature_name(feature_id) {
+        Some(name) => format!("0x{:04x} ({})", feature_id, name),
+        None => format!("0x{:04x}", feature_id)
+    }
+}
+
+fn decode_params(
+    feature_id: u16,
+    function_id: u8,
+    direction: Direction,
+    event: bool,
+    p: &[u8]
+) -> Option<String> {
+    use Direction::{Received, Sent};
+
+    // Short reports carry 3 parameter bytes; arms read them zero-padded and
+    // check `len` where the real length matters.
+    let len = p.len().min(LONG_PARAMS_LEN);
+    let mut padded = [0u8; LONG_PARAMS_LEN];
+    padded[..len].copy_from_slice(&p[..len]);
+    let p = &padded[..];
+
+    let decoded = match (feature_id, function_id, direction) {
+        (FEATURE_ROOT, 0, Sent) => format!("feature={}", named_feature(be16(p, 0))),
+        (FEATURE_ROOT, 0, Received) if p[0] == 0 => "not supported".to_string(),
+        (FEATURE_ROOT, 0, Received) => {
+            format!("index=0x{:02x} type=0x{:02x} version={}", p[0], p[1], p[2])
+        }
+        (FEATURE_ROOT, 1, Sent) => format!("data=0x{:02x}", p[2]),
+        (FEATURE_ROOT, 1, Received) => {
+            format!("protocol={}.{} data=0x{:02x}", p[0], p[1], p[2])
+        }
+
+        (FEATURE_FEATURE_SET, 0, Received) => format!("count={}", p[0]),
+        (FEATURE_FEATURE_SET, 1, Sent) => format!("index={}", p[0]),
+        (FEATURE_FEATURE_SET, 1, Received) => {
+            format!("feature={} type=0x{:02x}", named_feature(be16(p, 0)), p[2])
+        }
+
+        (FEATURE_DEVICE_INFO, 0, Received) if len >= 13 => {
+            let models: Vec<String> = p[7..13]
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .filter(|&id| id != 0)
+                .map(|id| format!("{:04x}", id))
+                .collect();
+            format!("entities={} model_ids=[{}]", p[0], models.join(","))
+        }
+        (FEATURE_DEVICE_INFO, 1, Sent) => format!("entity={}", p[0]),
+        (FEATURE_DEVICE_INFO, 1, Received) if len >= 8 => format!(
+            "type={} firmware={}{:02x}.{:02x}_B{:04x}",
+            p[0],
+            text(&p[1..4]),
+            p[4],
+            p[5],
+            be16(p, 6)
+        ),
+        (FEATURE_DEVICE_INFO, 2, Received) => format!("serial={:?}", text(&p[..len.min(12)])),
+
+        (FEATURE_DEVICE_NAME, 0, Received) => format!("length={}", p[0]),
+        (FEATURE_DEVICE_NAME, 1, Sent) => format!("offset={}", p[0]),
+        (FEATURE_DEVICE_NAME, 1, Received) => format!("text={:?}", text(p)),
+        (FEATURE_DEVICE_NAME, 2, Received) => format!("type={}", p[0]),
+
+        (FEATURE_DFU, 0..=3, Sent) => format!("block={}", hex(&p[..len])),
+        (FEATURE_DFU, 4, Sent) => format!("entity={} block={}", p[0], hex(&p[..len])),
+        (FEATURE_DFU, 5, Sent) => format!("entity={}", p[0]),
+        (FEATURE_DFU, 0..=4, Received) if len >= 5 => format!(
+            "counter={} status={:?}",
+            u32::from_be_bytes([p[0], p[1], p[2], p[3]]),
+            DfuStatus::from(p[4])
+        ),
+
+        (FEATURE_BATTERY_STATUS, 0, Received) => {
+            let status = match p[2] {
+                1 => "discharging",
+                2 => "charging",
+                3 => "full",
+                _ => "unknown"
+            };
+            format!("level={}% next_level={}% status={}", p[0], p[1], status)
+        }
+        (FEATURE_UNIFIED_BATTERY, 1, Received) => {
+            let status = match p[2] {
+                0 => "discharging",
+                1 => "charging",
+                2 => "charging slowly",
+                3 => "full",
+                _ => "error"
+            };
+            format!("level={}% flags=0x{:02x} status={}", p[0], p[1], status)
+        }
+
+        (FEATURE_CHANGE_HOST, 0, Received) => format!("hosts={} current={}", p[0], p[1]),
+        (FEATURE_CHANGE_HOST, 1, Sent) => format!("host={}", p[0]),
+        (FEATURE_HOSTS_INFO, 1..=3, Sent) => format!("host={} offset={}", p[0], p[1]),
+        (FEATURE_HOSTS_INFO, 1, Received) => {
+            format!("host={} paired={} name_length={}", p[0], p[1] != 0, p[4])
+        }
+        (FEATURE_HOSTS_INFO, 3, Received) => {
+            format!("host={} offset={} text={:?}", p[0], p[1], text(&p[2..]))
+        }
+
+        (FEATURE_REPROG_CONTROLS, 0, Received) if event => {
+            let cids: Vec<String> = p[..8]
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .filter(|&cid| cid != 0)
+                .map(|cid| format!("0x{:04x}", cid))
+                .collect();
+            format!("diverted_buttons=[{}]", cids.join(","))
+        }
+        (FEATURE_REPROG_CONTROLS, 0, Received) => format!("count={}", p[0]),
+        (FEATURE_REPROG_CONTROLS, 1, Sent) => format!("index={}", p[0]),
+        (FEATURE_REPROG_CONTROLS, 1, Received) => format!(
+            "cid=0x{:04x} task=0x{:04x} flags=0x{:02x}",
+            be16(p, 0),
+            be16(p, 2),
+            p[4]
+        ),
+        (FEATURE_REPROG_CONTROLS, 2, Sent) => format!("cid=0x{:04x}", be16(p, 0)),
+        (FEATURE_REPROG_CONTROLS, 2 | 3, _) if len >= 5 => format!(
+            "cid=0x{:04x} flags=0x{:02x} remap=0x{:04x}",
+            be16(p, 0),
+            p[2],
+            be16(p, 3)
+        ),
+        (FEATURE_REPROG_CONTROLS, 3, _) => {
+            format!("cid=0x{:04x} flags=0x{:02x}", be16(p, 0), p[2])
+        }
+
+        (FEATURE_PERSISTENT_REMAPPABLE_ACTION, 1, Received) => format!("count={}", p[0]),
+        (FEATURE_PERSISTENT_REMAPPABLE_ACTION, 2, Sent) => {
+            format!("index={} host=0x{:02x}", p[0], p[1])
+        }
+        (FEATURE_PERSISTENT_REMAPPABLE_ACTION, 2, Received) => {
+            format!("cid=0x{:04x}", be16(p, 0))
+        }
+        (FEATURE_PERSISTENT_REMAPPABLE_ACTION, 3, Sent)
+        | (FEATURE_PERSISTENT_REMAPPABLE_ACTION, 5, Sent) => {
+            format!("cid=0x{:04x} host=0x{:02x}", be16(p, 0), p[2])
+        }
+        (FEATURE_PERSISTENT_REMAPPABLE_ACTION, 3 | 4, _) if len >= 7 => format!(
+            "cid=0x{:04x} host=0x{:02x} action={:?}",
+            be16(p, 0),
+            p[2],
+            PersistentAction::from_report(&p[3..])
+        ),
+
+        (FEATURE_WIRELESS_DEVICE_STATUS, 0, Received) if event => {
+            let status = match p[0] {
+                1 => "reconnection",
+                _ => "unknown"
+            };
+            let request = match p[1] {
+                1 => "reconfigure",
+                _ => "none"
+            };
+            format!("status={} request={} reason={}", status, request, p[2])
+        }
+
+        (FEATURE_SMART_SHIFT, 0, Received) | (FEATURE_SMART_SHIFT, 1, Sent) => {
+            let mode = match p[0] {
+                0 => "unchanged",
+                1 => "freespin",
+                2 => "ratchet",
+                _ => "unknown"
+            };
+            let auto_disengage = match p[1] {
+                0xFF => "always".to_string(),
+                0 => "unchanged".to_string(),
+                value => value.to_string()
+            };
+            format!(
+                "mode={} auto_disengage={} default={}",
+                mode, auto_disengage, p[2]
+            )
+        }
+
+        (FEATURE_HIRES_WHEEL, 0, Received) => {
+            format!("multiplier={} flags=0x{:02x}", p[0], p[1])
+        }
+        (FEATURE_HIRES_WHEEL, 1, Received) | (FEATURE_HIRES_WHEEL, 2, Sent) => format!(
+            "diverted={} hires={} inverted={}",
+            p[0] & 0x01 != 0,
+            p[0] & 0x02 != 0,
+            p[0] & 0x04 != 0
+        ),
+        (FEATURE_HIRES_WHEEL, 3, Received) => {
+            format!("ratchet={}", if p[0] & 0x01 != 0 { "on" } else { "off" })
+        }
+
+        (FEATURE_ADJUSTABLE_DPI, 0, Received) => format!("sensors={}", p[0]),
+        (FEATURE_ADJUSTABLE_DPI, 1 | 2, Sent) => format!("sensor={}", p[0]),
+        (FEATURE_ADJUSTABLE_DPI, 2, Received) if len >= 5 => {
+            format!("sensor={} dpi={} default={}", p[0], be16(p, 1), be16(p, 3))
+        }
+        (FEATURE_ADJUSTABLE_DPI, 3, _) => format!("sensor={} dpi={}", p[0], be16(p, 1)),
+
+        (FEATURE_REPORT_RATE, 0, Received) => format!("rates_ms_mask=0x{:02x}", p[0]),
+        (FEATURE_REPORT_RATE, 1, Received) | (FEATURE_REPORT_RATE, 2, Sent) => {
+            format!("interval={}ms", p[0])
+        }
+        (FEATURE_EXTENDED_REPORT_RATE, 1, Received) => {
+            format!("rates_mask=0x{:04x}", be16(p, 0))
+        }
+        (FEATURE_EXTENDED_REPORT_RATE, 2, Received) | (FEATURE_EXTENDED_REPORT_RATE, 3, Sent) => {
+            format!("rate_code={}", p[0])
+        }
+
+        (FEATURE_ONBOARD_PROFILES, 1, Sent) | (FEATURE_ONBOARD_PROFILES, 2, Received) => {
+            let mode = match p[0] {
+                1 => "onboard",
+                2 => "host",
+                _ => "unknown"
+            };
+            format!("mode={}", mode)
+        }
+        (FEATURE_ONBOARD_PROFILES, 3, Sent) | (FEATURE_ONBOARD_PROFILES, 4, Received) => {
+            format!("profile={}", be16(p, 0))
+        }
+        (FEATURE_ONBOARD_PROFILES, 5, Sent) => {
+            format!("page={} offset={}", be16(p, 0), be16(p, 2))
+        }
+        (FEATURE_ONBOARD_PROFILES, 5, Received) => format!("data={}", hex(&p[..len])),
+
+        _ => return None
+    };
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hidpp::features::PersistentRemappableActionFunction;
+
+    #[test]
+    fn test_dissect_get_feature() {
+        let request = HidppPacket::new_short(0x01, 0x00, 0x00, 0x05, [0x22, 0x01, 0x00]);
+        assert_eq!(
+            dissect(&request, None, Direction::Sent),
+            "request dev=0x01 Root(0x0000)@0x00 GetFeature sw=5 | feature=0x2201 \
+             (AdjustableDpi)"
+        );
+
+        let reply = HidppPacket::new_short(0x01, 0x00, 0x00, 0x05, [0x07, 0x00, 0x01]);
+        assert!(
+            dissect(&reply, None, Direction::Received).ends_with("index=0x07 type=0x00 version=1")
+        );
+    }
+
+    #[test]
+    fn test_dissect_known_feature() {
+        let request = HidppPacket::new_short(0x01, 0x0A, 0x01, 0x05, [0x02, 0x0A, 0x00]);
+        assert_eq!(
+            dissect(&request, Some(FEATURE_SMART_SHIFT), Direction::Sent),
+            "request dev=0x01 SmartShift(0x2110)@0x0a SetRatchetControlMode sw=5 | \
+             mode=ratchet auto_disengage=10 default=0"
+        );
+    }
+
+    #[test]
+    fn test_dissect_unknown_index() {
+        let packet = HidppPacket::new_short(0x01, 0x0A, 0x01, 0x05, [0x02, 0x0A, 0x00]);
+        assert_eq!(
+            dissect(&packet, None, Direction::Sent),
+            "request dev=0x01 index=0x0a fn=1 sw=5 | params=020a00"
+        );
+    }
+
+    #[test]
+    fn test_dissect_errors() {
+        let hidpp20 =
+            HidppPacket::from_bytes(&[0x10, 0x01, 0xFF, 0x0A, 0x15, 0x02, 0x00]).unwrap();
+        assert_eq!(
+            dissect(&hidpp20, None, Direction::Received),
+            "reply dev=0x01 HID++ 2.0 error Invalid argument (0x02) index=0x0a fn=1"
+        );
+
+        let hidpp10 =
+            HidppPacket::from_bytes(&[0x10, 0xFF, 0x8F, 0x81, 0x02, 0x02, 0x00]).unwrap();
+        assert!(
+            dissect(&hidpp10, None, Direction::Received)
+                .contains("HID++ 1.0 error Invalid register address (0x02) sub_id=0x81")
+        );
+    }
+
+    #[test]
+    fn test_dissect_events() {
+        let mut params = [0u8; 16];
+        params[..2].copy_from_slice(&0x00C3u16.to_be_bytes());
+        let event = HidppPacket::new_long(0x01, 0x08, 0x00, 0x00, params);
+        assert!(
+            dissect(&event, Some(FEATURE_REPROG_CONTROLS), Direction::Received)
+                .ends_with("| diverted_buttons=[0x00c3]")
+        );
+
+        let link = HidppPacket::new_short(0x01, 0x41, 0x00, 0x00, [0x40, 0x34, 0xB0]);
+        assert_eq!(
+            dissect(&link, None, Direction::Received),
+            "event dev=0x01 DeviceConnection link=lost wpid=0xb034"
+        );
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let packet = parse_report("10:01:00:15:22:01:00").unwrap();
+        assert_eq!(packet.feature_index(), 0x00);
+        assert_eq!(packet.parameters(), &[0x22, 0x01, 0x00]);
+
+        assert_eq!(parse_report("0x10 01 00 15 22 01 00").unwrap(), packet);
+        assert!(parse_report("10 01 0").is_err());
+        assert!(parse_report("zz").is_err());
+        assert!(parse_report("aé0").is_err());
+        assert!(parse_report("é").is_err());
+    }
+
+    #[test]
+    fn test_dissect_short_reports() {
+        // Every decoded function, sent and received, as a short report.
+        let features = [
+            FEATURE_ROOT,
+            FEATURE_FEATURE_SET,
+            FEATURE_DEVICE_INFO,
+            FEATURE_DEVICE_NAME,
+            FEATURE_DFU,
+            FEATURE_BATTERY_STATUS,
+            FEATURE_UNIFIED_BATTERY,
+            FEATURE_CHANGE_HOST,
+            FEATURE_HOSTS_INFO,
+            FEATURE_REPROG_CONTROLS,
+            FEATURE_PERSISTENT_REMAPPABLE_ACTION,
+            FEATURE_WIRELESS_DEVICE_STATUS,
+            FEATURE_SMART_SHIFT,
+            FEATURE_HIRES_WHEEL,
+            FEATURE_ADJUSTABLE_DPI,
+            FEATURE_REPORT_RATE,
+            FEATURE_EXTENDED_REPORT_RATE,
+            FEATURE_ONBOARD_PROFILES
+        ];
+        for feature_id in features {
+            for function_id in 0..16 {
+                for software_id in [0, 5] {
+                    let packet =
+                        HidppPacket::new_short(0x01, 0x05, function_id, software_id, [0x10; 3]);
+                    dissect(&packet, Some(feature_id), Direction::Sent);
+                    dissect(&packet, Some(feature_id), Direction::Received);
+                }
+            }
+        }
+
+        let packet = parse_report("10 01 05 10 00 00 00").unwrap();
+        assert!(
+            dissect(&packet, Some(FEATURE_REPROG_CONTROLS), Direction::Received)
+                .ends_with("| cid=0x0000 task=0x0000 flags=0x00")
+        );
+    }
+
+    #[test]
+    fn test_every_function_enum_is_named() {
+        assert_eq!(
+            function_name(FEATURE_ONBOARD_PROFILES, 0x08),
+            Some("MemoryWriteEnd")
+        );
+        assert_eq!(
+            function_name(FEATURE_HOSTS_INFO, 0x07),
+            Some("GetHostOsVersion")
+        );
+        assert_eq!(function_name(FEATURE_DFU, 0x05), Some("Restart"));
+        for (function, name) in [
+            (PersistentRemappableActionFunction::GetCount, "GetCount"),
+            (PersistentRemappableActionFunction::GetCidInfo, "GetCidInfo"),
+            (
+                PersistentRemappableActionFunction::GetCidReporting,
+                "GetCidReporting"
+            ),
+            (
+                PersistentRemappableActionFunction::SetCidReporting,
+                "SetCidReporting"
+            ),
+            (
+                PersistentRemappableActionFunction::ResetToFactory,
+                "ResetToFactory"
+            )
+        ] {
+            assert_eq!(
+                function_name(FEATURE_PERSISTENT_REMAPPABLE_ACTION, function as u8),
+                Some(name)
+            );
+        }
+        assert_eq!(function_name(FEATURE_ROOT, 0x02), None);
+    }
+}
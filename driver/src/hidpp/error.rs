@@ -0,0 +1,363 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::{collections::HashMap, fmt};
+
+use masterror::{field, prelude::*};
+
+use super::{
+    constants::*,
+    features::{RootFunction, feature_id_for_index}
+};
+
+pub const ERROR_REPORT_HIDPP10: u8 = 0x8F;
+pub const ERROR_REPORT_HIDPP20: u8 = 0xFF;
+
+// Register access errors, reported with sub id 0x8F.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hidpp10Error {
+    InvalidSubId,
+    InvalidAddress,
+    InvalidValue,
+    ConnectFail,
+    TooManyDevices,
+    AlreadyExists,
+    Busy,
+    UnknownDevice,
+    ResourceError,
+    RequestUnavailable,
+    UnsupportedParam,
+    WrongPinCode,
+    Other(u8)
+}
+
+impl From<u8> for Hidpp10Error {
+    fn from(code: u8) -> Self {
+        match code {
+            ERROR_INVALID_SUBID => Hidpp10Error::InvalidSubId,
+            ERROR_INVALID_ADDRESS => Hidpp10Error::InvalidAddress,
+            ERROR_INVALID_VALUE => Hidpp10Error::InvalidValue,
+            ERROR_CONNECT_FAIL => Hidpp10Error::ConnectFail,
+            ERROR_TOO_MANY_DEVICES => Hidpp10Error::TooManyDevices,
+            ERROR_ALREADY_EXISTS => Hidpp10Error::AlreadyExists,
+            ERROR_BUSY => Hidpp10Error::Busy,
+            ERROR_UNKNOWN_DEVICE => Hidpp10Error::UnknownDevice,
+            ERROR_RESOURCE_ERROR => Hidpp10Error::ResourceError,
+            ERROR_REQUEST_UNAVAILABLE => Hidpp10Error::RequestUnavailable,
+            ERROR_UNSUPPORTED_PARAM => Hidpp10Error::UnsupportedParam,
+            ERROR_WRONG_PIN_CODE => Hidpp10Error::WrongPinCode,
+            other => Hidpp10Error::Other(other)
+        }
+    }
+}
+
+impl Hidpp10Error {
+    pub fn code(self) -> u8 {
+        match self {
+            Hidpp10Error::InvalidSubId => ERROR_INVALID_SUBID,
+            Hidpp10Error::InvalidAddress => ERROR_INVALID_ADDRESS,
+            Hidpp10Error::InvalidValue => ERROR_INVALID_VALUE,
+            Hidpp10Error::ConnectFail => ERROR_CONNECT_FAIL,
+            Hidpp10Error::TooManyDevices => ERROR_TOO_MANY_DEVICES,
+            Hidpp10Error::AlreadyExists => ERROR_ALREADY_EXISTS,
+            Hidpp10Error::Busy => ERROR_BUSY,
+            Hidpp10Error::UnknownDevice => ERROR_UNKNOWN_DEVICE,
+            Hidpp10Error::ResourceError => ERROR_RESOURCE_ERROR,
+            Hidpp10Error::RequestUnavailable => ERROR_REQUEST_UNAVAILABLE,
+            Hidpp10Error::UnsupportedParam => ERROR_UNSUPPORTED_PARAM,
+            Hidpp10Error::WrongPinCode => ERROR_WRONG_PIN_CODE,
+            Hidpp10Error::Other(code) => code
+        }
+    }
+}
+
+impl fmt::Display for Hidpp10Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Hidpp10Error::InvalidSubId => "Invalid sub id",
+            Hidpp10Error::InvalidAddress => "Invalid register address",
+            Hidpp10Error::InvalidValue => "Invalid register value",
+            Hidpp10Error::ConnectFail => "Connection failed",
+            Hidpp10Error::TooManyDevices => "Too many devices",
+            Hidpp10Error::AlreadyExists => "Already exists",
+            Hidpp10Error::Busy => "Receiver busy",
+            Hidpp10Error::UnknownDevice => "Unknown device",
+            Hidpp10Error::ResourceError => "Resource error",
+            Hidpp10Error::RequestUnavailable => "Request unavailable",
+            Hidpp10Error::UnsupportedParam => "Unsupported parameter",
+            Hidpp10Error::WrongPinCode => "Wrong PIN code",
+            Hidpp10Error::Other(_) => "Unknown HID++ 1.0 error"
+        };
+        f.write_str(message)
+    }
+}
+
+impl From<Hidpp10Error> for AppError {
+    fn from(error: Hidpp10Error) -> Self {
+        let message = error.to_string();
+        let app_error = match error {
+            Hidpp10Error::InvalidSubId
+            | Hidpp10Error::InvalidAddress
+            | Hidpp10Error::InvalidValue
+            | Hidpp10Error::RequestUnavailable
+            | Hidpp10Error::UnsupportedParam => AppError::bad_request(message),
+            Hidpp10Error::TooManyDevices | Hidpp10Error::AlreadyExists | Hidpp10Error::Busy => {
+                AppError::conflict(message)
+            }
+            Hidpp10Error::UnknownDevice => AppError::not_found(message),
+            Hidpp10Error::WrongPinCode => AppError::unauthorized(message),
+            Hidpp10Error::ConnectFail | Hidpp10Error::ResourceError | Hidpp10Error::Other(_) => {
+                AppError::internal(message)
+            }
+        };
+        app_error.with_field(field::u64("hidpp10_error", error.code() as u64))
+    }
+}
+
+// Feature call errors, reported with feature index 0xFF. The code table is
+// unrelated to the 1.0 one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hidpp20Error {
+    Unknown,
+    InvalidArgument,
+    OutOfRange,
+    HardwareError,
+    LogitechInternal,
+    InvalidFeatureIndex,
+    InvalidFunctionId,
+    Busy,
+    Unsupported,
+    Other(u8)
+}
+
+impl From<u8> for Hidpp20Error {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => Hidpp20Error::Unknown,
+            0x02 => Hidpp20Error::InvalidArgument,
+            0x03 => Hidpp20Error::OutOfRange,
+            0x04 => Hidpp20Error::HardwareError,
+            0x05 => Hidpp20Error::LogitechInternal,
+            0x06 => Hidpp20Error::InvalidFeatureIndex,
+            0x07 => Hidpp20Error::InvalidFunctionId,
+            0x08 => Hidpp20Error::Busy,
+            0x09 => Hidpp20Error::Unsupported,
+            other => Hidpp20Error::Other(other)
+        }
+    }
+}
+
+impl Hidpp20Error {
+    pub fn code(self) -> u8 {
+        match self {
+            Hidpp20Error::Unknown => 0x01,
+            Hidpp20Error::InvalidArgument => 0x02,
+            Hidpp20Error::OutOfRange => 0x03,
+            Hidpp20Error::HardwareError => 0x04,
+            Hidpp20Error::LogitechInternal => 0x05,
+            Hidpp20Error::InvalidFeatureIndex => 0x06,
+            Hidpp20Error::InvalidFunctionId => 0x07,
+            Hidpp20Error::Busy => 0x08,
+            Hidpp20Error::Unsupported => 0x09,
+            Hidpp20Error::Other(code) => code
+        }
+    }
+}
+
+impl fmt::Display for Hidpp20Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Hidpp20Error::Unknown => "Unknown device error",
+            Hidpp20Error::InvalidArgument => "Invalid argument",
+            Hidpp20Error::OutOfRange => "Argument out of range",
+            Hidpp20Error::HardwareError => "Hardware error",
+            Hidpp20Error::LogitechInternal => "Internal device error",
+            Hidpp20Error::InvalidFeatureIndex => "Invalid feature index",
+            Hidpp20Error::InvalidFunctionId => "Invalid function id",
+            Hidpp20Error::Busy => "Device busy",
+            Hidpp20Error::Unsupported => "Unsupported request",
+            Hidpp20Error::Other(_) => "Unknown HID++ 2.0 error"
+        };
+        f.write_str(message)
+    }
+}
+
+impl From<Hidpp20Error> for AppError {
+    fn from(error: Hidpp20Error) -> Self {
+        let message = error.to_string();
+        let app_error = match error {
+            Hidpp20Error::InvalidArgument
+            | Hidpp20Error::OutOfRange
+            | Hidpp20Error::InvalidFeatureIndex
+            | Hidpp20Error::InvalidFunctionId
+            | Hidpp20Error::Unsupported => AppError::bad_request(message),
+            Hidpp20Error::Busy => AppError::conflict(message),
+            Hidpp20Error::Unknown
+            | Hidpp20Error::HardwareError
+            | Hidpp20Error::LogitechInternal
+            | Hidpp20Error::Other(_) => AppError::internal(message)
+        };
+        app_error.with_field(field::u64("hidpp20_error", error.code() as u64))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HidppError {
+    V10(Hidpp10Error),
+    V20(Hidpp20Error)
+}
+
+impl HidppError {
+    pub fn is_busy(self) -> bool {
+        matches!(
+            self,
+            HidppError::V10(Hidpp10Error::Busy) | HidppError::V20(Hidpp20Error::Busy)
+        )
+    }
+}
+
+impl fmt::Display for HidppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HidppError::V10(error) => error.fmt(f),
+            HidppError::V20(error) => error.fmt(f)
+        }
+    }
+}
+
+impl From<HidppError> for AppError {
+    fn from(error: HidppError) -> Self {
+        match error {
+            HidppError::V10(error) => error.into(),
+            HidppError::V20(error) => error.into()
+        }
+    }
+}
+
+// Identifies the command an error belongs to. `feature_id` is only known
+// when the index was resolved through the feature table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandContext {
+    pub device_index:  u8,
+    pub feature_index: u8,
+    pub feature_id:    Option<u16>,
+    pub function_id:   u8
+}
+
+impl CommandContext {
+    pub fn new(
+        device_index: u8,
+        feature_index: u8,
+        function_id: u8,
+        feature_cache: &HashMap<u16, u8>
+    ) -> Self {
+        let feature_id = feature_id_for_index(feature_cache, feature_index);
+
+        Self {
+            device_index,
+            feature_index,
+            feature_id,
+            function_id
+        }
+    }
+
+    // A root GetFeature reports the feature asked about rather than Root.
+    pub fn lookup(device_index: u8, feature_id: u16) -> Self {
+        Self {
+            device_index,
+            feature_index: ROOT_INDEX,
+            feature_id: Some(feature_id),
+            function_id: RootFunction::GetFeature as u8
+        }
+    }
+
+    pub fn attach(&self, error: AppError) -> AppError {
+        let error = error
+            .with_field(field::u64("device_index", self.device_index as u64))
+            .with_field(field::u64("feature_index", self.feature_index as u64))
+            .with_field(field::u64("function_id", self.function_id as u64));
+
+        match self.feature_id {
+            Some(feature_id) => error.with_field(field::u64("feature_id", feature_id as u64)),
+            None => error
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_tables_differ() {
+        assert_eq!(Hidpp10Error::from(0x05), Hidpp10Error::TooManyDevices);
+        assert_eq!(Hidpp20Error::from(0x05), Hidpp20Error::LogitechInternal);
+        assert_eq!(Hidpp20Error::from(0x02), Hidpp20Error::InvalidArgument);
+        assert_eq!(Hidpp10Error::from(0x02), Hidpp10Error::InvalidAddress);
+    }
+
+    #[test]
+    fn test_codes_roundtrip() {
+        for code in 0..=0x10 {
+            assert_eq!(Hidpp10Error::from(code).code(), code);
+            assert_eq!(Hidpp20Error::from(code).code(), code);
+        }
+    }
+
+    #[test]
+    fn test_busy_detection() {
+        assert!(HidppError::V10(Hidpp10Error::Busy).is_busy());
+        assert!(HidppError::V20(Hidpp20Error::Busy).is_busy());
+        assert!(!HidppError::V20(Hidpp20Error::from(ERROR_BUSY)).is_busy());
+    }
+
+    #[test]
+    fn test_busy_maps_alike() {
+        assert_eq!(
+            AppError::from(Hidpp10Error::Busy).kind,
+            AppErrorKind::Conflict
+        );
+        assert_eq!(
+            AppError::from(Hidpp20Error::Busy).kind,
+            AppErrorKind::Conflict
+        );
+    }
+
+    #[test]
+    fn test_context_resolves_feature_id() {
+        let cache = HashMap::from([(0x2201, 0x0A), (0x8100, 0x00)]);
+
+        let context = CommandContext::new(0x02, 0x0A, 0x03, &cache);
+        assert_eq!(context.feature_id, Some(0x2201));
+        assert_eq!(
+            CommandContext::new(0x02, ROOT_INDEX, 0x00, &cache).feature_id,
+            Some(FEATURE_ROOT)
+        );
+        assert_eq!(
+            CommandContext::new(0x02, 0x0B, 0x00, &cache).feature_id,
+            None
+        );
+    }
+
+    #[test]
+    fn test_context_fields_attached() {
+        let context = CommandContext {
+            device_index:  0x02,
+            feature_index: 0x0A,
+            feature_id:    Some(0x2201),
+            function_id:   0x03
+        };
+        let error = context.attach(Hidpp20Error::InvalidArgument.into());
+
+        assert_eq!(error.kind, AppErrorKind::BadRequest);
+        for name in [
+            "device_index",
+            "feature_index",
+            "feature_id",
+            "function_id",
+            "hidpp20_error"
+        ] {
+            assert!(error.metadata().get(name).is_some(), "missing {name}");
+        }
+    }
+}
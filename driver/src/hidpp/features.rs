@@ -1,6 +1,8 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
+use std::collections::HashMap;
+
 use super::constants::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,18 +24,66 @@ impl Feature {
     }
 }
 
+// Reverse lookup in a feature index table; the root is always at index 0.
+pub fn feature_id_for_index(feature_cache: &HashMap<u16, u8>, index: u8) -> Option<u16> {
+    if index == ROOT_INDEX {
+        return Some(FEATURE_ROOT);
+    }
+
+    feature_cache
+        .iter()
+        .find(|&(_, &cached)| cached == index)
+        .map(|(&feature_id, _)| feature_id)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RootFunction {
     GetFeature = 0x00,
     Ping = 0x01
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureSetFunction {
+    GetCount = 0x00,
+    GetFeatureId = 0x01
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceInfoFunction {
+    GetDeviceInfo = 0x00,
+    GetFwInfo = 0x01,
+    GetDeviceSerialNumber = 0x02
+}
+
+// Shared by 0x00C2 and 0x00C3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DfuControlFunction {
+    GetDfuControl = 0x00,
+    SetDfuControl = 0x01
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DfuFunction {
+    DfuCmdData0 = 0x00,
+    DfuCmdData1 = 0x01,
+    DfuCmdData2 = 0x02,
+    DfuCmdData3 = 0x03,
+    DfuStart = 0x04,
+    Restart = 0x05
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BatteryFunction {
     GetStatus = 0x00,
     GetCapability = 0x01
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnifiedBatteryFunction {
+    GetCapabilities = 0x00,
+    GetStatus = 0x01
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DpiFunction {
     GetSensorCount = 0x00,
@@ -42,6 +92,18 @@ pub enum DpiFunction {
     SetSensorDpi = 0x03
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtendedDpiFunction {
+    GetSensorCount = 0x00,
+    GetSensorDpi = 0x05,
+    SetSensorDpi = 0x06
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryVoltageFunction {
+    GetBatteryVoltage = 0x00
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SmartShiftFunction {
     GetRatchetControlMode = 0x00,
@@ -63,3 +125,78 @@ pub enum ReprogControlsFunction {
     GetControlReporting = 0x02,
     SetControlReporting = 0x03
 }
+
+// Function 0 is capabilities. The ids follow Solaar's implementation
+// (hidpp20.py, PersistentRemappableAction), which sends 0x10 to 0x50 as the
+// function byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersistentRemappableActionFunction {
+    GetCount = 0x01,
+    GetCidInfo = 0x02,
+    GetCidReporting = 0x03,
+    SetCidReporting = 0x04,
+    ResetToFactory = 0x05
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportRateFunction {
+    GetReportRateList = 0x00,
+    GetReportRate = 0x01,
+    SetReportRate = 0x02
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtendedReportRateFunction {
+    GetDeviceCapabilities = 0x00,
+    GetReportRateList = 0x01,
+    GetReportRate = 0x02,
+    SetReportRate = 0x03
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeHostFunction {
+    GetHostInfo = 0x00,
+    SetCurrentHost = 0x01
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostsInfoFunction {
+    GetFeatureInfo = 0x00,
+    GetHostInfo = 0x01,
+    GetHostDescriptor = 0x02,
+    GetHostFriendlyName = 0x03,
+    GetHostOsVersion = 0x07
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BacklightFunction {
+    GetBacklightConfig = 0x00,
+    SetBacklightConfig = 0x01,
+    GetBacklightInfo = 0x02
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FnInversionFunction {
+    GetGlobalFnInversion = 0x00,
+    SetGlobalFnInversion = 0x01
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisableKeysFunction {
+    GetCapabilities = 0x00,
+    GetDisabledKeys = 0x01,
+    SetDisabledKeys = 0x02
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnboardProfilesFunction {
+    GetProfilesDescription = 0x00,
+    SetOnboardMode = 0x01,
+    GetOnboardMode = 0x02,
+    SetCurrentProfile = 0x03,
+    GetCurrentProfile = 0x04,
+    MemoryRead = 0x05,
+    MemoryAddrWrite = 0x06,
+    MemoryWrite = 0x07,
+    MemoryWriteEnd = 0x08
+}
@@ -29,6 +29,8 @@ mod feature_tests {
     fn test_battery_function_values() {
         assert_eq!(BatteryFunction::GetStatus as u8, 0x00);
         assert_eq!(BatteryFunction::GetCapability as u8, 0x01);
+        assert_eq!(UnifiedBatteryFunction::GetCapabilities as u8, 0x00);
+        assert_eq!(UnifiedBatteryFunction::GetStatus as u8, 0x01);
     }
 
     #[test]
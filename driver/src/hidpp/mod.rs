@@ -1,12 +1,26 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
+pub mod async_device;
+pub mod cache;
+pub mod capture;
 pub mod constants;
+pub mod descriptor;
 pub mod device;
+pub mod dissect;
+pub mod error;
 pub mod features;
 pub mod packet;
+pub mod transport;
 
+pub use async_device::{AsyncHidppDevice, DEFAULT_COMMAND_TIMEOUT};
+pub use cache::*;
+pub use capture::*;
 pub use constants::*;
-pub use device::HidppDevice;
+pub use descriptor::ReportDescriptor;
+pub use device::{DeviceIds, HidppDevice};
+pub use dissect::{dissect, feature_name, function_name, parse_report};
+pub use error::*;
 pub use features::*;
 pub use packet::*;
+pub use transport::*;
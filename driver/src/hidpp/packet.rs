@@ -1,7 +1,12 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
-use super::constants::*;
+use masterror::prelude::*;
+
+use super::{
+    constants::*,
+    error::{ERROR_REPORT_HIDPP10, ERROR_REPORT_HIDPP20, HidppError}
+};
 use crate::error::{DeviceErrorKind, Result};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -61,6 +66,41 @@ impl HidppPacket {
         })
     }
 
+    // Picks the short report when the parameters fit, the long one otherwise.
+    pub fn new_request(
+        device_index: u8,
+        feature_index: u8,
+        function_id: u8,
+        software_id: u8,
+        params: &[u8]
+    ) -> Result<Self> {
+        if params.len() <= 3 {
+            let mut parameters = [0u8; 3];
+            parameters[..params.len()].copy_from_slice(params);
+            Ok(HidppPacket::new_short(
+                device_index,
+                feature_index,
+                function_id,
+                software_id,
+                parameters
+            ))
+        } else if params.len() <= 16 {
+            let mut parameters = [0u8; 16];
+            parameters[..params.len()].copy_from_slice(params);
+            Ok(HidppPacket::new_long(
+                device_index,
+                feature_index,
+                function_id,
+                software_id,
+                parameters
+            ))
+        } else {
+            Err(AppError::bad_request(
+                "Parameters too long for HID++ packet"
+            ))
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
             HidppPacket::Short(packet) => {
@@ -124,27 +164,79 @@ impl HidppPacket {
         }
     }
 
-    pub fn is_error(&self) -> bool {
+    pub fn device_index(&self) -> u8 {
         match self {
-            HidppPacket::Short(p) => p.feature_index == 0x8F || p.feature_index == 0xFF,
-            HidppPacket::Long(p) => p.feature_index == 0x8F || p.feature_index == 0xFF
+            HidppPacket::Short(p) => p.device_index,
+            HidppPacket::Long(p) => p.device_index
         }
     }
 
-    pub fn get_error_code(&self) -> Option<u8> {
-        if !self.is_error() {
-            return None;
+    pub fn feature_index(&self) -> u8 {
+        match self {
+            HidppPacket::Short(p) => p.feature_index,
+            HidppPacket::Long(p) => p.feature_index
         }
+    }
+
+    pub fn function_id(&self) -> u8 {
+        match self {
+            HidppPacket::Short(p) => p.function_id,
+            HidppPacket::Long(p) => p.function_id
+        }
+    }
+
+    pub fn software_id(&self) -> u8 {
+        match self {
+            HidppPacket::Short(p) => p.software_id,
+            HidppPacket::Long(p) => p.software_id
+        }
+    }
+
+    pub fn parameters(&self) -> &[u8] {
         match self {
-            HidppPacket::Short(p) => Some(p.parameters[0]),
-            HidppPacket::Long(p) => Some(p.parameters[0])
+            HidppPacket::Short(p) => &p.parameters,
+            HidppPacket::Long(p) => &p.parameters
         }
     }
+
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.feature_index(),
+            ERROR_REPORT_HIDPP10 | ERROR_REPORT_HIDPP20
+        )
+    }
+
+    // Error reports carry the failed request's feature index (or sub id) in
+    // the function byte, its function byte in parameter 0 and the error code
+    // in parameter 1.
+    pub fn error(&self) -> Option<HidppError> {
+        let code = self.parameters()[1];
+        match self.feature_index() {
+            ERROR_REPORT_HIDPP10 => Some(HidppError::V10(code.into())),
+            ERROR_REPORT_HIDPP20 => Some(HidppError::V20(code.into())),
+            _ => None
+        }
+    }
+
+    pub fn get_error_code(&self) -> Option<u8> {
+        self.is_error().then(|| self.parameters()[1])
+    }
+
+    // Feature index and software id of the request an error report answers.
+    pub fn error_request(&self) -> Option<(u8, u8)> {
+        self.is_error().then(|| {
+            (
+                (self.function_id() << 4) | self.software_id(),
+                self.parameters()[0] & 0x0F
+            )
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hidpp::{Hidpp10Error, Hidpp20Error};
 
     #[test]
     fn test_short_packet_creation() {
@@ -190,10 +282,44 @@ mod tests {
         assert_eq!(packet, parsed);
     }
 
+    #[test]
+    fn test_header_accessors() {
+        let packet = HidppPacket::new_long(0x02, 0x09, 0x00, 0x00, [0xAB; 16]);
+        assert_eq!(packet.device_index(), 0x02);
+        assert_eq!(packet.feature_index(), 0x09);
+        assert_eq!(packet.function_id(), 0x00);
+        assert_eq!(packet.software_id(), 0x00);
+        assert_eq!(packet.parameters().len(), 16);
+        assert_eq!(packet.parameters()[0], 0xAB);
+    }
+
     #[test]
     fn test_error_detection() {
-        let error_packet = HidppPacket::new_short(0xFF, 0x8F, 0x01, 0x05, [0x02, 0x00, 0x00]);
+        let error_packet = HidppPacket::new_short(0xFF, 0x8F, 0x01, 0x05, [0x15, 0x02, 0x00]);
         assert!(error_packet.is_error());
         assert_eq!(error_packet.get_error_code(), Some(0x02));
+        assert_eq!(
+            error_packet.error(),
+            Some(HidppError::V10(Hidpp10Error::InvalidAddress))
+        );
+        assert_eq!(error_packet.error_request(), Some((0x15, 0x05)));
+    }
+
+    #[test]
+    fn test_hidpp20_error_detection() {
+        let bytes = [
+            0x11, 0x02, 0xFF, 0x0A, 0x35, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+        ];
+        let error_packet = HidppPacket::from_bytes(&bytes).unwrap();
+
+        assert_eq!(
+            error_packet.error(),
+            Some(HidppError::V20(Hidpp20Error::InvalidArgument))
+        );
+        assert_eq!(error_packet.error_request(), Some((0x0A, 0x05)));
+        assert_eq!(
+            HidppPacket::new_short(0x02, 0x0A, 0x03, 0x05, [0; 3]).error(),
+            None
+        );
     }
 }
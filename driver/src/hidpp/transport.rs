@@ -0,0 +1,108 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use std::collections::VecDeque;
+
+use hidapi::HidDevice;
+use masterror::prelude::*;
+
+use crate::error::Result;
+
+pub trait Transport: Send {
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    // Returns 0 when nothing arrived before the timeout.
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize>;
+}
+
+impl Transport for HidDevice {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        HidDevice::write(self, data)
+            .map_err(|e| AppError::internal("Failed to write to device").with_source(e))?;
+        Ok(())
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        HidDevice::read_timeout(self, buf, timeout_ms)
+            .map_err(|e| AppError::internal("Failed to read from device").with_source(e))
+    }
+}
+
+type Responder = Box<dyn FnMut(&[u8]) -> Result<Vec<Vec<u8>>> + Send>;
+
+// Answers every write with the reports produced by `responder`, so higher
+// layers can be exercised without hardware.
+pub struct ScriptedTransport {
+    responder: Responder,
+    pending:   VecDeque<Vec<u8>>,
+    written:   Vec<Vec<u8>>
+}
+
+impl ScriptedTransport {
+    pub fn new<F>(responder: F) -> Self
+    where
+        F: FnMut(&[u8]) -> Result<Vec<Vec<u8>>> + Send + 'static
+    {
+        Self {
+            responder: Box::new(responder),
+            pending:   VecDeque::new(),
+            written:   Vec::new()
+        }
+    }
+
+    pub fn written(&self) -> &[Vec<u8>] {
+        &self.written
+    }
+
+    pub fn push_report(&mut self, report: Vec<u8>) {
+        self.pending.push_back(report);
+    }
+}
+
+impl Transport for ScriptedTransport {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.written.push(data.to_vec());
+        let replies = (self.responder)(data)?;
+        self.pending.extend(replies);
+        Ok(())
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize> {
+        let Some(report) = self.pending.pop_front() else {
+            return Ok(0);
+        };
+
+        let size = report.len().min(buf.len());
+        buf[..size].copy_from_slice(&report[..size]);
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripted_transport_replies_in_order() {
+        let mut transport = ScriptedTransport::new(|data| Ok(vec![data.to_vec(), vec![0xAA]]));
+
+        transport.write(&[0x10, 0x01]).unwrap();
+
+        let mut buf = [0u8; 8];
+        assert_eq!(transport.read_timeout(&mut buf, 0).unwrap(), 2);
+        assert_eq!(&buf[..2], &[0x10, 0x01]);
+        assert_eq!(transport.read_timeout(&mut buf, 0).unwrap(), 1);
+        assert_eq!(buf[0], 0xAA);
+        assert_eq!(transport.read_timeout(&mut buf, 0).unwrap(), 0);
+        assert_eq!(transport.written(), &[vec![0x10, 0x01]]);
+    }
+
+    #[test]
+    fn test_scripted_transport_pushed_reports() {
+        let mut transport = ScriptedTransport::new(|_| Ok(vec![]));
+        transport.push_report(vec![0x10, 0x02, 0x00]);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(transport.read_timeout(&mut buf, 0).unwrap(), 3);
+    }
+}
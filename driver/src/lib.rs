@@ -2,10 +2,16 @@
 // SPDX-License-Identifier: MIT
 
 pub mod config;
+pub mod control;
 pub mod devices;
+pub mod emulator;
 pub mod error;
+pub mod firmware;
 pub mod hidpp;
+pub mod onboard;
 
 pub mod prelude {
-    pub use crate::{config::*, devices::*, error::*, hidpp::*};
+    pub use crate::{
+        config::*, control::*, devices::*, error::*, firmware::*, hidpp::*, onboard::*
+    };
 }
@@ -0,0 +1,537 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+use masterror::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{DeviceErrorKind, Result};
+
+pub const ROM_SECTOR_BASE: u16 = 0x0100;
+pub const DIRECTORY_SECTOR: u16 = 0x0000;
+pub const MAX_BUTTONS: usize = 16;
+
+const OFFSET_REPORT_RATE: usize = 0;
+const OFFSET_DEFAULT_DPI: usize = 1;
+const OFFSET_SHIFT_DPI: usize = 2;
+const OFFSET_DPI_LEVELS: usize = 3;
+const DPI_LEVEL_COUNT: usize = 5;
+const OFFSET_COLOR: usize = 13;
+const OFFSET_POWER_MODE: usize = 16;
+const OFFSET_ANGLE_SNAPPING: usize = 17;
+const OFFSET_BUTTONS: usize = 32;
+const OFFSET_SHIFTED_BUTTONS: usize = 96;
+const OFFSET_NAME: usize = 160;
+const NAME_LEN: usize = 48;
+const MIN_PROFILE_SECTOR_SIZE: usize = OFFSET_NAME + NAME_LEN + 2;
+
+const BEHAVIOR_MACRO: u8 = 0x0;
+const BEHAVIOR_SEND: u8 = 0x8;
+const BEHAVIOR_FUNCTION: u8 = 0x9;
+
+const SEND_MOUSE_BUTTON: u8 = 0x01;
+const SEND_KEY: u8 = 0x02;
+const SEND_CONSUMER: u8 = 0x03;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnboardProfilesInfo {
+    pub memory_model:      u8,
+    pub profile_format:    u8,
+    pub macro_format:      u8,
+    pub profile_count:     u8,
+    pub profile_count_oob: u8,
+    pub button_count:      u8,
+    pub sector_count:      u8,
+    pub sector_size:       u16,
+    pub mechanical_layout: u8
+}
+
+impl OnboardProfilesInfo {
+    pub fn from_response(params: &[u8]) -> Result<Self> {
+        if params.len() < 10 {
+            return Err(DeviceErrorKind::InvalidResponse.into());
+        }
+
+        Ok(Self {
+            memory_model:      params[0],
+            profile_format:    params[1],
+            macro_format:      params[2],
+            profile_count:     params[3],
+            profile_count_oob: params[4],
+            button_count:      params[5],
+            sector_count:      params[6],
+            sector_size:       u16::from_be_bytes([params[7], params[8]]),
+            mechanical_layout: params[9]
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryEntry {
+    pub sector:  u16,
+    pub enabled: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum OnboardButton {
+    Disabled,
+    MouseButton { mask: u16 },
+    Key { modifiers: u8, key: u8 },
+    Consumer { usage: u16 },
+    Function { id: u8, data: u8 },
+    Macro { sector: u16, address: u16 },
+    Raw { bytes: [u8; 4] }
+}
+
+impl OnboardButton {
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        if bytes == [0xFF; 4] {
+            return OnboardButton::Disabled;
+        }
+
+        match bytes[0] >> 4 {
+            BEHAVIOR_SEND => match bytes[1] {
+                SEND_MOUSE_BUTTON => OnboardButton::MouseButton {
+                    mask: u16::from_be_bytes([bytes[2], bytes[3]])
+                },
+                SEND_KEY => OnboardButton::Key {
+                    modifiers: bytes[2],
+                    key:       bytes[3]
+                },
+                SEND_CONSUMER => OnboardButton::Consumer {
+                    usage: u16::from_be_bytes([bytes[2], bytes[3]])
+                },
+                _ => OnboardButton::Raw {
+                    bytes
+                }
+            },
+            BEHAVIOR_FUNCTION => OnboardButton::Function {
+                id:   bytes[1],
+                data: bytes[3]
+            },
+            BEHAVIOR_MACRO => OnboardButton::Macro {
+                sector:  u16::from_be_bytes([bytes[0] & 0x0F, bytes[1]]),
+                address: u16::from_be_bytes([bytes[2], bytes[3]])
+            },
+            _ => OnboardButton::Raw {
+                bytes
+            }
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 4] {
+        match self {
+            OnboardButton::Disabled => [0xFF; 4],
+            OnboardButton::MouseButton {
+                mask
+            } => {
+                let [hi, lo] = mask.to_be_bytes();
+                [BEHAVIOR_SEND << 4, SEND_MOUSE_BUTTON, hi, lo]
+            }
+            OnboardButton::Key {
+                modifiers,
+                key
+            } => [BEHAVIOR_SEND << 4, SEND_KEY, modifiers, key],
+            OnboardButton::Consumer {
+                usage
+            } => {
+                let [hi, lo] = usage.to_be_bytes();
+                [BEHAVIOR_SEND << 4, SEND_CONSUMER, hi, lo]
+            }
+            OnboardButton::Function {
+                id,
+                data
+            } => [BEHAVIOR_FUNCTION << 4, id, 0x00, data],
+            OnboardButton::Macro {
+                sector,
+                address
+            } => {
+                let [sector_hi, sector_lo] = sector.to_be_bytes();
+                let [addr_hi, addr_lo] = address.to_be_bytes();
+                [
+                    (BEHAVIOR_MACRO << 4) | (sector_hi & 0x0F),
+                    sector_lo,
+                    addr_hi,
+                    addr_lo
+                ]
+            }
+            OnboardButton::Raw {
+                bytes
+            } => bytes
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnboardProfile {
+    pub name:              String,
+    pub report_rate_ms:    u8,
+    pub default_dpi_index: u8,
+    pub shift_dpi_index:   u8,
+    pub dpi_levels:        Vec<u16>,
+    pub color:             [u8; 3],
+    pub power_mode:        u8,
+    pub angle_snapping:    bool,
+    pub buttons:           Vec<OnboardButton>,
+    pub shifted_buttons:   Vec<OnboardButton>
+}
+
+impl OnboardProfile {
+    pub fn decode(sector: &[u8], button_count: usize) -> Result<Self> {
+        if sector.len() < MIN_PROFILE_SECTOR_SIZE {
+            return Err(AppError::bad_request("Profile sector too small"));
+        }
+        verify_sector(sector)?;
+
+        let button_count = button_count.min(MAX_BUTTONS);
+        let read_buttons = |offset: usize| {
+            (0..button_count)
+                .map(|i| {
+                    let start = offset + i * 4;
+                    let mut bytes = [0u8; 4];
+                    bytes.copy_from_slice(&sector[start..start + 4]);
+                    OnboardButton::from_bytes(bytes)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let dpi_levels = (0..DPI_LEVEL_COUNT)
+            .map(|i| {
+                let start = OFFSET_DPI_LEVELS + i * 2;
+                u16::from_le_bytes([sector[start], sector[start + 1]])
+            })
+            .collect();
+
+        let name_units: Vec<u16> = sector[OFFSET_NAME..OFFSET_NAME + NAME_LEN]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&unit| unit != 0x0000 && unit != 0xFFFF)
+            .collect();
+
+        Ok(Self {
+            name: String::from_utf16_lossy(&name_units),
+            report_rate_ms: sector[OFFSET_REPORT_RATE],
+            default_dpi_index: sector[OFFSET_DEFAULT_DPI],
+            shift_dpi_index: sector[OFFSET_SHIFT_DPI],
+            dpi_levels,
+            color: [
+                sector[OFFSET_COLOR],
+                sector[OFFSET_COLOR + 1],
+                sector[OFFSET_COLOR + 2]
+            ],
+            power_mode: sector[OFFSET_POWER_MODE],
+            angle_snapping: sector[OFFSET_ANGLE_SNAPPING] == 0x02,
+            buttons: read_buttons(OFFSET_BUTTONS),
+            shifted_buttons: read_buttons(OFFSET_SHIFTED_BUTTONS)
+        })
+    }
+
+    // Fields the profile does not model (lighting, timeouts, reserved bytes)
+    // are kept from `base`, so a decode/encode cycle never loses data.
+    pub fn encode(&self, base: &[u8]) -> Result<Vec<u8>> {
+        if base.len() < MIN_PROFILE_SECTOR_SIZE {
+            return Err(AppError::bad_request("Profile sector too small"));
+        }
+        if self.dpi_levels.len() > DPI_LEVEL_COUNT
+            || self.buttons.len() > MAX_BUTTONS
+            || self.shifted_buttons.len() > MAX_BUTTONS
+        {
+            return Err(AppError::bad_request(
+                "Profile has more DPI levels or buttons than the sector can hold"
+            ));
+        }
+
+        let name_units: Vec<u16> = self.name.encode_utf16().collect();
+        if name_units.len() > NAME_LEN / 2 {
+            return Err(AppError::bad_request("Profile name is too long"));
+        }
+
+        let mut sector = base.to_vec();
+        sector[OFFSET_REPORT_RATE] = self.report_rate_ms;
+        sector[OFFSET_DEFAULT_DPI] = self.default_dpi_index;
+        sector[OFFSET_SHIFT_DPI] = self.shift_dpi_index;
+        for (i, dpi) in self.dpi_levels.iter().enumerate() {
+            let start = OFFSET_DPI_LEVELS + i * 2;
+            sector[start..start + 2].copy_from_slice(&dpi.to_le_bytes());
+        }
+        sector[OFFSET_COLOR..OFFSET_COLOR + 3].copy_from_slice(&self.color);
+        sector[OFFSET_POWER_MODE] = self.power_mode;
+        sector[OFFSET_ANGLE_SNAPPING] = if self.angle_snapping { 0x02 } else { 0x01 };
+        for (i, button) in self.buttons.iter().enumerate() {
+            let start = OFFSET_BUTTONS + i * 4;
+            sector[start..start + 4].copy_from_slice(&button.to_bytes());
+        }
+        for (i, button) in self.shifted_buttons.iter().enumerate() {
+            let start = OFFSET_SHIFTED_BUTTONS + i * 4;
+            sector[start..start + 4].copy_from_slice(&button.to_bytes());
+        }
+
+        let name = &mut sector[OFFSET_NAME..OFFSET_NAME + NAME_LEN];
+        name.fill(0);
+        for (i, unit) in name_units.iter().enumerate() {
+            name[i * 2..i * 2 + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+
+        seal_sector(&mut sector);
+        Ok(sector)
+    }
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub fn verify_sector(sector: &[u8]) -> Result<()> {
+    if sector.len() < 2 {
+        return Err(AppError::bad_request("Sector too small"));
+    }
+
+    let (payload, stored) = sector.split_at(sector.len() - 2);
+    if crc16(payload) != u16::from_be_bytes([stored[0], stored[1]]) {
+        return Err(AppError::bad_request("Sector CRC mismatch"));
+    }
+
+    Ok(())
+}
+
+pub fn seal_sector(sector: &mut [u8]) {
+    let len = sector.len();
+    let crc = crc16(&sector[..len - 2]);
+    sector[len - 2..].copy_from_slice(&crc.to_be_bytes());
+}
+
+// A full directory has no 0xFFFF terminator; the trailing CRC is not an
+// entry.
+pub fn decode_directory(sector: &[u8]) -> Vec<DirectoryEntry> {
+    sector[..sector.len().saturating_sub(2)]
+        .chunks_exact(4)
+        .take_while(|entry| entry[0..2] != [0xFF, 0xFF])
+        .map(|entry| DirectoryEntry {
+            sector:  u16::from_be_bytes([entry[0], entry[1]]),
+            enabled: entry[2] != 0
+        })
+        .collect()
+}
+
+pub fn encode_directory(entries: &[DirectoryEntry], sector_size: usize) -> Result<Vec<u8>> {
+    if (entries.len() + 1) * 4 + 2 > sector_size {
+        return Err(AppError::bad_request(
+            "Too many profiles for directory sector"
+        ));
+    }
+
+    let mut sector = vec![0xFF; sector_size];
+    for (i, entry) in entries.iter().enumerate() {
+        let [hi, lo] = entry.sector.to_be_bytes();
+        sector[i * 4..i * 4 + 4].copy_from_slice(&[hi, lo, entry.enabled as u8, 0x00]);
+    }
+
+    seal_sector(&mut sector);
+    Ok(sector)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::bad_request("Invalid hex digit"));
+    }
+    if !hex.len().is_multiple_of(2) {
+        return Err(AppError::bad_request("Hex string has odd length"));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|e| AppError::bad_request("Invalid hex digit").with_source(e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank_sector(size: usize) -> Vec<u8> {
+        let mut sector = vec![0xFF; size];
+        seal_sector(&mut sector);
+        sector
+    }
+
+    fn sample_profile() -> OnboardProfile {
+        OnboardProfile {
+            name:              "Gaming".to_string(),
+            report_rate_ms:    1,
+            default_dpi_index: 1,
+            shift_dpi_index:   0,
+            dpi_levels:        vec![400, 800, 1600, 3200, 6400],
+            color:             [0x10, 0x20, 0x30],
+            power_mode:        0,
+            angle_snapping:    false,
+            buttons:           vec![
+                OnboardButton::MouseButton {
+                    mask: 0x0001
+                },
+                OnboardButton::Key {
+                    modifiers: 0x01,
+                    key:       0x06
+                },
+                OnboardButton::Disabled,
+            ],
+            shifted_buttons:   vec![
+                OnboardButton::Consumer {
+                    usage: 0x00E9
+                },
+                OnboardButton::Function {
+                    id:   0x03,
+                    data: 0x00
+                },
+                OnboardButton::Macro {
+                    sector:  0x0002,
+                    address: 0x0010
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn test_crc16_ccitt_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_seal_and_verify_sector() {
+        let mut sector = vec![0x42; 64];
+        seal_sector(&mut sector);
+        assert!(verify_sector(&sector).is_ok());
+
+        sector[3] ^= 0x01;
+        assert!(verify_sector(&sector).is_err());
+    }
+
+    #[test]
+    fn test_button_roundtrip() {
+        for button in sample_profile()
+            .buttons
+            .into_iter()
+            .chain(sample_profile().shifted_buttons)
+        {
+            assert_eq!(OnboardButton::from_bytes(button.to_bytes()), button);
+        }
+    }
+
+    #[test]
+    fn test_unknown_button_is_preserved() {
+        let bytes = [0x80, 0x7F, 0x12, 0x34];
+        assert_eq!(
+            OnboardButton::from_bytes(bytes),
+            OnboardButton::Raw {
+                bytes
+            }
+        );
+    }
+
+    #[test]
+    fn test_profile_roundtrip() {
+        let profile = sample_profile();
+        let sector = profile.encode(&blank_sector(256)).unwrap();
+
+        assert!(verify_sector(&sector).is_ok());
+        assert_eq!(OnboardProfile::decode(&sector, 3).unwrap(), profile);
+    }
+
+    #[test]
+    fn test_encode_preserves_unmodelled_bytes() {
+        let mut base = blank_sector(256);
+        base[210] = 0xAB;
+        seal_sector(&mut base);
+
+        let sector = sample_profile().encode(&base).unwrap();
+        assert_eq!(sector[210], 0xAB);
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_crc() {
+        let mut sector = sample_profile().encode(&blank_sector(256)).unwrap();
+        sector[0] ^= 0xFF;
+        assert!(OnboardProfile::decode(&sector, 3).is_err());
+    }
+
+    #[test]
+    fn test_encode_rejects_long_name() {
+        let mut profile = sample_profile();
+        profile.name = "x".repeat(25);
+        assert!(profile.encode(&blank_sector(256)).is_err());
+    }
+
+    #[test]
+    fn test_directory_roundtrip() {
+        let entries = vec![
+            DirectoryEntry {
+                sector:  0x0001,
+                enabled: true
+            },
+            DirectoryEntry {
+                sector:  0x0002,
+                enabled: false
+            },
+        ];
+        let sector = encode_directory(&entries, 256).unwrap();
+        assert!(verify_sector(&sector).is_ok());
+        assert_eq!(decode_directory(&sector), entries);
+    }
+
+    #[test]
+    fn test_full_directory_skips_crc() {
+        let entries: Vec<DirectoryEntry> = (1..=3)
+            .map(|sector| DirectoryEntry {
+                sector,
+                enabled: true
+            })
+            .collect();
+        // Three entries and two spare bytes fill the sector up to the CRC,
+        // so no 0xFFFF terminates the list.
+        let mut sector = vec![0u8; 16];
+        sector[..12].copy_from_slice(&[0, 1, 1, 0, 0, 2, 1, 0, 0, 3, 1, 0]);
+        seal_sector(&mut sector);
+        assert_eq!(decode_directory(&sector), entries);
+    }
+
+    #[test]
+    fn test_info_from_response() {
+        let info = OnboardProfilesInfo::from_response(&[
+            0x01, 0x03, 0x01, 0x05, 0x00, 0x0B, 0x10, 0x01, 0x00, 0x02
+        ])
+        .unwrap();
+        assert_eq!(info.profile_count, 5);
+        assert_eq!(info.button_count, 11);
+        assert_eq!(info.sector_size, 256);
+        assert!(OnboardProfilesInfo::from_response(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let bytes = vec![0x00, 0x7F, 0xAB, 0xFF];
+        assert_eq!(to_hex(&bytes), "007fabff");
+        assert_eq!(from_hex("007fabff").unwrap(), bytes);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        assert!(from_hex("aé").is_err());
+        assert!(from_hex("éé").is_err());
+    }
+}
@@ -0,0 +1,471 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+pub mod format;
+
+pub use format::*;
+use masterror::{field, prelude::*};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    error::Result,
+    hidpp::{FEATURE_ONBOARD_PROFILES, HidppDevice, OnboardProfilesFunction}
+};
+
+const MEMORY_CHUNK_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnboardMode {
+    Onboard,
+    Host
+}
+
+// A sector that does not decode keeps only `raw`, which is restored as is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileSlot {
+    pub sector:  u16,
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<OnboardProfile>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub raw:     String
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileBackup {
+    pub info:     OnboardProfilesInfo,
+    pub profiles: Vec<ProfileSlot>
+}
+
+pub struct OnboardProfiles<'a> {
+    hidpp:         &'a mut HidppDevice,
+    feature_index: u8,
+    info:          OnboardProfilesInfo
+}
+
+impl<'a> OnboardProfiles<'a> {
+    pub fn open(hidpp: &'a mut HidppDevice) -> Result<Self> {
+        let feature_index = hidpp.get_feature_index(FEATURE_ONBOARD_PROFILES)?;
+
+        let response = hidpp.send_command(
+            feature_index,
+            OnboardProfilesFunction::GetProfilesDescription as u8,
+            &[]
+        )?;
+        let info = OnboardProfilesInfo::from_response(response.parameters())?;
+
+        debug!(
+            "Onboard profiles: {} profiles, {} sectors of {} bytes",
+            info.profile_count, info.sector_count, info.sector_size
+        );
+
+        Ok(Self {
+            hidpp,
+            feature_index,
+            info
+        })
+    }
+
+    pub fn info(&self) -> OnboardProfilesInfo {
+        self.info
+    }
+
+    pub fn get_mode(&mut self) -> Result<OnboardMode> {
+        let response = self.hidpp.send_command(
+            self.feature_index,
+            OnboardProfilesFunction::GetOnboardMode as u8,
+            &[]
+        )?;
+
+        Ok(match response.parameters()[0] {
+            0x01 => OnboardMode::Onboard,
+            _ => OnboardMode::Host
+        })
+    }
+
+    pub fn set_mode(&mut self, mode: OnboardMode) -> Result<()> {
+        let value = match mode {
+            OnboardMode::Onboard => 0x01,
+            OnboardMode::Host => 0x02
+        };
+
+        self.hidpp.send_command(
+            self.feature_index,
+            OnboardProfilesFunction::SetOnboardMode as u8,
+            &[value]
+        )?;
+
+        info!("Onboard mode set to {:?}", mode);
+        Ok(())
+    }
+
+    pub fn read_sector(&mut self, sector: u16) -> Result<Vec<u8>> {
+        let size = self.info.sector_size as usize;
+        if size < MEMORY_CHUNK_SIZE {
+            return Err(AppError::bad_request(
+                "Sector size smaller than a memory read"
+            ));
+        }
+
+        let mut data = Vec::with_capacity(size);
+        while data.len() < size {
+            // Reads always return 16 bytes, so the last chunk is taken from
+            // `size - 16` and only its tail is kept.
+            let offset = data.len().min(size - MEMORY_CHUNK_SIZE);
+            let [sector_hi, sector_lo] = sector.to_be_bytes();
+            let [offset_hi, offset_lo] = (offset as u16).to_be_bytes();
+
+            let response = self.hidpp.send_command(
+                self.feature_index,
+                OnboardProfilesFunction::MemoryRead as u8,
+                &[sector_hi, sector_lo, offset_hi, offset_lo]
+            )?;
+
+            let chunk = response.parameters();
+            let skip = data.len() - offset;
+            data.extend_from_slice(&chunk[skip..MEMORY_CHUNK_SIZE.min(chunk.len())]);
+        }
+
+        Ok(data)
+    }
+
+    pub fn write_sector(&mut self, sector: u16, data: &[u8]) -> Result<()> {
+        verify_sector(data)?;
+        self.write_memory(sector, data)
+    }
+
+    // Writes `data` as it is, without checking its CRC.
+    fn write_memory(&mut self, sector: u16, data: &[u8]) -> Result<()> {
+        if sector >= ROM_SECTOR_BASE {
+            return Err(AppError::bad_request(
+                "Refusing to write a read-only ROM sector"
+            ));
+        }
+        if data.len() != self.info.sector_size as usize {
+            return Err(AppError::bad_request(
+                "Sector data does not match sector size"
+            ));
+        }
+
+        let [sector_hi, sector_lo] = sector.to_be_bytes();
+        let [len_hi, len_lo] = (data.len() as u16).to_be_bytes();
+
+        self.hidpp.send_command(
+            self.feature_index,
+            OnboardProfilesFunction::MemoryAddrWrite as u8,
+            &[sector_hi, sector_lo, 0x00, 0x00, len_hi, len_lo]
+        )?;
+
+        for chunk in data.chunks(MEMORY_CHUNK_SIZE) {
+            let mut params = [0u8; MEMORY_CHUNK_SIZE];
+            params[..chunk.len()].copy_from_slice(chunk);
+            self.hidpp.send_command(
+                self.feature_index,
+                OnboardProfilesFunction::MemoryWrite as u8,
+                &params
+            )?;
+        }
+
+        self.hidpp.send_command(
+            self.feature_index,
+            OnboardProfilesFunction::MemoryWriteEnd as u8,
+            &[]
+        )?;
+
+        if self.read_sector(sector)? != data {
+            return Err(AppError::internal("Sector verification failed after write"));
+        }
+
+        info!("Wrote sector {:04x} ({} bytes)", sector, data.len());
+        Ok(())
+    }
+
+    pub fn read_directory(&mut self) -> Result<Vec<DirectoryEntry>> {
+        let sector = self.read_sector(DIRECTORY_SECTOR)?;
+        if verify_sector(&sector).is_ok() {
+            return Ok(decode_directory(&sector));
+        }
+
+        warn!("User profile directory invalid, falling back to ROM defaults");
+        let sector = self.read_sector(ROM_SECTOR_BASE)?;
+        Ok(decode_directory(&sector))
+    }
+
+    pub fn backup(&mut self) -> Result<ProfileBackup> {
+        let mut profiles = Vec::new();
+
+        for entry in self.read_directory()? {
+            let raw = self.read_sector(entry.sector)?;
+            let profile = match OnboardProfile::decode(&raw, self.info.button_count as usize) {
+                Ok(profile) => Some(profile),
+                Err(e) => {
+                    warn!(
+                        "Keeping profile in sector {:04x} as raw data: {}",
+                        entry.sector, e
+                    );
+                    None
+                }
+            };
+            profiles.push(ProfileSlot {
+                sector: entry.sector,
+                enabled: entry.enabled,
+                profile,
+                raw: to_hex(&raw)
+            });
+        }
+
+        Ok(ProfileBackup {
+            info: self.info,
+            profiles
+        })
+    }
+
+    pub fn write_profile(&mut self, slot: &ProfileSlot) -> Result<()> {
+        let current = self.read_sector(slot.sector)?;
+        let data = match &slot.profile {
+            Some(profile) if slot.raw.is_empty() => profile.encode(&current)?,
+            Some(profile) => profile.encode(&from_hex(&slot.raw)?)?,
+            None if !slot.raw.is_empty() => from_hex(&slot.raw)?,
+            None => {
+                return Err(AppError::bad_request(
+                    "Profile slot has neither a profile nor raw data"
+                )
+                .with_field(field::u64("sector", slot.sector as u64)));
+            }
+        };
+        if current == data {
+            debug!("Sector {:04x} unchanged, skipping write", slot.sector);
+            return Ok(());
+        }
+
+        match slot.profile {
+            Some(_) => self.write_sector(slot.sector, &data),
+            None => self.write_memory(slot.sector, &data)
+        }
+    }
+
+    pub fn restore(&mut self, backup: &ProfileBackup) -> Result<()> {
+        if backup.info.sector_size != self.info.sector_size
+            || backup.info.profile_format != self.info.profile_format
+            || backup.info.button_count != self.info.button_count
+        {
+            return Err(AppError::bad_request(
+                "Backup was taken from a device with a different profile layout"
+            ));
+        }
+
+        for slot in &backup.profiles {
+            self.write_profile(slot)?;
+        }
+
+        let entries: Vec<DirectoryEntry> = backup
+            .profiles
+            .iter()
+            .map(|slot| DirectoryEntry {
+                sector:  slot.sector,
+                enabled: slot.enabled
+            })
+            .collect();
+
+        if self.read_directory()? != entries {
+            let directory = encode_directory(&entries, self.info.sector_size as usize)?;
+            self.write_sector(DIRECTORY_SECTOR, &directory)?;
+        }
+
+        info!("Restored {} onboard profiles", backup.profiles.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex}
+    };
+
+    use super::*;
+    use crate::hidpp::{HidppPacket, ROOT_INDEX, ScriptedTransport};
+
+    #[test]
+    fn test_backup_toml_roundtrip() {
+        let mut base = vec![0xFF; 256];
+        seal_sector(&mut base);
+
+        let profile = OnboardProfile {
+            name:              "Default".to_string(),
+            report_rate_ms:    1,
+            default_dpi_index: 0,
+            shift_dpi_index:   0,
+            dpi_levels:        vec![800, 1600, 0, 0, 0],
+            color:             [0xFF, 0xFF, 0xFF],
+            power_mode:        0,
+            angle_snapping:    false,
+            buttons:           vec![OnboardButton::MouseButton {
+                mask: 0x0001
+            }],
+            shifted_buttons:   vec![OnboardButton::Disabled]
+        };
+        let raw = profile.encode(&base).unwrap();
+
+        let backup = ProfileBackup {
+            info:     OnboardProfilesInfo {
+                memory_model:      1,
+                profile_format:    3,
+                macro_format:      1,
+                profile_count:     1,
+                profile_count_oob: 0,
+                button_count:      1,
+                sector_count:      8,
+                sector_size:       256,
+                mechanical_layout: 0
+            },
+            profiles: vec![ProfileSlot {
+                sector:  0x0001,
+                enabled: true,
+                profile: Some(profile),
+                raw:     to_hex(&raw)
+            }]
+        };
+
+        let toml_str = toml::to_string_pretty(&backup).unwrap();
+        let parsed: ProfileBackup = toml::from_str(&toml_str).unwrap();
+        assert_eq!(parsed, backup);
+        assert_eq!(from_hex(&parsed.profiles[0].raw).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_dump_omits_raw_sector() {
+        let slot = ProfileSlot {
+            sector:  0x0001,
+            enabled: true,
+            profile: Some(OnboardProfile {
+                name:              String::new(),
+                report_rate_ms:    1,
+                default_dpi_index: 0,
+                shift_dpi_index:   0,
+                dpi_levels:        vec![],
+                color:             [0, 0, 0],
+                power_mode:        0,
+                angle_snapping:    false,
+                buttons:           vec![],
+                shifted_buttons:   vec![]
+            }),
+            raw:     String::new()
+        };
+        let toml_str = toml::to_string(&slot).unwrap();
+        assert!(!toml_str.contains("raw"));
+    }
+
+    // Serves 0x8100 from `sectors`: 256-byte sectors, one button per profile.
+    fn scripted_onboard(sectors: Arc<Mutex<HashMap<u16, Vec<u8>>>>) -> HidppDevice {
+        let mut pending: Option<(u16, usize, Vec<u8>)> = None;
+        let transport = ScriptedTransport::new(move |report| {
+            let request = HidppPacket::from_bytes(report)?;
+            let sent = request.parameters();
+            let mut params = [0u8; 16];
+            let mut sectors = sectors.lock().unwrap();
+
+            match (request.feature_index(), request.function_id()) {
+                (ROOT_INDEX, 0x00) => {
+                    params[0] = 0x05;
+                    params[2] = 0x01;
+                }
+                (0x05, 0x00) => {
+                    params[..10].copy_from_slice(&[1, 3, 1, 2, 0, 1, 8, 0x01, 0x00, 0])
+                }
+                (0x05, 0x05) => {
+                    let sector = u16::from_be_bytes([sent[0], sent[1]]);
+                    let offset = u16::from_be_bytes([sent[2], sent[3]]) as usize;
+                    params.copy_from_slice(&sectors[&sector][offset..offset + 16]);
+                }
+                (0x05, 0x06) => {
+                    let sector = u16::from_be_bytes([sent[0], sent[1]]);
+                    let len = u16::from_be_bytes([sent[4], sent[5]]) as usize;
+                    pending = Some((sector, len, Vec::new()));
+                }
+                (0x05, 0x07) => pending.as_mut().unwrap().2.extend_from_slice(&sent[..16]),
+                (0x05, 0x08) => {
+                    let (sector, len, mut data) = pending.take().unwrap();
+                    data.truncate(len);
+                    sectors.insert(sector, data);
+                }
+                _ => {}
+            }
+
+            Ok(vec![
+                HidppPacket::new_long(
+                    request.device_index(),
+                    request.feature_index(),
+                    request.function_id(),
+                    request.software_id(),
+                    params
+                )
+                .to_bytes(),
+            ])
+        });
+
+        HidppDevice::with_transport(Box::new(transport), 1)
+    }
+
+    #[test]
+    fn test_corrupt_sector_survives_backup_and_restore() {
+        let mut base = vec![0xFF; 256];
+        seal_sector(&mut base);
+        let profile = OnboardProfile {
+            name:              "Work".to_string(),
+            report_rate_ms:    1,
+            default_dpi_index: 0,
+            shift_dpi_index:   0,
+            dpi_levels:        vec![1600, 0, 0, 0, 0],
+            color:             [0, 0, 0],
+            power_mode:        0,
+            angle_snapping:    false,
+            buttons:           vec![OnboardButton::Disabled],
+            shifted_buttons:   vec![OnboardButton::Disabled]
+        };
+        let entries = [
+            DirectoryEntry {
+                sector:  0x0001,
+                enabled: true
+            },
+            DirectoryEntry {
+                sector:  0x0002,
+                enabled: false
+            }
+        ];
+        // Fails its CRC, so it does not decode.
+        let corrupt = vec![0x42; 256];
+        let sectors = Arc::new(Mutex::new(HashMap::from([
+            (DIRECTORY_SECTOR, encode_directory(&entries, 256).unwrap()),
+            (0x0001, profile.encode(&base).unwrap()),
+            (0x0002, corrupt.clone())
+        ])));
+        let mut hidpp = scripted_onboard(sectors.clone());
+        let mut onboard = OnboardProfiles::open(&mut hidpp).unwrap();
+
+        let backup = onboard.backup().unwrap();
+        assert_eq!(backup.profiles.len(), 2);
+        assert_eq!(backup.profiles[0].profile, Some(profile.clone()));
+        assert_eq!(backup.profiles[1].profile, None);
+        assert_eq!(from_hex(&backup.profiles[1].raw).unwrap(), corrupt);
+
+        // The device loses the second profile; restoring brings it back.
+        {
+            let mut sectors = sectors.lock().unwrap();
+            sectors.insert(
+                DIRECTORY_SECTOR,
+                encode_directory(&entries[..1], 256).unwrap()
+            );
+            sectors.insert(0x0002, base.clone());
+        }
+        let backup: ProfileBackup =
+            toml::from_str(&toml::to_string_pretty(&backup).unwrap()).unwrap();
+        onboard.restore(&backup).unwrap();
+
+        let sectors = sectors.lock().unwrap();
+        assert_eq!(sectors[&0x0002], corrupt);
+        assert_eq!(decode_directory(&sectors[&DIRECTORY_SECTOR]), entries);
+    }
+}
@@ -118,21 +118,73 @@ fn test_empty_config_devices() {
 fn test_multiple_devices_config() {
     let mut config = Config::default();
     config.devices.push(DeviceConfig {
-        name:        "Second Device".to_string(),
-        dpi:         2000,
-        smartshift:  SmartShiftConfig {
+        name:           "Second Device".to_string(),
+        matcher:        None,
+        dpi:            2000,
+        smartshift:     SmartShiftConfig {
             enabled:   true,
             threshold: 30
         },
-        hiresscroll: HiResScrollConfig {
+        hiresscroll:    HiResScrollConfig {
             enabled:  false,
             inverted: true
         },
-        buttons:     std::collections::HashMap::new()
+        buttons:        std::collections::HashMap::from([(
+            ButtonId::Forward,
+            Action::Keypress {
+                keys: vec!["KEY_LEFTCTRL".to_string(), "KEY_C".to_string()]
+            }
+        )]),
+        button_modes:   std::collections::HashMap::from([(
+            ButtonId::Forward,
+            ButtonMode::Persistent
+        )]),
+        report_rate_hz: Some(500),
+        profiles:       std::collections::BTreeMap::new()
     });
 
     assert_eq!(config.devices.len(), 2);
     assert_eq!(config.devices[1].dpi, 2000);
+    assert_eq!(config.devices[1].report_rate_hz, Some(500));
+    assert_eq!(
+        config.devices[1].button_mode(ButtonId::Forward),
+        ButtonMode::Persistent
+    );
+    assert_eq!(
+        config.devices[1].button_mode(ButtonId::Back),
+        ButtonMode::Daemon
+    );
+}
+
+#[test]
+fn test_button_modes_from_toml() {
+    let config: Config = toml::from_str(
+        r#"
+        [[devices]]
+        name = "MX Master 3S"
+
+        [devices.buttons.Forward]
+        type = "Keypress"
+        keys = ["KEY_LEFTALT", "KEY_TAB"]
+
+        [devices.button_modes]
+        Forward = "persistent"
+        "#
+    )
+    .unwrap();
+
+    let device = &config.devices[0];
+    assert_eq!(
+        device.button_mode(ButtonId::Forward),
+        ButtonMode::Persistent
+    );
+    assert_eq!(
+        PersistentAction::from_action(&device.buttons[&ButtonId::Forward]).unwrap(),
+        PersistentAction::Key {
+            key:       0x2B,
+            modifiers: 0x04
+        }
+    );
 }
 
 #[test]
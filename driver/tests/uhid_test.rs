@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
+// SPDX-License-Identifier: MIT
+
+// These run against a receiver the kernel creates through /dev/uhid, so
+// they need root (or access to /dev/uhid) and are skipped otherwise.
+
+use std::sync::{Mutex, MutexGuard};
+
+use hidapi::HidApi;
+use logi_mx_driver::{
+    emulator::{
+        DEFAULT_SLOT, Emulator, MouseState,
+        uhid::{UHID_PATH, UhidDevice, uhid_available}
+    },
+    prelude::*
+};
+
+// Each test plugs in a Bolt receiver; one at a time keeps `open_vid_pid`
+// from seeing another test's.
+static UHID: Mutex<()> = Mutex::new(());
+
+fn virtual_receiver() -> Option<(MutexGuard<'static, ()>, Emulator, UhidDevice)> {
+    if !uhid_available() {
+        eprintln!("skipping: {} is not accessible", UHID_PATH);
+        return None;
+    }
+    let guard = UHID.lock().unwrap_or_else(|e| e.into_inner());
+    let emulator = Emulator::with_mouse(DEFAULT_SLOT, MouseState::mx_master_3s());
+    let device = UhidDevice::create(&emulator).unwrap();
+    Some((guard, emulator, device))
+}
+
+#[test]
+fn test_uhid_mouse_through_hidraw() {
+    let Some((_guard, emulator, device)) = virtual_receiver() else {
+        return;
+    };
+    let path = device.hidraw_path().unwrap();
+    assert!(ReportDescriptor::read_for_hidraw(&path).unwrap().is_hidpp());
+
+    let mut mouse =
+        GenericHidppMouse::open_path(&path, ConnectionType::Receiver, Some(DEFAULT_SLOT)).unwrap();
+    let identity = mouse.identity().unwrap();
+    assert_eq!(identity.serial.as_deref(), Some("EMU0B0340001"));
+
+    mouse.set_dpi(1600).unwrap();
+    assert_eq!(emulator.mouse(DEFAULT_SLOT).unwrap().unwrap().dpi, 1600);
+
+    mouse
+        .set_button_diverted(ButtonId::ThumbGesture, true)
+        .unwrap();
+    emulator.press_buttons(DEFAULT_SLOT, &[0x00C3]).unwrap();
+    assert_eq!(
+        mouse.read_event(1000).unwrap(),
+        Some(DeviceEvent::ButtonsPressed(vec![ButtonId::ThumbGesture]))
+    );
+
+    emulator.set_connected(DEFAULT_SLOT, false).unwrap();
+    assert_eq!(
+        mouse.read_event(1000).unwrap(),
+        Some(DeviceEvent::LinkChanged {
+            connected: false
+        })
+    );
+}
+
+#[test]
+fn test_uhid_open_vid_pid_selects_the_receiver() {
+    let Some((_guard, _emulator, device)) = virtual_receiver() else {
+        return;
+    };
+    let path = device.hidraw_path().unwrap();
+
+    let api = HidApi::new().unwrap();
+    let receivers = api
+        .device_list()
+        .filter(|info| (info.vendor_id(), info.product_id()) == (VID_LOGITECH, PID_BOLT_RECEIVER))
+        .filter(|info| info.path().to_string_lossy() != path)
+        .count();
+    if receivers > 0 {
+        eprintln!("skipping: a real Bolt receiver is plugged in");
+        return;
+    }
+
+    let hidpp = HidppDevice::open_vid_pid(VID_LOGITECH, PID_BOLT_RECEIVER, DEFAULT_SLOT).unwrap();
+    let mut mouse = GenericHidppMouse::identify(hidpp, None);
+    assert_eq!(
+        mouse.identity().unwrap().serial.as_deref(),
+        Some("EMU0B0340001")
+    );
+}
@@ -1,7 +1,7 @@
 // SPDX-FileCopyrightText: 2025 RAprogramm <andrey.rozanov.vl@gmail.com>
 // SPDX-License-Identifier: MIT
 
-use gtk4::{Box, Button, Image, Orientation, Scale, Switch, glib, prelude::*};
+use gtk4::{Box, Button, DropDown, Image, Orientation, Scale, Switch, glib, prelude::*};
 use libadwaita::{
     ActionRow, Application, ApplicationWindow, Clamp, HeaderBar, PreferencesGroup,
     PreferencesPage, StatusPage, Toast, ToastOverlay, prelude::*
@@ -30,11 +30,13 @@ fn build_ui(app: &Application) {
     let toast_overlay = ToastOverlay::new();
 
     // Check device connection
-    let content = if let Ok(mut device) = MxMaster3s::open_bolt_receiver(2) {
+    let content = if let Ok(mut device) = GenericHidppMouse::open_any()
+        && let Ok(capabilities) = device.capabilities()
+    {
         let name = device
             .get_device_name()
             .unwrap_or_else(|_| "MX Master 3S".to_string());
-        create_connected_ui(&name, toast_overlay.clone())
+        create_connected_ui(&name, &capabilities, toast_overlay.clone())
     } else {
         create_disconnected_ui()
     };
@@ -73,7 +75,36 @@ fn create_disconnected_ui() -> Box {
     main_box
 }
 
-fn create_connected_ui(device_name: &str, toast_overlay: ToastOverlay) -> Box {
+// Keeps the group visible so the user can see the setting exists, but
+// greys it out with the reason the device gave.
+fn mark_unsupported(
+    group: &PreferencesGroup,
+    capabilities: &DeviceCapabilities,
+    setting: Setting
+) -> bool {
+    match capabilities.unsupported_reason(setting) {
+        Some(reason) => {
+            group.set_description(Some(&reason));
+            group.set_sensitive(false);
+            true
+        }
+        None => false
+    }
+}
+
+fn report_result(toast_overlay: &ToastOverlay, result: Result<()>, success: &str) {
+    let message = match result {
+        Ok(()) => success.to_string(),
+        Err(e) => format!("Failed: {}", e)
+    };
+    toast_overlay.add_toast(Toast::new(&message));
+}
+
+fn create_connected_ui(
+    device_name: &str,
+    capabilities: &DeviceCapabilities,
+    toast_overlay: ToastOverlay
+) -> Box {
     let scrolled = gtk4::ScrolledWindow::new();
     scrolled.set_vexpand(true);
     scrolled.set_policy(gtk4::PolicyType::Never, gtk4::PolicyType::Automatic);
@@ -89,23 +120,30 @@ fn create_connected_ui(device_name: &str, toast_overlay: ToastOverlay) -> Box {
     prefs_page.set_margin_end(12);
 
     // Device Info
-    let device_info = create_device_info_group(device_name);
+    let device_info = create_device_info_group(device_name, capabilities);
     prefs_page.add(&device_info);
 
     // Battery
-    let battery_group = create_battery_group(toast_overlay.clone());
+    let battery_group = create_battery_group(capabilities, toast_overlay.clone());
     prefs_page.add(&battery_group);
 
     // DPI
-    let dpi_group = create_dpi_group(toast_overlay.clone());
+    let dpi_group = create_dpi_group(capabilities, toast_overlay.clone());
     prefs_page.add(&dpi_group);
 
+    // Report rate
+    if capabilities.supports(Setting::ReportRate)
+        && let Some(report_rate_group) = create_report_rate_group(toast_overlay.clone())
+    {
+        prefs_page.add(&report_rate_group);
+    }
+
     // SmartShift
-    let smartshift_group = create_smartshift_group(toast_overlay.clone());
+    let smartshift_group = create_smartshift_group(capabilities, toast_overlay.clone());
     prefs_page.add(&smartshift_group);
 
     // Scroll
-    let scroll_group = create_scroll_group(toast_overlay);
+    let scroll_group = create_scroll_group(capabilities, toast_overlay);
     prefs_page.add(&scroll_group);
 
     clamp.set_child(Some(&prefs_page));
@@ -116,7 +154,7 @@ fn create_connected_ui(device_name: &str, toast_overlay: ToastOverlay) -> Box {
     main_box
 }
 
-fn create_device_info_group(name: &str) -> PreferencesGroup {
+fn create_device_info_group(name: &str, capabilities: &DeviceCapabilities) -> PreferencesGroup {
     let group = PreferencesGroup::new();
     group.set_title("Device Information");
 
@@ -133,20 +171,49 @@ fn create_device_info_group(name: &str) -> PreferencesGroup {
     group.add(&name_row);
     group.add(&connection_row);
 
+    if capabilities.supports(Setting::EasySwitch)
+        && let Ok(hosts) = GenericHidppMouse::open_any().and_then(|mut d| d.get_host_info())
+    {
+        for host in &hosts.hosts {
+            let host_row = ActionRow::new();
+            host_row.add_prefix(&Image::from_icon_name("computer-symbolic"));
+            host_row.set_title(&format!(
+                "Host {} · {}",
+                host.index + 1,
+                host.name.as_deref().unwrap_or("Unnamed")
+            ));
+            host_row.set_subtitle(&if host.paired {
+                format!("Paired · {:?}", host.os)
+            } else {
+                "Not paired".to_string()
+            });
+            if host.index == hosts.current {
+                host_row.add_suffix(&Image::from_icon_name("object-select-symbolic"));
+            }
+            group.add(&host_row);
+        }
+    }
+
     group
 }
 
-fn create_battery_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
+fn create_battery_group(
+    capabilities: &DeviceCapabilities,
+    toast_overlay: ToastOverlay
+) -> PreferencesGroup {
     let group = PreferencesGroup::new();
     group.set_title("Battery");
     group.set_description(Some("Monitor battery status and charging"));
+    if mark_unsupported(&group, capabilities, Setting::Battery) {
+        return group;
+    }
 
     let battery_icon = Image::from_icon_name("battery-symbolic");
     let battery_row = ActionRow::new();
     battery_row.add_prefix(&battery_icon);
     battery_row.set_title("Battery Level");
 
-    match MxMaster3s::open_bolt_receiver(2).and_then(|mut d| d.get_battery_info()) {
+    match GenericHidppMouse::open_any().and_then(|mut d| d.get_battery_info()) {
         Ok(battery) => {
             let icon = match battery.level {
                 0..=20 => "battery-level-0-symbolic",
@@ -168,7 +235,7 @@ fn create_battery_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
     let bi = battery_icon.clone();
     let to = toast_overlay.clone();
     refresh_btn.connect_clicked(move |_| {
-        if let Ok(mut device) = MxMaster3s::open_bolt_receiver(2)
+        if let Ok(mut device) = GenericHidppMouse::open_any()
             && let Ok(battery) = device.get_battery_info()
         {
             let icon = match battery.level {
@@ -194,12 +261,29 @@ fn create_battery_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
     group
 }
 
-fn create_dpi_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
+fn create_dpi_group(
+    capabilities: &DeviceCapabilities,
+    toast_overlay: ToastOverlay
+) -> PreferencesGroup {
     let group = PreferencesGroup::new();
     group.set_title("Pointer Sensitivity");
-    group.set_description(Some("Adjust cursor speed from 400 to 8000 DPI"));
+    if mark_unsupported(&group, capabilities, Setting::Dpi) {
+        return group;
+    }
+
+    // Without a known range fall back to what most MX sensors accept.
+    let range = capabilities.dpi.clone().unwrap_or(DpiRange {
+        min:    400,
+        max:    8000,
+        step:   100,
+        values: Vec::new()
+    });
+    group.set_description(Some(&format!(
+        "Adjust cursor speed from {} to {} DPI",
+        range.min, range.max
+    )));
 
-    let current_dpi = MxMaster3s::open_bolt_receiver(2)
+    let current_dpi = GenericHidppMouse::open_any()
         .and_then(|mut d| d.get_dpi())
         .unwrap_or(1000);
 
@@ -215,7 +299,12 @@ fn create_dpi_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
     let scale_row = ActionRow::new();
     scale_row.set_title("Sensitivity");
 
-    let scale = Scale::with_range(Orientation::Horizontal, 400.0, 8000.0, 100.0);
+    let scale = Scale::with_range(
+        Orientation::Horizontal,
+        range.min as f64,
+        range.max as f64,
+        range.step.max(1) as f64
+    );
     scale.set_value(current_dpi as f64);
     scale.set_draw_value(true);
     scale.set_value_pos(gtk4::PositionType::Right);
@@ -239,12 +328,8 @@ fn create_dpi_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
     let to = toast_overlay.clone();
     apply_btn.connect_clicked(move |_| {
         let dpi = sc.value() as u16;
-        if let Ok(mut device) = MxMaster3s::open_bolt_receiver(2)
-            && device.set_dpi(dpi).is_ok()
-        {
-            let toast = Toast::new(&format!("DPI set to {}", dpi));
-            to.add_toast(toast);
-        }
+        let result = GenericHidppMouse::open_any().and_then(|mut d| d.set_dpi(dpi));
+        report_result(&to, result, &format!("DPI set to {}", dpi));
     });
 
     scale_box.append(&apply_btn);
@@ -254,14 +339,69 @@ fn create_dpi_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
     group
 }
 
-fn create_smartshift_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
+fn create_report_rate_group(toast_overlay: ToastOverlay) -> Option<PreferencesGroup> {
+    let mut device = GenericHidppMouse::open_any().ok()?;
+    let rates = device.get_report_rate_list().ok()?;
+    let current_rate = device.get_report_rate().unwrap_or(1000);
+
+    let group = PreferencesGroup::new();
+    group.set_title("Report Rate");
+    group.set_description(Some(
+        "Higher polling rates lower latency but use more battery"
+    ));
+
+    let labels: Vec<String> = rates.iter().map(|hz| format!("{} Hz", hz)).collect();
+    let label_refs: Vec<&str> = labels.iter().map(String::as_str).collect();
+    let dropdown = DropDown::from_strings(&label_refs);
+    dropdown.set_valign(gtk4::Align::Center);
+    if let Some(position) = rates.iter().position(|&hz| hz == current_rate) {
+        dropdown.set_selected(position as u32);
+    }
+
+    let rate_row = ActionRow::new();
+    rate_row.add_prefix(&Image::from_icon_name("preferences-system-time-symbolic"));
+    rate_row.set_title("Polling Rate");
+    rate_row.set_subtitle(&format!("Current: {} Hz", current_rate));
+    rate_row.add_suffix(&dropdown);
+
+    let apply_btn = Button::with_label("Apply");
+    apply_btn.add_css_class("suggested-action");
+    apply_btn.add_css_class("pill");
+    apply_btn.set_valign(gtk4::Align::Center);
+
+    let dd = dropdown.clone();
+    let rr = rate_row.clone();
+    let to = toast_overlay;
+    apply_btn.connect_clicked(move |_| {
+        let Some(&hz) = rates.get(dd.selected() as usize) else {
+            return;
+        };
+        let result = GenericHidppMouse::open_any().and_then(|mut d| d.set_report_rate(hz));
+        if result.is_ok() {
+            rr.set_subtitle(&format!("Current: {} Hz", hz));
+        }
+        report_result(&to, result, &format!("Report rate set to {} Hz", hz));
+    });
+
+    rate_row.add_suffix(&apply_btn);
+    group.add(&rate_row);
+    Some(group)
+}
+
+fn create_smartshift_group(
+    capabilities: &DeviceCapabilities,
+    toast_overlay: ToastOverlay
+) -> PreferencesGroup {
     let group = PreferencesGroup::new();
     group.set_title("SmartShift");
     group.set_description(Some(
         "Automatic switching between ratchet and freespin modes"
     ));
+    if mark_unsupported(&group, capabilities, Setting::SmartShift) {
+        return group;
+    }
 
-    let current_config = MxMaster3s::open_bolt_receiver(2)
+    let current_config = GenericHidppMouse::open_any()
         .and_then(|mut d| d.get_smartshift())
         .unwrap_or_default();
 
@@ -311,10 +451,11 @@ fn create_smartshift_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
             threshold: ts.value() as u8
         };
 
-        if let Ok(mut device) = MxMaster3s::open_bolt_receiver(2)
-            && device.set_smartshift(config).is_ok()
-        {
-            let toast = Toast::new(&format!(
+        let result = GenericHidppMouse::open_any().and_then(|mut d| d.set_smartshift(config));
+        report_result(
+            &to,
+            result,
+            &format!(
                 "SmartShift {} at threshold {}",
                 if config.enabled {
                     "enabled"
@@ -322,9 +463,8 @@ fn create_smartshift_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
                     "disabled"
                 },
                 config.threshold
-            ));
-            to.add_toast(toast);
-        }
+            )
+        );
     });
 
     threshold_box.append(&apply_btn);
@@ -334,12 +474,18 @@ fn create_smartshift_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
     group
 }
 
-fn create_scroll_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
+fn create_scroll_group(
+    capabilities: &DeviceCapabilities,
+    toast_overlay: ToastOverlay
+) -> PreferencesGroup {
     let group = PreferencesGroup::new();
     group.set_title("Scroll Settings");
     group.set_description(Some("Configure high-resolution and natural scrolling"));
+    if mark_unsupported(&group, capabilities, Setting::HiResScroll) {
+        return group;
+    }
 
-    let current_config = MxMaster3s::open_bolt_receiver(2)
+    let current_config = GenericHidppMouse::open_any()
         .and_then(|mut d| d.get_hires_scroll())
         .unwrap_or_default();
 
@@ -364,6 +510,10 @@ fn create_scroll_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
     inverted_switch.set_active(current_config.inverted);
     inverted_row.add_suffix(&inverted_switch);
     inverted_row.set_activatable_widget(Some(&inverted_switch));
+    if let Some(reason) = capabilities.unsupported_reason(Setting::ScrollInversion) {
+        inverted_row.set_subtitle(&reason);
+        inverted_row.set_sensitive(false);
+    }
 
     let apply_row = ActionRow::new();
     let apply_btn = Button::with_label("Apply Settings");
@@ -379,10 +529,11 @@ fn create_scroll_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
             inverted: is.is_active()
         };
 
-        if let Ok(mut device) = MxMaster3s::open_bolt_receiver(2)
-            && device.set_hires_scroll(config).is_ok()
-        {
-            let toast = Toast::new(&format!(
+        let result = GenericHidppMouse::open_any().and_then(|mut d| d.set_hires_scroll(config));
+        report_result(
+            &to,
+            result,
+            &format!(
                 "Scroll: {} · {}",
                 if config.enabled { "Hi-Res" } else { "Normal" },
                 if config.inverted {
@@ -390,9 +541,8 @@ fn create_scroll_group(toast_overlay: ToastOverlay) -> PreferencesGroup {
                 } else {
                     "Traditional"
                 }
-            ));
-            to.add_toast(toast);
-        }
+            )
+        );
     });
 
     apply_row.add_suffix(&apply_btn);